@@ -4,12 +4,35 @@
 cargo clean
 cargo build --release
 
+# Host port 5555 reaches `udp-echo -l 7` and `tcp-echo -l 7` in the guest,
+# `telnet localhost 2323` opens a shell, the host itself is 10.0.2.2 from
+# inside, e.g. `wget http://10.0.2.2:8000/` after `python3 -m http.server`
+
+# hvc0 is a shell on `socat -,raw,echo=0 unix-connect:nyannix-hvc.sock`,
+# hvc1 writes the kernel log to nyannix.log
+
+# Scratch disk for virtio-blk, kept between runs and mounted on /mnt/vda
+if [ ! -f disk.img ]; then
+    truncate -s 64M disk.img
+    mkfs.vfat -F 32 -n NYANNIX disk.img
+fi
+
 qemu-system-aarch64 \
     -machine virt,accel=hvf \
     -cpu cortex-a72 \
     -m 512M \
     -device virtio-gpu-pci,xres=1024,yres=768 \
     -device virtio-keyboard-pci \
-    -device virtio-mouse-pci \
+    -device virtio-tablet-pci \
+    -drive if=none,id=disk,file=disk.img,format=raw \
+    -device virtio-blk-pci,drive=disk \
+    -netdev user,id=net0,hostfwd=udp::5555-:7,hostfwd=tcp::5555-:7,hostfwd=tcp::2323-:23 \
+    -device virtio-net-pci,netdev=net0 \
+    -device virtio-rng-pci \
+    -device virtio-serial-pci \
+    -chardev socket,id=hvc0,path=nyannix-hvc.sock,server=on,wait=off \
+    -device virtconsole,chardev=hvc0 \
+    -chardev file,id=hvc1,path=nyannix.log \
+    -device virtserialport,chardev=hvc1,name=org.nyannix.log \
     -display cocoa,show-cursor=on \
     -kernel target/aarch64-unknown-none/release/nyannix
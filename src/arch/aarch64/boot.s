@@ -9,10 +9,15 @@ _start:
 1:  wfe
     b       1b
 2:
-    // Set stack before our code
-    ldr     x1, =_start
+    // Set up the boot stack
+    ldr     x1, =__stack_top
     mov     sp, x1
 
+    // Rust code uses FP/SIMD registers, stop them from trapping
+    mov     x1, #(3 << 20)
+    msr     cpacr_el1, x1
+    isb
+
     // Clear BSS
     ldr     x1, =__bss_start
     ldr     x2, =__bss_end
@@ -27,3 +32,9 @@ _start:
     // Should never reach here
 1:  wfe
     b       1b
+
+.section ".bss.stack", "aw", %nobits
+.align 16
+__stack_bottom:
+    .skip   64 * 1024
+__stack_top:
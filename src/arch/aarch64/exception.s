@@ -0,0 +1,137 @@
+// Exception vector table and register save/restore
+
+// Frame layout, must match `ExceptionFrame`
+.equ FRAME_ELR,     248
+.equ FRAME_SPSR,    256
+.equ FRAME_FPSR,    264
+.equ FRAME_FPCR,    272
+.equ FRAME_Q,       288
+.equ FRAME_SIZE,    800
+
+.macro RESTORE_FRAME
+    add     x0, sp, #FRAME_Q
+    ldp     q0, q1, [x0, #0]
+    ldp     q2, q3, [x0, #32]
+    ldp     q4, q5, [x0, #64]
+    ldp     q6, q7, [x0, #96]
+    ldp     q8, q9, [x0, #128]
+    ldp     q10, q11, [x0, #160]
+    ldp     q12, q13, [x0, #192]
+    ldp     q14, q15, [x0, #224]
+    ldp     q16, q17, [x0, #256]
+    ldp     q18, q19, [x0, #288]
+    ldp     q20, q21, [x0, #320]
+    ldp     q22, q23, [x0, #352]
+    ldp     q24, q25, [x0, #384]
+    ldp     q26, q27, [x0, #416]
+    ldp     q28, q29, [x0, #448]
+    ldp     q30, q31, [x0, #480]
+    ldp     x0, x1, [sp, #FRAME_FPSR]
+    msr     fpsr, x0
+    msr     fpcr, x1
+    ldp     x0, x1, [sp, #FRAME_ELR]
+    msr     elr_el1, x0
+    msr     spsr_el1, x1
+    ldp     x0, x1, [sp, #0]
+    ldp     x2, x3, [sp, #16]
+    ldp     x4, x5, [sp, #32]
+    ldp     x6, x7, [sp, #48]
+    ldp     x8, x9, [sp, #64]
+    ldp     x10, x11, [sp, #80]
+    ldp     x12, x13, [sp, #96]
+    ldp     x14, x15, [sp, #112]
+    ldp     x16, x17, [sp, #128]
+    ldp     x18, x19, [sp, #144]
+    ldp     x20, x21, [sp, #160]
+    ldp     x22, x23, [sp, #176]
+    ldp     x24, x25, [sp, #192]
+    ldp     x26, x27, [sp, #208]
+    ldp     x28, x29, [sp, #224]
+    ldr     x30, [sp, #240]
+    add     sp, sp, #FRAME_SIZE
+.endm
+
+// Every entry saves the frame and calls `handler(frame, kind)`
+.macro VECTOR handler, kind
+.align 7
+    sub     sp, sp, #FRAME_SIZE
+    stp     x0, x1, [sp, #0]
+    ldr     x0, =\handler
+    mov     x1, #\kind
+    b       __exception_common
+.endm
+
+.section ".text"
+
+// Entered from a vector with x0/x1 saved, x0 = handler and x1 = kind
+__exception_common:
+    stp     x2, x3, [sp, #16]
+    stp     x4, x5, [sp, #32]
+    stp     x6, x7, [sp, #48]
+    stp     x8, x9, [sp, #64]
+    stp     x10, x11, [sp, #80]
+    stp     x12, x13, [sp, #96]
+    stp     x14, x15, [sp, #112]
+    stp     x16, x17, [sp, #128]
+    stp     x18, x19, [sp, #144]
+    stp     x20, x21, [sp, #160]
+    stp     x22, x23, [sp, #176]
+    stp     x24, x25, [sp, #192]
+    stp     x26, x27, [sp, #208]
+    stp     x28, x29, [sp, #224]
+    str     x30, [sp, #240]
+    mov     x9, x0
+    mov     x10, x1
+    mrs     x11, elr_el1
+    mrs     x12, spsr_el1
+    stp     x11, x12, [sp, #FRAME_ELR]
+    mrs     x11, fpsr
+    mrs     x12, fpcr
+    stp     x11, x12, [sp, #FRAME_FPSR]
+    add     x11, sp, #FRAME_Q
+    stp     q0, q1, [x11, #0]
+    stp     q2, q3, [x11, #32]
+    stp     q4, q5, [x11, #64]
+    stp     q6, q7, [x11, #96]
+    stp     q8, q9, [x11, #128]
+    stp     q10, q11, [x11, #160]
+    stp     q12, q13, [x11, #192]
+    stp     q14, q15, [x11, #224]
+    stp     q16, q17, [x11, #256]
+    stp     q18, q19, [x11, #288]
+    stp     q20, q21, [x11, #320]
+    stp     q22, q23, [x11, #352]
+    stp     q24, q25, [x11, #384]
+    stp     q26, q27, [x11, #416]
+    stp     q28, q29, [x11, #448]
+    stp     q30, q31, [x11, #480]
+
+    mov     x0, sp
+    mov     x1, x10
+    blr     x9
+    RESTORE_FRAME
+    eret
+
+.align 11
+.global __exception_vectors
+__exception_vectors:
+    // Current EL with SP0
+    VECTOR handle_exception, 0
+    VECTOR handle_exception, 1
+    VECTOR handle_exception, 2
+    VECTOR handle_exception, 3
+    // Current EL with SPx
+    VECTOR handle_exception, 0
+    VECTOR handle_exception, 1
+    VECTOR handle_exception, 2
+    VECTOR handle_exception, 3
+    // Lower EL, AArch64
+    VECTOR handle_exception, 0
+    VECTOR handle_exception, 1
+    VECTOR handle_exception, 2
+    VECTOR handle_exception, 3
+    // Lower EL, AArch32
+    VECTOR handle_exception, 0
+    VECTOR handle_exception, 1
+    VECTOR handle_exception, 2
+    VECTOR handle_exception, 3
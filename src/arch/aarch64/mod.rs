@@ -1,6 +1,115 @@
 //! AArch64 architecture specific code
 
+core::arch::global_asm!(include_str!("boot.s"));
+core::arch::global_asm!(include_str!("exception.s"));
+core::arch::global_asm!(include_str!("switch.s"));
+
+extern "C" {
+    fn __switch_context(prev: *mut Context, next: *const Context);
+    fn __task_trampoline();
+    static __exception_vectors: u8;
+}
+
+/// EL1 virtual timer interrupt (PPI 11)
+pub const TIMER_IRQ: u32 = 27;
+
+/// What kind of exception the vector table took
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+#[allow(dead_code, reason = "only the vectors in exception.s create these")]
+pub enum ExceptionKind {
+    Synchronous = 0,
+    Irq = 1,
+    Fiq = 2,
+    SError = 3,
+}
+
+/// Registers saved on exception entry, see `exception.s`
+#[derive(Debug)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub x: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    pub fpsr: u64,
+    pub fpcr: u64,
+    _pad: u64,
+    pub q: [u128; 32],
+}
+
+/// Point VBAR_EL1 at the kernel's exception vectors
+pub fn install_vectors() {
+    unsafe {
+        core::arch::asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) &raw const __exception_vectors as usize,
+            options(nostack)
+        );
+    }
+}
+
+/// Exception syndrome, return address and fault address of the current exception
+pub fn exception_registers() -> (u64, u64, u64) {
+    let (esr, elr, far): (u64, u64, u64);
+    unsafe {
+        core::arch::asm!(
+            "mrs {}, esr_el1",
+            "mrs {}, elr_el1",
+            "mrs {}, far_el1",
+            out(reg) esr,
+            out(reg) elr,
+            out(reg) far,
+            options(nomem, nostack)
+        );
+    }
+    (esr, elr, far)
+}
+
+/// Callee-saved register state of a suspended task
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct Context {
+    x19_x28: [u64; 10],
+    fp: u64,
+    lr: u64,
+    sp: u64,
+    d8_d15: [u64; 8],
+}
+
+impl Context {
+    pub const fn empty() -> Self {
+        Self {
+            x19_x28: [0; 10],
+            fp: 0,
+            lr: 0,
+            sp: 0,
+            d8_d15: [0; 8],
+        }
+    }
+
+    /// Context that starts executing `entry(arg)` on the given stack
+    pub fn new_task(stack_top: usize, entry: extern "C" fn(usize) -> !, arg: usize) -> Self {
+        let mut context = Self::empty();
+        context.x19_x28[0] = arg as u64;
+        context.x19_x28[1] = entry as *const () as u64;
+        context.lr = __task_trampoline as *const () as u64;
+        context.sp = (stack_top & !0xF) as u64;
+        context
+    }
+}
+
+/// Save the current register state into `prev` and resume `next`
+///
+/// # Safety
+/// Both pointers must reference live contexts and IRQs must be masked.
+#[inline(always)]
+pub unsafe fn switch_context(prev: *mut Context, next: *const Context) {
+    __switch_context(prev, next);
+}
+
 #[inline(always)]
+#[allow(dead_code, reason = "padding for timing loops")]
 pub unsafe fn nop() {
     core::arch::asm!("nop");
 }
@@ -11,16 +120,19 @@ pub unsafe fn wfi() {
 }
 
 #[inline(always)]
+#[allow(dead_code, reason = "locks mask IRQs with local_irq_save instead")]
 pub unsafe fn disable_interrupts() {
     core::arch::asm!("msr daifset, #15");
 }
 
 #[inline(always)]
+#[allow(dead_code, reason = "locks unmask IRQs with local_irq_restore")]
 pub unsafe fn enable_interrupts() {
     core::arch::asm!("msr daifclr, #15");
 }
 
 #[inline(always)]
+#[allow(dead_code, reason = "for diagnosing the entry exception level")]
 pub fn current_el() -> u64 {
     let mut el: u64;
     unsafe {
@@ -31,3 +143,100 @@ pub fn current_el() -> u64 {
     }
     (el >> 2) & 0x3
 }
+
+/// Mask IRQs on the current core and return the previous DAIF value
+#[inline(always)]
+pub fn local_irq_save() -> u64 {
+    let daif: u64;
+    unsafe {
+        core::arch::asm!(
+            "mrs {}, daif",
+            "msr daifset, #2",
+            out(reg) daif,
+            options(nostack, preserves_flags)
+        );
+    }
+    daif
+}
+
+/// Restore a DAIF value returned by [`local_irq_save`]
+#[inline(always)]
+pub fn local_irq_restore(daif: u64) {
+    unsafe {
+        core::arch::asm!("msr daif, {}", in(reg) daif, options(nostack, preserves_flags));
+    }
+}
+
+/// Index of the current core (MPIDR_EL1.Aff0)
+#[cfg(feature = "lock-debug")]
+#[inline(always)]
+pub fn cpu_id() -> usize {
+    let mpidr: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack));
+    }
+    (mpidr & 0xFF) as usize
+}
+
+/// Unmask IRQs on the current core
+#[inline(always)]
+pub fn local_irq_enable() {
+    unsafe {
+        core::arch::asm!("msr daifclr, #2", options(nostack, preserves_flags));
+    }
+}
+
+/// Sleep until an interrupt arrives, letting it run before returning with IRQs masked
+#[inline(always)]
+pub fn wait_for_irq() {
+    unsafe {
+        core::arch::asm!(
+            "wfi",
+            "msr daifclr, #2",
+            "isb",
+            "msr daifset, #2",
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Current value of the generic timer's virtual counter
+#[inline(always)]
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe {
+        core::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) count, options(nostack));
+    }
+    count
+}
+
+/// Frequency of the generic timer counter in Hz
+#[inline(always)]
+pub fn counter_frequency() -> u64 {
+    let freq: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack));
+    }
+    freq
+}
+
+/// Fire [`TIMER_IRQ`] once the virtual counter reaches `deadline`
+pub fn timer_arm(deadline: u64) {
+    unsafe {
+        core::arch::asm!(
+            "msr cntv_cval_el0, {}",
+            "msr cntv_ctl_el0, {}",
+            "isb",
+            in(reg) deadline,
+            in(reg) 1u64,
+            options(nostack)
+        );
+    }
+}
+
+/// Stop the virtual timer from raising interrupts
+pub fn timer_disarm() {
+    unsafe {
+        core::arch::asm!("msr cntv_ctl_el0, {}", "isb", in(reg) 0u64, options(nostack));
+    }
+}
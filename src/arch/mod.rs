@@ -0,0 +1,5 @@
+//! Architecture specific code
+
+pub mod aarch64;
+
+pub use aarch64::*;
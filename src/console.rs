@@ -21,6 +21,7 @@ pub fn putc(c: u8) {
     }
 }
 
+#[allow(dead_code, reason = "keyboard input comes from the input drivers")]
 pub fn getc() -> u8 {
     unsafe {
         // Wait until UART has received data
@@ -37,6 +38,7 @@ pub fn puts(s: &str) {
     }
 }
 
+#[allow(dead_code, reason = "kernel output goes through drivers::uart")]
 pub fn clear_screen() {
     puts("\x1B[2J\x1B[H");
 }
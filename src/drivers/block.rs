@@ -0,0 +1,177 @@
+//! Block devices
+//!
+//! Drivers register every disk they find as a [`BlockDevice`]. Transfers
+//! are in whole blocks of [`BlockDevice::block_size`] bytes; block `n` starts
+//! at byte `n * block_size`. File systems look their volume up by name.
+
+use crate::drivers::virtio;
+use crate::sync::SafeMutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The transfer reaches past the end of the device
+    OutOfRange,
+    /// The buffer is not a whole number of blocks
+    Unaligned,
+    ReadOnly,
+    /// The device failed the request
+    Io,
+    /// The device cannot do this, e.g. flush without a write cache
+    Unsupported,
+    VirtIO(virtio::Error),
+}
+
+impl From<virtio::Error> for Error {
+    fn from(err: virtio::Error) -> Self {
+        Error::VirtIO(err)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A disk addressed in fixed-size blocks
+///
+/// Methods take `&self` so a device can be shared between file systems and
+/// the cache; drivers serialize requests themselves.
+pub trait BlockDevice: Send + Sync {
+    /// Short name, e.g. `vda`
+    fn name(&self) -> &str;
+    /// What the device says it is, empty if it does not say
+    fn model(&self) -> String {
+        String::new()
+    }
+    /// Bytes per block, a power of two and at least 512
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Read `buffer.len() / block_size` blocks starting at `block`
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<()>;
+    /// Write `buffer.len() / block_size` blocks starting at `block`
+    fn write_blocks(&self, block: u64, buffer: &[u8]) -> Result<()>;
+    /// Make completed writes durable
+    fn flush(&self) -> Result<()>;
+
+    /// Size in bytes
+    fn capacity(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+
+    /// Read bytes at any offset, whole blocks are read around them
+    fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = vec![0; block_size];
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let start = (position % block_size as u64) as usize;
+            let len = (block_size - start).min(buffer.len() - done);
+            self.read_blocks(position / block_size as u64, &mut block)?;
+            buffer[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write bytes at any offset, partly covered blocks are read first
+    fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = vec![0; block_size];
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let index = position / block_size as u64;
+            let start = (position % block_size as u64) as usize;
+            let len = (block_size - start).min(data.len() - done);
+            if len < block_size {
+                self.read_blocks(index, &mut block)?;
+            }
+            block[start..start + len].copy_from_slice(&data[done..done + len]);
+            self.write_blocks(index, &block)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Check that a transfer of `len` bytes at `block` fits the device
+    fn check_range(&self, block: u64, len: usize) -> Result<()> {
+        let block_size = self.block_size();
+        if !len.is_multiple_of(block_size) {
+            return Err(Error::Unaligned);
+        }
+        let end = block
+            .checked_add((len / block_size) as u64)
+            .ok_or(Error::OutOfRange)?;
+        if end > self.block_count() {
+            return Err(Error::OutOfRange);
+        }
+        Ok(())
+    }
+}
+
+/// Byte count with a binary unit, e.g. `64M`
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 5] = ["", "K", "M", "G", "T"];
+        let mut size = self.0;
+        let mut unit = 0;
+        while size >= 1024 && size % 1024 == 0 && unit < UNITS.len() - 1 {
+            size /= 1024;
+            unit += 1;
+        }
+        write!(f, "{}{}", size, UNITS[unit])
+    }
+}
+
+static DEVICES: SafeMutex<Vec<Arc<dyn BlockDevice>>> = SafeMutex::new(Vec::new());
+
+/// Make a disk available to file systems
+pub fn register(device: Arc<dyn BlockDevice>) {
+    crate::log_info!(
+        "block: {}: {} blocks of {} bytes ({}){}",
+        device.name(),
+        device.block_count(),
+        device.block_size(),
+        Size(device.capacity()),
+        if device.is_read_only() {
+            ", read-only"
+        } else {
+            ""
+        }
+    );
+    DEVICES.lock().push(device);
+}
+
+/// All registered disks, in the order they were found
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// Name for the next disk of a driver, `prefix` followed by a letter
+pub fn next_name(prefix: &str) -> String {
+    let count = DEVICES
+        .lock()
+        .iter()
+        .filter(|device| device.name().starts_with(prefix))
+        .count();
+    let mut name = String::from(prefix);
+    name.push((b'a' + (count % 26) as u8) as char);
+    name
+}
@@ -0,0 +1,201 @@
+//! Mouse cursor shapes and the software cursor
+//!
+//! Shapes are rendered into 64x64 ARGB images for hardware cursor planes.
+//! Without one, [`SoftwareCursor`] draws the same image into the
+//! framebuffer and puts back the pixels it covered before anything else is
+//! drawn there.
+
+use crate::drivers::display::Rect;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const CURSOR_SIZE: u32 = 64;
+
+const OUTLINE: u32 = 0xFF00_0000;
+const FILL: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Arrow,
+    IBeam,
+    #[allow(dead_code, reason = "the window has no resize border yet")]
+    Resize,
+    Busy,
+}
+
+// `X` is outline, `o` fill, anything else transparent
+const ARROW: &[&str] = &[
+    "X...........",
+    "XX..........",
+    "XoX.........",
+    "XooX........",
+    "XoooX.......",
+    "XooooX......",
+    "XoooooX.....",
+    "XooooooX....",
+    "XoooooooX...",
+    "XooooooooX..",
+    "XoooooooooX.",
+    "XooooooXXXXX",
+    "XoooXooX....",
+    "XooX.XooX...",
+    "XoX..XooX...",
+    "XX....XooX..",
+    "X.....XooX..",
+    ".......XooX.",
+    ".......XXX..",
+];
+
+const IBEAM: &[&str] = &[
+    "XXX.XXX", "XooXooX", "XXXoXXX", "..XoX..", "..XoX..", "..XoX..", "..XoX..", "..XoX..",
+    "..XoX..", "..XoX..", "..XoX..", "..XoX..", "..XoX..", "..XoX..", "XXXoXXX", "XooXooX",
+    "XXX.XXX",
+];
+
+const RESIZE: &[&str] = &[
+    "....X.......X....",
+    "...XX.......XX...",
+    "..XoX.......XoX..",
+    ".XooXXXXXXXXXooX.",
+    "XoooooooooooooooX",
+    ".XooXXXXXXXXXooX.",
+    "..XoX.......XoX..",
+    "...XX.......XX...",
+    "....X.......X....",
+];
+
+const BUSY: &[&str] = &[
+    "XXXXXXXXXXX",
+    "XoooooooooX",
+    ".XoooooooX.",
+    ".XoooooooX.",
+    "..XoooooX..",
+    "...XoooX...",
+    "....XoX....",
+    ".....X.....",
+    ".....X.....",
+    "....XoX....",
+    "...XoooX...",
+    "..XoooooX..",
+    ".XoooooooX.",
+    ".XoooooooX.",
+    "XoooooooooX",
+    "XXXXXXXXXXX",
+];
+
+/// A rendered cursor: `CURSOR_SIZE` squared pixels of `0xAARRGGBB`
+pub struct CursorImage {
+    pub pixels: Vec<u32>,
+    pub hot_x: u32,
+    pub hot_y: u32,
+}
+
+impl CursorImage {
+    pub fn new(shape: CursorShape) -> Self {
+        let (rows, hot_x, hot_y) = match shape {
+            CursorShape::Arrow => (ARROW, 0, 0),
+            CursorShape::IBeam => (IBEAM, 3, 8),
+            CursorShape::Resize => (RESIZE, 8, 4),
+            CursorShape::Busy => (BUSY, 5, 8),
+        };
+
+        let mut pixels = vec![0; (CURSOR_SIZE * CURSOR_SIZE) as usize];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.bytes().enumerate() {
+                pixels[y * CURSOR_SIZE as usize + x] = match c {
+                    b'X' => OUTLINE,
+                    b'o' => FILL,
+                    _ => 0,
+                };
+            }
+        }
+        Self {
+            pixels,
+            hot_x,
+            hot_y,
+        }
+    }
+}
+
+/// Cursor drawn into the framebuffer
+pub struct SoftwareCursor {
+    image: CursorImage,
+    x: u32,
+    y: u32,
+    /// Where the cursor is drawn and the pixels it covers
+    on_screen: Option<Rect>,
+    saved: Vec<u32>,
+}
+
+impl SoftwareCursor {
+    pub fn new(shape: CursorShape) -> Self {
+        Self {
+            image: CursorImage::new(shape),
+            x: 0,
+            y: 0,
+            on_screen: None,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn set_image(&mut self, image: CursorImage) {
+        self.image = image;
+    }
+
+    pub fn set_position(&mut self, x: u32, y: u32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    /// The framebuffer was replaced, there is nothing to put back
+    pub fn forget(&mut self) {
+        self.on_screen = None;
+    }
+
+    /// Put back the pixels under the cursor, returns the area changed
+    pub fn hide(&mut self, pixels: &mut [u32], stride: u32) -> Option<Rect> {
+        let rect = self.on_screen.take()?;
+        for row in 0..rect.height {
+            let start = ((rect.y + row) * stride + rect.x) as usize;
+            let saved = (row * rect.width) as usize;
+            if let Some(line) = pixels.get_mut(start..start + rect.width as usize) {
+                line.copy_from_slice(&self.saved[saved..saved + rect.width as usize]);
+            }
+        }
+        Some(rect)
+    }
+
+    /// Draw the cursor on a `width` x `height` framebuffer, returns the area changed
+    pub fn show(&mut self, pixels: &mut [u32], width: u32, height: u32) -> Option<Rect> {
+        if self.on_screen.is_some() || pixels.len() < (width * height) as usize {
+            return None;
+        }
+        // Parts of the image left of or above the screen are cut off
+        let skip_x = self.image.hot_x.saturating_sub(self.x);
+        let skip_y = self.image.hot_y.saturating_sub(self.y);
+        let left = self.x.saturating_sub(self.image.hot_x);
+        let top = self.y.saturating_sub(self.image.hot_y);
+        let rect =
+            Rect::new(left, top, CURSOR_SIZE - skip_x, CURSOR_SIZE - skip_y).clip(width, height);
+        if rect.is_empty() {
+            return None;
+        }
+
+        self.saved.clear();
+        for row in 0..rect.height {
+            let start = ((rect.y + row) * width + rect.x) as usize;
+            let line = &mut pixels[start..start + rect.width as usize];
+            self.saved.extend_from_slice(line);
+
+            let image_row = ((row + skip_y) * CURSOR_SIZE + skip_x) as usize;
+            let image = &self.image.pixels[image_row..image_row + rect.width as usize];
+            for (pixel, &argb) in line.iter_mut().zip(image) {
+                if argb >> 24 != 0 {
+                    *pixel = argb & 0x00FF_FFFF;
+                }
+            }
+        }
+        self.on_screen = Some(rect);
+        Some(rect)
+    }
+}
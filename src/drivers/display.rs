@@ -0,0 +1,459 @@
+//! Display independent of the device that shows it
+//!
+//! Everything is drawn into a guest framebuffer of `0x00RRGGBB` pixels.
+//! A [`Backend`] scans it out: virtio-gpu if there is one, otherwise ramfb
+//! through fw_cfg. Nothing is guaranteed to be visible before the changed
+//! area is flushed, see [`Display::flush`].
+//!
+//! The mode follows the host where the backend can tell: when its window
+//! changes size [`Display::poll_event`] switches to the new preferred size.
+//! [`Display::set_mode`] picks a mode explicitly.
+//!
+//! The mouse cursor uses the backend's cursor plane if it has one,
+//! otherwise it is drawn into the framebuffer at flush time.
+
+use crate::drivers::cursor::{CursorImage, CursorShape, SoftwareCursor};
+use crate::drivers::{fw_cfg, ramfb, virtio};
+use crate::kernel::memory::DmaBuffer;
+use crate::sync::Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub static DISPLAY: Mutex<Display> = Mutex::new(Display::new());
+
+// Used when the backend has no preferred size
+const SCREEN_WIDTH: u32 = 800;
+const SCREEN_HEIGHT: u32 = 600;
+
+// Two framebuffers are alive while switching modes, keep them within the heap
+const MAX_WIDTH: u32 = 2560;
+const MAX_HEIGHT: u32 = 1600;
+
+/// Offered in addition to the host's preferred mode
+const STANDARD_MODES: [(u32, u32); 7] = [
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1280, 1024),
+    (1600, 900),
+    (1920, 1080),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No display device was found
+    NotPresent,
+    /// The size is zero or larger than the framebuffer limit
+    InvalidMode,
+    /// The backend has no such feature
+    Unsupported,
+    VirtIO(virtio::Error),
+    FwCfg(fw_cfg::Error),
+}
+
+impl From<virtio::Error> for Error {
+    fn from(err: virtio::Error) -> Self {
+        Error::VirtIO(err)
+    }
+}
+
+impl From<fw_cfg::Error> for Error {
+    fn from(err: fw_cfg::Error) -> Self {
+        Error::FwCfg(err)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayEvent {
+    /// The screen now has a different size, everything must be redrawn
+    Resized { width: u32, height: u32 },
+}
+
+/// A screen area in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Smallest rectangle covering both
+    pub fn union(&self, other: Rect) -> Rect {
+        if self.is_empty() {
+            return other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// The part of the rectangle inside a `width` x `height` screen
+    pub fn clip(&self, width: u32, height: u32) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        let right = self.x.saturating_add(self.width).min(width);
+        let bottom = self.y.saturating_add(self.height).min(height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// A device that scans out the framebuffer
+pub trait Backend: Send {
+    fn name(&self) -> &'static str;
+
+    /// Size the host asks for, if it has an opinion
+    fn preferred_mode(&mut self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// True once after the host changed its preferred mode
+    fn mode_changed(&mut self) -> bool {
+        false
+    }
+
+    /// Show `framebuffer` of `width` x `height` pixels instead of the current one
+    ///
+    /// The previous framebuffer stays alive until this returns.
+    fn set_framebuffer(&mut self, framebuffer: &DmaBuffer, width: u32, height: u32) -> Result<()>;
+
+    /// Make `rect` visible, nothing to do if the host reads the framebuffer itself
+    fn flush(&mut self, _rect: Rect, _stride: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the cursor methods below work
+    fn has_cursor(&self) -> bool {
+        false
+    }
+
+    /// Show `image` with its hotspot at `x`, `y`
+    fn update_cursor(&mut self, _image: &CursorImage, _x: u32, _y: u32) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    fn move_cursor(&mut self, _x: u32, _y: u32) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+}
+
+fn as_pixels(framebuffer: Option<&mut DmaBuffer>) -> &mut [u32] {
+    match framebuffer {
+        Some(framebuffer) => {
+            let bytes = framebuffer.as_mut_slice();
+            unsafe {
+                core::slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut u32, bytes.len() / 4)
+            }
+        }
+        None => &mut [],
+    }
+}
+
+pub struct Display {
+    backend: Option<Box<dyn Backend>>,
+    framebuffer: Option<DmaBuffer>,
+    width: u32,
+    height: u32,
+    /// Size the host asked for last
+    preferred: Option<(u32, u32)>,
+    pending_event: Option<DisplayEvent>,
+    /// Area drawn since the last flush
+    damage: Rect,
+    /// Used when the backend has no cursor plane
+    software_cursor: Option<SoftwareCursor>,
+    cursor_shape: Option<CursorShape>,
+    cursor_position: (u32, u32),
+    /// Area the software cursor left or entered since the last flush
+    cursor_damage: Rect,
+}
+
+impl Display {
+    const fn new() -> Self {
+        Self {
+            backend: None,
+            framebuffer: None,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            preferred: None,
+            pending_event: None,
+            damage: Rect::new(0, 0, 0, 0),
+            software_cursor: None,
+            cursor_shape: None,
+            cursor_position: (0, 0),
+            cursor_damage: Rect::new(0, 0, 0, 0),
+        }
+    }
+
+    /// Pick a backend, virtio-gpu first, set the preferred mode and fill
+    /// the screen with `background`
+    pub fn init(&mut self, background: u32) {
+        if self.backend.is_some() {
+            return;
+        }
+        let backend: Box<dyn Backend> = if let Some(gpu) = virtio::gpu::probe() {
+            Box::new(gpu)
+        } else if let Some(ramfb) = ramfb::probe() {
+            Box::new(ramfb)
+        } else {
+            crate::log_warn!("display: no device found, nothing will be displayed");
+            return;
+        };
+        crate::log_info!("display: using {}", backend.name());
+        if !backend.has_cursor() {
+            self.software_cursor = Some(SoftwareCursor::new(CursorShape::Arrow));
+        }
+        self.backend = Some(backend);
+
+        self.preferred = self.backend.as_mut().and_then(|b| b.preferred_mode());
+        let (width, height) = self.preferred.unwrap_or((SCREEN_WIDTH, SCREEN_HEIGHT));
+        if let Err(err) = self.set_mode(width.min(MAX_WIDTH), height.min(MAX_HEIGHT)) {
+            crate::log_error!("display: setting {}x{} failed: {:?}", width, height, err);
+            self.backend = None;
+            return;
+        }
+
+        self.set_cursor_shape(CursorShape::Arrow);
+        self.clear_screen(background);
+        self.flush_damage();
+    }
+
+    /// Name of the device in use
+    pub fn backend_name(&self) -> Option<&'static str> {
+        self.backend.as_ref().map(|backend| backend.name())
+    }
+
+    /// Change the mouse cursor image
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        if self.cursor_shape == Some(shape) {
+            return;
+        }
+        self.cursor_shape = Some(shape);
+        let image = CursorImage::new(shape);
+
+        if self.software_cursor.is_some() {
+            self.hide_cursor();
+            if let Some(cursor) = self.software_cursor.as_mut() {
+                cursor.set_image(image);
+            }
+        } else if let Some(backend) = self.backend.as_mut() {
+            let (x, y) = self.cursor_position;
+            if let Err(err) = backend.update_cursor(&image, x, y) {
+                crate::log_warn!("display: cursor update failed: {:?}", err);
+            }
+        }
+    }
+
+    /// Move the mouse cursor's hotspot to `x`, `y`
+    pub fn move_cursor(&mut self, x: u32, y: u32) {
+        if self.cursor_position == (x, y) {
+            return;
+        }
+        self.cursor_position = (x, y);
+
+        if self.software_cursor.is_some() {
+            self.hide_cursor();
+            if let Some(cursor) = self.software_cursor.as_mut() {
+                cursor.set_position(x, y);
+            }
+        } else if let Some(backend) = self.backend.as_mut() {
+            if let Err(err) = backend.move_cursor(x, y) {
+                crate::log_warn!("display: cursor move failed: {:?}", err);
+            }
+        }
+    }
+
+    /// Take the software cursor off the framebuffer before drawing under it
+    fn hide_cursor(&mut self) {
+        let Some(cursor) = self.software_cursor.as_mut() else {
+            return;
+        };
+        if let Some(rect) = cursor.hide(as_pixels(self.framebuffer.as_mut()), self.width) {
+            self.cursor_damage = self.cursor_damage.union(rect);
+        }
+    }
+
+    fn show_cursor(&mut self) {
+        let Some(cursor) = self.software_cursor.as_mut() else {
+            return;
+        };
+        let pixels = as_pixels(self.framebuffer.as_mut());
+        if let Some(rect) = cursor.show(pixels, self.width, self.height) {
+            self.cursor_damage = self.cursor_damage.union(rect);
+        }
+    }
+
+    /// Switch to a new framebuffer of `width` x `height`
+    ///
+    /// The screen is blank afterwards, a [`DisplayEvent::Resized`] tells
+    /// the UI to redraw.
+    pub fn set_mode(&mut self, width: u32, height: u32) -> Result<()> {
+        if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err(Error::InvalidMode);
+        }
+        let backend = self.backend.as_mut().ok_or(Error::NotPresent)?;
+
+        let framebuffer = DmaBuffer::new((width * height * 4) as usize);
+        backend.set_framebuffer(&framebuffer, width, height)?;
+        crate::log_info!("display: {} mode {}x{}", backend.name(), width, height);
+
+        self.framebuffer = Some(framebuffer);
+        self.width = width;
+        self.height = height;
+        self.damage = Rect::default();
+        self.cursor_damage = Rect::default();
+        if let Some(cursor) = self.software_cursor.as_mut() {
+            cursor.forget();
+        }
+        self.pending_event = Some(DisplayEvent::Resized { width, height });
+        Ok(())
+    }
+
+    /// Modes that can be passed to [`Display::set_mode`], the host's preferred one first
+    pub fn modes(&self) -> Vec<(u32, u32)> {
+        let mut modes: Vec<(u32, u32)> = self.preferred.into_iter().collect();
+        for mode in STANDARD_MODES {
+            if !modes.contains(&mode) {
+                modes.push(mode);
+            }
+        }
+        modes
+    }
+
+    /// Follow host display changes and report mode switches
+    pub fn poll_event(&mut self) -> Option<DisplayEvent> {
+        if let Some(backend) = self.backend.as_mut() {
+            if backend.mode_changed() {
+                self.preferred = backend.preferred_mode();
+                match self.preferred {
+                    Some((width, height)) if (width, height) != (self.width, self.height) => {
+                        let (width, height) = (width.min(MAX_WIDTH), height.min(MAX_HEIGHT));
+                        if let Err(err) = self.set_mode(width, height) {
+                            crate::log_warn!("display: following host resize: {:?}", err);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.pending_event.take()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixels(&mut self) -> &mut [u32] {
+        as_pixels(self.framebuffer.as_mut())
+    }
+
+    fn damage(&mut self, rect: Rect) {
+        self.damage = self.damage.union(rect.clip(self.width, self.height));
+    }
+
+    /// Make `rect` of the framebuffer visible on the display
+    pub fn flush(&mut self, rect: Rect) {
+        self.show_cursor();
+        let cursor_damage = core::mem::take(&mut self.cursor_damage);
+        let rect = rect.union(cursor_damage).clip(self.width, self.height);
+        let stride = self.width * 4;
+        let Some(backend) = self.backend.as_mut() else {
+            return;
+        };
+        if rect.is_empty() {
+            return;
+        }
+        if let Err(err) = backend.flush(rect, stride) {
+            crate::log_warn!("display: flush of {:?} failed: {:?}", rect, err);
+        }
+    }
+
+    /// Flush everything drawn since the last call
+    pub fn flush_damage(&mut self) {
+        let damage = core::mem::take(&mut self.damage);
+        self.flush(damage);
+    }
+
+    pub fn clear_screen(&mut self, color: u32) {
+        self.hide_cursor();
+        self.pixels().fill(color);
+        self.damage(Rect::new(0, 0, self.width, self.height));
+    }
+
+    pub fn draw_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: u32) {
+        let rect = Rect::new(x, y, width, height).clip(self.width, self.height);
+        if rect.is_empty() {
+            return;
+        }
+        self.hide_cursor();
+        let stride = self.width as usize;
+        let pixels = self.pixels();
+        for row in rect.y..rect.y + rect.height {
+            let start = row as usize * stride + rect.x as usize;
+            if let Some(line) = pixels.get_mut(start..start + rect.width as usize) {
+                line.fill(color);
+            }
+        }
+        self.damage(rect);
+    }
+
+    pub fn draw_text(&mut self, x: u32, y: u32, text: &str, color: u32) {
+        use crate::drivers::font::FONT_8X8;
+
+        let mut cursor_x = x;
+        let mut cursor_y = y;
+
+        for c in text.chars() {
+            if c == '\n' {
+                cursor_y += 8;
+                cursor_x = x;
+                continue;
+            }
+
+            let char_index = c as usize;
+            if char_index < FONT_8X8.len() {
+                let char_bitmap = FONT_8X8[char_index];
+                for (row, bitmap) in char_bitmap.iter().enumerate() {
+                    for col in 0..8 {
+                        if (bitmap >> (7 - col)) & 1 != 0 {
+                            self.draw_rect(
+                                cursor_x + col as u32,
+                                cursor_y + row as u32,
+                                1,
+                                1,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+            cursor_x += 8;
+        }
+    }
+}
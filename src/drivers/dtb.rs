@@ -0,0 +1,212 @@
+//! Flattened device tree parsing
+//!
+//! QEMU places the device tree at the start of RAM when booting a bare-metal
+//! image. Only what drivers need is implemented: walking nodes, reading
+//! properties and decoding `reg`.
+
+use alloc::vec::Vec;
+
+/// Where QEMU's virt machine loads the device tree for non-Linux guests
+pub const DTB_ADDRESS: usize = 0x4000_0000;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+fn c_str(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..end]).unwrap_or("")
+}
+
+/// Read a number made of `cells` big-endian 32-bit cells
+pub fn read_cells(bytes: &[u8], cells: u32) -> Option<u64> {
+    (0..cells as usize).try_fold(0u64, |value, i| {
+        Some(value << 32 | be32(bytes, i * 4)? as u64)
+    })
+}
+
+#[derive(Clone, Copy)]
+pub struct DeviceTree {
+    structs: &'static [u8],
+    strings: &'static [u8],
+}
+
+/// One node with its properties, `address_cells`/`size_cells` are the
+/// parent's and apply to this node's `reg`
+#[derive(Clone, Copy)]
+pub struct Node {
+    pub address_cells: u32,
+    pub size_cells: u32,
+    /// Node's own `#address-cells`, used to decode child addresses in `ranges`
+    pub child_address_cells: u32,
+    pub child_size_cells: u32,
+    props: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl DeviceTree {
+    /// Parse the device tree blob at `address`
+    ///
+    /// # Safety
+    /// `address` must be readable memory that stays valid for the kernel's lifetime.
+    pub unsafe fn from_address(address: usize) -> Option<Self> {
+        let header = core::slice::from_raw_parts(address as *const u8, 40);
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total = be32(header, 4)? as usize;
+        let blob = core::slice::from_raw_parts(address as *const u8, total);
+        let off_struct = be32(header, 8)? as usize;
+        let off_strings = be32(header, 12)? as usize;
+        let size_strings = be32(header, 32)? as usize;
+        let size_struct = be32(header, 36)? as usize;
+
+        Some(Self {
+            structs: blob.get(off_struct..off_struct + size_struct)?,
+            strings: blob.get(off_strings..off_strings + size_strings)?,
+        })
+    }
+
+    /// The device tree handed over by the boot loader, if there is one
+    pub fn get() -> Option<Self> {
+        unsafe { Self::from_address(DTB_ADDRESS) }
+    }
+
+    /// Visit nodes in tree order until `visit` returns false
+    pub fn walk(&self, mut visit: impl FnMut(&Node) -> bool) {
+        // (#address-cells, #size-cells) of each open node
+        let mut cells: Vec<(u32, u32)> = Vec::new();
+        let mut offset = 0;
+
+        while let Some(token) = be32(self.structs, offset) {
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(&self.structs[offset..]);
+                    offset = align4(offset + name.len() + 1);
+                    let props_start = offset;
+                    offset = self.skip_props(offset);
+
+                    let (address_cells, size_cells) = cells.last().copied().unwrap_or((2, 1));
+                    let mut node = Node {
+                        address_cells,
+                        size_cells,
+                        child_address_cells: 2,
+                        child_size_cells: 1,
+                        props: &self.structs[props_start..offset],
+                        strings: self.strings,
+                    };
+                    if let Some(value) = node.property_u32("#address-cells") {
+                        node.child_address_cells = value;
+                    }
+                    if let Some(value) = node.property_u32("#size-cells") {
+                        node.child_size_cells = value;
+                    }
+                    cells.push((node.child_address_cells, node.child_size_cells));
+
+                    if !visit(&node) {
+                        return;
+                    }
+                }
+                FDT_END_NODE => {
+                    cells.pop();
+                }
+                FDT_PROP => {
+                    let len = be32(self.structs, offset).unwrap_or(0) as usize;
+                    offset = align4(offset + 8 + len);
+                }
+                FDT_NOP => {}
+                FDT_END => return,
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip the property records that follow a node header
+    fn skip_props(&self, mut offset: usize) -> usize {
+        loop {
+            match be32(self.structs, offset) {
+                Some(FDT_PROP) => {
+                    let len = be32(self.structs, offset + 4).unwrap_or(0) as usize;
+                    offset = align4(offset + 12 + len);
+                }
+                Some(FDT_NOP) => offset += 4,
+                _ => return offset,
+            }
+        }
+    }
+
+    /// All nodes whose `compatible` list contains `compatible`
+    pub fn find_compatible(&self, compatible: &str) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        self.walk(|node| {
+            if node.is_compatible(compatible) {
+                nodes.push(*node);
+            }
+            true
+        });
+        nodes
+    }
+}
+
+impl Node {
+    pub fn property(&self, name: &str) -> Option<&'static [u8]> {
+        let mut offset = 0;
+        while let Some(token) = be32(self.props, offset) {
+            if token != FDT_PROP {
+                offset += 4;
+                continue;
+            }
+            let len = be32(self.props, offset + 4)? as usize;
+            let name_offset = be32(self.props, offset + 8)? as usize;
+            let value = self.props.get(offset + 12..offset + 12 + len)?;
+            if c_str(self.strings.get(name_offset..)?) == name {
+                return Some(value);
+            }
+            offset = align4(offset + 12 + len);
+        }
+        None
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        be32(self.property(name)?, 0)
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").is_some_and(|value| {
+            value
+                .split(|&b| b == 0)
+                .any(|entry| entry == compatible.as_bytes())
+        })
+    }
+
+    /// Decoded `reg` entries as (address, size)
+    pub fn reg(&self) -> Vec<(u64, u64)> {
+        let Some(reg) = self.property("reg") else {
+            return Vec::new();
+        };
+        let entry = (self.address_cells + self.size_cells) as usize * 4;
+        if entry == 0 {
+            return Vec::new();
+        }
+        reg.chunks_exact(entry)
+            .filter_map(|chunk| {
+                let address = read_cells(chunk, self.address_cells)?;
+                let size = read_cells(&chunk[self.address_cells as usize * 4..], self.size_cells)?;
+                Some((address, size))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,261 @@
+//! QEMU firmware configuration device (fw_cfg), MMIO interface
+//!
+//! Items are picked with a 16-bit selector and read byte by byte through
+//! the data register. Named items are listed in the file directory and read
+//! whole with [`read_file`], which uses DMA when the device offers it. Writes,
+//! e.g. the ramfb configuration, need the DMA interface.
+//!
+//! QEMU adds files with `-fw_cfg name=opt/nyannix/<path>,file=<host file>`.
+
+use crate::drivers::dtb::DeviceTree;
+use crate::sync::SafeMutex;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+const COMPATIBLE: &str = "qemu,fw-cfg-mmio";
+/// Where QEMU virt puts the device when the device tree says nothing
+const DEFAULT_BASE: usize = 0x0902_0000;
+
+// Register offsets
+const REG_DATA: usize = 0x00;
+const REG_SELECTOR: usize = 0x08;
+const REG_DMA: usize = 0x10;
+
+// Well-known selectors
+const SELECT_SIGNATURE: u16 = 0x0000;
+const SELECT_ID: u16 = 0x0001;
+const SELECT_FILE_DIR: u16 = 0x0019;
+
+const SIGNATURE: &[u8; 4] = b"QEMU";
+const ID_DMA: u32 = 1 << 1;
+
+// DMA control bits, the selector goes in the upper 16 bits
+const DMA_ERROR: u32 = 1 << 0;
+const DMA_READ: u32 = 1 << 1;
+const DMA_SELECT: u32 = 1 << 3;
+const DMA_WRITE: u32 = 1 << 4;
+
+/// Length of the NUL padded name in a directory entry
+const FILE_NAME_LEN: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No fw_cfg device, or the signature did not match
+    NotPresent,
+    /// The device only has the slower register interface
+    NoDma,
+    FileNotFound,
+    /// The device set the error bit of a DMA request
+    DmaFailed,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An entry of the file directory
+#[derive(Debug, Clone)]
+pub struct File {
+    pub name: String,
+    pub size: u32,
+    pub select: u16,
+}
+
+/// Request read by the device, every field big-endian
+#[repr(C, align(8))]
+struct DmaAccess {
+    control: u32,
+    length: u32,
+    address: u64,
+}
+
+struct FwCfg {
+    base: usize,
+    dma: bool,
+    files: Vec<File>,
+}
+
+impl FwCfg {
+    fn probe(base: usize) -> Result<Self> {
+        let mut device = Self {
+            base,
+            dma: false,
+            files: Vec::new(),
+        };
+        let mut signature = [0; 4];
+        device.read(SELECT_SIGNATURE, &mut signature);
+        if &signature != SIGNATURE {
+            return Err(Error::NotPresent);
+        }
+        let mut id = [0; 4];
+        device.read(SELECT_ID, &mut id);
+        device.dma = u32::from_le_bytes(id) & ID_DMA != 0;
+        device.files = device.read_directory();
+        Ok(device)
+    }
+
+    fn select(&self, select: u16) {
+        // The selector register is big-endian, unlike the rest of the machine
+        unsafe { write_volatile((self.base + REG_SELECTOR) as *mut u16, select.to_be()) }
+    }
+
+    /// Continue reading the selected item
+    fn read_data(&self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = unsafe { read_volatile((self.base + REG_DATA) as *const u8) };
+        }
+    }
+
+    /// Read the start of an item, missing bytes read as zero
+    fn read(&self, select: u16, buffer: &mut [u8]) {
+        self.select(select);
+        self.read_data(buffer);
+    }
+
+    fn read_directory(&self) -> Vec<File> {
+        let mut count = [0; 4];
+        self.read(SELECT_FILE_DIR, &mut count);
+        let count = u32::from_be_bytes(count);
+
+        let mut files = Vec::new();
+        for _ in 0..count {
+            let mut entry = [0; 8 + FILE_NAME_LEN];
+            self.read_data(&mut entry);
+            let size = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            let select = u16::from_be_bytes([entry[4], entry[5]]);
+            let name = &entry[8..];
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            files.push(File {
+                name: String::from_utf8_lossy(&name[..len]).into_owned(),
+                size,
+                select,
+            });
+        }
+        files
+    }
+
+    fn find(&self, name: &str) -> Option<&File> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    /// Read a whole item, through DMA if possible
+    fn read_item(&self, select: u16, size: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; size];
+        if self.dma {
+            self.dma(select, DMA_READ, data.as_mut_ptr(), size)?;
+        } else {
+            self.read(select, &mut data);
+        }
+        Ok(data)
+    }
+
+    /// Overwrite the start of an item
+    fn write(&self, select: u16, data: &[u8]) -> Result<()> {
+        if !self.dma {
+            return Err(Error::NoDma);
+        }
+        self.dma(select, DMA_WRITE, data.as_ptr() as *mut u8, data.len())
+    }
+
+    /// Select an item and transfer `length` bytes at `address`
+    fn dma(&self, select: u16, operation: u32, address: *mut u8, length: usize) -> Result<()> {
+        // The MMU is off, so the stack address is what the device sees
+        let access = DmaAccess {
+            control: ((select as u32) << 16 | DMA_SELECT | operation).to_be(),
+            length: (length as u32).to_be(),
+            address: (address as u64).to_be(),
+        };
+        let access_ptr = &access as *const DmaAccess;
+        fence(Ordering::SeqCst);
+        unsafe {
+            write_volatile(
+                (self.base + REG_DMA) as *mut u64,
+                (access_ptr as u64).to_be(),
+            )
+        };
+
+        // QEMU completes the request before the register write returns,
+        // the loop is for devices that do not
+        loop {
+            fence(Ordering::SeqCst);
+            let control = u32::from_be(unsafe { read_volatile(&raw const access.control) });
+            if control & DMA_ERROR != 0 {
+                return Err(Error::DmaFailed);
+            }
+            if control == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+static FW_CFG: SafeMutex<Option<FwCfg>> = SafeMutex::new(None);
+
+fn base_from_device_tree() -> Option<usize> {
+    let tree = DeviceTree::get()?;
+    let node = *tree.find_compatible(COMPATIBLE).first()?;
+    let (base, _) = *node.reg().first()?;
+    Some(base as usize)
+}
+
+/// Find the device and read its file directory
+pub fn init() {
+    let base = base_from_device_tree().unwrap_or(DEFAULT_BASE);
+    match FwCfg::probe(base) {
+        Ok(device) => {
+            crate::log_info!(
+                "fw_cfg: at {:#x}, {} files{}",
+                base,
+                device.files.len(),
+                if device.dma { ", DMA" } else { "" }
+            );
+            *FW_CFG.lock() = Some(device);
+        }
+        Err(err) => crate::log_info!("fw_cfg: not available: {:?}", err),
+    }
+}
+
+pub fn is_present() -> bool {
+    FW_CFG.lock().is_some()
+}
+
+/// The file directory, empty without a device
+pub fn files() -> Vec<File> {
+    FW_CFG
+        .lock()
+        .as_ref()
+        .map(|device| device.files.clone())
+        .unwrap_or_default()
+}
+
+pub fn find(name: &str) -> Option<File> {
+    FW_CFG.lock().as_ref()?.find(name).cloned()
+}
+
+/// Contents of the file called `name`
+pub fn read_file(name: &str) -> Result<Vec<u8>> {
+    let device = FW_CFG.lock();
+    let device = device.as_ref().ok_or(Error::NotPresent)?;
+    let file = device.find(name).ok_or(Error::FileNotFound)?;
+    device.read_item(file.select, file.size as usize)
+}
+
+/// Read the start of the item `select` into `buffer`
+#[allow(dead_code, reason = "read_file covers every caller so far")]
+pub fn read(select: u16, buffer: &mut [u8]) -> Result<()> {
+    let device = FW_CFG.lock();
+    let device = device.as_ref().ok_or(Error::NotPresent)?;
+    device.read(select, buffer);
+    Ok(())
+}
+
+/// Overwrite the start of the item `select` with `data`
+pub fn write(select: u16, data: &[u8]) -> Result<()> {
+    FW_CFG
+        .lock()
+        .as_ref()
+        .ok_or(Error::NotPresent)?
+        .write(select, data)
+}
@@ -0,0 +1,107 @@
+//! GICv2 interrupt controller driver
+
+use core::ptr::{read_volatile, write_volatile};
+
+// QEMU virt machine GICv2 addresses
+const GICD_BASE: usize = 0x0800_0000;
+const GICC_BASE: usize = 0x0801_0000;
+
+const GICD_CTLR: usize = 0x000;
+const GICD_TYPER: usize = 0x004;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_ICENABLER: usize = 0x180;
+const GICD_ICPENDR: usize = 0x280;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00C;
+const GICC_EOIR: usize = 0x010;
+
+/// Interrupt ids at or above this value are special (e.g. spurious)
+pub const SPECIAL_IRQ_START: u32 = 1020;
+
+const DEFAULT_PRIORITY: u8 = 0xA0;
+
+pub struct Gic {
+    dist: usize,
+    cpu: usize,
+}
+
+impl Gic {
+    pub const fn new(dist: usize, cpu: usize) -> Self {
+        Self { dist, cpu }
+    }
+
+    fn dist_read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.dist + offset) as *const u32) }
+    }
+
+    fn dist_write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.dist + offset) as *mut u32, value) }
+    }
+
+    fn cpu_read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.cpu + offset) as *const u32) }
+    }
+
+    fn cpu_write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.cpu + offset) as *mut u32, value) }
+    }
+
+    /// Number of interrupt lines implemented by the distributor
+    pub fn lines(&self) -> u32 {
+        ((self.dist_read(GICD_TYPER) & 0x1F) + 1) * 32
+    }
+
+    pub fn init(&self) {
+        self.dist_write(GICD_CTLR, 0);
+
+        let lines = self.lines();
+        for irq in (0..lines).step_by(32) {
+            let reg = (irq / 32) as usize * 4;
+            self.dist_write(GICD_ICENABLER + reg, u32::MAX);
+            self.dist_write(GICD_ICPENDR + reg, u32::MAX);
+        }
+        for irq in 0..lines {
+            self.set_priority(irq, DEFAULT_PRIORITY);
+            if irq >= 32 {
+                self.set_target(irq, 1);
+            }
+        }
+
+        self.dist_write(GICD_CTLR, 1);
+        self.cpu_write(GICC_PMR, 0xF0);
+        self.cpu_write(GICC_CTLR, 1);
+    }
+
+    pub fn enable(&self, irq: u32) {
+        self.dist_write(GICD_ISENABLER + (irq / 32) as usize * 4, 1 << (irq % 32));
+    }
+
+    pub fn disable(&self, irq: u32) {
+        self.dist_write(GICD_ICENABLER + (irq / 32) as usize * 4, 1 << (irq % 32));
+    }
+
+    pub fn set_priority(&self, irq: u32, priority: u8) {
+        let addr = self.dist + GICD_IPRIORITYR + irq as usize;
+        unsafe { write_volatile(addr as *mut u8, priority) }
+    }
+
+    fn set_target(&self, irq: u32, cpu_mask: u8) {
+        let addr = self.dist + GICD_ITARGETSR + irq as usize;
+        unsafe { write_volatile(addr as *mut u8, cpu_mask) }
+    }
+
+    /// Acknowledge the highest priority pending interrupt
+    pub fn acknowledge(&self) -> u32 {
+        self.cpu_read(GICC_IAR) & 0x3FF
+    }
+
+    pub fn end_of_interrupt(&self, irq: u32) {
+        self.cpu_write(GICC_EOIR, irq);
+    }
+}
+
+pub static GIC: Gic = Gic::new(GICD_BASE, GICC_BASE);
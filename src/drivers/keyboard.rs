@@ -1,21 +1,152 @@
+//! Keyboard state and key events
+//!
+//! Input drivers feed evdev key events into [`Keyboard::handle_event`],
+//! which tracks the modifier and lock keys and queues a [`KeyEvent`] per
+//! press, autorepeat and release. The text a press types comes from the
+//! selected [`Keymap`], dead keys combine with the following character.
+//! [`Keyboard::read_key`] returns just the text.
+
+use crate::drivers::keymap::{self, Keymap, Sym};
+use crate::drivers::virtio::input::{InputEvent, EV_KEY};
+use crate::sync::IrqSafeSpinlock;
+use bitflags::bitflags;
 use core::sync::atomic::{AtomicBool, Ordering};
-use spin::Mutex;
 
-pub static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+pub static KEYBOARD: IrqSafeSpinlock<Keyboard> = IrqSafeSpinlock::new(Keyboard::new());
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+const QUEUE_LEN: usize = 32;
+
+// evdev key codes
+pub const KEY_ESC: u16 = 1;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_BACKSPACE: u16 = 14;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_TAB: u16 = 15;
+pub const KEY_ENTER: u16 = 28;
+pub const KEY_LEFTCTRL: u16 = 29;
+pub const KEY_LEFTSHIFT: u16 = 42;
+pub const KEY_RIGHTSHIFT: u16 = 54;
+pub const KEY_LEFTALT: u16 = 56;
+pub const KEY_SPACE: u16 = 57;
+pub const KEY_CAPSLOCK: u16 = 58;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_F1: u16 = 59;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_F10: u16 = 68;
+pub const KEY_NUMLOCK: u16 = 69;
+pub const KEY_KP7: u16 = 71;
+pub const KEY_KP8: u16 = 72;
+pub const KEY_KP9: u16 = 73;
+pub const KEY_KP4: u16 = 75;
+pub const KEY_KP6: u16 = 77;
+pub const KEY_KP1: u16 = 79;
+pub const KEY_KP2: u16 = 80;
+pub const KEY_KP3: u16 = 81;
+pub const KEY_KP0: u16 = 82;
+pub const KEY_KPDOT: u16 = 83;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_F11: u16 = 87;
+#[allow(dead_code, reason = "part of the evdev key code table")]
+pub const KEY_F12: u16 = 88;
+pub const KEY_KPENTER: u16 = 96;
+pub const KEY_RIGHTCTRL: u16 = 97;
+pub const KEY_RIGHTALT: u16 = 100;
+pub const KEY_HOME: u16 = 102;
+pub const KEY_UP: u16 = 103;
+pub const KEY_PAGEUP: u16 = 104;
+pub const KEY_LEFT: u16 = 105;
+pub const KEY_RIGHT: u16 = 106;
+pub const KEY_END: u16 = 107;
+pub const KEY_DOWN: u16 = 108;
+pub const KEY_PAGEDOWN: u16 = 109;
+pub const KEY_INSERT: u16 = 110;
+pub const KEY_DELETE: u16 = 111;
+pub const KEY_LEFTMETA: u16 = 125;
+pub const KEY_RIGHTMETA: u16 = 126;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Modifiers: u8 {
+        const SHIFT = 1 << 0;
+        const CTRL = 1 << 1;
+        const ALT = 1 << 2;
+        const META = 1 << 3;
+        /// Right Alt on layouts with a third level
+        const ALT_GR = 1 << 4;
+        const CAPS_LOCK = 1 << 5;
+        const NUM_LOCK = 1 << 6;
+    }
+}
+
+impl Modifiers {
+    /// The modifier a key code stands for, empty for other keys
+    fn from_code(code: u16, keymap: &Keymap) -> Self {
+        match code {
+            KEY_LEFTSHIFT | KEY_RIGHTSHIFT => Modifiers::SHIFT,
+            KEY_LEFTCTRL | KEY_RIGHTCTRL => Modifiers::CTRL,
+            KEY_RIGHTALT if keymap.has_altgr() => Modifiers::ALT_GR,
+            KEY_LEFTALT | KEY_RIGHTALT => Modifiers::ALT,
+            KEY_LEFTMETA | KEY_RIGHTMETA => Modifiers::META,
+            _ => Modifiers::empty(),
+        }
+    }
+}
+
+/// The navigation key a keypad key stands for while Num Lock is off
+fn keypad_navigation(code: u16) -> Option<u16> {
+    match code {
+        KEY_KP7 => Some(KEY_HOME),
+        KEY_KP8 => Some(KEY_UP),
+        KEY_KP9 => Some(KEY_PAGEUP),
+        KEY_KP4 => Some(KEY_LEFT),
+        KEY_KP6 => Some(KEY_RIGHT),
+        KEY_KP1 => Some(KEY_END),
+        KEY_KP2 => Some(KEY_DOWN),
+        KEY_KP3 => Some(KEY_PAGEDOWN),
+        KEY_KP0 => Some(KEY_INSERT),
+        KEY_KPDOT => Some(KEY_DELETE),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// evdev key code, see the `KEY_*` constants
+    pub code: u16,
+    /// False on release, autorepeat counts as another press
+    pub pressed: bool,
+    /// Modifiers and locks active when the event happened
+    pub modifiers: Modifiers,
+    /// What a press types, Ctrl with a letter gives a control character
+    pub text: Option<char>,
+}
+
+impl KeyEvent {
+    pub fn to_char(self) -> Option<char> {
+        self.text
+    }
+}
+
 pub struct Keyboard {
-    buffer: [char; 16],
+    buffer: [Option<KeyEvent>; QUEUE_LEN],
     read_pos: usize,
     write_pos: usize,
+    modifiers: Modifiers,
+    keymap: &'static Keymap,
+    /// Accent of a dead key waiting for the next character
+    dead_key: Option<char>,
 }
 
 impl Keyboard {
     const fn new() -> Self {
         Self {
-            buffer: ['\0'; 16],
+            buffer: [None; QUEUE_LEN],
             read_pos: 0,
             write_pos: 0,
+            modifiers: Modifiers::NUM_LOCK,
+            keymap: &keymap::US,
+            dead_key: None,
         }
     }
 
@@ -26,20 +157,103 @@ impl Keyboard {
         INITIALIZED.store(true, Ordering::SeqCst);
     }
 
+    #[allow(dead_code, reason = "key events carry their own modifiers")]
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn keymap(&self) -> &'static Keymap {
+        self.keymap
+    }
+
+    /// Switch layouts, held modifiers are released
+    pub fn set_keymap(&mut self, keymap: &'static Keymap) {
+        self.keymap = keymap;
+        self.modifiers &= Modifiers::CAPS_LOCK | Modifiers::NUM_LOCK;
+        self.dead_key = None;
+    }
+
+    /// Take the oldest key event
+    pub fn read_event(&mut self) -> Option<KeyEvent> {
+        if self.read_pos == self.write_pos {
+            return None;
+        }
+        let event = self.buffer[self.read_pos].take();
+        self.read_pos = (self.read_pos + 1) % QUEUE_LEN;
+        event
+    }
+
+    /// Take the next typed character, skipping events that type nothing
+    #[allow(dead_code, reason = "the terminal handles full key events")]
     pub fn read_key(&mut self) -> Option<char> {
-        if self.read_pos != self.write_pos {
-            let key = self.buffer[self.read_pos];
-            self.read_pos = (self.read_pos + 1) % self.buffer.len();
-            Some(key)
+        while let Some(event) = self.read_event() {
+            if let Some(c) = event.to_char() {
+                return Some(c);
+            }
+        }
+        None
+    }
+
+    /// Record an evdev event, everything except `EV_KEY` is ignored
+    pub fn handle_event(&mut self, event: InputEvent) {
+        if event.type_ != EV_KEY {
+            return;
+        }
+        let pressed = event.value != 0;
+        let repeat = event.value == 2;
+        // Modifiers apply to the events after them, not to themselves
+        let modifiers = self.modifiers;
+        match event.code {
+            KEY_CAPSLOCK if pressed && !repeat => self.modifiers.toggle(Modifiers::CAPS_LOCK),
+            KEY_NUMLOCK if pressed && !repeat => self.modifiers.toggle(Modifiers::NUM_LOCK),
+            code => self
+                .modifiers
+                .set(Modifiers::from_code(code, self.keymap), pressed),
+        }
+
+        let code = match keypad_navigation(event.code) {
+            Some(code) if !modifiers.contains(Modifiers::NUM_LOCK) => code,
+            _ => event.code,
+        };
+        let text = if pressed {
+            self.translate(code, modifiers)
         } else {
             None
+        };
+        self.push_event(KeyEvent {
+            code,
+            pressed,
+            modifiers,
+            text,
+        });
+    }
+
+    fn translate(&mut self, code: u16, modifiers: Modifiers) -> Option<char> {
+        if modifiers.contains(Modifiers::ALT) {
+            return None;
+        }
+        let code = if code == KEY_KPENTER { KEY_ENTER } else { code };
+        let c = match self.keymap.translate(code, modifiers)? {
+            Sym::Dead(accent) => {
+                self.dead_key = Some(accent);
+                return None;
+            }
+            // An accent that does not combine is dropped
+            Sym::Char(c) => match self.dead_key.take() {
+                Some(accent) => keymap::compose(accent, c).unwrap_or(c),
+                None => c,
+            },
+        };
+        if modifiers.contains(Modifiers::CTRL) && c.is_ascii_alphabetic() {
+            return Some((c.to_ascii_lowercase() as u8 - b'a' + 1) as char);
         }
+        Some(c)
     }
 
-    pub(crate) fn push_key(&mut self, key: char) {
-        let next_write = (self.write_pos + 1) % self.buffer.len();
+    fn push_event(&mut self, event: KeyEvent) {
+        let next_write = (self.write_pos + 1) % QUEUE_LEN;
         if next_write != self.read_pos {
-            self.buffer[self.write_pos] = key;
+            self.buffer[self.write_pos] = Some(event);
             self.write_pos = next_write;
         }
     }
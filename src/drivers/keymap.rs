@@ -0,0 +1,249 @@
+//! Keyboard layouts
+//!
+//! A [`Keymap`] turns an evdev key code and the modifier state into a
+//! character or a dead key. The main block, from `KEY_ESC` to `KEY_SPACE`,
+//! is a string per level indexed by key code; AltGr characters, the extra
+//! ISO key and dead keys are listed separately.
+
+use crate::drivers::keyboard::{Modifiers, KEY_ESC, KEY_SPACE};
+
+// Key codes the tables below refer to
+const KEY_2: u16 = 3;
+const KEY_3: u16 = 4;
+const KEY_4: u16 = 5;
+const KEY_5: u16 = 6;
+const KEY_6: u16 = 7;
+const KEY_7: u16 = 8;
+const KEY_8: u16 = 9;
+const KEY_9: u16 = 10;
+const KEY_0: u16 = 11;
+const KEY_MINUS: u16 = 12;
+const KEY_EQUAL: u16 = 13;
+const KEY_Q: u16 = 16;
+const KEY_E: u16 = 18;
+const KEY_U: u16 = 22;
+const KEY_I: u16 = 23;
+const KEY_O: u16 = 24;
+const KEY_LEFTBRACE: u16 = 26;
+const KEY_RIGHTBRACE: u16 = 27;
+const KEY_A: u16 = 30;
+const KEY_GRAVE: u16 = 41;
+const KEY_M: u16 = 50;
+const KEY_KPASTERISK: u16 = 55;
+const KEY_KP7: u16 = 71;
+const KEY_KPMINUS: u16 = 74;
+const KEY_KPPLUS: u16 = 78;
+const KEY_KPDOT: u16 = 83;
+const KEY_102ND: u16 = 86;
+const KEY_KPSLASH: u16 = 98;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Plain,
+    Shift,
+    AltGr,
+}
+
+/// What a key produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sym {
+    Char(char),
+    /// An accent that combines with the next character
+    Dead(char),
+}
+
+pub struct Keymap {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// `KEY_ESC` to `KEY_SPACE` without modifiers, `\0` where nothing is typed
+    plain: &'static str,
+    shift: &'static str,
+    altgr: &'static [(u16, char)],
+    /// `KEY_102ND`, between left Shift and Z on ISO keyboards
+    iso_key: (char, char),
+    dead: &'static [(u16, Level)],
+    /// Right Alt acts as AltGr rather than Alt
+    has_altgr: bool,
+}
+
+pub static US: Keymap = Keymap {
+    name: "us",
+    description: "English (US)",
+    plain: "\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ",
+    shift: "\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ",
+    altgr: &[],
+    iso_key: ('\\', '|'),
+    dead: &[],
+    has_altgr: false,
+};
+
+pub static UK: Keymap = Keymap {
+    name: "uk",
+    description: "English (UK)",
+    plain: "\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0#zxcvbnm,./\0*\0 ",
+    shift: "\x1b!\"£$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:@¬\0~ZXCVBNM<>?\0*\0 ",
+    altgr: &[
+        (KEY_4, '€'),
+        (KEY_GRAVE, '¦'),
+        (KEY_A, 'á'),
+        (KEY_E, 'é'),
+        (KEY_I, 'í'),
+        (KEY_O, 'ó'),
+        (KEY_U, 'ú'),
+    ],
+    iso_key: ('\\', '|'),
+    dead: &[],
+    has_altgr: true,
+};
+
+pub static DE: Keymap = Keymap {
+    name: "de",
+    description: "German",
+    plain: "\x1b1234567890ß´\x08\tqwertzuiopü+\n\0asdfghjklöä^\0#yxcvbnm,.-\0*\0 ",
+    shift: "\x1b!\"§$%&/()=?`\x08\tQWERTZUIOPÜ*\n\0ASDFGHJKLÖÄ°\0'YXCVBNM;:_\0*\0 ",
+    altgr: &[
+        (KEY_2, '²'),
+        (KEY_3, '³'),
+        (KEY_7, '{'),
+        (KEY_8, '['),
+        (KEY_9, ']'),
+        (KEY_0, '}'),
+        (KEY_MINUS, '\\'),
+        (KEY_Q, '@'),
+        (KEY_E, '€'),
+        (KEY_RIGHTBRACE, '~'),
+        (KEY_M, 'µ'),
+        (KEY_102ND, '|'),
+    ],
+    iso_key: ('<', '>'),
+    dead: &[
+        (KEY_EQUAL, Level::Plain),
+        (KEY_EQUAL, Level::Shift),
+        (KEY_GRAVE, Level::Plain),
+    ],
+    has_altgr: true,
+};
+
+pub static FR: Keymap = Keymap {
+    name: "fr",
+    description: "French (AZERTY)",
+    plain: "\x1b&é\"'(-è_çà)=\x08\tazertyuiop^$\n\0qsdfghjklmù²\0*wxcvbn,;:!\0*\0 ",
+    shift: "\x1b1234567890°+\x08\tAZERTYUIOP¨£\n\0QSDFGHJKLM%\0\0µWXCVBN?./§\0*\0 ",
+    altgr: &[
+        (KEY_2, '~'),
+        (KEY_3, '#'),
+        (KEY_4, '{'),
+        (KEY_5, '['),
+        (KEY_6, '|'),
+        (KEY_7, '`'),
+        (KEY_8, '\\'),
+        (KEY_9, '^'),
+        (KEY_0, '@'),
+        (KEY_MINUS, ']'),
+        (KEY_EQUAL, '}'),
+        (KEY_E, '€'),
+    ],
+    iso_key: ('<', '>'),
+    dead: &[
+        (KEY_LEFTBRACE, Level::Plain),
+        (KEY_LEFTBRACE, Level::Shift),
+        (KEY_2, Level::AltGr),
+        (KEY_7, Level::AltGr),
+    ],
+    has_altgr: true,
+};
+
+pub static KEYMAPS: [&Keymap; 4] = [&US, &UK, &DE, &FR];
+
+/// Look a layout up by its short name, e.g. `de`
+pub fn find(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS
+        .iter()
+        .copied()
+        .find(|keymap| keymap.name.eq_ignore_ascii_case(name))
+}
+
+impl Keymap {
+    pub fn has_altgr(&self) -> bool {
+        self.has_altgr
+    }
+
+    /// The symbol for `code`, `None` for keys that type nothing
+    ///
+    /// Caps Lock inverts the case of letters, Num Lock picks digits on the
+    /// keypad. Ctrl is left to the caller.
+    pub fn translate(&self, code: u16, modifiers: Modifiers) -> Option<Sym> {
+        if let Some(c) = keypad(code, modifiers.contains(Modifiers::NUM_LOCK)) {
+            return Some(Sym::Char(c));
+        }
+
+        let level = if self.has_altgr && modifiers.contains(Modifiers::ALT_GR) {
+            Level::AltGr
+        } else if modifiers.contains(Modifiers::SHIFT) {
+            Level::Shift
+        } else {
+            Level::Plain
+        };
+        let c = match (level, code) {
+            (Level::AltGr, _) => self.altgr.iter().find(|(key, _)| *key == code)?.1,
+            (Level::Plain, KEY_102ND) => self.iso_key.0,
+            (Level::Shift, KEY_102ND) => self.iso_key.1,
+            (Level::Plain, KEY_ESC..=KEY_SPACE) => self.main_block(self.plain, code)?,
+            (Level::Shift, KEY_ESC..=KEY_SPACE) => self.main_block(self.shift, code)?,
+            _ => return None,
+        };
+
+        if self.dead.contains(&(code, level)) {
+            return Some(Sym::Dead(c));
+        }
+        if modifiers.contains(Modifiers::CAPS_LOCK) && c.is_alphabetic() {
+            return Some(Sym::Char(swap_case(c)));
+        }
+        Some(Sym::Char(c))
+    }
+
+    fn main_block(&self, table: &str, code: u16) -> Option<char> {
+        let c = table.chars().nth((code - KEY_ESC) as usize)?;
+        (c != '\0').then_some(c)
+    }
+}
+
+/// Keypad characters, digits only while Num Lock is on
+fn keypad(code: u16, num_lock: bool) -> Option<char> {
+    match code {
+        KEY_KPASTERISK => Some('*'),
+        KEY_KPMINUS => Some('-'),
+        KEY_KPPLUS => Some('+'),
+        KEY_KPSLASH => Some('/'),
+        // Codes run row by row from the top, minus and plus are handled above
+        KEY_KP7..=KEY_KPDOT if num_lock => "789-456+1230.".chars().nth((code - KEY_KP7) as usize),
+        _ => None,
+    }
+}
+
+fn swap_case(c: char) -> char {
+    if c.is_lowercase() {
+        c.to_uppercase().next().unwrap_or(c)
+    } else {
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+/// Combine a dead key's accent with the next character
+///
+/// The accent followed by a space types the accent itself.
+pub fn compose(accent: char, c: char) -> Option<char> {
+    if c == ' ' {
+        return Some(accent);
+    }
+    let (base, composed) = match accent {
+        '´' => ("aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+        '`' => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+        '^' => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        '¨' => ("aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+        '~' => ("anoANO", "ãñõÃÑÕ"),
+        _ => return None,
+    };
+    let index = base.chars().position(|b| b == c)?;
+    composed.chars().nth(index)
+}
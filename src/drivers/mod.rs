@@ -1,9 +1,21 @@
+pub mod block;
+pub mod cursor;
+pub mod display;
+pub mod dtb;
 pub mod font;
+pub mod fw_cfg;
+pub mod gic;
 pub mod keyboard;
+pub mod keymap;
 pub mod mouse;
+pub mod net;
+pub mod pci;
+pub mod ramfb;
+pub mod rtc;
+pub mod uart;
 pub mod virtio;
 
 // Export commonly used items
+pub use display::DISPLAY;
 pub use keyboard::KEYBOARD;
 pub use mouse::MOUSE;
-pub use virtio::GPU;
@@ -1,28 +1,128 @@
+//! Pointer state and pointer events
+//!
+//! Input drivers feed evdev events into [`Mouse::handle_event`]. Relative
+//! mice move the pointer by deltas, tablets report absolute positions that
+//! are scaled to the screen. Everything between two `SYN_REPORT`s is one
+//! report, which becomes at most one move, the button edges and one scroll,
+//! in that order. [`Mouse::poll`] returns nothing when there was no input.
+
+use crate::drivers::virtio::input::{InputEvent, EV_ABS, EV_KEY, EV_REL, EV_SYN};
+use crate::kernel::time::Instant;
+use crate::sync::IrqSafeSpinlock;
+use bitflags::bitflags;
 use core::sync::atomic::{AtomicBool, Ordering};
-use spin::Mutex;
 
-pub static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+pub static MOUSE: IrqSafeSpinlock<Mouse> = IrqSafeSpinlock::new(Mouse::new());
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
-#[derive(Debug, Clone, Copy)]
-pub struct MouseState {
+const QUEUE_LEN: usize = 64;
+
+// evdev codes
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_TOUCH: u16 = 0x14a;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Buttons: u8 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+    }
+}
+
+impl Buttons {
+    fn from_code(code: u16) -> Self {
+        match code {
+            BTN_LEFT | BTN_TOUCH => Buttons::LEFT,
+            BTN_RIGHT => Buttons::RIGHT,
+            BTN_MIDDLE => Buttons::MIDDLE,
+            _ => Buttons::empty(),
+        }
+    }
+}
+
+/// Value range of an absolute axis, as the device reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbsRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerEventKind {
+    Move,
+    Press(Buttons),
+    Release(Buttons),
+    /// Wheel clicks, positive is up and right
+    Scroll {
+        vertical: i32,
+        horizontal: i32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerEvent {
+    pub kind: PointerEventKind,
+    /// Pointer position and held buttons after the event
     pub x: i32,
     pub y: i32,
-    pub buttons: u8,
+    pub buttons: Buttons,
+    pub timestamp: Instant,
+}
+
+/// Changes collected until the device ends the report
+#[derive(Debug, Clone, Copy, Default)]
+struct Report {
+    dx: i32,
+    dy: i32,
+    abs_x: Option<i32>,
+    abs_y: Option<i32>,
+    wheel: i32,
+    hwheel: i32,
 }
 
 pub struct Mouse {
-    state: MouseState,
+    x: i32,
+    y: i32,
+    buttons: Buttons,
+    /// Buttons as the device reports them, `buttons` catches up at the end of a report
+    held: Buttons,
+    /// Screen size the pointer is kept within
+    bounds: (i32, i32),
+    report: Report,
+    events: [Option<PointerEvent>; QUEUE_LEN],
+    read_pos: usize,
+    write_pos: usize,
 }
 
 impl Mouse {
     const fn new() -> Self {
         Self {
-            state: MouseState {
-                x: 0,
-                y: 0,
-                buttons: 0,
+            x: 0,
+            y: 0,
+            buttons: Buttons::empty(),
+            held: Buttons::empty(),
+            bounds: (800, 600),
+            report: Report {
+                dx: 0,
+                dy: 0,
+                abs_x: None,
+                abs_y: None,
+                wheel: 0,
+                hwheel: 0,
             },
+            events: [None; QUEUE_LEN],
+            read_pos: 0,
+            write_pos: 0,
         }
     }
 
@@ -33,20 +133,96 @@ impl Mouse {
         INITIALIZED.store(true, Ordering::SeqCst);
     }
 
-    // This is now used internally by the interrupt handler
-    #[inline]
-    fn update_state(&mut self, dx: i32, dy: i32, buttons: u8) {
-        self.state.x = (self.state.x + dx).clamp(0, 799);
-        self.state.y = (self.state.y + dy).clamp(0, 599);
-        self.state.buttons = buttons;
+    /// Follow a display mode change
+    pub fn set_bounds(&mut self, width: u32, height: u32) {
+        self.bounds = (width.max(1) as i32, height.max(1) as i32);
+        self.x = self.x.clamp(0, self.bounds.0 - 1);
+        self.y = self.y.clamp(0, self.bounds.1 - 1);
     }
 
-    pub fn poll(&self) -> Option<(i32, i32, u8)> {
-        Some((self.state.x, self.state.y, self.state.buttons))
+    /// Take the oldest pointer event
+    pub fn poll(&mut self) -> Option<PointerEvent> {
+        if self.read_pos == self.write_pos {
+            return None;
+        }
+        let event = self.events[self.read_pos].take();
+        self.read_pos = (self.read_pos + 1) % QUEUE_LEN;
+        event
     }
 
-    // Add this method to handle interrupts
-    pub fn handle_interrupt(&mut self, dx: i32, dy: i32, buttons: u8) {
-        self.update_state(dx, dy, buttons);
+    /// Record an evdev event, `ranges` are the device's X and Y axes if it is absolute
+    pub fn handle_event(&mut self, event: InputEvent, ranges: Option<[AbsRange; 2]>) {
+        let value = event.value as i32;
+        let report = &mut self.report;
+        match (event.type_, event.code) {
+            (EV_REL, REL_X) => report.dx += value,
+            (EV_REL, REL_Y) => report.dy += value,
+            (EV_REL, REL_WHEEL) => report.wheel += value,
+            (EV_REL, REL_HWHEEL) => report.hwheel += value,
+            (EV_ABS, ABS_X) => {
+                report.abs_x = ranges.map(|r| scale(value, r[0], self.bounds.0));
+            }
+            (EV_ABS, ABS_Y) => {
+                report.abs_y = ranges.map(|r| scale(value, r[1], self.bounds.1));
+            }
+            (EV_KEY, code) => self.held.set(Buttons::from_code(code), value != 0),
+            (EV_SYN, SYN_REPORT) => self.finish_report(),
+            _ => {}
+        }
+    }
+
+    /// Turn the collected changes into events
+    fn finish_report(&mut self) {
+        let report = core::mem::take(&mut self.report);
+        let timestamp = Instant::now();
+
+        let x = report.abs_x.unwrap_or(self.x + report.dx);
+        let y = report.abs_y.unwrap_or(self.y + report.dy);
+        let (x, y) = (x.clamp(0, self.bounds.0 - 1), y.clamp(0, self.bounds.1 - 1));
+        if (x, y) != (self.x, self.y) {
+            (self.x, self.y) = (x, y);
+            self.push(PointerEventKind::Move, timestamp);
+        }
+
+        let pressed = self.held - self.buttons;
+        let released = self.buttons - self.held;
+        if !pressed.is_empty() {
+            self.buttons |= pressed;
+            self.push(PointerEventKind::Press(pressed), timestamp);
+        }
+        if !released.is_empty() {
+            self.buttons -= released;
+            self.push(PointerEventKind::Release(released), timestamp);
+        }
+
+        if report.wheel != 0 || report.hwheel != 0 {
+            let kind = PointerEventKind::Scroll {
+                vertical: report.wheel,
+                horizontal: report.hwheel,
+            };
+            self.push(kind, timestamp);
+        }
+    }
+
+    fn push(&mut self, kind: PointerEventKind, timestamp: Instant) {
+        let next_write = (self.write_pos + 1) % QUEUE_LEN;
+        if next_write == self.read_pos {
+            return;
+        }
+        self.events[self.write_pos] = Some(PointerEvent {
+            kind,
+            x: self.x,
+            y: self.y,
+            buttons: self.buttons,
+            timestamp,
+        });
+        self.write_pos = next_write;
     }
 }
+
+/// Map `value` from the axis range onto `0..size`
+fn scale(value: i32, range: AbsRange, size: i32) -> i32 {
+    let span = (range.max - range.min).max(1) as i64;
+    let offset = (value - range.min).clamp(0, span as i32) as i64;
+    (offset * (size - 1) as i64 / span) as i32
+}
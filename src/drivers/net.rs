@@ -0,0 +1,131 @@
+//! Network devices
+//!
+//! Drivers register every network card they find as a [`NetDevice`]. Frames
+//! are whole Ethernet frames without the frame check sequence. Received
+//! frames wait in the driver until the network stack asks for them; drivers
+//! wake [`RECEIVE_WAITERS`] whenever one arrives.
+
+use crate::drivers::virtio;
+use crate::sync::{SafeMutex, WaitQueue};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Tasks waiting for a frame on any device
+pub static RECEIVE_WAITERS: WaitQueue = WaitQueue::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The frame is larger than the device can send
+    TooLarge,
+    /// No carrier
+    LinkDown,
+    VirtIO(virtio::Error),
+}
+
+impl From<virtio::Error> for Error {
+    fn from(err: virtio::Error) -> Self {
+        Error::VirtIO(err)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An Ethernet hardware address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = ();
+
+    /// Six hex bytes separated by `:`
+    fn from_str(text: &str) -> core::result::Result<Self, ()> {
+        let mut bytes = [0; 6];
+        let mut parts = text.split(':');
+        for byte in bytes.iter_mut() {
+            *byte = u8::from_str_radix(parts.next().ok_or(())?, 16).map_err(|_| ())?;
+        }
+        if parts.next().is_some() {
+            return Err(());
+        }
+        Ok(MacAddress(bytes))
+    }
+}
+
+/// A network card sending and receiving Ethernet frames
+///
+/// Methods take `&self`, drivers serialize access to their queues.
+pub trait NetDevice: Send + Sync {
+    /// Short name, e.g. `eth0`
+    fn name(&self) -> &str;
+    fn mac_address(&self) -> MacAddress;
+    /// Largest payload of a frame, 1500 for plain Ethernet
+    fn mtu(&self) -> usize {
+        1500
+    }
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// Send one frame, returns once the device has taken it
+    fn send(&self, frame: &[u8]) -> Result<()>;
+    /// The oldest received frame, `None` if there is none
+    fn receive(&self) -> Option<Vec<u8>>;
+    /// True if [`NetDevice::receive`] has a frame, callable with IRQs masked
+    fn can_receive(&self) -> bool;
+}
+
+static DEVICES: SafeMutex<Vec<Arc<dyn NetDevice>>> = SafeMutex::new(Vec::new());
+
+/// Make a network card available to the network stack
+pub fn register(device: Arc<dyn NetDevice>) {
+    crate::log_info!(
+        "net: {}: {}, MTU {}, link {}",
+        device.name(),
+        device.mac_address(),
+        device.mtu(),
+        if device.link_up() { "up" } else { "down" }
+    );
+    DEVICES.lock().push(device);
+}
+
+/// All registered network cards, in the order they were found
+pub fn devices() -> Vec<Arc<dyn NetDevice>> {
+    DEVICES.lock().clone()
+}
+
+#[allow(dead_code, reason = "no command names an interface yet")]
+pub fn find(name: &str) -> Option<Arc<dyn NetDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// Name for the next network card, `eth` followed by a number
+pub fn next_name() -> String {
+    alloc::format!("eth{}", DEVICES.lock().len())
+}
@@ -0,0 +1,703 @@
+//! PCI Express enumeration through the ECAM configuration window
+//!
+//! The host bridge and its address windows come from the device tree. There
+//! is no firmware to do it for us, so BARs are sized and assigned here and
+//! bridges get bus numbers and forwarding windows.
+
+use crate::drivers::dtb::{self, DeviceTree};
+use crate::sync::SafeMutex;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::{read_volatile, write_volatile};
+
+const ECAM_COMPATIBLE: &str = "pci-host-ecam-generic";
+
+// QEMU virt layout, used when there is no device tree
+const DEFAULT_ECAM_BASE: u64 = 0x3F00_0000;
+const DEFAULT_ECAM_BUSES: u8 = 16;
+const DEFAULT_MEM_BASE: u64 = 0x1000_0000;
+const DEFAULT_MEM_SIZE: u64 = 0x2EFF_0000;
+const DEFAULT_IO_BASE: u64 = 0x3EFF_0000;
+const DEFAULT_IO_SIZE: u64 = 0x1_0000;
+
+// QEMU virt routes INTA..INTD of slot n to SPI 3..6, rotated by n
+const INTX_IRQ_BASE: u32 = 35;
+
+pub const CONFIG_VENDOR_ID: u16 = 0x00;
+pub const CONFIG_DEVICE_ID: u16 = 0x02;
+pub const CONFIG_COMMAND: u16 = 0x04;
+pub const CONFIG_STATUS: u16 = 0x06;
+pub const CONFIG_REVISION: u16 = 0x08;
+pub const CONFIG_HEADER_TYPE: u16 = 0x0E;
+pub const CONFIG_BAR0: u16 = 0x10;
+pub const CONFIG_SUBSYSTEM_ID: u16 = 0x2E;
+pub const CONFIG_CAPABILITIES: u16 = 0x34;
+pub const CONFIG_INTERRUPT_LINE: u16 = 0x3C;
+pub const CONFIG_INTERRUPT_PIN: u16 = 0x3D;
+
+// Type 1 (bridge) header
+const CONFIG_PRIMARY_BUS: u16 = 0x18;
+const CONFIG_SECONDARY_BUS: u16 = 0x19;
+const CONFIG_SUBORDINATE_BUS: u16 = 0x1A;
+const CONFIG_IO_BASE: u16 = 0x1C;
+const CONFIG_IO_LIMIT: u16 = 0x1D;
+const CONFIG_MEMORY_BASE: u16 = 0x20;
+const CONFIG_MEMORY_LIMIT: u16 = 0x22;
+const CONFIG_PREFETCH_BASE: u16 = 0x24;
+const CONFIG_PREFETCH_LIMIT: u16 = 0x26;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+pub const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const BRIDGE_MEMORY_ALIGN: u64 = 0x10_0000;
+const BRIDGE_IO_ALIGN: u64 = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// An assigned base address register
+#[derive(Debug, Clone, Copy)]
+pub enum Bar {
+    /// `address` is the CPU physical address the BAR is mapped at
+    Memory {
+        address: u64,
+        size: u64,
+        is_64bit: bool,
+        prefetchable: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                is_64bit,
+                prefetchable,
+            } => write!(
+                f,
+                "memory at {:#x} ({}-bit, {}prefetchable) [size={}]",
+                address,
+                if is_64bit { 64 } else { 32 },
+                if prefetchable { "" } else { "non-" },
+                SizeDisplay(size)
+            ),
+            Bar::Io { port, size } => {
+                write!(
+                    f,
+                    "I/O ports at {:#x} [size={}]",
+                    port,
+                    SizeDisplay(size as u64)
+                )
+            }
+        }
+    }
+}
+
+struct SizeDisplay(u64);
+
+impl fmt::Display for SizeDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            size if size >= 1 << 30 && size % (1 << 30) == 0 => write!(f, "{}G", size >> 30),
+            size if size >= 1 << 20 && size % (1 << 20) == 0 => write!(f, "{}M", size >> 20),
+            size if size >= 1 << 10 && size % (1 << 10) == 0 => write!(f, "{}K", size >> 10),
+            size => write!(f, "{}", size),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub subsystem_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub revision: u8,
+    /// Interrupt the device's INTx pin is routed to
+    pub irq: Option<u32>,
+    bars: [Option<Bar>; 6],
+}
+
+impl PciDevice {
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    /// Assigned BARs with their index
+    pub fn bars(&self) -> impl Iterator<Item = (usize, Bar)> + '_ {
+        self.bars
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bar)| Some((index, (*bar)?)))
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x00) => "SCSI storage controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "Non-Volatile memory controller",
+            (0x01, _) => "Mass storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, 0x00) => "VGA compatible controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x05, _) => "Memory controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x07, _) => "Communication controller",
+            (0x08, _) => "System peripheral",
+            (0x09, _) => "Input device controller",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, _) => "Serial bus controller",
+            (0xFF, _) => "Unassigned class",
+            _ => "Unclassified device",
+        }
+    }
+
+    pub fn vendor_name(&self) -> &'static str {
+        match self.vendor_id {
+            0x1AF4 => "Red Hat, Inc. (virtio)",
+            0x1B36 => "Red Hat, Inc.",
+            0x8086 => "Intel Corporation",
+            _ => "Unknown vendor",
+        }
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} [{:02x}{:02x}]: {} [{:04x}:{:04x}] (rev {:02x})",
+            self.address,
+            self.class_name(),
+            self.class,
+            self.subclass,
+            self.vendor_name(),
+            self.vendor_id,
+            self.device_id,
+            self.revision
+        )?;
+        if let Some(irq) = self.irq {
+            write!(f, " irq {}", irq)?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory-mapped configuration space
+struct Ecam {
+    base: usize,
+    bus_start: u8,
+    bus_end: u8,
+}
+
+impl Ecam {
+    fn config_address(&self, address: PciAddress, offset: u16) -> Option<usize> {
+        if address.bus < self.bus_start || address.bus > self.bus_end {
+            return None;
+        }
+        let bus = (address.bus - self.bus_start) as usize;
+        Some(
+            self.base
+                + (bus << 20
+                    | (address.device as usize) << 15
+                    | (address.function as usize) << 12
+                    | offset as usize),
+        )
+    }
+
+    fn read_u32(&self, address: PciAddress, offset: u16) -> u32 {
+        match self.config_address(address, offset & !3) {
+            Some(addr) => unsafe { read_volatile(addr as *const u32) },
+            None => u32::MAX,
+        }
+    }
+
+    fn write_u32(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(addr) = self.config_address(address, offset & !3) {
+            unsafe { write_volatile(addr as *mut u32, value) }
+        }
+    }
+
+    fn read_u16(&self, address: PciAddress, offset: u16) -> u16 {
+        (self.read_u32(address, offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    fn read_u8(&self, address: PciAddress, offset: u16) -> u8 {
+        (self.read_u32(address, offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn write_u16(&self, address: PciAddress, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(address, offset);
+        let new = old & !(0xFFFF << shift) | (value as u32) << shift;
+        self.write_u32(address, offset, new);
+    }
+
+    fn write_u8(&self, address: PciAddress, offset: u16, value: u8) {
+        let shift = (offset & 3) * 8;
+        let old = self.read_u32(address, offset);
+        let new = old & !(0xFF << shift) | (value as u32) << shift;
+        self.write_u32(address, offset, new);
+    }
+}
+
+/// An address window of the host bridge, allocated bottom up
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    pci_base: u64,
+    cpu_base: u64,
+    size: u64,
+    next: u64,
+}
+
+impl Window {
+    fn new(pci_base: u64, cpu_base: u64, size: u64) -> Self {
+        Self {
+            pci_base,
+            cpu_base,
+            size,
+            next: pci_base,
+        }
+    }
+
+    /// Allocate a naturally aligned range, returns its bus address
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        let start = self.next.checked_add(size - 1)? & !(size - 1);
+        let end = start.checked_add(size)?;
+        if end > self.pci_base + self.size {
+            return None;
+        }
+        self.next = end;
+        Some(start)
+    }
+
+    fn align(&mut self, align: u64) {
+        self.next = (self.next + align - 1) & !(align - 1);
+    }
+
+    fn to_cpu(self, pci_address: u64) -> u64 {
+        pci_address - self.pci_base + self.cpu_base
+    }
+}
+
+struct Enumerator<'a> {
+    ecam: &'a Ecam,
+    mem32: Option<Window>,
+    mem64: Option<Window>,
+    io: Option<Window>,
+    next_bus: u8,
+    devices: Vec<PciDevice>,
+}
+
+impl Enumerator<'_> {
+    fn scan_bus(&mut self, bus: u8, swizzle: u8) {
+        for device in 0..32 {
+            let address = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            if self.ecam.read_u16(address, CONFIG_VENDOR_ID) == 0xFFFF {
+                continue;
+            }
+            let multifunction = self.ecam.read_u8(address, CONFIG_HEADER_TYPE) & 0x80 != 0;
+            let functions = if multifunction { 8 } else { 1 };
+
+            for function in 0..functions {
+                let address = PciAddress {
+                    function,
+                    ..address
+                };
+                if self.ecam.read_u16(address, CONFIG_VENDOR_ID) != 0xFFFF {
+                    self.probe_function(address, swizzle);
+                }
+            }
+        }
+    }
+
+    fn probe_function(&mut self, address: PciAddress, swizzle: u8) {
+        let ecam = self.ecam;
+        let class_rev = ecam.read_u32(address, CONFIG_REVISION);
+        let header_type = ecam.read_u8(address, CONFIG_HEADER_TYPE);
+        let bridge = header_type & 0x7F == 1;
+
+        let pin = ecam.read_u8(address, CONFIG_INTERRUPT_PIN);
+        let irq = (1..=4).contains(&pin).then(|| {
+            INTX_IRQ_BASE + ((pin - 1) as u32 + address.device as u32 + swizzle as u32) % 4
+        });
+        if let Some(irq) = irq {
+            ecam.write_u8(address, CONFIG_INTERRUPT_LINE, irq as u8);
+        }
+
+        let mut device = PciDevice {
+            address,
+            vendor_id: ecam.read_u16(address, CONFIG_VENDOR_ID),
+            device_id: ecam.read_u16(address, CONFIG_DEVICE_ID),
+            subsystem_id: if bridge {
+                0
+            } else {
+                ecam.read_u16(address, CONFIG_SUBSYSTEM_ID)
+            },
+            class: (class_rev >> 24) as u8,
+            subclass: (class_rev >> 16) as u8,
+            revision: class_rev as u8,
+            irq,
+            bars: [None; 6],
+        };
+
+        let command = ecam.read_u16(address, CONFIG_COMMAND);
+        ecam.write_u16(
+            address,
+            CONFIG_COMMAND,
+            command & !(COMMAND_IO | COMMAND_MEMORY),
+        );
+        self.assign_bars(&mut device, if bridge { 2 } else { 6 });
+
+        let mut command = (command | COMMAND_BUS_MASTER) & !COMMAND_INTX_DISABLE;
+        for (_, bar) in device.bars() {
+            command |= match bar {
+                Bar::Memory { .. } => COMMAND_MEMORY,
+                Bar::Io { .. } => COMMAND_IO,
+            };
+        }
+
+        self.devices.push(device);
+
+        if bridge {
+            // Decode whatever ends up behind the bridge
+            command |= COMMAND_MEMORY | COMMAND_IO;
+            self.configure_bridge(address, swizzle);
+        }
+        ecam.write_u16(address, CONFIG_COMMAND, command);
+    }
+
+    fn assign_bars(&mut self, device: &mut PciDevice, count: usize) {
+        let ecam = self.ecam;
+        let address = device.address;
+        let mut index = 0;
+
+        while index < count {
+            let offset = CONFIG_BAR0 + index as u16 * 4;
+            let original = ecam.read_u32(address, offset);
+            ecam.write_u32(address, offset, u32::MAX);
+            let mask = ecam.read_u32(address, offset);
+            ecam.write_u32(address, offset, original);
+            if mask == 0 {
+                index += 1;
+                continue;
+            }
+
+            if original & 1 == 1 {
+                let size = ((!(mask & !0x3)).wrapping_add(1) & 0xFFFF) as u64;
+                let port = self.io.as_mut().and_then(|io| io.alloc(size.max(4)));
+                match port {
+                    Some(port) => {
+                        ecam.write_u32(address, offset, port as u32);
+                        device.bars[index] = Some(Bar::Io {
+                            port: port as u32,
+                            size: size as u32,
+                        });
+                    }
+                    None => crate::log_warn!("pci {}: no I/O space for BAR{}", address, index),
+                }
+                index += 1;
+                continue;
+            }
+
+            let is_64bit = (original >> 1) & 3 == 2;
+            let prefetchable = original & 8 != 0;
+            let mut size_mask = (mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000;
+            if is_64bit {
+                let high = offset + 4;
+                let original_high = ecam.read_u32(address, high);
+                ecam.write_u32(address, high, u32::MAX);
+                let mask_high = ecam.read_u32(address, high);
+                ecam.write_u32(address, high, original_high);
+                size_mask = (mask & !0xF) as u64 | (mask_high as u64) << 32;
+            }
+            let size = (!size_mask).wrapping_add(1);
+
+            // Prefer the 32-bit window, 64-bit BARs may spill into the high one
+            let mut placement = self.mem32.as_mut().and_then(|window| window.alloc(size));
+            if placement.is_none() && is_64bit {
+                placement = self.mem64.as_mut().and_then(|window| window.alloc(size));
+            }
+            match placement {
+                Some(bus_address) => {
+                    let window = self.window_for(bus_address);
+                    ecam.write_u32(address, offset, bus_address as u32);
+                    if is_64bit {
+                        ecam.write_u32(address, offset + 4, (bus_address >> 32) as u32);
+                    }
+                    device.bars[index] = Some(Bar::Memory {
+                        address: window.map_or(bus_address, |w| w.to_cpu(bus_address)),
+                        size,
+                        is_64bit,
+                        prefetchable,
+                    });
+                }
+                None => crate::log_warn!(
+                    "pci {}: no memory space for BAR{} ({} bytes)",
+                    address,
+                    index,
+                    size
+                ),
+            }
+            index += if is_64bit { 2 } else { 1 };
+        }
+    }
+
+    fn window_for(&self, bus_address: u64) -> Option<Window> {
+        [self.mem32, self.mem64]
+            .into_iter()
+            .flatten()
+            .find(|w| bus_address >= w.pci_base && bus_address < w.pci_base + w.size)
+    }
+
+    /// Number the bus behind a bridge, scan it and open forwarding windows
+    fn configure_bridge(&mut self, address: PciAddress, swizzle: u8) {
+        let ecam = self.ecam;
+        if self.next_bus == ecam.bus_end {
+            crate::log_warn!("pci {}: out of bus numbers", address);
+            return;
+        }
+        self.next_bus += 1;
+        let secondary = self.next_bus;
+        ecam.write_u8(address, CONFIG_PRIMARY_BUS, address.bus);
+        ecam.write_u8(address, CONFIG_SECONDARY_BUS, secondary);
+        ecam.write_u8(address, CONFIG_SUBORDINATE_BUS, ecam.bus_end);
+
+        if let Some(mem) = self.mem32.as_mut() {
+            mem.align(BRIDGE_MEMORY_ALIGN);
+        }
+        if let Some(io) = self.io.as_mut() {
+            io.align(BRIDGE_IO_ALIGN);
+        }
+        let mem_start = self.mem32.map(|w| w.next);
+        let io_start = self.io.map(|w| w.next);
+
+        self.scan_bus(secondary, swizzle.wrapping_add(address.device));
+        ecam.write_u8(address, CONFIG_SUBORDINATE_BUS, self.next_bus);
+
+        if let Some(mem) = self.mem32.as_mut() {
+            mem.align(BRIDGE_MEMORY_ALIGN);
+        }
+        if let Some(io) = self.io.as_mut() {
+            io.align(BRIDGE_IO_ALIGN);
+        }
+
+        // A base above the limit closes a window
+        match (mem_start, self.mem32.map(|w| w.next)) {
+            (Some(start), Some(end)) if end > start => {
+                ecam.write_u16(address, CONFIG_MEMORY_BASE, (start >> 16) as u16 & 0xFFF0);
+                ecam.write_u16(
+                    address,
+                    CONFIG_MEMORY_LIMIT,
+                    ((end - 1) >> 16) as u16 & 0xFFF0,
+                );
+            }
+            _ => {
+                ecam.write_u16(address, CONFIG_MEMORY_BASE, 0xFFF0);
+                ecam.write_u16(address, CONFIG_MEMORY_LIMIT, 0);
+            }
+        }
+        match (io_start, self.io.map(|w| w.next)) {
+            (Some(start), Some(end)) if end > start => {
+                ecam.write_u8(address, CONFIG_IO_BASE, (start >> 8) as u8 & 0xF0);
+                ecam.write_u8(address, CONFIG_IO_LIMIT, ((end - 1) >> 8) as u8 & 0xF0);
+            }
+            _ => {
+                ecam.write_u8(address, CONFIG_IO_BASE, 0xF0);
+                ecam.write_u8(address, CONFIG_IO_LIMIT, 0);
+            }
+        }
+        ecam.write_u16(address, CONFIG_PREFETCH_BASE, 0xFFF0);
+        ecam.write_u16(address, CONFIG_PREFETCH_LIMIT, 0);
+    }
+}
+
+struct HostBridge {
+    ecam: Ecam,
+    devices: Vec<PciDevice>,
+}
+
+static HOST: SafeMutex<Option<HostBridge>> = SafeMutex::new(None);
+
+/// Configuration window and address windows of a host bridge
+struct Resources {
+    ecam: Ecam,
+    mem32: Option<Window>,
+    mem64: Option<Window>,
+    io: Option<Window>,
+}
+
+fn host_from_device_tree() -> Option<Resources> {
+    let tree = DeviceTree::get()?;
+    let node = *tree.find_compatible(ECAM_COMPATIBLE).first()?;
+    let (base, size) = *node.reg().first()?;
+
+    let (bus_start, bus_end) = match node.property("bus-range") {
+        Some(range) => (
+            dtb::read_cells(range, 1)? as u8,
+            dtb::read_cells(&range[4..], 1)? as u8,
+        ),
+        None => (0, ((size >> 20).clamp(1, 256) - 1) as u8),
+    };
+
+    let (mut mem32, mut mem64, mut io) = (None, None, None);
+    if let Some(ranges) = node.property("ranges") {
+        // PCI address is always 3 cells, then the CPU address and the size
+        let parent_cells = node.address_cells as usize;
+        let size_cells = node.child_size_cells as usize;
+        let entry = (3 + parent_cells + size_cells) * 4;
+        for chunk in ranges.chunks_exact(entry) {
+            let flags = dtb::read_cells(chunk, 1)? as u32;
+            let pci_base = dtb::read_cells(&chunk[4..], 2)?;
+            let cpu_base = dtb::read_cells(&chunk[12..], parent_cells as u32)?;
+            let size = dtb::read_cells(&chunk[(3 + parent_cells) * 4..], size_cells as u32)?;
+            let window = Window::new(pci_base, cpu_base, size);
+            match (flags >> 24) & 3 {
+                1 => io = Some(window),
+                2 => mem32 = Some(window),
+                3 => mem64 = Some(window),
+                _ => {}
+            }
+        }
+    }
+
+    let ecam = Ecam {
+        base: base as usize,
+        bus_start,
+        bus_end,
+    };
+    Some(Resources {
+        ecam,
+        mem32,
+        mem64,
+        io,
+    })
+}
+
+/// Find the host bridge, assign resources and record every function
+pub fn init() {
+    let resources = host_from_device_tree().unwrap_or_else(|| {
+        crate::log_warn!("pci: no host bridge in the device tree, assuming QEMU virt");
+        let ecam = Ecam {
+            base: DEFAULT_ECAM_BASE as usize,
+            bus_start: 0,
+            bus_end: DEFAULT_ECAM_BUSES - 1,
+        };
+        let mem = Window::new(DEFAULT_MEM_BASE, DEFAULT_MEM_BASE, DEFAULT_MEM_SIZE);
+        let io = Window::new(0, DEFAULT_IO_BASE, DEFAULT_IO_SIZE);
+        Resources {
+            ecam,
+            mem32: Some(mem),
+            mem64: None,
+            io: Some(io),
+        }
+    });
+    let Resources {
+        ecam,
+        mem32,
+        mem64,
+        io,
+    } = resources;
+    crate::log_info!(
+        "pci: ECAM at {:#x}, buses {:02x}-{:02x}",
+        ecam.base,
+        ecam.bus_start,
+        ecam.bus_end
+    );
+
+    let mut enumerator = Enumerator {
+        ecam: &ecam,
+        mem32,
+        mem64,
+        // Port 0 is never handed out
+        io: io.map(|mut io| {
+            io.next = io.next.max(BRIDGE_IO_ALIGN);
+            io
+        }),
+        next_bus: ecam.bus_start,
+        devices: Vec::new(),
+    };
+    enumerator.scan_bus(ecam.bus_start, 0);
+    let devices = enumerator.devices;
+
+    for device in &devices {
+        crate::log_info!("pci: {}", device);
+    }
+    *HOST.lock() = Some(HostBridge { ecam, devices });
+}
+
+/// All functions found during enumeration
+pub fn devices() -> Vec<PciDevice> {
+    HOST.lock()
+        .as_ref()
+        .map(|host| host.devices.clone())
+        .unwrap_or_default()
+}
+
+pub fn read_config_u8(address: PciAddress, offset: u16) -> u8 {
+    HOST.lock()
+        .as_ref()
+        .map_or(u8::MAX, |host| host.ecam.read_u8(address, offset))
+}
+
+pub fn read_config_u16(address: PciAddress, offset: u16) -> u16 {
+    HOST.lock()
+        .as_ref()
+        .map_or(u16::MAX, |host| host.ecam.read_u16(address, offset))
+}
+
+pub fn read_config_u32(address: PciAddress, offset: u16) -> u32 {
+    HOST.lock()
+        .as_ref()
+        .map_or(u32::MAX, |host| host.ecam.read_u32(address, offset))
+}
+
+/// Walk the capability list, returns the offsets of capabilities with `id`
+pub fn capabilities(address: PciAddress, id: u8) -> Vec<u16> {
+    let mut found = Vec::new();
+    if read_config_u16(address, CONFIG_STATUS) & STATUS_CAPABILITIES == 0 {
+        return found;
+    }
+    let mut offset = (read_config_u8(address, CONFIG_CAPABILITIES) & !3) as u16;
+    // Bounded in case of a malformed, looping list
+    for _ in 0..48 {
+        if offset == 0 {
+            break;
+        }
+        if read_config_u8(address, offset) == id {
+            found.push(offset);
+        }
+        offset = (read_config_u8(address, offset + 1) & !3) as u16;
+    }
+    found
+}
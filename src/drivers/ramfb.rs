@@ -0,0 +1,47 @@
+//! ramfb: a framebuffer in guest memory that QEMU scans out on its own
+//!
+//! The framebuffer is registered by writing its address and layout to the
+//! `etc/ramfb` fw_cfg file. QEMU reads guest memory on every display
+//! refresh, so there is nothing to flush.
+
+use crate::drivers::display::{Backend, Result};
+use crate::drivers::fw_cfg;
+use crate::kernel::memory::DmaBuffer;
+
+const CONFIG_FILE: &str = "etc/ramfb";
+
+/// DRM_FORMAT_XRGB8888: a little-endian `u32` of `0x00RRGGBB`
+const FOURCC_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+
+/// Size of the big-endian configuration written to the file
+const CONFIG_SIZE: usize = 28;
+
+pub struct Ramfb {
+    select: u16,
+}
+
+/// Find the configuration file, `None` if QEMU has no ramfb device
+pub fn probe() -> Option<Ramfb> {
+    let file = fw_cfg::find(CONFIG_FILE)?;
+    Some(Ramfb {
+        select: file.select,
+    })
+}
+
+impl Backend for Ramfb {
+    fn name(&self) -> &'static str {
+        "ramfb"
+    }
+
+    fn set_framebuffer(&mut self, framebuffer: &DmaBuffer, width: u32, height: u32) -> Result<()> {
+        // addr: u64, fourcc, flags, width, height, stride: u32, all big-endian
+        let mut config = [0; CONFIG_SIZE];
+        config[0..8].copy_from_slice(&(framebuffer.paddr() as u64).to_be_bytes());
+        config[8..12].copy_from_slice(&FOURCC_XRGB8888.to_be_bytes());
+        config[16..20].copy_from_slice(&width.to_be_bytes());
+        config[20..24].copy_from_slice(&height.to_be_bytes());
+        config[24..28].copy_from_slice(&(width * 4).to_be_bytes());
+        fw_cfg::write(self.select, &config)?;
+        Ok(())
+    }
+}
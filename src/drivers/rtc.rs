@@ -0,0 +1,80 @@
+//! PL031 real-time clock
+//!
+//! The RTC counts seconds since the Unix epoch and keeps going while the
+//! machine is off; QEMU starts it at the host's time in UTC. [`init`] sets
+//! the wall clock from it once, from then on [`SystemTime`] follows the
+//! generic timer, which has a far finer resolution. [`set_time`] updates
+//! both.
+
+use crate::drivers::dtb::DeviceTree;
+use crate::kernel::time::{self, DateTime, SystemTime};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const COMPATIBLE: &str = "arm,pl031";
+/// Where QEMU virt puts the device when the device tree says nothing
+const DEFAULT_BASE: usize = 0x0901_0000;
+
+// Register offsets
+const REG_DATA: usize = 0x00;
+const REG_LOAD: usize = 0x08;
+const REG_CONTROL: usize = 0x0c;
+const REG_PERIPH_ID: usize = 0xfe0;
+
+const CONTROL_START: u32 = 1 << 0;
+/// Low bytes of the four peripheral ID registers
+const PERIPH_ID: [u8; 4] = [0x31, 0x10, 0x04, 0x00];
+
+/// Base address of the device, 0 without one
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn read_reg(base: usize, offset: usize) -> u32 {
+    unsafe { read_volatile((base + offset) as *const u32) }
+}
+
+fn write_reg(base: usize, offset: usize, value: u32) {
+    unsafe { write_volatile((base + offset) as *mut u32, value) }
+}
+
+fn base_from_device_tree() -> Option<usize> {
+    let tree = DeviceTree::get()?;
+    let node = *tree.find_compatible(COMPATIBLE).first()?;
+    let (base, _) = *node.reg().first()?;
+    Some(base as usize)
+}
+
+/// Check the peripheral ID, so a wrong address is not taken for an RTC
+fn probe(base: usize) -> bool {
+    (0..4).all(|index| read_reg(base, REG_PERIPH_ID + index * 4) as u8 == PERIPH_ID[index])
+}
+
+/// Set the wall clock and, if there is one, the RTC
+pub fn set_time(now: SystemTime) {
+    let base = BASE.load(Ordering::Relaxed);
+    if base != 0 {
+        // The counter is 32 bits wide, enough until 2106
+        write_reg(base, REG_LOAD, now.unix_secs().min(u32::MAX as u64) as u32);
+    }
+    time::set_system_time(now);
+}
+
+/// Find the RTC and set the wall clock from it
+pub fn init() {
+    let base = base_from_device_tree().unwrap_or(DEFAULT_BASE);
+    if !probe(base) {
+        crate::log_info!("rtc: no PL031, the clock starts at the epoch");
+        return;
+    }
+    if read_reg(base, REG_CONTROL) & CONTROL_START == 0 {
+        write_reg(base, REG_CONTROL, CONTROL_START);
+    }
+    BASE.store(base, Ordering::Relaxed);
+
+    let now = SystemTime::from_unix_secs(read_reg(base, REG_DATA) as u64);
+    time::set_system_time(now);
+    crate::log_info!(
+        "rtc: PL031 at {:#x}, {} UTC",
+        base,
+        DateTime::from_system_time(now)
+    );
+}
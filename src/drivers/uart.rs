@@ -54,6 +54,7 @@ impl Uart {
         }
     }
 
+    #[allow(dead_code, reason = "the kernel only writes to the UART")]
     pub fn getc(&self) -> Option<u8> {
         unsafe {
             if (read_volatile(UART0_FR) & (1 << 4)) == 0 {
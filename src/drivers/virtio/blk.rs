@@ -0,0 +1,192 @@
+//! virtio-blk driver
+//!
+//! Every request is a chain of a header naming the operation and the first
+//! 512-byte sector, the data, and a status byte the device fills in. The
+//! caller sleeps until the interrupt handler reports the request complete.
+//! QEMU attaches a disk image with
+//! `-drive if=none,id=disk,file=disk.img,format=raw -device virtio-blk-pci,drive=disk`.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Transport};
+use crate::drivers::block::{self, BlockDevice, Error, Result};
+use crate::sync::{Mutex, WaitQueue};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+/// Tasks waiting for a request of any disk
+static REQUEST_WAITERS: WaitQueue = WaitQueue::new();
+
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+
+/// The device always counts in these, whatever its block size
+const SECTOR_SIZE: usize = 512;
+
+// Feature bits
+const F_RO: u64 = 1 << 5;
+const F_BLK_SIZE: u64 = 1 << 6;
+const F_FLUSH: u64 = 1 << 9;
+
+// Device configuration space
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+const T_GET_ID: u32 = 8;
+
+const S_OK: u8 = 0;
+const S_UNSUPP: u8 = 2;
+
+/// Length of the serial number GET_ID returns, not NUL terminated if full
+const ID_LEN: usize = 20;
+
+#[repr(C)]
+struct RequestHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+pub struct VirtIOBlk {
+    name: String,
+    model: String,
+    transport: Arc<dyn Transport>,
+    queue: Mutex<VirtQueue>,
+    block_size: usize,
+    block_count: u64,
+    read_only: bool,
+    /// The device has a write cache that FLUSH empties
+    can_flush: bool,
+}
+
+impl VirtIOBlk {
+    fn new(transport: Arc<dyn Transport>, name: String) -> super::Result<Self> {
+        let features = transport.begin_init(F_RO | F_BLK_SIZE | F_FLUSH)?;
+        let queue = VirtQueue::new(&*transport, REQUEST_QUEUE, QUEUE_SIZE)?;
+        super::attach_irq(&transport, || REQUEST_WAITERS.wake_all());
+        transport.finish_init();
+
+        let block_size = match transport.read_config_u32(CONFIG_BLK_SIZE) as usize {
+            size if features & F_BLK_SIZE != 0 && size.is_power_of_two() => size.max(SECTOR_SIZE),
+            _ => SECTOR_SIZE,
+        };
+        let sectors = transport.read_config_u64(CONFIG_CAPACITY);
+        let mut device = Self {
+            name,
+            model: String::new(),
+            transport,
+            queue: Mutex::new(queue),
+            block_size,
+            block_count: sectors / (block_size / SECTOR_SIZE) as u64,
+            read_only: features & F_RO != 0,
+            can_flush: features & F_FLUSH != 0,
+        };
+        device.model = device.read_id().unwrap_or_default();
+        Ok(device)
+    }
+
+    /// Send one request and wait for its status
+    fn request(&self, type_: u32, sector: u64, input: &[u8], output: &mut [u8]) -> Result<()> {
+        let header = RequestHeader {
+            type_,
+            reserved: 0,
+            sector,
+        };
+        let header = as_bytes(&header);
+        let mut status = [0xff_u8];
+        let mut queue = self.queue.lock();
+        let (transport, waiters) = (&*self.transport, &REQUEST_WAITERS);
+        if !input.is_empty() {
+            queue.submit_and_wait(transport, waiters, &[header, input], &mut [&mut status])?;
+        } else if !output.is_empty() {
+            queue.submit_and_wait(transport, waiters, &[header], &mut [output, &mut status])?;
+        } else {
+            queue.submit_and_wait(transport, waiters, &[header], &mut [&mut status])?;
+        }
+        drop(queue);
+        match status[0] {
+            S_OK => Ok(()),
+            S_UNSUPP => Err(Error::Unsupported),
+            _ => Err(Error::Io),
+        }
+    }
+
+    fn sector(&self, block: u64) -> u64 {
+        block * (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    /// The serial number, which QEMU takes from `-drive serial=`
+    fn read_id(&self) -> Result<String> {
+        let mut id = [0; ID_LEN];
+        self.request(T_GET_ID, 0, &[], &mut id)?;
+        let len = id.iter().position(|&b| b == 0).unwrap_or(ID_LEN);
+        Ok(String::from_utf8_lossy(&id[..len]).into_owned())
+    }
+}
+
+impl BlockDevice for VirtIOBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+        self.check_range(block, buffer.len())?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.request(T_IN, self.sector(block), &[], buffer)
+    }
+
+    fn write_blocks(&self, block: u64, buffer: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.check_range(block, buffer.len())?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.request(T_OUT, self.sector(block), buffer, &mut [])
+    }
+
+    fn flush(&self) -> Result<()> {
+        // Without a write cache every completed write is already durable
+        if !self.can_flush {
+            return Ok(());
+        }
+        self.request(T_FLUSH, 0, &[], &mut [])
+    }
+}
+
+/// Claim every virtio-blk device and register it as `vda`, `vdb`, ...
+pub fn init() {
+    while let Some(transport) = super::take(DeviceType::Block) {
+        let location = transport.location();
+        match VirtIOBlk::new(transport, block::next_name("vd")) {
+            Ok(device) => block::register(Arc::new(device)),
+            Err(err) => crate::log_error!("virtio-blk: {}: {:?}", location, err),
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! virtio-console driver
+//!
+//! Every port has a receive and a transmit queue. With the multiport
+//! feature the device announces ports on a control queue: we answer each
+//! `DEVICE_ADD` with `PORT_READY`, learn the port's name and whether a host
+//! program is connected, and hand the port out through [`accept`]. Without
+//! it there is just port 0, always open. Queues of every possible port are
+//! set up before the device starts, as the device expects.
+//!
+//! QEMU adds the device with `-device virtio-serial-pci`, a console port
+//! with `-device virtconsole,chardev=...` and other ports with
+//! `-device virtserialport,chardev=...,name=...`.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Transport};
+use crate::fs::proc;
+use crate::kernel::memory::DmaBuffer;
+use crate::kernel::sched;
+use crate::kernel::timer::Timer;
+use crate::sync::{Mutex, SafeMutex, WaitQueue};
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+/// Tasks waiting for input, control messages or a port to change state
+static WAITERS: WaitQueue = WaitQueue::new();
+/// Tasks waiting for the device to take output
+static TRANSMIT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Ports announced but not yet accepted
+static NEW_PORTS: SafeMutex<VecDeque<Arc<Port>>> = SafeMutex::new(VecDeque::new());
+/// Ports in use, by id
+static PORTS: SafeMutex<Vec<Arc<Port>>> = SafeMutex::new(Vec::new());
+
+const CONTROL_RECEIVE_QUEUE: u16 = 2;
+const CONTROL_TRANSMIT_QUEUE: u16 = 3;
+const QUEUE_SIZE: u16 = 16;
+
+/// More ports than this are ignored, QEMU allows 31
+const MAX_PORTS: u32 = 8;
+const RECEIVE_BUFFER_SIZE: usize = 256;
+/// Control messages, a port name follows the header
+const CONTROL_BUFFER_SIZE: usize = 256;
+/// How often queues are checked without an interrupt line
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Feature bits
+const F_SIZE: u64 = 1 << 0;
+const F_MULTIPORT: u64 = 1 << 1;
+
+// Device configuration space
+const CONFIG_COLS: usize = 0;
+const CONFIG_ROWS: usize = 2;
+const CONFIG_MAX_NR_PORTS: usize = 4;
+
+// Control events
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const DEVICE_REMOVE: u16 = 2;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const RESIZE: u16 = 5;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+
+/// Control message header: port id, event and value
+const CONTROL_HEADER_LEN: usize = 8;
+
+/// Receive and transmit queue of port `id`, the control queues sit in between
+fn port_queues(id: u32) -> (u16, u16) {
+    match id {
+        0 => (0, 1),
+        id => (2 * id as u16 + 2, 2 * id as u16 + 3),
+    }
+}
+
+/// Receive queue kept full of buffers, like virtio-net's
+struct Receiver {
+    queue: VirtQueue,
+    /// One `buffer_size` slot per queue entry
+    buffer: DmaBuffer,
+    buffer_size: usize,
+    /// Slot behind each queue token
+    slots: Vec<usize>,
+}
+
+impl Receiver {
+    fn new(transport: &dyn Transport, index: u16, buffer_size: usize) -> super::Result<Self> {
+        let queue = VirtQueue::new(transport, index, QUEUE_SIZE)?;
+        let size = queue.size() as usize;
+        let mut receiver = Self {
+            queue,
+            buffer: DmaBuffer::new(size * buffer_size),
+            buffer_size,
+            slots: vec![0; size],
+        };
+        for slot in 0..size {
+            receiver.queue_slot(slot)?;
+        }
+        Ok(receiver)
+    }
+
+    fn queue_slot(&mut self, slot: usize) -> super::Result<()> {
+        let start = slot * self.buffer_size;
+        let bytes = &mut self.buffer.as_mut_slice()[start..start + self.buffer_size];
+        // The buffer lives as long as the queue
+        let token = unsafe { self.queue.add(&[], &[bytes])? };
+        self.slots[token as usize] = slot;
+        Ok(())
+    }
+
+    /// Copy out the next filled buffer and queue it again
+    fn pop(&mut self, transport: &dyn Transport) -> Option<Vec<u8>> {
+        let (token, len) = self.queue.pop_used()?;
+        let slot = self.slots[token as usize];
+        let start = slot * self.buffer_size;
+        let len = (len as usize).min(self.buffer_size);
+        let data = self.buffer.as_slice()[start..start + len].to_vec();
+        if self.queue_slot(slot).is_ok() {
+            transport.notify(self.queue.index());
+        }
+        Some(data)
+    }
+}
+
+/// One channel to the host, `hvc<id>`
+pub struct Port {
+    id: u32,
+    device: Arc<Device>,
+    receiver: SafeMutex<Receiver>,
+    transmitter: Mutex<VirtQueue>,
+    /// Received bytes a reader has not taken yet
+    pending: SafeMutex<VecDeque<u8>>,
+    /// What the host called the port
+    name: SafeMutex<Option<String>>,
+    /// Columns and rows the host reported
+    size: SafeMutex<Option<(usize, usize)>>,
+    is_console: AtomicBool,
+    /// A host program is connected
+    host_connected: AtomicBool,
+    /// The device announced the port and has not removed it
+    present: AtomicBool,
+}
+
+impl Port {
+    /// `hvc<id>`, the way the ports are numbered
+    pub fn tty(&self) -> String {
+        format!("hvc{}", self.id)
+    }
+
+    /// Name the host gave the port, e.g. `org.nyannix.log`
+    pub fn name(&self) -> Option<String> {
+        self.name.lock().clone()
+    }
+
+    pub fn size(&self) -> Option<(usize, usize)> {
+        if let Some(size) = *self.size.lock() {
+            return Some(size);
+        }
+        // The console port may have its size in the configuration space
+        let transport = &self.device.transport;
+        if self.id != 0 || self.device.features & F_SIZE == 0 {
+            return None;
+        }
+        let cols = transport.read_config_u16(CONFIG_COLS) as usize;
+        let rows = transport.read_config_u16(CONFIG_ROWS) as usize;
+        (cols > 0 && rows > 0).then_some((cols, rows))
+    }
+
+    pub fn is_console(&self) -> bool {
+        self.is_console.load(Ordering::Relaxed)
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// Present and a host program is at the other end
+    pub fn is_open(&self) -> bool {
+        self.is_present() && self.host_connected.load(Ordering::Relaxed)
+    }
+
+    /// Park until the port is open, false if it was removed first
+    pub fn wait_until_open(&self) -> bool {
+        WAITERS.wait_until(|| self.is_open() || !self.is_present());
+        self.is_present()
+    }
+
+    /// Wait for input, 0 once the port is closed
+    pub fn read(&self, buffer: &mut [u8]) -> usize {
+        loop {
+            {
+                let mut pending = self.pending.lock();
+                if !pending.is_empty() {
+                    let len = pending.len().min(buffer.len());
+                    for (byte, pending) in buffer.iter_mut().zip(pending.drain(..len)) {
+                        *byte = pending;
+                    }
+                    return len;
+                }
+            }
+            let data = self.receiver.lock().pop(&*self.device.transport);
+            match data {
+                Some(data) => self.pending.lock().extend(data),
+                None if !self.is_open() => return 0,
+                None => {
+                    WAITERS.wait_until(|| !self.is_open() || self.receiver.lock().queue.can_pop())
+                }
+            }
+        }
+    }
+
+    /// Send `bytes` to the host, the device drops them while nobody listens
+    pub fn write(&self, bytes: &[u8]) -> super::Result<()> {
+        let mut queue = self.transmitter.lock();
+        let transport = &*self.device.transport;
+        queue.submit_and_wait(transport, &TRANSMIT_WAITERS, &[bytes], &mut [])?;
+        Ok(())
+    }
+}
+
+/// State shared by all ports of one device
+struct Device {
+    transport: Arc<dyn Transport>,
+    features: u64,
+    control_transmitter: Option<Mutex<VirtQueue>>,
+}
+
+impl Device {
+    fn send_control(&self, id: u32, event: u16, value: u16) {
+        let Some(transmitter) = &self.control_transmitter else {
+            return;
+        };
+        let mut message = [0; CONTROL_HEADER_LEN];
+        message[..4].copy_from_slice(&id.to_le_bytes());
+        message[4..6].copy_from_slice(&event.to_le_bytes());
+        message[6..].copy_from_slice(&value.to_le_bytes());
+        let mut queue = transmitter.lock();
+        let result =
+            queue.submit_and_wait(&*self.transport, &TRANSMIT_WAITERS, &[&message], &mut []);
+        if let Err(err) = result {
+            crate::log_error!("virtio-console: control message: {:?}", err);
+        }
+    }
+}
+
+/// A port of the device that is not necessarily in use
+fn new_port(device: &Arc<Device>, id: u32) -> super::Result<Port> {
+    let transport = &*device.transport;
+    let (receive, transmit) = port_queues(id);
+    Ok(Port {
+        id,
+        device: device.clone(),
+        receiver: SafeMutex::new(Receiver::new(transport, receive, RECEIVE_BUFFER_SIZE)?),
+        transmitter: Mutex::new(VirtQueue::new(transport, transmit, QUEUE_SIZE)?),
+        pending: SafeMutex::new(VecDeque::new()),
+        name: SafeMutex::new(None),
+        size: SafeMutex::new(None),
+        is_console: AtomicBool::new(false),
+        host_connected: AtomicBool::new(false),
+        present: AtomicBool::new(false),
+    })
+}
+
+/// Start using port `port` and queue it for [`accept`]
+fn add_port(port: &Arc<Port>) {
+    port.present.store(true, Ordering::Relaxed);
+    PORTS.lock().push(port.clone());
+    NEW_PORTS.lock().push_back(port.clone());
+    WAITERS.wake_all();
+}
+
+/// Act on one message from the control queue
+fn handle_control(ports: &[Arc<Port>], message: &[u8]) {
+    if message.len() < CONTROL_HEADER_LEN {
+        return;
+    }
+    let id = u32::from_le_bytes(message[..4].try_into().unwrap());
+    let event = u16::from_le_bytes([message[4], message[5]]);
+    let value = u16::from_le_bytes([message[6], message[7]]);
+    let payload = &message[CONTROL_HEADER_LEN..];
+    let Some(port) = ports.get(id as usize) else {
+        crate::log_warn!(
+            "virtio-console: port {} beyond the {} we set up",
+            id,
+            MAX_PORTS
+        );
+        return;
+    };
+    match event {
+        DEVICE_ADD if !port.is_present() => {
+            // Every port gets used, so it is opened on our side right away
+            port.device.send_control(id, PORT_READY, 1);
+            port.device.send_control(id, PORT_OPEN, 1);
+            add_port(port);
+        }
+        DEVICE_REMOVE => {
+            port.present.store(false, Ordering::Relaxed);
+            PORTS.lock().retain(|other| other.id != id);
+        }
+        CONSOLE_PORT => port.is_console.store(true, Ordering::Relaxed),
+        RESIZE if payload.len() >= 4 => {
+            let cols = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+            let rows = u16::from_le_bytes([payload[2], payload[3]]) as usize;
+            *port.size.lock() = Some((cols, rows));
+        }
+        PORT_OPEN => port.host_connected.store(value != 0, Ordering::Relaxed),
+        PORT_NAME => {
+            let name = String::from_utf8_lossy(payload);
+            *port.name.lock() = Some(String::from(name.trim_end_matches('\0')));
+        }
+        _ => {}
+    }
+    WAITERS.wake_all();
+}
+
+/// Serve the control queue, runs as a `virtio-console` task per device
+fn control_loop(control: SafeMutex<Receiver>, ports: Vec<Arc<Port>>, device: Arc<Device>) {
+    device.send_control(0, DEVICE_READY, 1);
+    loop {
+        WAITERS.wait_until(|| control.lock().queue.can_pop());
+        loop {
+            let message = control.lock().pop(&*device.transport);
+            let Some(message) = message else {
+                break;
+            };
+            handle_control(&ports, &message);
+        }
+    }
+}
+
+fn init_device(transport: Arc<dyn Transport>) -> super::Result<()> {
+    let features = transport.begin_init(F_SIZE | F_MULTIPORT)?;
+    let multiport = features & F_MULTIPORT != 0;
+    let port_count = match multiport {
+        true => transport
+            .read_config_u32(CONFIG_MAX_NR_PORTS)
+            .clamp(1, MAX_PORTS),
+        false => 1,
+    };
+
+    let mut control = None;
+    let mut control_transmitter = None;
+    if multiport {
+        let receiver = Receiver::new(&*transport, CONTROL_RECEIVE_QUEUE, CONTROL_BUFFER_SIZE)?;
+        control = Some(SafeMutex::new(receiver));
+        let queue = VirtQueue::new(&*transport, CONTROL_TRANSMIT_QUEUE, QUEUE_SIZE)?;
+        control_transmitter = Some(Mutex::new(queue));
+    }
+    let device = Arc::new(Device {
+        transport: transport.clone(),
+        features,
+        control_transmitter,
+    });
+    let ports = (0..port_count)
+        .map(|id| new_port(&device, id).map(Arc::new))
+        .collect::<super::Result<Vec<_>>>()?;
+
+    let wakeup = || {
+        WAITERS.wake_all();
+        TRANSMIT_WAITERS.wake_all();
+    };
+    if !super::attach_irq(&transport, wakeup) {
+        Timer::every(POLL_INTERVAL, || WAITERS.wake_all());
+    }
+    transport.finish_init();
+    for port in &ports {
+        transport.notify(port_queues(port.id).0);
+    }
+
+    match control {
+        Some(control) => {
+            transport.notify(CONTROL_RECEIVE_QUEUE);
+            sched::spawn("virtio-console", move || {
+                control_loop(control, ports, device)
+            });
+        }
+        None => {
+            // A single console port that is always connected
+            let port = &ports[0];
+            port.is_console.store(true, Ordering::Relaxed);
+            port.host_connected.store(true, Ordering::Relaxed);
+            add_port(port);
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the next port the host adds
+pub fn accept() -> Arc<Port> {
+    loop {
+        if let Some(port) = NEW_PORTS.lock().pop_front() {
+            return port;
+        }
+        WAITERS.wait_until(|| !NEW_PORTS.lock().is_empty());
+    }
+}
+
+/// Ports in use, by id
+pub fn ports() -> Vec<Arc<Port>> {
+    PORTS.lock().clone()
+}
+
+/// Contents of `/proc/hvc`
+fn render_ports() -> String {
+    let mut text = String::from("tty    name                     console  host\n");
+    for port in ports() {
+        let _ = writeln!(
+            text,
+            "{:<6} {:<24} {:<8} {}",
+            port.tty(),
+            port.name().unwrap_or_else(|| String::from("-")),
+            if port.is_console() { "yes" } else { "no" },
+            if port.is_open() { "connected" } else { "-" }
+        );
+    }
+    text
+}
+
+pub fn init() {
+    proc::register("hvc", render_ports);
+    while let Some(transport) = super::take(DeviceType::Console) {
+        let location = transport.location();
+        if let Err(err) = init_device(transport) {
+            crate::log_error!("virtio-console: {}: {:?}", location, err);
+        }
+    }
+}
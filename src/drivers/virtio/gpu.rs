@@ -0,0 +1,460 @@
+//! virtio-gpu 2D driver, a [`Backend`] for the display
+//!
+//! The guest framebuffer backs a host resource shown on scanout 0. Changed
+//! areas must be transferred to the host and flushed to become visible.
+//! Resizing the host window raises a display event with a new preferred
+//! size. The cursor plane is driven through the cursor queue.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Error, Result, Transport};
+use crate::drivers::cursor::{CursorImage, CURSOR_SIZE};
+use crate::drivers::display::{self, Backend, Rect};
+use crate::kernel::memory::DmaBuffer;
+use crate::sync::WaitQueue;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Tasks waiting for the control or the cursor queue
+static QUEUE_WAITERS: WaitQueue = WaitQueue::new();
+/// Set from the interrupt handler, the config space is checked on the next poll
+static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+
+const CONTROL_QUEUE: u16 = 0;
+const CURSOR_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 16;
+
+const MAX_SCANOUTS: usize = 16;
+const SCANOUT_ID: u32 = 0;
+/// Framebuffers alternate between resources 1 and 2
+const CURSOR_RESOURCE: u32 = 3;
+
+// Device configuration space
+const CONFIG_EVENTS_READ: usize = 0;
+const CONFIG_EVENTS_CLEAR: usize = 4;
+const EVENT_DISPLAY: u32 = 1;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_RESOURCE_UNREF: u32 = 0x0102;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_UPDATE_CURSOR: u32 = 0x0300;
+const CMD_MOVE_CURSOR: u32 = 0x0301;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Byte order B, G, R, A: a little-endian `u32` of `0xAARRGGBB`
+const FORMAT_B8G8R8A8_UNORM: u32 = 1;
+/// Byte order B, G, R, X: a little-endian `u32` of `0x00RRGGBB`
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct CtrlHeader {
+    type_: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    ring_idx: u8,
+    padding: [u8; 3],
+}
+
+impl CtrlHeader {
+    fn new(type_: u32) -> Self {
+        Self {
+            type_,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct RespDisplayInfo {
+    header: CtrlHeader,
+    modes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+struct ResourceCreate2d {
+    header: CtrlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+struct ResourceUnref {
+    header: CtrlHeader,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct MemEntry {
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+/// Attach a single contiguous backing region
+#[repr(C)]
+struct ResourceAttachBacking {
+    header: CtrlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    entry: MemEntry,
+}
+
+#[repr(C)]
+struct SetScanout {
+    header: CtrlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+struct TransferToHost2d {
+    header: CtrlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct ResourceFlush {
+    header: CtrlHeader,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct CursorPos {
+    scanout_id: u32,
+    x: u32,
+    y: u32,
+    padding: u32,
+}
+
+/// Used for both UPDATE_CURSOR and MOVE_CURSOR, the latter only reads `pos`
+#[repr(C)]
+struct UpdateCursor {
+    header: CtrlHeader,
+    pos: CursorPos,
+    resource_id: u32,
+    hot_x: u32,
+    hot_y: u32,
+    padding: u32,
+}
+
+/// View a plain `repr(C)` command structure as the bytes sent to the device
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+struct Device {
+    transport: Arc<dyn Transport>,
+    control: VirtQueue,
+    cursor: Option<VirtQueue>,
+}
+
+impl Device {
+    fn new(transport: Arc<dyn Transport>) -> Result<Self> {
+        transport.begin_init(0)?;
+        let control = VirtQueue::new(&*transport, CONTROL_QUEUE, QUEUE_SIZE)?;
+        let cursor = VirtQueue::new(&*transport, CURSOR_QUEUE, QUEUE_SIZE).ok();
+        super::attach_irq(&transport, || {
+            CONFIG_CHANGED.store(true, Ordering::Release);
+            QUEUE_WAITERS.wake_all();
+        });
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            control,
+            cursor,
+        })
+    }
+
+    /// Send a command and wait for its response
+    fn request<Req, Resp: Default>(&mut self, request: &Req) -> Result<Resp> {
+        let mut response = Resp::default();
+        self.control.submit_and_wait(
+            &*self.transport,
+            &QUEUE_WAITERS,
+            &[as_bytes(request)],
+            &mut [as_bytes_mut(&mut response)],
+        )?;
+        Ok(response)
+    }
+
+    /// Send a command that only reports success or failure
+    fn command<Req>(&mut self, request: &Req) -> Result<()> {
+        let response: CtrlHeader = self.request(request)?;
+        if response.type_ != RESP_OK_NODATA {
+            return Err(Error::RequestFailed);
+        }
+        Ok(())
+    }
+
+    fn display_info(&mut self) -> Result<RespDisplayInfo> {
+        let info: RespDisplayInfo = self.request(&CtrlHeader::new(CMD_GET_DISPLAY_INFO))?;
+        if info.header.type_ != RESP_OK_DISPLAY_INFO {
+            return Err(Error::RequestFailed);
+        }
+        Ok(info)
+    }
+
+    /// Create a host resource whose contents come from `backing`
+    fn create_resource(
+        &mut self,
+        resource_id: u32,
+        format: u32,
+        width: u32,
+        height: u32,
+        backing: &DmaBuffer,
+    ) -> Result<()> {
+        self.command(&ResourceCreate2d {
+            header: CtrlHeader::new(CMD_RESOURCE_CREATE_2D),
+            resource_id,
+            format,
+            width,
+            height,
+        })?;
+        self.command(&ResourceAttachBacking {
+            header: CtrlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id,
+            nr_entries: 1,
+            entry: MemEntry {
+                addr: backing.paddr() as u64,
+                length: backing.len() as u32,
+                padding: 0,
+            },
+        })
+    }
+
+    /// Create a host resource backed by `framebuffer` and show it
+    fn attach_framebuffer(
+        &mut self,
+        resource_id: u32,
+        framebuffer: &DmaBuffer,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        self.create_resource(
+            resource_id,
+            FORMAT_B8G8R8X8_UNORM,
+            width,
+            height,
+            framebuffer,
+        )?;
+        self.command(&SetScanout {
+            header: CtrlHeader::new(CMD_SET_SCANOUT),
+            rect: Rect::new(0, 0, width, height),
+            scanout_id: SCANOUT_ID,
+            resource_id,
+        })
+    }
+
+    fn unref(&mut self, resource_id: u32) -> Result<()> {
+        self.command(&ResourceUnref {
+            header: CtrlHeader::new(CMD_RESOURCE_UNREF),
+            resource_id,
+            padding: 0,
+        })
+    }
+
+    fn transfer(&mut self, resource_id: u32, rect: Rect, stride: u32) -> Result<()> {
+        self.command(&TransferToHost2d {
+            header: CtrlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect,
+            offset: rect.y as u64 * stride as u64 + rect.x as u64 * 4,
+            resource_id,
+            padding: 0,
+        })
+    }
+
+    fn flush(&mut self, resource_id: u32, rect: Rect, stride: u32) -> Result<()> {
+        self.transfer(resource_id, rect, stride)?;
+        self.command(&ResourceFlush {
+            header: CtrlHeader::new(CMD_RESOURCE_FLUSH),
+            rect,
+            resource_id,
+            padding: 0,
+        })
+    }
+}
+
+impl Device {
+    /// Cursor commands complete without a response
+    fn cursor_command(&mut self, command: u32, x: u32, y: u32, hot: (u32, u32)) -> Result<()> {
+        let queue = self.cursor.as_mut().ok_or(Error::QueueUnavailable)?;
+        let request = UpdateCursor {
+            header: CtrlHeader::new(command),
+            pos: CursorPos {
+                scanout_id: SCANOUT_ID,
+                x,
+                y,
+                padding: 0,
+            },
+            resource_id: CURSOR_RESOURCE,
+            hot_x: hot.0,
+            hot_y: hot.1,
+            padding: 0,
+        };
+        queue.submit_and_wait(
+            &*self.transport,
+            &QUEUE_WAITERS,
+            &[as_bytes(&request)],
+            &mut [],
+        )?;
+        Ok(())
+    }
+}
+
+pub struct VirtIOGPU {
+    device: Device,
+    /// Host resource backed by the framebuffer, 0 before the first mode set
+    resource_id: u32,
+    /// Image backing the cursor resource, `None` without a cursor queue
+    cursor_image: Option<DmaBuffer>,
+}
+
+/// Claim the first virtio-gpu device, `None` if there is none or it fails
+pub fn probe() -> Option<VirtIOGPU> {
+    let transport = super::take(DeviceType::Gpu)?;
+    let mut device = match Device::new(transport) {
+        Ok(device) => device,
+        Err(err) => {
+            crate::log_error!("virtio-gpu: initialization failed: {:?}", err);
+            return None;
+        }
+    };
+    let cursor_image = match setup_cursor(&mut device) {
+        Ok(image) => Some(image),
+        Err(err) => {
+            crate::log_info!("virtio-gpu: no hardware cursor: {:?}", err);
+            None
+        }
+    };
+    Some(VirtIOGPU {
+        device,
+        resource_id: 0,
+        cursor_image,
+    })
+}
+
+fn setup_cursor(device: &mut Device) -> Result<DmaBuffer> {
+    if device.cursor.is_none() {
+        return Err(Error::QueueUnavailable);
+    }
+    let image = DmaBuffer::new((CURSOR_SIZE * CURSOR_SIZE * 4) as usize);
+    device.create_resource(
+        CURSOR_RESOURCE,
+        FORMAT_B8G8R8A8_UNORM,
+        CURSOR_SIZE,
+        CURSOR_SIZE,
+        &image,
+    )?;
+    Ok(image)
+}
+
+impl Backend for VirtIOGPU {
+    fn name(&self) -> &'static str {
+        "virtio-gpu"
+    }
+
+    fn preferred_mode(&mut self) -> Option<(u32, u32)> {
+        match self.device.display_info() {
+            Ok(info) => {
+                let mode = info.modes[SCANOUT_ID as usize];
+                (mode.enabled != 0 && !mode.rect.is_empty())
+                    .then_some((mode.rect.width, mode.rect.height))
+            }
+            Err(err) => {
+                crate::log_warn!("virtio-gpu: reading display info: {:?}", err);
+                None
+            }
+        }
+    }
+
+    fn mode_changed(&mut self) -> bool {
+        if !CONFIG_CHANGED.swap(false, Ordering::Acquire) {
+            return false;
+        }
+        let transport = &self.device.transport;
+        if transport.read_config_u32(CONFIG_EVENTS_READ) & EVENT_DISPLAY == 0 {
+            return false;
+        }
+        transport.write_config_u32(CONFIG_EVENTS_CLEAR, EVENT_DISPLAY);
+        true
+    }
+
+    fn set_framebuffer(
+        &mut self,
+        framebuffer: &DmaBuffer,
+        width: u32,
+        height: u32,
+    ) -> display::Result<()> {
+        let resource_id = if self.resource_id == 1 { 2 } else { 1 };
+        self.device
+            .attach_framebuffer(resource_id, framebuffer, width, height)?;
+        if self.resource_id != 0 {
+            if let Err(err) = self.device.unref(self.resource_id) {
+                crate::log_warn!("virtio-gpu: releasing old framebuffer: {:?}", err);
+            }
+        }
+        self.resource_id = resource_id;
+        Ok(())
+    }
+
+    fn flush(&mut self, rect: Rect, stride: u32) -> display::Result<()> {
+        self.device.flush(self.resource_id, rect, stride)?;
+        Ok(())
+    }
+
+    fn has_cursor(&self) -> bool {
+        self.cursor_image.is_some()
+    }
+
+    fn update_cursor(&mut self, image: &CursorImage, x: u32, y: u32) -> display::Result<()> {
+        let backing = self
+            .cursor_image
+            .as_mut()
+            .ok_or(display::Error::Unsupported)?;
+        let (chunks, _) = backing.as_mut_slice().as_chunks_mut::<4>();
+        for (bytes, argb) in chunks.iter_mut().zip(&image.pixels) {
+            *bytes = argb.to_le_bytes();
+        }
+        let full = Rect::new(0, 0, CURSOR_SIZE, CURSOR_SIZE);
+        self.device
+            .transfer(CURSOR_RESOURCE, full, CURSOR_SIZE * 4)?;
+        self.device
+            .cursor_command(CMD_UPDATE_CURSOR, x, y, (image.hot_x, image.hot_y))?;
+        Ok(())
+    }
+
+    fn move_cursor(&mut self, x: u32, y: u32) -> display::Result<()> {
+        self.device.cursor_command(CMD_MOVE_CURSOR, x, y, (0, 0))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! virtio-input driver
+//!
+//! The device reports Linux evdev events through its event queue. Every
+//! queue entry is one 8-byte event buffer; completed buffers are handed to
+//! the matching driver and queued again right away. Events are drained from
+//! the interrupt handler, [`poll`] covers devices without an interrupt line.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Result, Transport};
+use crate::drivers::keyboard::KEYBOARD;
+use crate::drivers::mouse::{AbsRange, MOUSE};
+use crate::kernel::memory::DmaBuffer;
+use crate::sync::{IrqSafeSpinlock, SafeMutex};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+const EVENT_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+
+// Device configuration space
+const CONFIG_SELECT: usize = 0;
+const CONFIG_SUBSEL: usize = 1;
+const CONFIG_SIZE: usize = 2;
+const CONFIG_DATA: usize = 8;
+
+const CFG_ID_NAME: u8 = 0x01;
+const CFG_EV_BITS: u8 = 0x11;
+const CFG_ABS_INFO: u8 = 0x12;
+
+const ABS_X: u8 = 0x00;
+const ABS_Y: u8 = 0x01;
+
+// evdev event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+
+/// Buttons start here, lower `EV_KEY` codes are keyboard keys
+const BTN_MISC: u16 = 0x100;
+
+/// One evdev event as the device writes it
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputEvent {
+    pub type_: u16,
+    pub code: u16,
+    /// 0 release, 1 press, 2 autorepeat for `EV_KEY`
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Keyboard,
+    Pointer,
+    Other,
+}
+
+struct InputDevice {
+    transport: Arc<dyn Transport>,
+    name: String,
+    kind: Kind,
+    /// X and Y ranges of tablets, `None` for relative pointers
+    abs_ranges: Option<[AbsRange; 2]>,
+    events: VirtQueue,
+    /// `QUEUE_SIZE` event slots shared with the device
+    buffer: DmaBuffer,
+    /// Slot behind each queue token
+    slots: Vec<usize>,
+}
+
+impl InputDevice {
+    fn new(transport: Arc<dyn Transport>) -> Result<Self> {
+        transport.begin_init(0)?;
+        let events = VirtQueue::new(&*transport, EVENT_QUEUE, QUEUE_SIZE)?;
+        let size = events.size() as usize;
+        let mut device = Self {
+            name: config_string(&*transport, CFG_ID_NAME, 0),
+            kind: Kind::Other,
+            abs_ranges: None,
+            buffer: DmaBuffer::new(size * size_of::<InputEvent>()),
+            slots: vec![0; size],
+            transport,
+            events,
+        };
+        device.kind = device.classify();
+        if device.kind == Kind::Pointer {
+            device.abs_ranges = abs_range(&*device.transport, ABS_X)
+                .zip(abs_range(&*device.transport, ABS_Y))
+                .map(|(x, y)| [x, y]);
+        }
+        for slot in 0..size {
+            device.queue_slot(slot)?;
+        }
+        device.transport.finish_init();
+        device.transport.notify(EVENT_QUEUE);
+        Ok(device)
+    }
+
+    /// Tell keyboards and pointers apart by the events they can send
+    fn classify(&self) -> Kind {
+        let keys = config_bytes(&*self.transport, CFG_EV_BITS, EV_KEY as u8);
+        let has_key = |code: usize| {
+            keys.get(code / 8)
+                .is_some_and(|b| b & (1 << (code % 8)) != 0)
+        };
+        let has_type =
+            |type_: u16| !config_bytes(&*self.transport, CFG_EV_BITS, type_ as u8).is_empty();
+
+        if has_type(EV_REL) || has_type(EV_ABS) {
+            Kind::Pointer
+        } else if (1..BTN_MISC as usize).any(has_key) {
+            Kind::Keyboard
+        } else {
+            Kind::Other
+        }
+    }
+
+    fn queue_slot(&mut self, slot: usize) -> Result<()> {
+        let len = size_of::<InputEvent>();
+        let bytes = &mut self.buffer.as_mut_slice()[slot * len..(slot + 1) * len];
+        // The buffer lives as long as the queue
+        let token = unsafe { self.events.add(&[], &[bytes])? };
+        self.slots[token as usize] = slot;
+        Ok(())
+    }
+
+    /// Hand completed events to the drivers and recycle their buffers
+    fn drain(&mut self) {
+        let mut requeued = false;
+        while let Some((token, _)) = self.events.pop_used() {
+            let slot = self.slots[token as usize];
+            let len = size_of::<InputEvent>();
+            let offset = slot * len;
+            let event = unsafe {
+                core::ptr::read_volatile(self.buffer.as_ptr().add(offset) as *const InputEvent)
+            };
+            self.dispatch(event);
+            if self.queue_slot(slot).is_ok() {
+                requeued = true;
+            }
+        }
+        if requeued {
+            self.transport.notify(EVENT_QUEUE);
+        }
+    }
+
+    fn dispatch(&self, event: InputEvent) {
+        match self.kind {
+            Kind::Keyboard => KEYBOARD.lock().handle_event(event),
+            Kind::Pointer => MOUSE.lock().handle_event(event, self.abs_ranges),
+            Kind::Other => {}
+        }
+    }
+}
+
+/// Select a config item, returns its payload
+fn config_bytes(transport: &dyn Transport, select: u8, subsel: u8) -> Vec<u8> {
+    transport.write_config_u8(CONFIG_SELECT, select);
+    transport.write_config_u8(CONFIG_SUBSEL, subsel);
+    let size = transport.read_config_u8(CONFIG_SIZE) as usize;
+    (0..size)
+        .map(|i| transport.read_config_u8(CONFIG_DATA + i))
+        .collect()
+}
+
+/// Range of an absolute axis, `None` if the device does not have it
+fn abs_range(transport: &dyn Transport, axis: u8) -> Option<AbsRange> {
+    let info = config_bytes(transport, CFG_ABS_INFO, axis);
+    let min = info.get(0..4)?;
+    let max = info.get(4..8)?;
+    Some(AbsRange {
+        min: i32::from_le_bytes(min.try_into().ok()?),
+        max: i32::from_le_bytes(max.try_into().ok()?),
+    })
+}
+
+fn config_string(transport: &dyn Transport, select: u8, subsel: u8) -> String {
+    String::from_utf8_lossy(&config_bytes(transport, select, subsel)).into_owned()
+}
+
+static DEVICES: SafeMutex<Vec<Arc<IrqSafeSpinlock<InputDevice>>>> = SafeMutex::new(Vec::new());
+
+/// Claim every virtio-input device and start receiving its events
+pub fn init() {
+    while let Some(transport) = super::take(DeviceType::Input) {
+        let location = transport.location();
+        let device = match InputDevice::new(transport.clone()) {
+            Ok(device) => device,
+            Err(err) => {
+                crate::log_error!("virtio-input: {}: {:?}", location, err);
+                continue;
+            }
+        };
+        crate::log_info!(
+            "virtio-input: {} \"{}\" ({:?})",
+            location,
+            device.name,
+            device.kind
+        );
+
+        let device = Arc::new(IrqSafeSpinlock::new(device));
+        let handler = device.clone();
+        super::attach_irq(&transport, move || handler.lock().drain());
+        DEVICES.lock().push(device);
+    }
+}
+
+/// Pick up events of devices that have no interrupt line
+pub fn poll() {
+    for device in DEVICES.lock().iter() {
+        device.lock().drain();
+    }
+}
@@ -0,0 +1,192 @@
+//! virtio-mmio transport (legacy version 1 and modern version 2)
+
+use super::{DeviceStatus, DeviceType, Error, Result, Transport};
+use crate::kernel::memory::PAGE_SIZE;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::ptr::{read_volatile, write_volatile};
+
+// QEMU virt machine: 32 slots of 0x200 bytes, SPI 16 upwards
+const VIRTIO_MMIO_BASE: usize = 0x0A00_0000;
+const VIRTIO_MMIO_SIZE: usize = 0x200;
+const VIRTIO_MMIO_SLOTS: usize = 32;
+const VIRTIO_MMIO_IRQ: u32 = 48;
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03C;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0A0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0A4;
+const REG_CONFIG: usize = 0x100;
+
+pub struct MmioTransport {
+    base: usize,
+    version: u32,
+    device_type: DeviceType,
+    irq: u32,
+}
+
+impl MmioTransport {
+    /// Check for a device in the register window at `base`
+    pub fn probe(base: usize, irq: u32) -> Result<Self> {
+        let read = |offset: usize| unsafe { read_volatile((base + offset) as *const u32) };
+
+        if read(REG_MAGIC) != MAGIC_VALUE {
+            return Err(Error::NotPresent);
+        }
+        let version = read(REG_VERSION);
+        if version != 1 && version != 2 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        // Device id 0 marks an empty slot
+        let device_id = read(REG_DEVICE_ID);
+        if device_id == 0 {
+            return Err(Error::NotPresent);
+        }
+
+        Ok(Self {
+            base,
+            version,
+            device_type: DeviceType::from_id(device_id),
+            irq,
+        })
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+impl Transport for MmioTransport {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn location(&self) -> String {
+        format!("mmio@{:#x}", self.base)
+    }
+
+    fn irq(&self) -> Option<u32> {
+        Some(self.irq)
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    fn read_device_features(&self) -> u64 {
+        self.write(REG_DEVICE_FEATURES_SEL, 0);
+        let low = self.read(REG_DEVICE_FEATURES) as u64;
+        self.write(REG_DEVICE_FEATURES_SEL, 1);
+        let high = self.read(REG_DEVICE_FEATURES) as u64;
+        high << 32 | low
+    }
+
+    fn write_driver_features(&self, features: u64) {
+        self.write(REG_DRIVER_FEATURES_SEL, 0);
+        self.write(REG_DRIVER_FEATURES, features as u32);
+        self.write(REG_DRIVER_FEATURES_SEL, 1);
+        self.write(REG_DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_truncate(self.read(REG_STATUS))
+    }
+
+    fn set_status(&self, status: DeviceStatus) {
+        self.write(REG_STATUS, status.bits());
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        self.read(REG_QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: usize, avail: usize, used: usize) {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        self.write(REG_QUEUE_NUM, size as u32);
+
+        if self.is_legacy() {
+            // Legacy devices locate the rings from one page frame number
+            self.write(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            self.write(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(REG_QUEUE_PFN, (desc / PAGE_SIZE) as u32);
+        } else {
+            self.write(REG_QUEUE_DESC_LOW, desc as u32);
+            self.write(REG_QUEUE_DESC_HIGH, (desc as u64 >> 32) as u32);
+            self.write(REG_QUEUE_DRIVER_LOW, avail as u32);
+            self.write(REG_QUEUE_DRIVER_HIGH, (avail as u64 >> 32) as u32);
+            self.write(REG_QUEUE_DEVICE_LOW, used as u32);
+            self.write(REG_QUEUE_DEVICE_HIGH, (used as u64 >> 32) as u32);
+            self.write(REG_QUEUE_READY, 1);
+        }
+    }
+
+    fn notify(&self, queue: u16) {
+        self.write(REG_QUEUE_NOTIFY, queue as u32);
+    }
+
+    fn ack_interrupt(&self) -> bool {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        if status == 0 {
+            return false;
+        }
+        self.write(REG_INTERRUPT_ACK, status);
+        true
+    }
+
+    fn read_config_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.base + REG_CONFIG + offset) as *const u8) }
+    }
+
+    fn write_config_u8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.base + REG_CONFIG + offset) as *mut u8, value) }
+    }
+
+    fn read_config_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + REG_CONFIG + offset) as *const u32) }
+    }
+
+    fn write_config_u32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + REG_CONFIG + offset) as *mut u32, value) }
+    }
+}
+
+/// Register every populated virtio-mmio slot of the QEMU virt machine
+pub fn probe() {
+    for slot in 0..VIRTIO_MMIO_SLOTS {
+        let base = VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_SIZE;
+        let irq = VIRTIO_MMIO_IRQ + slot as u32;
+        match MmioTransport::probe(base, irq) {
+            Ok(transport) => super::register(Arc::new(transport)),
+            Err(Error::NotPresent) => {}
+            Err(err) => crate::log_warn!("virtio-mmio: slot at {:#x}: {:?}", base, err),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! VirtIO device framework
+//!
+//! Transports ([`mmio`], [`pci`]) discover devices and expose them through the
+//! [`Transport`] trait. Device drivers claim a transport with [`take`],
+//! negotiate features and talk to the device over [`queue::VirtQueue`]s.
+
+pub mod blk;
+pub mod console;
+pub mod gpu;
+pub mod input;
+pub mod mmio;
+pub mod net;
+pub mod pci;
+pub mod queue;
+pub mod rng;
+
+use crate::kernel::interrupt;
+use crate::sync::SafeMutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+/// Device implements VirtIO 1.0 or later (non-legacy interface)
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Gpu,
+    Input,
+    Other(u32),
+}
+
+impl DeviceType {
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            1 => DeviceType::Network,
+            2 => DeviceType::Block,
+            3 => DeviceType::Console,
+            4 => DeviceType::Entropy,
+            16 => DeviceType::Gpu,
+            18 => DeviceType::Input,
+            other => DeviceType::Other(other),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceType::Network => "net",
+            DeviceType::Block => "block",
+            DeviceType::Console => "console",
+            DeviceType::Entropy => "rng",
+            DeviceType::Gpu => "gpu",
+            DeviceType::Input => "input",
+            DeviceType::Other(_) => "unknown",
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DeviceStatus: u32 {
+        const ACKNOWLEDGE = 1;
+        const DRIVER = 2;
+        const DRIVER_OK = 4;
+        const FEATURES_OK = 8;
+        const DEVICE_NEEDS_RESET = 64;
+        const FAILED = 128;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No device, or the magic value did not match
+    NotPresent,
+    UnsupportedVersion(u32),
+    /// The device did not accept the negotiated features
+    FeaturesRejected,
+    QueueUnavailable,
+    QueueFull,
+    /// The device reported an error for a request
+    RequestFailed,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Access to one device's registers, independent of how it is attached
+///
+/// All methods take `&self` so the transport can be shared with the
+/// device's interrupt handler.
+pub trait Transport: Send + Sync {
+    fn device_type(&self) -> DeviceType;
+    /// Human readable location, e.g. `mmio@0xa003e00`
+    fn location(&self) -> String;
+    /// Interrupt line used by the device, if it has one
+    fn irq(&self) -> Option<u32>;
+    /// True for pre-1.0 devices that do not negotiate VERSION_1
+    fn is_legacy(&self) -> bool;
+
+    fn read_device_features(&self) -> u64;
+    fn write_driver_features(&self, features: u64);
+    fn status(&self) -> DeviceStatus;
+    fn set_status(&self, status: DeviceStatus);
+
+    fn max_queue_size(&self, queue: u16) -> u16;
+    /// Hand the ring addresses of `queue` to the device and enable it
+    fn setup_queue(&self, queue: u16, size: u16, desc: usize, avail: usize, used: usize);
+    fn notify(&self, queue: u16);
+    /// Acknowledge a pending interrupt, returns false if this device did not raise it
+    fn ack_interrupt(&self) -> bool;
+
+    fn read_config_u8(&self, offset: usize) -> u8;
+    fn write_config_u8(&self, offset: usize, value: u8);
+
+    fn read_config_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([
+            self.read_config_u8(offset),
+            self.read_config_u8(offset + 1),
+        ])
+    }
+
+    fn read_config_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([
+            self.read_config_u8(offset),
+            self.read_config_u8(offset + 1),
+            self.read_config_u8(offset + 2),
+            self.read_config_u8(offset + 3),
+        ])
+    }
+
+    fn write_config_u32(&self, offset: usize, value: u32) {
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_config_u8(offset + i, byte);
+        }
+    }
+
+    fn read_config_u64(&self, offset: usize) -> u64 {
+        self.read_config_u32(offset) as u64 | (self.read_config_u32(offset + 4) as u64) << 32
+    }
+
+    /// Reset the device and negotiate features, returns the accepted set
+    ///
+    /// Queues are set up after this, then [`Transport::finish_init`].
+    fn begin_init(&self, supported: u64) -> Result<u64> {
+        self.set_status(DeviceStatus::empty());
+        self.set_status(DeviceStatus::ACKNOWLEDGE);
+        self.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+
+        let supported = if self.is_legacy() {
+            supported & !VIRTIO_F_VERSION_1
+        } else {
+            supported | VIRTIO_F_VERSION_1
+        };
+        let features = self.read_device_features() & supported;
+        self.write_driver_features(features);
+
+        if !self.is_legacy() {
+            let status = self.status() | DeviceStatus::FEATURES_OK;
+            self.set_status(status);
+            if !self.status().contains(DeviceStatus::FEATURES_OK) {
+                self.set_status(DeviceStatus::FAILED);
+                return Err(Error::FeaturesRejected);
+            }
+        }
+        Ok(features)
+    }
+
+    fn finish_init(&self) {
+        self.set_status(self.status() | DeviceStatus::DRIVER_OK);
+    }
+}
+
+struct Slot {
+    transport: Arc<dyn Transport>,
+    claimed: bool,
+}
+
+static DEVICES: SafeMutex<Vec<Slot>> = SafeMutex::new(Vec::new());
+
+/// Make a discovered device available to drivers
+pub fn register(transport: Arc<dyn Transport>) {
+    crate::log_info!(
+        "virtio: {} device at {}{}",
+        transport.device_type().name(),
+        transport.location(),
+        if transport.is_legacy() { " (legacy)" } else { "" }
+    );
+    DEVICES.lock().push(Slot {
+        transport,
+        claimed: false,
+    });
+}
+
+/// Claim the first unclaimed device of the given type
+pub fn take(device_type: DeviceType) -> Option<Arc<dyn Transport>> {
+    let mut devices = DEVICES.lock();
+    let slot = devices
+        .iter_mut()
+        .find(|slot| !slot.claimed && slot.transport.device_type() == device_type)?;
+    slot.claimed = true;
+    Some(slot.transport.clone())
+}
+
+/// Call `on_interrupt` whenever the device signals an interrupt
+///
+/// Returns false if the transport has no interrupt line, in which case the
+/// driver has to poll its queues.
+pub fn attach_irq(
+    transport: &Arc<dyn Transport>,
+    on_interrupt: impl Fn() + Send + Sync + 'static,
+) -> bool {
+    let Some(irq) = transport.irq() else {
+        return false;
+    };
+    let transport = transport.clone();
+    interrupt::register(irq, move || {
+        if transport.ack_interrupt() {
+            on_interrupt();
+        }
+    });
+    true
+}
+
+/// Discover devices on all transports
+pub fn probe() {
+    mmio::probe();
+    pci::probe();
+}
@@ -0,0 +1,201 @@
+//! virtio-net driver
+//!
+//! Queue 0 receives and queue 1 transmits. Every frame is preceded by a
+//! virtio-net header; we offer no offloads, so it is all zeros on transmit
+//! and ignored on receive. The receive queue is kept full of buffers large
+//! enough for a whole frame, each is queued again once the network stack
+//! has copied its frame out. QEMU attaches a card to user networking with
+//! `-netdev user,id=net0 -device virtio-net-pci,netdev=net0`.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Transport};
+use crate::drivers::net::{self, Error, MacAddress, NetDevice, Result, RECEIVE_WAITERS};
+use crate::kernel::memory::DmaBuffer;
+use crate::kernel::timer::Timer;
+use crate::sync::{Mutex, SafeMutex, WaitQueue};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Tasks waiting for the device to take a frame
+static TRANSMIT_WAITERS: WaitQueue = WaitQueue::new();
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 64;
+
+// Feature bits
+const F_MTU: u64 = 1 << 3;
+const F_MAC: u64 = 1 << 5;
+const F_STATUS: u64 = 1 << 16;
+
+// Device configuration space
+const CONFIG_MAC: usize = 0;
+const CONFIG_STATUS: usize = 6;
+const CONFIG_MTU: usize = 10;
+
+const S_LINK_UP: u16 = 1;
+
+/// Header of legacy devices, modern ones add a buffer count
+const LEGACY_HEADER_LEN: usize = 10;
+const HEADER_LEN: usize = 12;
+/// Ethernet header, a VLAN tag and the payload fit with room to spare
+const RECEIVE_BUFFER_SIZE: usize = 2048;
+const DEFAULT_MTU: usize = 1500;
+/// Smallest MTU IPv4 works with
+const MIN_MTU: usize = 68;
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Address of cards that do not report one, locally administered
+const FALLBACK_MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+/// How often the receive queue is checked without an interrupt line
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Receiver {
+    queue: VirtQueue,
+    /// One `RECEIVE_BUFFER_SIZE` slot per queue entry
+    buffer: DmaBuffer,
+    /// Slot behind each queue token
+    slots: Vec<usize>,
+}
+
+impl Receiver {
+    fn queue_slot(&mut self, slot: usize) -> super::Result<()> {
+        let start = slot * RECEIVE_BUFFER_SIZE;
+        let bytes = &mut self.buffer.as_mut_slice()[start..start + RECEIVE_BUFFER_SIZE];
+        // The buffer lives as long as the queue
+        let token = unsafe { self.queue.add(&[], &[bytes])? };
+        self.slots[token as usize] = slot;
+        Ok(())
+    }
+}
+
+pub struct VirtIONet {
+    name: String,
+    mac: MacAddress,
+    mtu: usize,
+    transport: Arc<dyn Transport>,
+    receiver: SafeMutex<Receiver>,
+    transmitter: Mutex<VirtQueue>,
+    header_len: usize,
+    /// The device reports the link state
+    has_status: bool,
+}
+
+impl VirtIONet {
+    fn new(transport: Arc<dyn Transport>, name: String) -> super::Result<Self> {
+        let features = transport.begin_init(F_MTU | F_MAC | F_STATUS)?;
+        let receive = VirtQueue::new(&*transport, RECEIVE_QUEUE, QUEUE_SIZE)?;
+        let transmit = VirtQueue::new(&*transport, TRANSMIT_QUEUE, QUEUE_SIZE)?;
+        let size = receive.size() as usize;
+        let mut receiver = Receiver {
+            queue: receive,
+            buffer: DmaBuffer::new(size * RECEIVE_BUFFER_SIZE),
+            slots: vec![0; size],
+        };
+        for slot in 0..size {
+            receiver.queue_slot(slot)?;
+        }
+
+        let wakeup = || {
+            RECEIVE_WAITERS.wake_all();
+            TRANSMIT_WAITERS.wake_all();
+        };
+        if !super::attach_irq(&transport, wakeup) {
+            Timer::every(POLL_INTERVAL, || RECEIVE_WAITERS.wake_all());
+        }
+        transport.finish_init();
+        transport.notify(RECEIVE_QUEUE);
+
+        let mac = if features & F_MAC != 0 {
+            let mut mac = [0; 6];
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = transport.read_config_u8(CONFIG_MAC + i);
+            }
+            MacAddress(mac)
+        } else {
+            FALLBACK_MAC
+        };
+        let mtu = match transport.read_config_u16(CONFIG_MTU) as usize {
+            mtu if features & F_MTU != 0 && mtu >= MIN_MTU => mtu.min(DEFAULT_MTU),
+            _ => DEFAULT_MTU,
+        };
+        Ok(Self {
+            name,
+            mac,
+            mtu,
+            header_len: if transport.is_legacy() {
+                LEGACY_HEADER_LEN
+            } else {
+                HEADER_LEN
+            },
+            transport,
+            receiver: SafeMutex::new(receiver),
+            transmitter: Mutex::new(transmit),
+            has_status: features & F_STATUS != 0,
+        })
+    }
+}
+
+impl NetDevice for VirtIONet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn link_up(&self) -> bool {
+        !self.has_status || self.transport.read_config_u16(CONFIG_STATUS) & S_LINK_UP != 0
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > ETHERNET_HEADER_LEN + self.mtu {
+            return Err(Error::TooLarge);
+        }
+        let header = [0; HEADER_LEN];
+        let header = &header[..self.header_len];
+        let mut queue = self.transmitter.lock();
+        queue.submit_and_wait(
+            &*self.transport,
+            &TRANSMIT_WAITERS,
+            &[header, frame],
+            &mut [],
+        )?;
+        Ok(())
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut receiver = self.receiver.lock();
+        let (token, len) = receiver.queue.pop_used()?;
+        let slot = receiver.slots[token as usize];
+        let start = slot * RECEIVE_BUFFER_SIZE;
+        let len = (len as usize).clamp(self.header_len, RECEIVE_BUFFER_SIZE);
+        let frame = receiver.buffer.as_slice()[start + self.header_len..start + len].to_vec();
+        if receiver.queue_slot(slot).is_ok() {
+            self.transport.notify(RECEIVE_QUEUE);
+        }
+        Some(frame)
+    }
+
+    fn can_receive(&self) -> bool {
+        self.receiver.lock().queue.can_pop()
+    }
+}
+
+/// Claim every virtio-net device and register it as `eth0`, `eth1`, ...
+pub fn init() {
+    while let Some(transport) = super::take(DeviceType::Network) {
+        let location = transport.location();
+        match VirtIONet::new(transport, net::next_name()) {
+            Ok(device) => net::register(Arc::new(device)),
+            Err(err) => crate::log_error!("virtio-net: {}: {:?}", location, err),
+        }
+    }
+}
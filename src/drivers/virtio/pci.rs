@@ -0,0 +1,246 @@
+//! virtio-pci transport (modern interface through vendor capabilities)
+//!
+//! Transitional devices also expose the legacy I/O BAR, we only use the
+//! memory-mapped structures the capabilities point to. MSI-X stays disabled
+//! so the device interrupts through its INTx pin and the ISR register.
+
+use super::{DeviceStatus, DeviceType, Error, Result, Transport};
+use crate::drivers::pci::{self, Bar, PciAddress, PciDevice};
+use crate::sync::SafeMutex;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const TRANSITIONAL_DEVICE_IDS: core::ops::Range<u16> = 0x1000..0x1040;
+const MODERN_DEVICE_IDS: core::ops::Range<u16> = 0x1040..0x1080;
+
+const PCI_CAP_VENDOR: u8 = 0x09;
+
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_ISR_CFG: u8 = 3;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// Common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1E;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+pub struct PciTransport {
+    address: PciAddress,
+    device_type: DeviceType,
+    irq: Option<u32>,
+    common: usize,
+    notify: usize,
+    notify_multiplier: u32,
+    isr: usize,
+    device: usize,
+    /// Notification offset of each queue, recorded by `setup_queue`
+    queue_notify: SafeMutex<Vec<u16>>,
+}
+
+impl PciTransport {
+    /// Locate the virtio structures of a PCI function
+    pub fn new(device: &PciDevice) -> Result<Self> {
+        if device.vendor_id != VIRTIO_VENDOR_ID {
+            return Err(Error::NotPresent);
+        }
+        let device_type = if MODERN_DEVICE_IDS.contains(&device.device_id) {
+            DeviceType::from_id((device.device_id - MODERN_DEVICE_IDS.start) as u32)
+        } else if TRANSITIONAL_DEVICE_IDS.contains(&device.device_id) {
+            DeviceType::from_id(device.subsystem_id as u32)
+        } else {
+            return Err(Error::NotPresent);
+        };
+
+        let address = device.address;
+        let (mut common, mut notify, mut isr, mut config) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+
+        for cap in pci::capabilities(address, PCI_CAP_VENDOR) {
+            let cfg_type = pci::read_config_u8(address, cap + 3);
+            let bar = pci::read_config_u8(address, cap + 4) as usize;
+            let offset = pci::read_config_u32(address, cap + 8) as u64;
+            let Some(Bar::Memory { address: base, .. }) = device.bar(bar) else {
+                continue;
+            };
+            let region = Some((base + offset) as usize);
+
+            match cfg_type {
+                CAP_COMMON_CFG if common.is_none() => common = region,
+                CAP_NOTIFY_CFG if notify.is_none() => {
+                    notify = region;
+                    notify_multiplier = pci::read_config_u32(address, cap + 16);
+                }
+                CAP_ISR_CFG if isr.is_none() => isr = region,
+                CAP_DEVICE_CFG if config.is_none() => config = region,
+                _ => {}
+            }
+        }
+
+        // Without the capabilities only the legacy I/O interface is left
+        let (Some(common), Some(notify), Some(isr)) = (common, notify, isr) else {
+            return Err(Error::UnsupportedVersion(0));
+        };
+
+        Ok(Self {
+            address,
+            device_type,
+            irq: device.irq,
+            common,
+            notify,
+            notify_multiplier,
+            isr,
+            device: config.unwrap_or(0),
+            queue_notify: SafeMutex::new(Vec::new()),
+        })
+    }
+
+    fn read8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.common + offset) as *const u8) }
+    }
+
+    fn write8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.common + offset) as *mut u8, value) }
+    }
+
+    fn read16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile((self.common + offset) as *const u16) }
+    }
+
+    fn write16(&self, offset: usize, value: u16) {
+        unsafe { write_volatile((self.common + offset) as *mut u16, value) }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.common + offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.common + offset) as *mut u32, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl Transport for PciTransport {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn location(&self) -> String {
+        format!("pci@{}", self.address)
+    }
+
+    fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+
+    fn is_legacy(&self) -> bool {
+        false
+    }
+
+    fn read_device_features(&self) -> u64 {
+        self.write32(COMMON_DEVICE_FEATURE_SELECT, 0);
+        let low = self.read32(COMMON_DEVICE_FEATURE) as u64;
+        self.write32(COMMON_DEVICE_FEATURE_SELECT, 1);
+        let high = self.read32(COMMON_DEVICE_FEATURE) as u64;
+        high << 32 | low
+    }
+
+    fn write_driver_features(&self, features: u64) {
+        self.write32(COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.write32(COMMON_DRIVER_FEATURE, features as u32);
+        self.write32(COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.write32(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_truncate(self.read8(COMMON_DEVICE_STATUS) as u32)
+    }
+
+    fn set_status(&self, status: DeviceStatus) {
+        self.write8(COMMON_DEVICE_STATUS, status.bits() as u8);
+    }
+
+    fn max_queue_size(&self, queue: u16) -> u16 {
+        self.write16(COMMON_QUEUE_SELECT, queue);
+        self.read16(COMMON_QUEUE_SIZE)
+    }
+
+    fn setup_queue(&self, queue: u16, size: u16, desc: usize, avail: usize, used: usize) {
+        self.write16(COMMON_QUEUE_SELECT, queue);
+        self.write16(COMMON_QUEUE_SIZE, size);
+        self.write64(COMMON_QUEUE_DESC, desc as u64);
+        self.write64(COMMON_QUEUE_DRIVER, avail as u64);
+        self.write64(COMMON_QUEUE_DEVICE, used as u64);
+
+        let notify_off = self.read16(COMMON_QUEUE_NOTIFY_OFF);
+        let mut offsets = self.queue_notify.lock();
+        if offsets.len() <= queue as usize {
+            offsets.resize(queue as usize + 1, 0);
+        }
+        offsets[queue as usize] = notify_off;
+
+        self.write16(COMMON_QUEUE_ENABLE, 1);
+    }
+
+    fn notify(&self, queue: u16) {
+        let notify_off = self.queue_notify.lock().get(queue as usize).copied();
+        let Some(notify_off) = notify_off else {
+            return;
+        };
+        let address = self.notify + notify_off as usize * self.notify_multiplier as usize;
+        unsafe { write_volatile(address as *mut u16, queue) }
+    }
+
+    fn ack_interrupt(&self) -> bool {
+        // Reading the ISR status clears it
+        unsafe { read_volatile(self.isr as *const u8) != 0 }
+    }
+
+    fn read_config_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.device + offset) as *const u8) }
+    }
+
+    fn write_config_u8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.device + offset) as *mut u8, value) }
+    }
+
+    fn read_config_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.device + offset) as *const u32) }
+    }
+
+    fn write_config_u32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.device + offset) as *mut u32, value) }
+    }
+}
+
+/// Register every virtio function found during PCI enumeration
+pub fn probe() {
+    for device in pci::devices() {
+        if device.vendor_id != VIRTIO_VENDOR_ID {
+            continue;
+        }
+        match PciTransport::new(&device) {
+            Ok(transport) => super::register(Arc::new(transport)),
+            Err(Error::NotPresent) => {}
+            Err(err) => crate::log_warn!("virtio-pci: {}: {:?}", device.address, err),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Split virtqueues
+//!
+//! The descriptor table, available ring and used ring live in one page
+//! aligned allocation laid out as legacy devices expect, which also
+//! satisfies the alignment rules of modern devices.
+
+use super::{Error, Result, Transport};
+use crate::kernel::memory::{DmaBuffer, PAGE_SIZE};
+use crate::kernel::sched;
+use crate::sync::WaitQueue;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    memory: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Allocate queue `index` with up to `size` entries and register it with the device
+    pub fn new(transport: &dyn Transport, index: u16, size: u16) -> Result<Self> {
+        let max = transport.max_queue_size(index);
+        if max == 0 {
+            return Err(Error::QueueUnavailable);
+        }
+        // Legacy devices need a power of two
+        let size = size.min(max);
+        let size = 1u16 << (15 - size.leading_zeros().min(15));
+
+        let n = size as usize;
+        let desc_size = size_of::<Descriptor>() * n;
+        let avail_offset = desc_size;
+        let avail_size = 2 * (3 + n);
+        let used_offset = align_up(avail_offset + avail_size, PAGE_SIZE);
+        let used_size = 2 * 3 + size_of::<UsedElem>() * n;
+        let memory = DmaBuffer::new(used_offset + align_up(used_size, PAGE_SIZE));
+
+        let queue = Self {
+            index,
+            size,
+            memory,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size {
+            let desc = queue.desc(i);
+            unsafe { (*desc).next = i + 1 };
+        }
+
+        let base = queue.memory.paddr();
+        transport.setup_queue(index, size, base, base + avail_offset, base + used_offset);
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn desc(&self, i: u16) -> *mut Descriptor {
+        unsafe { (self.memory.as_ptr() as *mut Descriptor).add(i as usize) }
+    }
+
+    fn avail_ring(&self, slot: u16) -> *mut u16 {
+        unsafe {
+            (self.memory.as_ptr().add(self.avail_offset) as *mut u16).add(2 + slot as usize)
+        }
+    }
+
+    fn avail_idx_ptr(&self) -> *mut u16 {
+        unsafe { (self.memory.as_ptr().add(self.avail_offset) as *mut u16).add(1) }
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { read_volatile((self.memory.as_ptr().add(self.used_offset) as *const u16).add(1)) }
+    }
+
+    fn used_elem(&self, slot: u16) -> UsedElem {
+        unsafe {
+            let ring = self.memory.as_ptr().add(self.used_offset + 4) as *const UsedElem;
+            read_volatile(ring.add(slot as usize))
+        }
+    }
+
+    /// Queue a descriptor chain: `inputs` are read by the device, `outputs` written by it
+    ///
+    /// Returns the token that [`VirtQueue::pop_used`] reports on completion.
+    ///
+    /// # Safety
+    /// The buffers must stay valid and untouched until the chain is popped.
+    pub unsafe fn add(&mut self, inputs: &[&[u8]], outputs: &[&mut [u8]]) -> Result<u16> {
+        let count = inputs.len() + outputs.len();
+        if count == 0 || count > self.num_free as usize {
+            return Err(Error::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut last = head;
+        let mut current = head;
+        let buffers = inputs
+            .iter()
+            .map(|buf| (buf.as_ptr(), buf.len(), 0))
+            .chain(outputs.iter().map(|buf| (buf.as_ptr(), buf.len(), DESC_F_WRITE)));
+
+        for (ptr, len, flags) in buffers {
+            let desc = self.desc(current);
+            (*desc).addr = ptr as u64;
+            (*desc).len = len as u32;
+            (*desc).flags = flags | DESC_F_NEXT;
+            last = current;
+            current = (*desc).next;
+        }
+        (*self.desc(last)).flags &= !DESC_F_NEXT;
+        self.free_head = current;
+        self.num_free -= count as u16;
+
+        // Publish the chain before the index the device polls
+        write_volatile(self.avail_ring(self.avail_idx % self.size), head);
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        write_volatile(self.avail_idx_ptr(), self.avail_idx);
+        fence(Ordering::SeqCst);
+
+        Ok(head)
+    }
+
+    /// True if the device has returned a chain we have not popped yet
+    pub fn can_pop(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.used_idx() != self.last_used_idx
+    }
+
+    /// Take the next completed chain: its token and the bytes written by the device
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        let elem = self.used_elem(self.last_used_idx % self.size);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        let head = elem.id as u16;
+        let mut current = head;
+        loop {
+            let desc = self.desc(current);
+            self.num_free += 1;
+            let flags = unsafe { (*desc).flags };
+            if flags & DESC_F_NEXT == 0 {
+                unsafe { (*desc).next = self.free_head };
+                break;
+            }
+            current = unsafe { (*desc).next };
+        }
+        self.free_head = head;
+
+        Some((head, elem.len))
+    }
+
+    /// Submit a request and park until the device has completed it
+    ///
+    /// `waiters` must be woken from the device's interrupt handler; without
+    /// an interrupt line the queue is polled.
+    pub fn submit_and_wait(
+        &mut self,
+        transport: &dyn Transport,
+        waiters: &WaitQueue,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<u32> {
+        let token = unsafe { self.add(inputs, outputs)? };
+        transport.notify(self.index);
+
+        loop {
+            if transport.irq().is_some() {
+                waiters.wait_until(|| self.can_pop());
+            } else {
+                while !self.can_pop() {
+                    sched::yield_now();
+                }
+            }
+            if let Some((used, len)) = self.pop_used() {
+                if used == token {
+                    return Ok(len);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! virtio-rng driver
+//!
+//! The device has one queue; every buffer handed to it comes back filled
+//! with random bytes from the host. They go into the kernel entropy pool at
+//! boot and again every [`REFILL_INTERVAL`], credited in full since the host
+//! is trusted anyway. QEMU adds the device with `-device virtio-rng-pci`.
+
+use super::queue::VirtQueue;
+use super::{DeviceType, Transport};
+use crate::kernel::random;
+use crate::kernel::timer::Timer;
+use crate::sync::{Mutex, WaitQueue};
+use alloc::sync::Arc;
+use core::time::Duration;
+
+/// Tasks waiting for a request of any device
+static REQUEST_WAITERS: WaitQueue = WaitQueue::new();
+
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 8;
+
+/// Bytes taken per request, enough to reseed the generator
+const REQUEST_LEN: usize = 64;
+const REFILL_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct VirtIORng {
+    transport: Arc<dyn Transport>,
+    queue: Mutex<VirtQueue>,
+}
+
+impl VirtIORng {
+    fn new(transport: Arc<dyn Transport>) -> super::Result<Self> {
+        transport.begin_init(0)?;
+        let queue = VirtQueue::new(&*transport, REQUEST_QUEUE, QUEUE_SIZE)?;
+        super::attach_irq(&transport, || REQUEST_WAITERS.wake_all());
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            queue: Mutex::new(queue),
+        })
+    }
+
+    /// Fill `buffer` as far as the device wants, returning how much it did
+    pub fn read(&self, buffer: &mut [u8]) -> super::Result<usize> {
+        let mut queue = self.queue.lock();
+        let len = queue.submit_and_wait(&*self.transport, &REQUEST_WAITERS, &[], &mut [buffer])?;
+        Ok((len as usize).min(buffer.len()))
+    }
+
+    /// Feed the entropy pool
+    fn refill(&self) {
+        let mut buffer = [0; REQUEST_LEN];
+        match self.read(&mut buffer) {
+            Ok(len) => random::add_entropy("virtio-rng", &buffer[..len], len * 8),
+            Err(err) => crate::log_error!("virtio-rng: {:?}", err),
+        }
+    }
+}
+
+pub fn init() {
+    while let Some(transport) = super::take(DeviceType::Entropy) {
+        let location = transport.location();
+        match VirtIORng::new(transport) {
+            Ok(device) => {
+                crate::log_info!("virtio-rng: {}", location);
+                let device = Arc::new(device);
+                device.refill();
+                // Timer callbacks run on the workqueue, which may sleep
+                Timer::every(REFILL_INTERVAL, move || device.refill());
+            }
+            Err(err) => crate::log_error!("virtio-rng: {}: {:?}", location, err),
+        }
+    }
+}
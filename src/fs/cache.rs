@@ -0,0 +1,263 @@
+//! Block buffer cache
+//!
+//! A [`BlockCache`] sits between file systems and a disk and is itself a
+//! [`BlockDevice`], so file systems do not care whether they are cached.
+//! Blocks are kept until the cache is full, then the least recently used
+//! one is dropped. Writes only mark the cached block dirty; dirty blocks go
+//! to the disk when they are evicted, every [`WRITEBACK_INTERVAL`] and on
+//! [`sync_all`]. Statistics are shown in `/proc/blockcache`.
+
+use crate::drivers::block::{self, BlockDevice, Result};
+use crate::fs::proc;
+use crate::kernel::timer::Timer;
+use crate::sync::{Mutex, SafeMutex};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+
+/// Memory for cached blocks, per device
+const CACHE_BYTES: usize = 2 * 1024 * 1024;
+pub const WRITEBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Counters since the cache was created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    /// Blocks asked for by file systems
+    pub reads: u64,
+    pub writes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Dirty blocks written to the disk
+    pub writebacks: u64,
+    pub evictions: u64,
+}
+
+struct Entry {
+    data: Vec<u8>,
+    dirty: bool,
+    /// Value of the use counter when the block was last touched
+    last_used: u64,
+}
+
+struct Inner {
+    entries: BTreeMap<u64, Entry>,
+    /// Grows with every access, orders the entries by recency
+    clock: u64,
+    stats: Stats,
+}
+
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    /// Most blocks kept at once
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl BlockCache {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            capacity: (CACHE_BYTES / device.block_size()).max(1),
+            device,
+            inner: Mutex::new(Inner {
+                entries: BTreeMap::new(),
+                clock: 0,
+                stats: Stats::default(),
+            }),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().stats
+    }
+
+    /// Number of cached and of dirty blocks
+    pub fn usage(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        let dirty = inner.entries.values().filter(|entry| entry.dirty).count();
+        (inner.entries.len(), dirty)
+    }
+
+    /// Write every dirty block to the disk, returns how many there were
+    pub fn write_back(&self) -> Result<usize> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        let mut written = 0;
+        for (&block, entry) in inner.entries.iter_mut().filter(|(_, e)| e.dirty) {
+            self.device.write_blocks(block, &entry.data)?;
+            entry.dirty = false;
+            inner.stats.writebacks += 1;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// The cached copy of `block`, read from the disk on a miss
+    fn entry<'a>(&self, inner: &'a mut Inner, block: u64, load: bool) -> Result<&'a mut Entry> {
+        inner.clock += 1;
+        let clock = inner.clock;
+        if inner.entries.contains_key(&block) {
+            inner.stats.hits += 1;
+        } else {
+            inner.stats.misses += 1;
+            if inner.entries.len() >= self.capacity {
+                self.evict(inner)?;
+            }
+            let mut data = vec![0; self.device.block_size()];
+            if load {
+                self.device.read_blocks(block, &mut data)?;
+            }
+            inner.entries.insert(
+                block,
+                Entry {
+                    data,
+                    dirty: false,
+                    last_used: clock,
+                },
+            );
+        }
+        let entry = inner.entries.get_mut(&block).unwrap();
+        entry.last_used = clock;
+        Ok(entry)
+    }
+
+    /// Drop the least recently used block, writing it back if it is dirty
+    fn evict(&self, inner: &mut Inner) -> Result<()> {
+        let Some((&block, entry)) = inner.entries.iter().min_by_key(|(_, e)| e.last_used) else {
+            return Ok(());
+        };
+        if entry.dirty {
+            self.device.write_blocks(block, &entry.data)?;
+            inner.stats.writebacks += 1;
+        }
+        inner.entries.remove(&block);
+        inner.stats.evictions += 1;
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn model(&self) -> String {
+        self.device.model()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+        self.check_range(block, buffer.len())?;
+        let mut inner = self.inner.lock();
+        for (index, chunk) in buffer.chunks_mut(self.block_size()).enumerate() {
+            inner.stats.reads += 1;
+            let entry = self.entry(&mut inner, block + index as u64, true)?;
+            chunk.copy_from_slice(&entry.data);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, block: u64, buffer: &[u8]) -> Result<()> {
+        if self.is_read_only() {
+            return Err(block::Error::ReadOnly);
+        }
+        self.check_range(block, buffer.len())?;
+        let mut inner = self.inner.lock();
+        for (index, chunk) in buffer.chunks(self.block_size()).enumerate() {
+            inner.stats.writes += 1;
+            // The whole block is overwritten, no need to read it first
+            let entry = self.entry(&mut inner, block + index as u64, false)?;
+            entry.data.copy_from_slice(chunk);
+            entry.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.write_back()?;
+        self.device.flush()
+    }
+}
+
+static CACHES: SafeMutex<Vec<Arc<BlockCache>>> = SafeMutex::new(Vec::new());
+
+/// The cache in front of the disk called `name`, created on first use
+pub fn get(name: &str) -> Option<Arc<BlockCache>> {
+    let mut caches = CACHES.lock();
+    if let Some(cache) = caches.iter().find(|cache| cache.name() == name) {
+        return Some(cache.clone());
+    }
+    let cache = Arc::new(BlockCache::new(block::find(name)?));
+    caches.push(cache.clone());
+    Some(cache)
+}
+
+/// Write back and flush every cache, returns the number of blocks written
+pub fn sync_all() -> Result<usize> {
+    let caches = CACHES.lock().clone();
+    let mut written = 0;
+    for cache in caches {
+        written += cache.write_back()?;
+        cache.device().flush()?;
+    }
+    Ok(written)
+}
+
+fn render_stats() -> String {
+    let caches = CACHES.lock().clone();
+    let mut text =
+        String::from("device    reads   writes     hits   misses  written  evicted cached dirty\n");
+    for cache in caches {
+        let stats = cache.stats();
+        let (cached, dirty) = cache.usage();
+        let _ = writeln!(
+            text,
+            "{:<6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6} {:>5}",
+            cache.name(),
+            stats.reads,
+            stats.writes,
+            stats.hits,
+            stats.misses,
+            stats.writebacks,
+            stats.evictions,
+            cached,
+            dirty
+        );
+    }
+    text
+}
+
+/// Start periodic write-back and publish `/proc/blockcache`
+pub fn init() {
+    for device in block::devices() {
+        get(device.name());
+    }
+    proc::register("blockcache", render_stats);
+    // The timer runs on the workqueue, where waiting for the disk is fine
+    Timer::every(WRITEBACK_INTERVAL, || {
+        let caches = CACHES.lock().clone();
+        for cache in caches {
+            if let Err(err) = cache.write_back() {
+                crate::log_warn!("cache: {}: write-back failed: {:?}", cache.name(), err);
+            }
+        }
+    });
+}
@@ -0,0 +1,37 @@
+//! Character devices shown under `/dev`
+//!
+//! Like [`super::proc`], subsystems register a name and a function; reading
+//! the file fills a buffer from the device.
+
+use crate::sync::SafeMutex;
+use alloc::vec::Vec;
+
+/// Fill the buffer, returning how many bytes were read
+type Read = fn(&mut [u8]) -> usize;
+
+static DEVICES: SafeMutex<Vec<(&'static str, Read)>> = SafeMutex::new(Vec::new());
+
+/// Publish `/dev/<name>`, a later registration replaces an earlier one
+pub fn register(name: &'static str, read: Read) {
+    let mut devices = DEVICES.lock();
+    devices.retain(|(existing, _)| *existing != name);
+    devices.push((name, read));
+}
+
+/// Names of all devices, sorted
+pub fn names() -> Vec<&'static str> {
+    let mut names: Vec<_> = DEVICES.lock().iter().map(|(name, _)| *name).collect();
+    names.sort_unstable();
+    names
+}
+
+/// Read from `/dev/<name>` into `buffer`
+pub fn read(name: &str, buffer: &mut [u8]) -> Option<usize> {
+    // Read without the lock, the device may block
+    let read = DEVICES
+        .lock()
+        .iter()
+        .find(|(existing, _)| *existing == name)
+        .map(|(_, read)| *read)?;
+    Some(read(buffer))
+}
@@ -0,0 +1,591 @@
+//! ext2 file system, read-only for now
+//!
+//! The superblock sits 1024 bytes into the volume, the block group
+//! descriptors follow in the next block. Each group has a block bitmap, an
+//! inode bitmap and a slice of the inode table. An inode maps file blocks
+//! through 12 direct pointers and single, double and triple indirect blocks;
+//! a pointer of 0 is a hole that reads as zeros. Directories are files of
+//! variable length entries. Short symbolic links keep their target in the
+//! block pointers.
+//!
+//! Inodes and group descriptors are decoded into structures that encode
+//! back to the same bytes, which is what writing will build on. Images come
+//! from `mke2fs -t ext2 -d <dir> disk.img`.
+
+use super::{DirEntry, Error, FileType, Result, Volume};
+use crate::drivers::block::BlockDevice;
+use crate::kernel::time::SystemTime;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+
+const ROOT_INODE: u32 = 2;
+/// Inode size of revision 0 file systems
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+const GROUP_DESC_SIZE: usize = 32;
+
+// Features, a volume with unknown incompatible ones must not be read
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+// Inode mode
+const S_IFMT: u16 = 0xf000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xa000;
+const PERMISSION_MASK: u16 = 0o7777;
+
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT: usize = 12;
+const BLOCK_POINTERS: usize = 15;
+/// Targets shorter than this are stored in the block pointers
+const FAST_SYMLINK_MAX: u64 = 60;
+/// Links followed while resolving one path
+const MAX_SYMLINKS: usize = 8;
+/// Directory entry header: inode, record length, name length, type
+const DIR_ENTRY_HEADER: usize = 8;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The superblock fields the driver uses
+#[derive(Debug, Clone)]
+struct Superblock {
+    inodes_count: u32,
+    blocks_count: u32,
+    free_blocks: u32,
+    #[allow(dead_code, reason = "nothing allocates inodes yet")]
+    free_inodes: u32,
+    first_data_block: u32,
+    block_size: usize,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u16,
+    feature_incompat: u32,
+    feature_ro_compat: u32,
+    label: String,
+}
+
+impl Superblock {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if read_u16(bytes, 56) != MAGIC {
+            return Err(Error::UnknownFileSystem);
+        }
+        let log_block_size = read_u32(bytes, 24);
+        let revision = read_u32(bytes, 76);
+        let (inode_size, feature_incompat, feature_ro_compat) = if revision == 0 {
+            (GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (
+                read_u16(bytes, 88),
+                read_u32(bytes, 96),
+                read_u32(bytes, 100),
+            )
+        };
+        let label = &bytes[120..136];
+        let label_len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+
+        let superblock = Self {
+            inodes_count: read_u32(bytes, 0),
+            blocks_count: read_u32(bytes, 4),
+            free_blocks: read_u32(bytes, 12),
+            free_inodes: read_u32(bytes, 16),
+            first_data_block: read_u32(bytes, 20),
+            block_size: 1024usize.checked_shl(log_block_size).unwrap_or(0),
+            blocks_per_group: read_u32(bytes, 32),
+            inodes_per_group: read_u32(bytes, 40),
+            inode_size,
+            feature_incompat,
+            feature_ro_compat,
+            label: String::from_utf8_lossy(&label[..label_len]).into_owned(),
+        };
+        // ext3 journals are compatible, ext4 extents and 64-bit are not
+        if superblock.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(Error::Unsupported);
+        }
+        let valid = (1024..=65536).contains(&superblock.block_size)
+            && superblock.blocks_count > superblock.first_data_block
+            && superblock.blocks_per_group > 0
+            && superblock.inodes_per_group > 0
+            && superblock.inode_size >= GOOD_OLD_INODE_SIZE
+            && superblock.inode_size.is_power_of_two();
+        if !valid {
+            return Err(Error::Corrupt);
+        }
+        Ok(superblock)
+    }
+
+    fn group_count(&self) -> usize {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group) as usize
+    }
+}
+
+/// One entry of the block group descriptor table
+#[derive(Debug, Clone, Copy)]
+struct GroupDesc {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+impl GroupDesc {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            block_bitmap: read_u32(bytes, 0),
+            inode_bitmap: read_u32(bytes, 4),
+            inode_table: read_u32(bytes, 8),
+            free_blocks: read_u16(bytes, 12),
+            free_inodes: read_u16(bytes, 14),
+            used_dirs: read_u16(bytes, 16),
+        }
+    }
+
+    /// Store the fields back into an on-disk descriptor
+    fn encode(&self, bytes: &mut [u8]) {
+        write_u32(bytes, 0, self.block_bitmap);
+        write_u32(bytes, 4, self.inode_bitmap);
+        write_u32(bytes, 8, self.inode_table);
+        write_u16(bytes, 12, self.free_blocks);
+        write_u16(bytes, 14, self.free_inodes);
+        write_u16(bytes, 16, self.used_dirs);
+    }
+}
+
+/// The inode fields the driver uses, the rest is left alone on writes
+#[derive(Debug, Clone)]
+struct Inode {
+    mode: u16,
+    uid: u16,
+    gid: u16,
+    size: u64,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    links: u16,
+    /// Allocated space in 512-byte units
+    sectors: u32,
+    flags: u32,
+    block: [u32; BLOCK_POINTERS],
+}
+
+impl Inode {
+    fn parse(bytes: &[u8], large_files: bool) -> Self {
+        let mode = read_u16(bytes, 0);
+        let mut size = read_u32(bytes, 4) as u64;
+        // The high half shares its field with the directory ACL
+        if large_files && mode & S_IFMT == S_IFREG {
+            size |= (read_u32(bytes, 108) as u64) << 32;
+        }
+        let mut block = [0; BLOCK_POINTERS];
+        for (index, pointer) in block.iter_mut().enumerate() {
+            *pointer = read_u32(bytes, 40 + index * 4);
+        }
+        Self {
+            mode,
+            uid: read_u16(bytes, 2),
+            gid: read_u16(bytes, 24),
+            size,
+            atime: read_u32(bytes, 8),
+            ctime: read_u32(bytes, 12),
+            mtime: read_u32(bytes, 16),
+            links: read_u16(bytes, 26),
+            sectors: read_u32(bytes, 28),
+            flags: read_u32(bytes, 32),
+            block,
+        }
+    }
+
+    /// Store the fields back into an on-disk inode
+    fn encode(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 0, self.mode);
+        write_u16(bytes, 2, self.uid);
+        write_u32(bytes, 4, self.size as u32);
+        write_u32(bytes, 8, self.atime);
+        write_u32(bytes, 12, self.ctime);
+        write_u32(bytes, 16, self.mtime);
+        write_u16(bytes, 24, self.gid);
+        write_u16(bytes, 26, self.links);
+        write_u32(bytes, 28, self.sectors);
+        write_u32(bytes, 32, self.flags);
+        for (index, pointer) in self.block.iter().enumerate() {
+            write_u32(bytes, 40 + index * 4, *pointer);
+        }
+        if self.file_type() == FileType::File {
+            write_u32(bytes, 108, (self.size >> 32) as u32);
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+
+    fn permissions(&self) -> u16 {
+        self.mode & PERMISSION_MASK
+    }
+
+    /// Short links live in the block pointers and own no blocks
+    fn is_fast_symlink(&self) -> bool {
+        self.file_type() == FileType::Symlink && self.size < FAST_SYMLINK_MAX && self.sectors == 0
+    }
+
+    fn to_entry(&self, name: String) -> DirEntry {
+        DirEntry {
+            name,
+            file_type: self.file_type(),
+            size: self.size,
+            permissions: self.permissions(),
+            modified: (self.mtime != 0).then(|| SystemTime::from_unix_secs(self.mtime as u64)),
+        }
+    }
+}
+
+pub struct Ext2Volume {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    groups: Vec<GroupDesc>,
+}
+
+impl Ext2Volume {
+    /// Check for an ext2 superblock and read the group descriptors
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let mut bytes = vec![0; SUPERBLOCK_SIZE];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut bytes)?;
+        let superblock = Superblock::parse(&bytes)?;
+
+        let count = superblock.group_count();
+        let mut table = vec![0; count * GROUP_DESC_SIZE];
+        let table_block = superblock.first_data_block as u64 + 1;
+        device.read_bytes(table_block * superblock.block_size as u64, &mut table)?;
+        let groups = table
+            .as_chunks::<GROUP_DESC_SIZE>()
+            .0
+            .iter()
+            .map(|bytes| GroupDesc::parse(bytes))
+            .collect();
+
+        crate::log_info!(
+            "ext2: {} blocks of {} bytes in {} groups, {} free, label \"{}\"",
+            superblock.blocks_count,
+            superblock.block_size,
+            count,
+            superblock.free_blocks,
+            superblock.label
+        );
+        Ok(Self {
+            device,
+            superblock,
+            groups,
+        })
+    }
+
+    fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        Ok(self.device.read_bytes(offset, buffer)?)
+    }
+
+    /// Byte offset of inode `number` in the inode table
+    fn inode_offset(&self, number: u32) -> Result<u64> {
+        if number == 0 || number > self.superblock.inodes_count {
+            return Err(Error::Corrupt);
+        }
+        let index = number - 1;
+        let group = self
+            .groups
+            .get((index / self.superblock.inodes_per_group) as usize)
+            .ok_or(Error::Corrupt)?;
+        let slot = (index % self.superblock.inodes_per_group) as u64;
+        Ok(group.inode_table as u64 * self.block_size() as u64
+            + slot * self.superblock.inode_size as u64)
+    }
+
+    fn read_inode(&self, number: u32) -> Result<Inode> {
+        let mut bytes = vec![0; self.superblock.inode_size as usize];
+        self.read_bytes(self.inode_offset(number)?, &mut bytes)?;
+        let large_files = self.superblock.feature_ro_compat & RO_COMPAT_LARGE_FILE != 0;
+        Ok(Inode::parse(&bytes, large_files))
+    }
+
+    /// Store an inode, keeping the fields the driver does not know about
+    #[allow(dead_code, reason = "the encode side is kept for write support")]
+    fn write_inode(&self, number: u32, inode: &Inode) -> Result<()> {
+        let offset = self.inode_offset(number)?;
+        let mut bytes = vec![0; self.superblock.inode_size as usize];
+        self.read_bytes(offset, &mut bytes)?;
+        inode.encode(&mut bytes);
+        Ok(self.device.write_bytes(offset, &bytes)?)
+    }
+
+    /// Store group descriptor `index` in the primary descriptor table
+    #[allow(dead_code, reason = "the encode side is kept for write support")]
+    fn write_group(&self, index: usize) -> Result<()> {
+        let table = (self.superblock.first_data_block as u64 + 1) * self.block_size() as u64;
+        let offset = table + (index * GROUP_DESC_SIZE) as u64;
+        let mut bytes = [0; GROUP_DESC_SIZE];
+        self.read_bytes(offset, &mut bytes)?;
+        self.groups[index].encode(&mut bytes);
+        Ok(self.device.write_bytes(offset, &bytes)?)
+    }
+
+    /// Disk block holding block `index` of the inode's data, 0 for a hole
+    fn map_block(&self, inode: &Inode, index: u64) -> Result<u32> {
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok(inode.block[index as usize]);
+        }
+        let per_block = (self.block_size() / 4) as u64;
+        let mut index = index - DIRECT_BLOCKS as u64;
+        let mut span = per_block;
+        for depth in 1..=3u32 {
+            if index < span {
+                return self.walk_indirect(
+                    inode.block[INDIRECT + depth as usize - 1],
+                    index,
+                    depth,
+                );
+            }
+            index -= span;
+            span *= per_block;
+        }
+        Err(Error::Corrupt)
+    }
+
+    /// Follow `depth` levels of indirect blocks starting at `block`
+    fn walk_indirect(&self, mut block: u32, index: u64, depth: u32) -> Result<u32> {
+        let per_block = (self.block_size() / 4) as u64;
+        for level in (0..depth).rev() {
+            if block == 0 {
+                return Ok(0);
+            }
+            if block >= self.superblock.blocks_count {
+                return Err(Error::Corrupt);
+            }
+            let slot = (index / per_block.pow(level)) % per_block;
+            let mut pointer = [0; 4];
+            self.read_bytes(
+                block as u64 * self.block_size() as u64 + slot * 4,
+                &mut pointer,
+            )?;
+            block = u32::from_le_bytes(pointer);
+        }
+        Ok(block)
+    }
+
+    /// Read file data at `offset`, returns the number of bytes read
+    fn read_data(&self, inode: &Inode, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let len = inode.size.saturating_sub(offset).min(buffer.len() as u64) as usize;
+        let block_size = self.block_size() as u64;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let start = (position % block_size) as usize;
+            let run = (block_size as usize - start).min(len - done);
+            let chunk = &mut buffer[done..done + run];
+            match self.map_block(inode, position / block_size)? {
+                0 => chunk.fill(0),
+                block => self.read_bytes(block as u64 * block_size + start as u64, chunk)?,
+            }
+            done += run;
+        }
+        Ok(len)
+    }
+
+    /// Entries of a directory inode as (name, inode number), with `.` and `..`
+    fn entries(&self, directory: &Inode) -> Result<Vec<(String, u32)>> {
+        if directory.file_type() != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        let mut data = vec![0; directory.size as usize];
+        self.read_data(directory, 0, &mut data)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + DIR_ENTRY_HEADER <= data.len() {
+            let inode = read_u32(&data, offset);
+            let record_len = read_u16(&data, offset + 4) as usize;
+            let name_len = data[offset + 6] as usize;
+            if record_len < DIR_ENTRY_HEADER
+                || offset + record_len > data.len()
+                || DIR_ENTRY_HEADER + name_len > record_len
+            {
+                return Err(Error::Corrupt);
+            }
+            if inode != 0 {
+                let name = &data[offset + DIR_ENTRY_HEADER..][..name_len];
+                entries.push((String::from_utf8_lossy(name).into_owned(), inode));
+            }
+            offset += record_len;
+        }
+        Ok(entries)
+    }
+
+    fn symlink_target(&self, inode: &Inode) -> Result<String> {
+        let mut target = vec![0; inode.size as usize];
+        if inode.is_fast_symlink() {
+            for (index, byte) in target.iter_mut().enumerate() {
+                *byte = inode.block[index / 4].to_le_bytes()[index % 4];
+            }
+        } else {
+            self.read_data(inode, 0, &mut target)?;
+        }
+        Ok(String::from_utf8_lossy(&target).into_owned())
+    }
+
+    /// Inode number of `path`, following symbolic links on the way and,
+    /// with `follow`, the one the path ends in
+    fn lookup(&self, path: &str, follow: bool) -> Result<u32> {
+        let mut parts: VecDeque<String> = split(path).collect();
+        // Directories from the root down to the current one
+        let mut stack = vec![ROOT_INODE];
+        let mut links = 0;
+
+        while let Some(part) = parts.pop_front() {
+            let directory = *stack.last().unwrap();
+            match part.as_str() {
+                "." => continue,
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let entries = self.entries(&self.read_inode(directory)?)?;
+            let number = entries
+                .iter()
+                .find(|(name, _)| *name == part)
+                .map(|(_, number)| *number)
+                .ok_or(Error::NotFound)?;
+            let inode = self.read_inode(number)?;
+
+            if inode.file_type() == FileType::Symlink && (follow || !parts.is_empty()) {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(Error::NotFound);
+                }
+                let target = self.symlink_target(&inode)?;
+                // Absolute targets start at the root of this volume
+                if target.starts_with('/') {
+                    stack.truncate(1);
+                }
+                for (index, part) in split(&target).enumerate() {
+                    parts.insert(index, part);
+                }
+                continue;
+            }
+            stack.push(number);
+        }
+        Ok(*stack.last().unwrap())
+    }
+}
+
+/// Components of a path
+fn split(path: &str) -> impl Iterator<Item = String> + '_ {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .map(String::from)
+}
+
+impl Volume for Ext2Volume {
+    fn fs_type(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn label(&self) -> String {
+        self.superblock.label.clone()
+    }
+
+    fn stat(&self, path: &str) -> Result<DirEntry> {
+        let number = self.lookup(path, true)?;
+        let name = split(path).last().unwrap_or_else(|| String::from("/"));
+        Ok(self.read_inode(number)?.to_entry(name))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let directory = self.read_inode(self.lookup(path, true)?)?;
+        let mut entries = Vec::new();
+        for (name, number) in self.entries(&directory)? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            entries.push(self.read_inode(number)?.to_entry(name));
+        }
+        Ok(entries)
+    }
+
+    fn read_link(&self, path: &str) -> Result<String> {
+        let inode = self.read_inode(self.lookup(path, false)?)?;
+        if inode.file_type() != FileType::Symlink {
+            return Err(Error::InvalidName);
+        }
+        self.symlink_target(&inode)
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let inode = self.read_inode(self.lookup(path, true)?)?;
+        if inode.file_type() == FileType::Directory {
+            return Err(Error::IsADirectory);
+        }
+        self.read_data(&inode, offset, buffer)
+    }
+
+    // Writing needs block and inode allocation from the bitmaps
+
+    fn write(&self, _path: &str, _offset: u64, _data: &[u8]) -> Result<usize> {
+        Err(Error::ReadOnly)
+    }
+
+    fn truncate(&self, _path: &str, _size: u64) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn create_file(&self, _path: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn remove(&self, _path: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,1064 @@
+//! FAT32 file system
+//!
+//! The volume starts with the boot sector and its BIOS parameter block,
+//! followed by the reserved sectors (FSInfo among them), the copies of the
+//! FAT and the data clusters. Files and directories, the root included, are
+//! chains of clusters linked through the FAT. Names that do not fit 8.3 get
+//! a generated short name and long file name entries in front of it.
+//!
+//! Images are made on the host with `mkfs.vfat -F 32` and filled with
+//! `mcopy`. Entries are stamped with the wall-clock time, taken as local
+//! time the way FAT expects; without an RTC that is 1980-01-01.
+
+use super::{DirEntry, Error, FileType, Result, Volume};
+use crate::drivers::block::BlockDevice;
+use crate::kernel::time::{DateTime, SystemTime};
+use crate::sync::Mutex;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// Fewer clusters than this make a FAT12 or FAT16 volume
+const MIN_CLUSTERS: u32 = 65525;
+
+// FSInfo sector
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+// FAT entries, only the low 28 bits count
+const FAT_MASK: u32 = 0x0fff_ffff;
+const FAT_FREE: u32 = 0;
+const FAT_BAD: u32 = 0x0fff_fff7;
+const FAT_END: u32 = 0x0fff_ffff;
+/// Values from here on end a chain
+const FAT_END_MIN: u32 = 0x0fff_fff8;
+/// Clusters 0 and 1 are reserved, data starts at 2
+const FIRST_CLUSTER: u32 = 2;
+
+// Directory entries
+const ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xe5;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// Case flags in the reserved byte, set by Windows for all lower case parts
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Byte offsets of the 13 UCS-2 characters in a long name entry
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME: usize = 255;
+
+/// 1980-01-01 00:00, the earliest date FAT can store, used for times before it
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+const DEFAULT_TIME: u16 = 0;
+const FAT_EPOCH_YEAR: u32 = 1980;
+
+/// Date and time fields for `time`, local time is taken to be UTC
+fn fat_timestamp(time: SystemTime) -> (u16, u16) {
+    let time = DateTime::from_system_time(time);
+    if !(FAT_EPOCH_YEAR..FAT_EPOCH_YEAR + 128).contains(&time.year) {
+        return (DEFAULT_DATE, DEFAULT_TIME);
+    }
+    let date =
+        ((time.year - FAT_EPOCH_YEAR) as u16) << 9 | (time.month as u16) << 5 | time.day as u16;
+    // Two-second resolution
+    let clock = (time.hour as u16) << 11 | (time.minute as u16) << 5 | (time.second as u16 / 2);
+    (date, clock)
+}
+
+/// The other way round, `None` for fields that make no sense
+fn from_fat_timestamp(date: u16, clock: u16) -> Option<SystemTime> {
+    DateTime {
+        year: FAT_EPOCH_YEAR + (date >> 9) as u32,
+        month: ((date >> 5) & 0xf) as u8,
+        day: (date & 0x1f) as u8,
+        hour: (clock >> 11) as u8,
+        minute: ((clock >> 5) & 0x3f) as u8,
+        second: (clock & 0x1f) as u8 * 2,
+    }
+    .to_system_time()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Volume layout from the boot sector
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    bytes_per_sector: u32,
+    sectors_per_cluster: u32,
+    fat_start: u64,
+    fat_size: u64,
+    fat_count: u32,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// Byte offset of the FSInfo sector, if there is one
+    fsinfo: Option<u64>,
+}
+
+impl Layout {
+    fn parse(boot: &[u8]) -> Result<Self> {
+        if boot[510..512] != BOOT_SIGNATURE {
+            return Err(Error::UnknownFileSystem);
+        }
+        let bytes_per_sector = read_u16(boot, 11) as u32;
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = read_u16(boot, 14) as u64;
+        let fat_count = boot[16] as u32;
+        let root_entries = read_u16(boot, 17);
+        let total_sectors = match read_u16(boot, 19) {
+            0 => read_u32(boot, 32) as u64,
+            sectors => sectors as u64,
+        };
+        let fat_sectors = read_u32(boot, 36) as u64;
+        let root_cluster = read_u32(boot, 44);
+        let fsinfo_sector = read_u16(boot, 48) as u64;
+
+        let valid = bytes_per_sector.is_power_of_two()
+            && (512..=4096).contains(&bytes_per_sector)
+            && sectors_per_cluster.is_power_of_two()
+            && fat_count > 0
+            && reserved > 0;
+        // FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size
+        if !valid || root_entries != 0 || read_u16(boot, 22) != 0 || fat_sectors == 0 {
+            return Err(Error::UnknownFileSystem);
+        }
+
+        let sector = bytes_per_sector as u64;
+        let data_sectors = total_sectors
+            .checked_sub(reserved + fat_count as u64 * fat_sectors)
+            .ok_or(Error::Corrupt)?;
+        let cluster_count = (data_sectors / sectors_per_cluster as u64) as u32;
+        if cluster_count < MIN_CLUSTERS {
+            return Err(Error::UnknownFileSystem);
+        }
+        let layout = Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_start: reserved * sector,
+            fat_size: fat_sectors * sector,
+            fat_count,
+            data_start: (reserved + fat_count as u64 * fat_sectors) * sector,
+            cluster_count,
+            root_cluster,
+            fsinfo: match fsinfo_sector {
+                0 | 0xffff => None,
+                sector_number => Some(sector_number * sector),
+            },
+        };
+        // The FAT must have an entry for every cluster
+        if (layout.max_cluster() as u64 + 1) * 4 > layout.fat_size
+            || !layout.is_data_cluster(root_cluster)
+        {
+            return Err(Error::Corrupt);
+        }
+        Ok(layout)
+    }
+
+    fn cluster_size(&self) -> usize {
+        (self.bytes_per_sector * self.sectors_per_cluster) as usize
+    }
+
+    fn max_cluster(&self) -> u32 {
+        self.cluster_count + FIRST_CLUSTER - 1
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..=self.max_cluster()).contains(&cluster)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.cluster_size() as u64
+    }
+}
+
+/// A name as stored in a short entry: 8 characters, 3 of extension, space padded
+type ShortName = [u8; 11];
+
+/// A file or directory found in a directory
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    short_name: ShortName,
+    attributes: u8,
+    cluster: u32,
+    size: u32,
+    /// First cluster of the directory holding the entry
+    parent: u32,
+    /// Slot of the first long name entry, equal to `slot` without one
+    first_slot: usize,
+    /// Slot of the short entry
+    slot: usize,
+    /// Write date and time
+    modified: (u16, u16),
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    fn to_entry(&self) -> DirEntry {
+        DirEntry {
+            name: self.name.clone(),
+            file_type: if self.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::File
+            },
+            size: self.size as u64,
+            permissions: if self.is_dir() {
+                0o755
+            } else if self.attributes & ATTR_READ_ONLY != 0 {
+                0o444
+            } else {
+                0o644
+            },
+            modified: from_fat_timestamp(self.modified.0, self.modified.1),
+        }
+    }
+}
+
+/// What a path names: the root directory or an entry
+enum Target {
+    Root,
+    Node(Node),
+}
+
+/// Allocation hints, also stored in the FSInfo sector
+struct State {
+    free_count: u32,
+    next_free: u32,
+}
+
+pub struct FatVolume {
+    device: Arc<dyn BlockDevice>,
+    layout: Layout,
+    label: String,
+    /// Held for every operation, so changes never interleave
+    state: Mutex<State>,
+}
+
+impl FatVolume {
+    /// Check for a FAT32 boot sector and read the volume layout
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let mut boot = vec![0; device.block_size().max(512)];
+        device.read_blocks(0, &mut boot)?;
+        let layout = Layout::parse(&boot)?;
+        let label = String::from_utf8_lossy(&boot[71..82]).trim_end().into();
+
+        let mut volume = Self {
+            device,
+            layout,
+            label,
+            state: Mutex::new(State {
+                free_count: FSINFO_UNKNOWN,
+                next_free: FIRST_CLUSTER,
+            }),
+        };
+        let (free_count, next_free) = volume.read_fsinfo()?;
+        volume.state = Mutex::new(State {
+            free_count,
+            next_free: if layout.is_data_cluster(next_free) {
+                next_free
+            } else {
+                FIRST_CLUSTER
+            },
+        });
+        crate::log_info!(
+            "fat32: {} clusters of {} bytes, label \"{}\"",
+            volume.layout.cluster_count,
+            volume.layout.cluster_size(),
+            volume.label
+        );
+        Ok(volume)
+    }
+
+    // Byte-level access to the disk
+
+    fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        Ok(self.device.read_bytes(offset, buffer)?)
+    }
+
+    fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<()> {
+        Ok(self.device.write_bytes(offset, data)?)
+    }
+
+    // FSInfo
+
+    /// Free cluster count and allocation hint, unknown if there is no FSInfo
+    fn read_fsinfo(&self) -> Result<(u32, u32)> {
+        let Some(offset) = self.layout.fsinfo else {
+            return Ok((FSINFO_UNKNOWN, FSINFO_UNKNOWN));
+        };
+        let mut sector = [0; 512];
+        self.read_bytes(offset, &mut sector)?;
+        if read_u32(&sector, 0) != FSINFO_LEAD_SIGNATURE
+            || read_u32(&sector, 484) != FSINFO_STRUCT_SIGNATURE
+        {
+            return Ok((FSINFO_UNKNOWN, FSINFO_UNKNOWN));
+        }
+        Ok((
+            read_u32(&sector, FSINFO_FREE_COUNT),
+            read_u32(&sector, FSINFO_NEXT_FREE),
+        ))
+    }
+
+    fn write_fsinfo(&self, state: &State) -> Result<()> {
+        let Some(offset) = self.layout.fsinfo else {
+            return Ok(());
+        };
+        let mut fields = [0; 8];
+        write_u32(&mut fields, 0, state.free_count);
+        write_u32(&mut fields, 4, state.next_free);
+        self.write_bytes(offset + FSINFO_FREE_COUNT as u64, &fields)
+    }
+
+    // The FAT
+
+    fn read_fat(&self, cluster: u32) -> Result<u32> {
+        let mut entry = [0; 4];
+        self.read_bytes(self.layout.fat_start + cluster as u64 * 4, &mut entry)?;
+        Ok(u32::from_le_bytes(entry) & FAT_MASK)
+    }
+
+    /// Set an entry in every copy of the FAT, keeping the reserved top bits
+    fn write_fat(&self, cluster: u32, value: u32) -> Result<()> {
+        for copy in 0..self.layout.fat_count as u64 {
+            let offset = self.layout.fat_start + copy * self.layout.fat_size + cluster as u64 * 4;
+            let mut entry = [0; 4];
+            self.read_bytes(offset, &mut entry)?;
+            let value = (u32::from_le_bytes(entry) & !FAT_MASK) | (value & FAT_MASK);
+            self.write_bytes(offset, &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Clusters of the chain starting at `start`, empty for cluster 0
+    fn chain(&self, start: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        while cluster != FAT_FREE {
+            if !self.layout.is_data_cluster(cluster)
+                || chain.len() > self.layout.cluster_count as usize
+            {
+                return Err(Error::Corrupt);
+            }
+            chain.push(cluster);
+            cluster = match self.read_fat(cluster)? {
+                next if next >= FAT_END_MIN => FAT_FREE,
+                FAT_FREE | FAT_BAD => return Err(Error::Corrupt),
+                next => next,
+            };
+        }
+        Ok(chain)
+    }
+
+    /// Take a free cluster, zero it and append it to the chain ending in `last`
+    fn allocate(&self, state: &mut State, last: Option<u32>) -> Result<u32> {
+        let cluster = self.find_free(state.next_free)?.ok_or(Error::NoSpace)?;
+        self.write_fat(cluster, FAT_END)?;
+        if let Some(last) = last {
+            self.write_fat(last, cluster)?;
+        }
+        let zeros = vec![0; self.layout.cluster_size()];
+        self.write_bytes(self.layout.cluster_offset(cluster), &zeros)?;
+
+        if state.free_count != FSINFO_UNKNOWN {
+            state.free_count = state.free_count.saturating_sub(1);
+        }
+        state.next_free = if cluster < self.layout.max_cluster() {
+            cluster + 1
+        } else {
+            FIRST_CLUSTER
+        };
+        self.write_fsinfo(state)?;
+        Ok(cluster)
+    }
+
+    /// First free cluster at or after `hint`, wrapping around once
+    fn find_free(&self, hint: u32) -> Result<Option<u32>> {
+        let entries_per_block = self.device.block_size() / 4;
+        let mut block = vec![0; self.device.block_size()];
+        let ranges = [
+            (hint, self.layout.max_cluster()),
+            (FIRST_CLUSTER, hint.saturating_sub(1)),
+        ];
+        for (first, last) in ranges {
+            let mut cluster = first;
+            while cluster <= last {
+                // Scan the FAT a block at a time
+                let offset = self.layout.fat_start + cluster as u64 * 4;
+                self.read_bytes(offset - offset % block.len() as u64, &mut block)?;
+                let index = (offset % block.len() as u64) as usize / 4;
+                for (i, entry) in block.as_chunks::<4>().0.iter().enumerate().skip(index) {
+                    let candidate = cluster + (i - index) as u32;
+                    if candidate > last {
+                        break;
+                    }
+                    if read_u32(entry, 0) & FAT_MASK == FAT_FREE {
+                        return Ok(Some(candidate));
+                    }
+                }
+                cluster += (entries_per_block - index) as u32;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Give back the clusters from `start` to the end of its chain
+    fn free_chain(&self, state: &mut State, start: u32) -> Result<()> {
+        let chain = self.chain(start)?;
+        for &cluster in &chain {
+            self.write_fat(cluster, FAT_FREE)?;
+        }
+        if state.free_count != FSINFO_UNKNOWN {
+            state.free_count += chain.len() as u32;
+        }
+        if let Some(&first) = chain.iter().min() {
+            state.next_free = state.next_free.min(first);
+        }
+        self.write_fsinfo(state)
+    }
+
+    // File contents
+
+    /// Read or write `len` bytes at `offset` of the file in `chain`
+    fn for_each_run(
+        &self,
+        chain: &[u32],
+        offset: u64,
+        len: usize,
+        mut f: impl FnMut(u64, usize, usize) -> Result<()>,
+    ) -> Result<()> {
+        let cluster_size = self.layout.cluster_size() as u64;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let cluster = *chain
+                .get((position / cluster_size) as usize)
+                .ok_or(Error::Corrupt)?;
+            let start = position % cluster_size;
+            let run = ((cluster_size - start) as usize).min(len - done);
+            f(self.layout.cluster_offset(cluster) + start, done, run)?;
+            done += run;
+        }
+        Ok(())
+    }
+
+    /// Grow the chain to `clusters` clusters
+    fn extend_chain(&self, state: &mut State, chain: &mut Vec<u32>, clusters: usize) -> Result<()> {
+        while chain.len() < clusters {
+            let cluster = self.allocate(state, chain.last().copied())?;
+            chain.push(cluster);
+        }
+        Ok(())
+    }
+
+    // Directories
+
+    /// The raw entries of a directory and the clusters holding them
+    fn read_dir_raw(&self, cluster: u32) -> Result<(Vec<u32>, Vec<u8>)> {
+        let chain = self.chain(cluster)?;
+        let cluster_size = self.layout.cluster_size();
+        let mut bytes = vec![0; chain.len() * cluster_size];
+        for (index, &cluster) in chain.iter().enumerate() {
+            let offset = self.layout.cluster_offset(cluster);
+            self.read_bytes(offset, &mut bytes[index * cluster_size..][..cluster_size])?;
+        }
+        Ok((chain, bytes))
+    }
+
+    fn slot_offset(&self, chain: &[u32], slot: usize) -> u64 {
+        let per_cluster = self.layout.cluster_size() / ENTRY_SIZE;
+        self.layout.cluster_offset(chain[slot / per_cluster])
+            + ((slot % per_cluster) * ENTRY_SIZE) as u64
+    }
+
+    /// Entries of the directory at `cluster`, with `.` and `..`
+    fn nodes(&self, cluster: u32) -> Result<Vec<Node>> {
+        let (_, bytes) = self.read_dir_raw(cluster)?;
+        let mut nodes = Vec::new();
+        let mut long_name: Vec<u16> = Vec::new();
+        let mut long_start = None;
+        let mut checksum = 0;
+
+        for (slot, entry) in bytes.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+            match entry[0] {
+                ENTRY_END => break,
+                ENTRY_DELETED => {
+                    long_start = None;
+                    continue;
+                }
+                _ => {}
+            }
+            if entry[11] & 0x3f == ATTR_LONG_NAME {
+                let order = entry[0];
+                if order & LFN_LAST != 0 {
+                    let count = (order & 0x1f) as usize;
+                    long_name = vec![0xffff; count * LFN_CHARS];
+                    long_start = Some(slot);
+                    checksum = entry[13];
+                }
+                let index = (order & 0x1f) as usize;
+                if long_start.is_none() || index == 0 || index * LFN_CHARS > long_name.len() {
+                    long_start = None;
+                    continue;
+                }
+                for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+                    long_name[(index - 1) * LFN_CHARS + i] = read_u16(entry, offset);
+                }
+                continue;
+            }
+
+            let attributes = entry[11];
+            let mut short_name: ShortName = [0; 11];
+            short_name.copy_from_slice(&entry[..11]);
+            let long = long_start
+                .take()
+                .filter(|_| lfn_checksum(&short_name) == checksum);
+            if attributes & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            let name = match long {
+                Some(_) => {
+                    let end = long_name.iter().position(|&c| c == 0 || c == 0xffff);
+                    String::from_utf16_lossy(&long_name[..end.unwrap_or(long_name.len())])
+                }
+                None => display_short_name(&short_name, entry[12]),
+            };
+            nodes.push(Node {
+                name,
+                short_name,
+                attributes,
+                cluster: (read_u16(entry, 20) as u32) << 16 | read_u16(entry, 26) as u32,
+                size: read_u32(entry, 28),
+                parent: cluster,
+                first_slot: long.unwrap_or(slot),
+                slot,
+                modified: (read_u16(entry, 24), read_u16(entry, 22)),
+            });
+        }
+        Ok(nodes)
+    }
+
+    fn find(&self, directory: u32, name: &str) -> Result<Node> {
+        self.nodes(directory)?
+            .into_iter()
+            .find(|node| node.name.eq_ignore_ascii_case(name))
+            .ok_or(Error::NotFound)
+    }
+
+    fn lookup(&self, path: &str) -> Result<Target> {
+        let mut target = Target::Root;
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            let directory = match &target {
+                Target::Root => self.layout.root_cluster,
+                Target::Node(node) if node.is_dir() => node.cluster,
+                Target::Node(_) => return Err(Error::NotADirectory),
+            };
+            target = match self.find(directory, part)? {
+                // `..` of a directory in the root points at cluster 0
+                node if node.is_dir() && node.cluster == 0 => Target::Root,
+                node => Target::Node(node),
+            };
+        }
+        Ok(target)
+    }
+
+    /// The first cluster of the directory at `path`
+    fn directory(&self, path: &str) -> Result<u32> {
+        match self.lookup(path)? {
+            Target::Root => Ok(self.layout.root_cluster),
+            Target::Node(node) if node.is_dir() => Ok(node.cluster),
+            Target::Node(_) => Err(Error::NotADirectory),
+        }
+    }
+
+    fn file(&self, path: &str) -> Result<Node> {
+        match self.lookup(path)? {
+            Target::Node(node) if !node.is_dir() => Ok(node),
+            _ => Err(Error::IsADirectory),
+        }
+    }
+
+    /// Update the first cluster and size in a node's short entry
+    fn update_node(&self, node: &Node) -> Result<()> {
+        let chain = self.chain(node.parent)?;
+        let offset = self.slot_offset(&chain, node.slot);
+        let mut entry = [0; ENTRY_SIZE];
+        self.read_bytes(offset, &mut entry)?;
+        write_u16(&mut entry, 20, (node.cluster >> 16) as u16);
+        write_u16(&mut entry, 26, node.cluster as u16);
+        write_u32(&mut entry, 28, node.size);
+        let (date, time) = fat_timestamp(SystemTime::now());
+        write_u16(&mut entry, 22, time);
+        write_u16(&mut entry, 24, date);
+        self.write_bytes(offset, &entry)
+    }
+
+    /// Add an entry named `name` to the directory at `directory`
+    fn add_node(
+        &self,
+        state: &mut State,
+        directory: u32,
+        name: &str,
+        attributes: u8,
+        cluster: u32,
+    ) -> Result<()> {
+        validate_name(name)?;
+        let existing = self.nodes(directory)?;
+        if existing
+            .iter()
+            .any(|node| node.name.eq_ignore_ascii_case(name))
+        {
+            return Err(Error::AlreadyExists);
+        }
+
+        let (short_name, case, needs_long) = match short_name_for(name) {
+            Some((short_name, case))
+                if !existing.iter().any(|node| node.short_name == short_name) =>
+            {
+                (short_name, case, false)
+            }
+            _ => (numbered_short_name(name, &existing)?, 0, true),
+        };
+        let mut entries = if needs_long {
+            long_name_entries(name, lfn_checksum(&short_name))
+        } else {
+            Vec::new()
+        };
+        let mut entry = [0; ENTRY_SIZE];
+        entry[..11].copy_from_slice(&short_name);
+        entry[11] = attributes;
+        entry[12] = case;
+        // Created, accessed and written now
+        let (date, time) = fat_timestamp(SystemTime::now());
+        for offset in [14, 22] {
+            write_u16(&mut entry, offset, time);
+        }
+        for offset in [16, 18, 24] {
+            write_u16(&mut entry, offset, date);
+        }
+        write_u16(&mut entry, 20, (cluster >> 16) as u16);
+        write_u16(&mut entry, 26, cluster as u16);
+        entries.push(entry);
+
+        // Find a run of free slots, growing the directory if there is none
+        let (mut chain, bytes) = self.read_dir_raw(directory)?;
+        let mut run = 0;
+        let mut start = None;
+        for (slot, existing) in bytes.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+            if existing[0] == ENTRY_END || existing[0] == ENTRY_DELETED {
+                run += 1;
+                if run == entries.len() {
+                    start = Some(slot + 1 - run);
+                    break;
+                }
+            } else {
+                run = 0;
+            }
+        }
+        let start = match start {
+            Some(start) => start,
+            None => {
+                let slots = bytes.len() / ENTRY_SIZE;
+                let per_cluster = self.layout.cluster_size() / ENTRY_SIZE;
+                let needed = slots - run + entries.len();
+                self.extend_chain(state, &mut chain, needed.div_ceil(per_cluster))?;
+                slots - run
+            }
+        };
+        for (index, entry) in entries.iter().enumerate() {
+            self.write_bytes(self.slot_offset(&chain, start + index), entry)?;
+        }
+        Ok(())
+    }
+
+    /// Mark a node's entries deleted
+    fn remove_node(&self, node: &Node) -> Result<()> {
+        let chain = self.chain(node.parent)?;
+        for slot in node.first_slot..=node.slot {
+            self.write_bytes(self.slot_offset(&chain, slot), &[ENTRY_DELETED])?;
+        }
+        Ok(())
+    }
+}
+
+impl Volume for FatVolume {
+    fn fs_type(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn stat(&self, path: &str) -> Result<DirEntry> {
+        match self.lookup(path)? {
+            Target::Root => Ok(DirEntry {
+                name: String::from("/"),
+                file_type: FileType::Directory,
+                size: 0,
+                permissions: 0o755,
+                modified: None,
+            }),
+            Target::Node(node) => Ok(node.to_entry()),
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let nodes = self.nodes(self.directory(path)?)?;
+        Ok(nodes
+            .iter()
+            .filter(|node| node.name != "." && node.name != "..")
+            .map(Node::to_entry)
+            .collect())
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let node = self.file(path)?;
+        let len = (node.size as u64)
+            .saturating_sub(offset)
+            .min(buffer.len() as u64) as usize;
+        let chain = self.chain(node.cluster)?;
+        self.for_each_run(&chain, offset, len, |disk, done, run| {
+            self.read_bytes(disk, &mut buffer[done..done + run])
+        })?;
+        Ok(len)
+    }
+
+    fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        let mut node = self.file(path)?;
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(Error::NoSpace);
+        }
+        // Bytes between the old end and `offset` read as zeros
+        if offset > node.size as u64 {
+            self.resize(&mut state, &mut node, offset)?;
+        }
+        let mut chain = self.chain(node.cluster)?;
+        let clusters = (end as usize).div_ceil(self.layout.cluster_size());
+        self.extend_chain(&mut state, &mut chain, clusters)?;
+        self.for_each_run(&chain, offset, data.len(), |disk, done, run| {
+            self.write_bytes(disk, &data[done..done + run])
+        })?;
+
+        node.cluster = chain.first().copied().unwrap_or(0);
+        node.size = node.size.max(end as u32);
+        self.update_node(&node)?;
+        Ok(data.len())
+    }
+
+    fn truncate(&self, path: &str, size: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        let mut node = self.file(path)?;
+        if size > u32::MAX as u64 {
+            return Err(Error::NoSpace);
+        }
+        self.resize(&mut state, &mut node, size)?;
+        self.update_node(&node)
+    }
+
+    fn create_file(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock();
+        let (parent, name) = split_path(path)?;
+        let directory = self.directory(parent)?;
+        self.add_node(&mut state, directory, name, ATTR_ARCHIVE, 0)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock();
+        let (parent, name) = split_path(path)?;
+        let directory = self.directory(parent)?;
+        validate_name(name)?;
+        if self.find(directory, name).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+
+        let cluster = self.allocate(&mut state, None)?;
+        // `..` of a directory in the root is 0 rather than the root's cluster
+        let parent_cluster = if directory == self.layout.root_cluster {
+            0
+        } else {
+            directory
+        };
+        for (slot, (name, target)) in [
+            (*b".          ", cluster),
+            (*b"..         ", parent_cluster),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[..11].copy_from_slice(&name);
+            entry[11] = ATTR_DIRECTORY;
+            write_u16(&mut entry, 20, (target >> 16) as u16);
+            write_u16(&mut entry, 26, target as u16);
+            let (date, time) = fat_timestamp(SystemTime::now());
+            write_u16(&mut entry, 22, time);
+            write_u16(&mut entry, 24, date);
+            let offset = self.layout.cluster_offset(cluster) + (slot * ENTRY_SIZE) as u64;
+            self.write_bytes(offset, &entry)?;
+        }
+
+        let result = self.add_node(&mut state, directory, name, ATTR_DIRECTORY, cluster);
+        if result.is_err() {
+            self.free_chain(&mut state, cluster)?;
+        }
+        result
+    }
+
+    fn remove(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock();
+        let node = match self.lookup(path)? {
+            Target::Root => return Err(Error::Unsupported),
+            Target::Node(node) => node,
+        };
+        if node.name == "." || node.name == ".." {
+            return Err(Error::InvalidName);
+        }
+        if node.is_dir()
+            && self
+                .nodes(node.cluster)?
+                .iter()
+                .any(|child| child.name != "." && child.name != "..")
+        {
+            return Err(Error::DirectoryNotEmpty);
+        }
+        self.remove_node(&node)?;
+        if node.cluster != 0 {
+            self.free_chain(&mut state, node.cluster)?;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        let state = self.state.lock();
+        self.write_fsinfo(&state)?;
+        self.device.flush().map_err(Error::from)
+    }
+}
+
+impl FatVolume {
+    /// Set a file's size, zeroing new bytes and freeing clusters past the end
+    fn resize(&self, state: &mut State, node: &mut Node, size: u64) -> Result<()> {
+        let cluster_size = self.layout.cluster_size();
+        let old_size = node.size as u64;
+        let mut chain = self.chain(node.cluster)?;
+        let clusters = (size as usize).div_ceil(cluster_size);
+
+        if size < old_size {
+            if clusters == 0 {
+                if node.cluster != 0 {
+                    self.free_chain(state, node.cluster)?;
+                }
+            } else if let Some(&next) = chain.get(clusters) {
+                self.write_fat(chain[clusters - 1], FAT_END)?;
+                self.free_chain(state, next)?;
+            }
+            chain.truncate(clusters);
+        } else if size > old_size {
+            // Clear the stale tail of the last cluster, new clusters are zeroed
+            let tail_end = ((old_size as usize).div_ceil(cluster_size) * cluster_size) as u64;
+            let zeros = vec![0; (tail_end.min(size) - old_size.min(tail_end)) as usize];
+            self.for_each_run(&chain, old_size, zeros.len(), |disk, done, run| {
+                self.write_bytes(disk, &zeros[done..done + run])
+            })?;
+            self.extend_chain(state, &mut chain, clusters)?;
+        }
+
+        node.cluster = chain.first().copied().unwrap_or(0);
+        node.size = size as u32;
+        Ok(())
+    }
+}
+
+/// Split a volume path into its parent directory and last component
+fn split_path(path: &str) -> Result<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Error::InvalidName);
+    }
+    Ok((parent, name))
+}
+
+/// Characters a long name may not contain
+fn validate_name(name: &str) -> Result<()> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty()
+        || name.encode_utf16().count() > MAX_NAME
+        || name.chars().any(invalid)
+        || name.ends_with('.')
+        || name.ends_with(' ')
+    {
+        return Err(Error::InvalidName);
+    }
+    Ok(())
+}
+
+/// Characters allowed in a short name besides letters and digits
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// The short name for a name that fits 8.3 without a long entry, and its case flags
+fn short_name_for(name: &str) -> Option<(ShortName, u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || ext.contains('.')
+        || !name.bytes().all(|c| c == b'.' || is_short_char(c))
+    {
+        return None;
+    }
+    // Each part has to be all lower or all upper case to be stored as flags
+    let mut case = 0;
+    for (part, flag) in [(base, CASE_LOWER_BASE), (ext, CASE_LOWER_EXT)] {
+        let lower = part.bytes().any(|c| c.is_ascii_lowercase());
+        let upper = part.bytes().any(|c| c.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true) => return None,
+            (true, false) => case |= flag,
+            _ => {}
+        }
+    }
+    let mut short_name = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        short_name[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        short_name[8 + i] = c.to_ascii_uppercase();
+    }
+    Some((short_name, case))
+}
+
+/// A `BASE~N.EXT` short name not yet used in the directory
+fn numbered_short_name(name: &str, existing: &[Node]) -> Result<ShortName> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (name, ""),
+    };
+    let clean = |part: &str, len: usize| -> Vec<u8> {
+        part.bytes()
+            .filter(|&c| c != b' ' && c != b'.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_short_char(c) {
+                    c
+                } else {
+                    b'_'
+                }
+            })
+            .take(len)
+            .collect()
+    };
+    let base = clean(base, 8);
+    let ext = clean(ext, 3);
+
+    for number in 1..1_000_000u32 {
+        let tail = format!("~{}", number);
+        let keep = base.len().min(8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&base[..keep]);
+        short_name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        if !existing.iter().any(|node| node.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(Error::AlreadyExists)
+}
+
+/// Long name entries for `name`, the last part first as they are stored
+fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    // NUL terminated unless it fills the last entry exactly, then 0xffff padded
+    if !units.len().is_multiple_of(LFN_CHARS) {
+        units.push(0);
+    }
+    units.resize(count * LFN_CHARS, 0xffff);
+
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = order as u8 | if order == count { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+                write_u16(&mut entry, offset, units[(order - 1) * LFN_CHARS + i]);
+            }
+            entry
+        })
+        .collect()
+}
+
+fn lfn_checksum(short_name: &ShortName) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// `NAME.EXT` from a short entry, lower-cased where the case flags say so
+fn display_short_name(short_name: &ShortName, case: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let mut part: String = bytes
+            .iter()
+            .map(|&c| c as char)
+            .collect::<String>()
+            .trim_end()
+            .into();
+        if lower {
+            part.make_ascii_lowercase();
+        }
+        part
+    };
+    let base = part(&short_name[..8], case & CASE_LOWER_BASE != 0);
+    let ext = part(&short_name[8..], case & CASE_LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
@@ -0,0 +1,253 @@
+//! File systems and the block cache below them
+//!
+//! Disk file systems implement [`Volume`] and are mounted on a directory of
+//! the terminal's tree. Paths handed to a volume are relative to its root,
+//! components separated by `/`. Every disk with a known file system is
+//! mounted on `/mnt/<disk>` at boot.
+
+pub mod cache;
+pub mod dev;
+pub mod ext2;
+pub mod fat32;
+pub mod proc;
+
+use crate::drivers::block::{self, BlockDevice};
+use crate::kernel::time::SystemTime;
+use crate::sync::RwLock;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Where disks are mounted at boot
+pub const MOUNT_ROOT: &str = "/mnt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    /// Empty, too long, or has characters the file system does not allow
+    InvalidName,
+    NoSpace,
+    ReadOnly,
+    /// The disk does not hold a file system this driver understands
+    UnknownFileSystem,
+    /// On-disk structures do not make sense
+    Corrupt,
+    Unsupported,
+    Block(block::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(err: block::Error) -> Self {
+        match err {
+            block::Error::ReadOnly => Error::ReadOnly,
+            err => Error::Block(err),
+        }
+    }
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::NotFound => "No such file or directory",
+            Error::AlreadyExists => "File exists",
+            Error::NotADirectory => "Not a directory",
+            Error::IsADirectory => "Is a directory",
+            Error::DirectoryNotEmpty => "Directory not empty",
+            Error::InvalidName => "Invalid file name",
+            Error::NoSpace => "No space left on device",
+            Error::ReadOnly => "Read-only file system",
+            Error::UnknownFileSystem => "Unknown file system",
+            Error::Corrupt => "File system is corrupt",
+            Error::Unsupported => "Operation not supported",
+            Error::Block(_) => "I/O error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+    pub size: u64,
+    /// Unix permission bits, e.g. `0o644`
+    pub permissions: u16,
+    /// Last change of the contents, if the file system records it
+    pub modified: Option<SystemTime>,
+}
+
+impl DirEntry {
+    /// `ls -l` style type and permissions, e.g. `drwxr-xr-x`
+    pub fn mode_string(&self) -> String {
+        let mut mode = String::from(match self.file_type {
+            FileType::File => '-',
+            FileType::Directory => 'd',
+            FileType::Symlink => 'l',
+        });
+        for shift in [6, 3, 0] {
+            let bits = self.permissions >> shift;
+            mode.push(if bits & 4 != 0 { 'r' } else { '-' });
+            mode.push(if bits & 2 != 0 { 'w' } else { '-' });
+            mode.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        mode
+    }
+}
+
+/// A mounted file system
+///
+/// Methods take `&self`, volumes serialize changes themselves.
+pub trait Volume: Send + Sync {
+    /// File system type, e.g. `fat32`
+    fn fs_type(&self) -> &'static str;
+    /// Label stored on the disk, may be empty
+    fn label(&self) -> String;
+
+    /// The entry at `path`, the root is a directory called `/`
+    ///
+    /// Symbolic links are followed, [`Volume::read_dir`] shows them as links.
+    fn stat(&self, path: &str) -> Result<DirEntry>;
+    /// Entries of a directory, without `.` and `..`
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>>;
+    /// Target of the symbolic link at `path`
+    fn read_link(&self, _path: &str) -> Result<String> {
+        Err(Error::Unsupported)
+    }
+    /// Read from `offset`, returns the number of bytes read, 0 at the end
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize>;
+    /// Write at `offset`, extending the file as needed
+    fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize>;
+    /// Cut the file to `size` bytes or extend it with zeros
+    fn truncate(&self, path: &str, size: u64) -> Result<()>;
+    fn create_file(&self, path: &str) -> Result<()>;
+    fn create_dir(&self, path: &str) -> Result<()>;
+    /// Delete a file or an empty directory
+    fn remove(&self, path: &str) -> Result<()>;
+    /// Write everything out to the disk
+    fn sync(&self) -> Result<()>;
+
+    /// The whole contents of a file
+    fn read_to_end(&self, path: &str) -> Result<Vec<u8>> {
+        let entry = self.stat(path)?;
+        if entry.file_type == FileType::Directory {
+            return Err(Error::IsADirectory);
+        }
+        let mut data = alloc::vec![0; entry.size as usize];
+        let mut filled = 0;
+        while filled < data.len() {
+            let read = self.read(path, filled as u64, &mut data[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        data.truncate(filled);
+        Ok(data)
+    }
+}
+
+/// A volume and the directory it is mounted on
+#[derive(Clone)]
+pub struct Mount {
+    pub path: String,
+    pub device: String,
+    pub volume: Arc<dyn Volume>,
+}
+
+/// Read on every path lookup, written only by [`mount`]
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Find the file system on a disk, reading through its block cache
+pub fn probe(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn Volume>> {
+    match fat32::FatVolume::open(device.clone()) {
+        Err(Error::UnknownFileSystem) => {}
+        result => return result.map(|volume| Arc::new(volume) as Arc<dyn Volume>),
+    }
+    match ext2::Ext2Volume::open(device) {
+        Err(Error::UnknownFileSystem) => {}
+        result => return result.map(|volume| Arc::new(volume) as Arc<dyn Volume>),
+    }
+    Err(Error::UnknownFileSystem)
+}
+
+/// Mount the file system on the disk `device` at the absolute `path`
+pub fn mount(device: &str, path: &str) -> Result<Arc<dyn Volume>> {
+    let path = String::from(path.trim_end_matches('/'));
+    if MOUNTS.read().iter().any(|mount| mount.path == path) {
+        return Err(Error::AlreadyExists);
+    }
+    let cache = cache::get(device).ok_or(Error::NotFound)?;
+    let volume = probe(cache)?;
+    crate::log_info!(
+        "fs: mounted {} ({}) on {}",
+        device,
+        volume.fs_type(),
+        if path.is_empty() { "/" } else { &path }
+    );
+    MOUNTS.write().push(Mount {
+        path,
+        device: String::from(device),
+        volume: volume.clone(),
+    });
+    Ok(volume)
+}
+
+pub fn mounts() -> Vec<Mount> {
+    MOUNTS.read().clone()
+}
+
+/// Write out what the mounted volumes keep in memory, into their block caches
+pub fn sync_all() -> Result<()> {
+    for mount in mounts() {
+        mount.volume.sync()?;
+    }
+    Ok(())
+}
+
+/// The volume an absolute path lies on and the path within it
+pub fn lookup(path: &str) -> Option<(Arc<dyn Volume>, String)> {
+    let mounts = MOUNTS.read();
+    mounts
+        .iter()
+        .filter_map(|mount| {
+            let rest = path.strip_prefix(mount.path.as_str())?;
+            if !rest.is_empty() && !rest.starts_with('/') {
+                return None;
+            }
+            Some((mount, rest))
+        })
+        .max_by_key(|(mount, _)| mount.path.len())
+        .map(|(mount, rest)| (mount.volume.clone(), String::from(rest.trim_start_matches('/'))))
+}
+
+/// Start background write-back, publish the cache statistics and mount
+/// every disk that has a known file system
+pub fn init() {
+    cache::init();
+    for device in block::devices() {
+        let path = format!("{}/{}", MOUNT_ROOT, device.name());
+        if let Err(err) = mount(device.name(), &path) {
+            crate::log_info!("fs: {}: not mounted: {}", device.name(), err);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Generated files shown under `/proc`
+//!
+//! Subsystems register a name and a function that renders the file each
+//! time it is read, the way Linux shows kernel state.
+
+use crate::sync::SafeMutex;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+type Render = fn() -> String;
+
+static FILES: SafeMutex<Vec<(&'static str, Render)>> = SafeMutex::new(Vec::new());
+
+/// Publish `/proc/<name>`, a later registration replaces an earlier one
+pub fn register(name: &'static str, render: Render) {
+    let mut files = FILES.lock();
+    files.retain(|(existing, _)| *existing != name);
+    files.push((name, render));
+}
+
+/// Names of all files, sorted
+pub fn names() -> Vec<&'static str> {
+    let mut names: Vec<_> = FILES.lock().iter().map(|(name, _)| *name).collect();
+    names.sort_unstable();
+    names
+}
+
+/// Current contents of `/proc/<name>`
+pub fn read(name: &str) -> Option<String> {
+    // Render without the lock, the function may take its own
+    let render = FILES
+        .lock()
+        .iter()
+        .find(|(existing, _)| *existing == name)
+        .map(|(_, render)| *render)?;
+    Some(render())
+}
@@ -1,9 +1,22 @@
 //! Device management
 
 use crate::console;
+use crate::drivers::{fw_cfg, pci, rtc, virtio};
 
 /// Initialize device subsystems
 pub fn init() {
     // Initialize basic devices
     console::init();
+
+    // Assign PCI resources before virtio looks at the functions
+    pci::init();
+
+    // Host-provided files, including the ramfb configuration
+    fw_cfg::init();
+
+    // Wall-clock time
+    rtc::init();
+
+    // Find devices for the drivers to claim
+    virtio::probe();
 }
@@ -0,0 +1,42 @@
+//! Kernel heap
+//!
+//! The allocator lock masks IRQs so interrupt handlers may allocate, e.g.
+//! when they queue deferred work.
+
+use crate::sync::IrqSafeSpinlock;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+
+pub struct KernelHeap {
+    heap: IrqSafeSpinlock<Heap>,
+}
+
+impl KernelHeap {
+    pub const fn empty() -> Self {
+        Self {
+            heap: IrqSafeSpinlock::new(Heap::empty()),
+        }
+    }
+
+    /// # Safety
+    /// The memory range must be unused and stay valid forever.
+    pub unsafe fn init(&self, start: *mut u8, size: usize) {
+        self.heap.lock().init(start, size);
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap
+            .lock()
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |block| block.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.heap.lock().deallocate(ptr, layout);
+        }
+    }
+}
@@ -1,20 +1,80 @@
 //! Interrupt handling
 
+use crate::arch::{self, ExceptionFrame, ExceptionKind};
+use crate::drivers::gic::{GIC, SPECIAL_IRQ_START};
+use crate::kernel::random;
+use crate::sync::IrqSafeSpinlock;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+type Handler = Box<dyn Fn() + Send + Sync>;
+
+static HANDLERS: IrqSafeSpinlock<BTreeMap<u32, Vec<Handler>>> =
+    IrqSafeSpinlock::new(BTreeMap::new());
+
 /// Initialize interrupt handling
 pub fn init() {
-    unsafe {
-        // Disable interrupts during init
-        core::arch::asm!("msr daifset, #2");
+    // Disable interrupts during init
+    let daif = arch::local_irq_save();
+
+    // Set up exception vectors and the interrupt controller
+    arch::install_vectors();
+    GIC.init();
+
+    arch::local_irq_restore(daif);
+}
+
+/// Unmask IRQs on this core once handlers are in place
+pub fn enable() {
+    arch::local_irq_enable();
+}
+
+/// Call `handler` whenever `irq` fires
+///
+/// Several handlers may share one line, each must check whether its own
+/// device raised the interrupt. Handlers run with IRQs masked.
+pub fn register(irq: u32, handler: impl Fn() + Send + Sync + 'static) {
+    HANDLERS
+        .lock()
+        .entry(irq)
+        .or_default()
+        .push(Box::new(handler));
+    GIC.enable(irq);
+}
 
-        // Set up exception vectors
-        core::arch::asm!("msr vbar_el1, {}", in(reg) &VECTORS as *const _ as usize);
+fn handle_irq() {
+    let irq = GIC.acknowledge();
+    if irq >= SPECIAL_IRQ_START {
+        return;
+    }
 
-        // Enable interrupts
-        core::arch::asm!("msr daifclr, #2");
+    random::add_interrupt_timing(irq);
+    match HANDLERS.lock().get(&irq) {
+        Some(handlers) => {
+            for handler in handlers {
+                handler();
+            }
+        }
+        None => {
+            crate::log_warn!("unhandled irq {}", irq);
+            GIC.disable(irq);
+        }
     }
+
+    GIC.end_of_interrupt(irq);
 }
 
-#[repr(align(2048))]
-struct ExceptionVectors([u32; 16]);
+#[no_mangle]
+extern "C" fn handle_exception(frame: &mut ExceptionFrame, kind: ExceptionKind) {
+    if kind == ExceptionKind::Irq {
+        handle_irq();
+        return;
+    }
 
-static VECTORS: ExceptionVectors = ExceptionVectors([0xd503201f; 16]);
+    let (esr, _, far) = arch::exception_registers();
+    panic!(
+        "unhandled {:?} exception: esr={:#x} elr={:#x} far={:#x} spsr={:#x}",
+        kind, esr, frame.elr, far, frame.spsr
+    );
+}
@@ -1,14 +1,67 @@
 //! Memory management
 
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use core::ptr::NonNull;
+
 /// Memory page size (4KB)
 pub const PAGE_SIZE: usize = 4096;
 
+/// Page aligned, zeroed memory that is shared with devices
+///
+/// The MMU is off, so the buffer's address is its physical address and
+/// accesses are uncached.
+pub struct DmaBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    pub fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size.max(1), PAGE_SIZE).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+
+    /// Physical address of the first byte
+    pub fn paddr(&self) -> usize {
+        self.ptr.as_ptr() as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
 /// Physical memory manager
+#[allow(dead_code, reason = "the heap uses a fixed RAM window for now")]
 pub struct PhysicalMemory {
     start: usize,
     size: usize,
 }
 
+#[allow(dead_code, reason = "the heap uses a fixed RAM window for now")]
 impl PhysicalMemory {
     pub const fn new(start: usize, size: usize) -> Self {
         Self { start, size }
@@ -16,6 +69,7 @@ impl PhysicalMemory {
 }
 
 /// Initialize memory subsystem
+#[allow(dead_code, reason = "the boot stub clears BSS before Rust code runs")]
 pub fn init() {
     unsafe {
         // Clear BSS
@@ -25,11 +79,11 @@ pub fn init() {
         }
 
         let bss_len = {
-            let start = &raw const __bss_start as *const u8 as usize;
-            let end = &raw const __bss_end as *const u8 as usize;
+            let start = &raw const __bss_start as usize;
+            let end = &raw const __bss_end as usize;
             end - start
         };
 
-        core::ptr::write_bytes(&raw mut __bss_start as *mut u8, 0, bss_len);
+        core::ptr::write_bytes(&raw mut __bss_start, 0, bss_len);
     }
 }
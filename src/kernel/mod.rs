@@ -1,20 +1,33 @@
 //! Kernel core functionality
 
 pub mod device;
+pub mod heap;
 pub mod interrupt;
 pub mod memory;
 pub mod process;
+pub mod random;
+pub mod sched;
+pub mod time;
+pub mod timer;
+pub mod workqueue;
 
 /// Initialize the kernel
+///
+/// The heap must be set up first. BSS was already cleared by the boot stub.
 pub fn init() {
     // Initialize kernel subsystems
     interrupt::init();
-    memory::init();
     process::init();
+    time::init();
+    timer::init();
+    workqueue::init();
+    random::init();
     device::init();
+    interrupt::enable();
 }
 
 /// Kernel information
+#[allow(dead_code, reason = "release string for banners and uname")]
 pub fn version() -> &'static str {
     "NyanNix v0.1.0"
 }
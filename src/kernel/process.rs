@@ -1,6 +1,9 @@
 //! Process management
 
+use super::sched;
+
 /// Initialize process management
 pub fn init() {
-    // Basic process initialization
+    // The boot context becomes the first schedulable task
+    sched::init();
 }
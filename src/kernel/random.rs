@@ -0,0 +1,317 @@
+//! Kernel random numbers
+//!
+//! Entropy goes into a pool: counter readings at every interrupt, jitter
+//! of the timer at boot, and whatever hardware sources like virtio-rng
+//! hand to [`add_entropy`]. The pool is a sponge over the ChaCha20
+//! permutation, so every input stirs the whole state.
+//!
+//! Random bytes come from a ChaCha20 generator keyed from the pool. It is
+//! reseeded once [`RESEED_BITS`] of fresh entropy have been credited, at
+//! most every [`RESEED_INTERVAL`], and replaces its key after every request
+//! so earlier output cannot be recovered. [`fill_bytes`] never blocks;
+//! callers that need a seeded generator wait with [`wait_until_seeded`].
+
+use crate::arch;
+use crate::fs::proc;
+use crate::kernel::time::Instant;
+use crate::sync::{IrqSafeSpinlock, SafeMutex, WaitQueue};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Fresh entropy the pool must hold to (re)seed the generator
+pub const RESEED_BITS: usize = 256;
+pub const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+/// Most the pool can be credited with, the size of its capacity part
+const POOL_BITS: usize = 512;
+/// Interrupts that make up one credited bit
+const INTERRUPTS_PER_BIT: usize = 64;
+/// Counter samples taken at boot, and how many make one credited bit.
+/// A guest's timing is partly predictable, so jitter alone stays well
+/// short of [`RESEED_BITS`].
+const JITTER_SAMPLES: usize = 2048;
+const JITTER_SAMPLES_PER_BIT: usize = 64;
+
+/// "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The 20 ChaCha rounds, without the final addition
+fn permute(state: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(state, 0, 4, 8, 12);
+        quarter_round(state, 1, 5, 9, 13);
+        quarter_round(state, 2, 6, 10, 14);
+        quarter_round(state, 3, 7, 11, 15);
+        quarter_round(state, 0, 5, 10, 15);
+        quarter_round(state, 1, 6, 11, 12);
+        quarter_round(state, 2, 7, 8, 13);
+        quarter_round(state, 3, 4, 9, 14);
+    }
+}
+
+/// One 64-byte ChaCha20 block, RFC 8439
+pub fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u8; 64] {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter;
+    input[13..].copy_from_slice(nonce);
+
+    let mut state = input;
+    permute(&mut state);
+    let mut block = [0; 64];
+    for (i, word) in state.iter().enumerate() {
+        let word = word.wrapping_add(input[i]);
+        block[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    block
+}
+
+/// Entropy collected but not yet used for seeding
+struct Pool {
+    /// Sponge state, inputs are absorbed into the first half
+    state: [u32; 16],
+    position: usize,
+    /// Credited bits since the last reseed
+    entropy_bits: usize,
+    /// Interrupts since the last credited bit
+    interrupts: usize,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            state: [0; 16],
+            position: 0,
+            entropy_bits: 0,
+            interrupts: 0,
+        }
+    }
+
+    fn absorb(&mut self, word: u32) {
+        self.state[self.position] ^= word;
+        self.position += 1;
+        if self.position == 8 {
+            permute(&mut self.state);
+            self.position = 0;
+        }
+    }
+
+    fn credit(&mut self, bits: usize) {
+        self.entropy_bits = (self.entropy_bits + bits).min(POOL_BITS);
+    }
+
+    /// A 256-bit seed, the pool moves on so it cannot be produced again
+    fn extract(&mut self) -> [u32; 8] {
+        permute(&mut self.state);
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&self.state[..8]);
+        permute(&mut self.state);
+        self.position = 0;
+        self.entropy_bits = 0;
+        seed
+    }
+}
+
+/// The ChaCha20 generator
+struct Generator {
+    key: [u32; 8],
+    /// Counts requests, so equal keys never repeat a stream
+    nonce: u64,
+    seeded: bool,
+    last_reseed: Option<Instant>,
+    reseeds: u64,
+}
+
+impl Generator {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            nonce: 0,
+            seeded: false,
+            last_reseed: None,
+            reseeds: 0,
+        }
+    }
+
+    fn reseed(&mut self, seed: [u32; 8]) {
+        for (key, seed) in self.key.iter_mut().zip(seed) {
+            *key ^= seed;
+        }
+        self.seeded = true;
+        self.last_reseed = Some(Instant::now());
+        self.reseeds += 1;
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        self.nonce += 1;
+        let nonce = [0, self.nonce as u32, (self.nonce >> 32) as u32];
+        // Block 0 becomes the next key, the rest is output
+        let next = chacha20_block(&self.key, 0, &nonce);
+        for (counter, chunk) in buffer.chunks_mut(64).enumerate() {
+            let block = chacha20_block(&self.key, counter as u32 + 1, &nonce);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        for (i, key) in self.key.iter_mut().enumerate() {
+            *key = u32::from_le_bytes(next[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+    }
+}
+
+/// Interrupt handlers feed the pool, so it is taken with IRQs masked
+static POOL: IrqSafeSpinlock<Pool> = IrqSafeSpinlock::new(Pool::new());
+static GENERATOR: SafeMutex<Generator> = SafeMutex::new(Generator::new());
+/// Names of the hardware sources that fed the pool
+static SOURCES: SafeMutex<Vec<&'static str>> = SafeMutex::new(Vec::new());
+/// Tasks waiting for the generator to be seeded
+static SEED_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Mix `data` from a hardware source into the pool, crediting `bits`
+pub fn add_entropy(source: &'static str, data: &[u8], bits: usize) {
+    {
+        let mut pool = POOL.lock();
+        for chunk in data.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            pool.absorb(u32::from_le_bytes(word));
+        }
+        pool.credit(bits.min(data.len() * 8));
+    }
+    let mut sources = SOURCES.lock();
+    if !sources.contains(&source) {
+        sources.push(source);
+    }
+    drop(sources);
+    SEED_WAITERS.wake_all();
+}
+
+/// Mix in when interrupt `irq` arrived, called by the interrupt handler
+pub fn add_interrupt_timing(irq: u32) {
+    let mut pool = POOL.lock();
+    pool.absorb(arch::counter() as u32 ^ irq.rotate_right(8));
+    pool.interrupts += 1;
+    if pool.interrupts == INTERRUPTS_PER_BIT {
+        pool.interrupts = 0;
+        pool.credit(1);
+        if pool.entropy_bits == RESEED_BITS {
+            SEED_WAITERS.wake_all();
+        }
+    }
+}
+
+/// Sample how long a little work takes, the low bits of the counter vary
+/// with caches, pipelines and the host
+fn collect_jitter() {
+    let mut previous = arch::counter();
+    for sample in 0..JITTER_SAMPLES {
+        let mut spin = previous as u32;
+        for _ in 0..(previous & 0x1f) {
+            spin = spin.rotate_left(5) ^ sample as u32;
+            core::hint::black_box(spin);
+        }
+        let now = arch::counter();
+        let mut pool = POOL.lock();
+        pool.absorb(now.wrapping_sub(previous) as u32 ^ spin);
+        if sample % JITTER_SAMPLES_PER_BIT == 0 {
+            pool.credit(1);
+        }
+        previous = now;
+    }
+}
+
+/// Reseed from the pool if it holds enough new entropy
+fn maybe_reseed(generator: &mut Generator) {
+    let due = generator
+        .last_reseed
+        .is_none_or(|last| last.elapsed() >= RESEED_INTERVAL);
+    if !due {
+        return;
+    }
+    let seed = {
+        let mut pool = POOL.lock();
+        if pool.entropy_bits < RESEED_BITS {
+            return;
+        }
+        pool.extract()
+    };
+    generator.reseed(seed);
+}
+
+/// Fill `buffer` with random bytes, even before the generator is seeded
+pub fn fill_bytes(buffer: &mut [u8]) {
+    let mut generator = GENERATOR.lock();
+    maybe_reseed(&mut generator);
+    generator.fill(buffer);
+}
+
+pub fn next_u32() -> u32 {
+    let mut bytes = [0; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn is_seeded() -> bool {
+    GENERATOR.lock().seeded
+}
+
+/// Park until the generator has been seeded once
+pub fn wait_until_seeded() {
+    SEED_WAITERS.wait_until(|| {
+        let mut generator = GENERATOR.lock();
+        maybe_reseed(&mut generator);
+        generator.seeded
+    });
+}
+
+/// `/dev/random`, blocks until seeded
+pub fn read_random(buffer: &mut [u8]) -> usize {
+    wait_until_seeded();
+    fill_bytes(buffer);
+    buffer.len()
+}
+
+/// `/dev/urandom`, never blocks
+pub fn read_urandom(buffer: &mut [u8]) -> usize {
+    fill_bytes(buffer);
+    buffer.len()
+}
+
+/// Contents of `/proc/random`
+fn render_status() -> String {
+    let entropy = POOL.lock().entropy_bits;
+    let generator = GENERATOR.lock();
+    let last = generator.last_reseed.map_or(String::from("never"), |last| {
+        format!("{}s ago", last.elapsed().as_secs())
+    });
+    let sources = SOURCES.lock();
+    format!(
+        "seeded: {}\nentropy: {} bits\nreseeds: {}\nlast reseed: {}\nsources: interrupts, jitter{}{}\n",
+        if generator.seeded { "yes" } else { "no" },
+        entropy,
+        generator.reseeds,
+        last,
+        if sources.is_empty() { "" } else { ", " },
+        sources.join(", ")
+    )
+}
+
+/// Gather boot jitter and seed the generator from it
+pub fn init() {
+    collect_jitter();
+    maybe_reseed(&mut GENERATOR.lock());
+    proc::register("random", render_status);
+    crate::fs::dev::register("random", read_random);
+    crate::fs::dev::register("urandom", read_urandom);
+}
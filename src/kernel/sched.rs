@@ -0,0 +1,254 @@
+//! Cooperative task scheduler
+//!
+//! Tasks run until they yield or block on a [`WaitQueue`](crate::sync::WaitQueue).
+//! The code that called [`init`] becomes the first task.
+
+use crate::arch::{self, Context};
+use crate::sync::IrqSafeSpinlock;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    #[cfg(feature = "lock-debug")]
+    pub fn as_usize(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Ready,
+    Running,
+    Blocked,
+    Dead,
+}
+
+struct Task {
+    id: TaskId,
+    name: &'static str,
+    state: TaskState,
+    /// Set when a wakeup arrives before the task managed to block
+    wake_pending: bool,
+    context: Context,
+    _stack: Option<Box<[u8]>>,
+}
+
+struct Scheduler {
+    // Boxed so saved contexts stay put while the vector grows
+    #[allow(clippy::vec_box)]
+    tasks: Vec<Box<Task>>,
+    ready: VecDeque<TaskId>,
+    next_id: usize,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            ready: VecDeque::new(),
+            next_id: 1,
+        }
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> Option<&mut Task> {
+        self.tasks.iter_mut().find(|t| t.id == id).map(|t| &mut **t)
+    }
+}
+
+static SCHEDULER: IrqSafeSpinlock<Scheduler> = IrqSafeSpinlock::new(Scheduler::new());
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Turn the calling context into task 0
+pub fn init() {
+    if INITIALIZED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    SCHEDULER.lock().tasks.push(Box::new(Task {
+        id: TaskId(0),
+        name: "kernel",
+        state: TaskState::Running,
+        wake_pending: false,
+        context: Context::empty(),
+        _stack: None,
+    }));
+    INITIALIZED.store(true, Ordering::SeqCst);
+}
+
+/// Id of the running task
+pub fn current() -> TaskId {
+    TaskId(CURRENT.load(Ordering::Relaxed))
+}
+
+/// Create a new task running `f`
+pub fn spawn<F>(name: &'static str, f: F) -> TaskId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let entry: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    let stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let stack_top = stack.as_ptr() as usize + stack.len();
+
+    let mut scheduler = SCHEDULER.lock();
+    let id = TaskId(scheduler.next_id);
+    scheduler.next_id += 1;
+    scheduler.tasks.push(Box::new(Task {
+        id,
+        name,
+        state: TaskState::Ready,
+        wake_pending: false,
+        context: Context::new_task(stack_top, task_entry, Box::into_raw(entry) as usize),
+        _stack: Some(stack),
+    }));
+    scheduler.ready.push_back(id);
+    id
+}
+
+extern "C" fn task_entry(arg: usize) -> ! {
+    let f = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce() + Send>) };
+    arch::local_irq_enable();
+    f();
+    exit();
+}
+
+/// Terminate the current task
+pub fn exit() -> ! {
+    let daif = arch::local_irq_save();
+    if let Some(task) = SCHEDULER.lock().task_mut(current()) {
+        task.state = TaskState::Dead;
+    }
+    reschedule();
+    arch::local_irq_restore(daif);
+    unreachable!("dead task was scheduled again");
+}
+
+/// Let other ready tasks run
+pub fn yield_now() {
+    let daif = arch::local_irq_save();
+    reschedule();
+    arch::local_irq_restore(daif);
+}
+
+/// Park the current task until [`wake`] is called for it
+///
+/// Must be called with IRQs masked, after the task has been recorded
+/// somewhere its waker will find it. Before [`init`] this only waits
+/// for the next interrupt.
+pub fn block_current() {
+    if !INITIALIZED.load(Ordering::SeqCst) {
+        arch::wait_for_irq();
+        return;
+    }
+
+    {
+        let mut scheduler = SCHEDULER.lock();
+        let Some(task) = scheduler.task_mut(current()) else {
+            return;
+        };
+        if task.wake_pending {
+            task.wake_pending = false;
+            return;
+        }
+        task.state = TaskState::Blocked;
+    }
+    reschedule();
+}
+
+/// Make a blocked task runnable again, safe to call from interrupt handlers
+pub fn wake(id: TaskId) {
+    let mut scheduler = SCHEDULER.lock();
+    let Some(task) = scheduler.task_mut(id) else {
+        return;
+    };
+    match task.state {
+        TaskState::Blocked => {
+            task.state = TaskState::Ready;
+            scheduler.ready.push_back(id);
+        }
+        TaskState::Running | TaskState::Ready => task.wake_pending = true,
+        TaskState::Dead => {}
+    }
+}
+
+/// Names and states of all tasks
+#[allow(dead_code, reason = "introspection for a ps-style listing")]
+pub fn tasks() -> Vec<(TaskId, &'static str, TaskState)> {
+    SCHEDULER
+        .lock()
+        .tasks
+        .iter()
+        .map(|t| (t.id, t.name, t.state))
+        .collect()
+}
+
+/// Switch to the next ready task, idling while there is none
+///
+/// Called with IRQs masked. Returns once the current task runs again.
+fn reschedule() {
+    let current = current();
+
+    loop {
+        let mut scheduler = SCHEDULER.lock();
+
+        // Stacks of finished tasks can be freed once we are off them
+        scheduler
+            .tasks
+            .retain(|t| t.state != TaskState::Dead || t.id == current);
+
+        let still_running = match scheduler.task_mut(current) {
+            Some(task) if task.state == TaskState::Running => {
+                task.state = TaskState::Ready;
+                true
+            }
+            _ => false,
+        };
+        if still_running {
+            scheduler.ready.push_back(current);
+        }
+
+        let next = loop {
+            match scheduler.ready.pop_front() {
+                Some(id) => {
+                    if let Some(task) = scheduler.task_mut(id) {
+                        if task.state == TaskState::Ready {
+                            break Some(id);
+                        }
+                    }
+                }
+                None => break None,
+            }
+        };
+
+        let Some(next) = next else {
+            // Nothing to run: wait for an interrupt to wake somebody up
+            drop(scheduler);
+            arch::wait_for_irq();
+            continue;
+        };
+
+        scheduler.task_mut(next).unwrap().state = TaskState::Running;
+        if next == current {
+            return;
+        }
+
+        let prev_context = match scheduler.task_mut(current) {
+            Some(task) => &mut task.context as *mut Context,
+            None => return,
+        };
+        let next_context = &scheduler.task_mut(next).unwrap().context as *const Context;
+        CURRENT.store(next.0, Ordering::Relaxed);
+        drop(scheduler);
+
+        unsafe { arch::switch_context(prev_context, next_context) };
+        return;
+    }
+}
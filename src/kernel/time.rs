@@ -0,0 +1,261 @@
+//! Monotonic and wall-clock time based on the ARM generic timer
+//!
+//! [`Instant`] counts timer ticks. [`SystemTime`] adds an offset that the
+//! RTC driver sets at boot, so wall-clock time advances with the generic
+//! timer's resolution; until then it starts at the Unix epoch.
+
+use crate::arch;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+static BOOT_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Wall-clock time at counter value 0, in nanoseconds since the epoch
+static WALL_CLOCK_OFFSET: AtomicU64 = AtomicU64::new(0);
+static WALL_CLOCK_SET: AtomicBool = AtomicBool::new(false);
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// Days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar
+const EPOCH_DAYS: i64 = 719_468;
+/// Days in 400 years
+const ERA_DAYS: i64 = 146_097;
+
+/// Remember the counter value at boot
+pub fn init() {
+    BOOT_TICKS.store(arch::counter(), Ordering::Relaxed);
+}
+
+/// A point on the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(arch::counter())
+    }
+
+    pub fn ticks(self) -> u64 {
+        self.0
+    }
+
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+}
+
+impl core::ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration_to_ticks(duration)))
+    }
+}
+
+/// Time since [`init`] ran
+pub fn uptime() -> Duration {
+    ticks_to_duration(arch::counter().saturating_sub(BOOT_TICKS.load(Ordering::Relaxed)))
+}
+
+/// A point in wall-clock time, UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SystemTime(Duration);
+
+impl SystemTime {
+    pub fn now() -> Self {
+        let offset = Duration::from_nanos(WALL_CLOCK_OFFSET.load(Ordering::Relaxed));
+        Self(offset + ticks_to_duration(arch::counter()))
+    }
+
+    pub fn from_unix_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    /// Whole seconds since the epoch
+    pub fn unix_secs(self) -> u64 {
+        self.0.as_secs()
+    }
+}
+
+impl core::ops::Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, duration: Duration) -> SystemTime {
+        SystemTime(self.0.saturating_add(duration))
+    }
+}
+
+/// Make [`SystemTime::now`] return `time` from now on
+pub fn set_system_time(time: SystemTime) {
+    let since_zero = ticks_to_duration(arch::counter());
+    let offset = time.0.saturating_sub(since_zero).as_nanos();
+    WALL_CLOCK_OFFSET.store(offset.min(u64::MAX as u128) as u64, Ordering::Relaxed);
+    WALL_CLOCK_SET.store(true, Ordering::Relaxed);
+}
+
+/// False until something, usually the RTC, has set the wall clock
+pub fn is_system_time_set() -> bool {
+    WALL_CLOCK_SET.load(Ordering::Relaxed)
+}
+
+/// A calendar date and time of day, UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    const WEEKDAYS: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&'static str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    /// Calendar form of `time`, the civil-from-days algorithm of Howard Hinnant
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time.unix_secs();
+        let days = (secs / SECONDS_PER_DAY) as i64 + EPOCH_DAYS;
+        let seconds = secs % SECONDS_PER_DAY;
+        let era = days.div_euclid(ERA_DAYS);
+        let day_of_era = days - era * ERA_DAYS;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Years start in March, so the leap day is the last one
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        Self {
+            year: year as u32,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// `None` before the epoch or for fields out of range
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        let valid = (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60;
+        if !valid {
+            return None;
+        }
+        let month = self.month as i64;
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month_index = (month + 9) % 12;
+        let day_of_year = (153 * month_index + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * ERA_DAYS + day_of_era - EPOCH_DAYS;
+        let secs = u64::try_from(days).ok()? * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+        Some(SystemTime::from_unix_secs(secs))
+    }
+
+    /// `YYYY-MM-DD HH:MM[:SS]`, or the date alone for midnight
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split_whitespace();
+        let mut date = parts.next()?.split('-');
+        let mut time = parts.next().unwrap_or("0:0").split(':');
+        if parts.next().is_some() {
+            return None;
+        }
+        let date_time = Self {
+            year: date.next()?.parse().ok()?,
+            month: date.next()?.parse().ok()?,
+            day: date.next()?.parse().ok()?,
+            hour: time.next()?.parse().ok()?,
+            minute: time.next()?.parse().ok()?,
+            second: time.next().map_or(Some(0), |second| second.parse().ok())?,
+        };
+        if date.next().is_some() || time.next().is_some() {
+            return None;
+        }
+        date_time.to_system_time().map(|_| date_time)
+    }
+
+    /// 0 for Sunday
+    pub fn weekday(self) -> usize {
+        let days = self
+            .to_system_time()
+            .map_or(0, |time| time.unix_secs() / SECONDS_PER_DAY);
+        // 1970-01-01 was a Thursday
+        ((days + 4) % 7) as usize
+    }
+
+    /// `date` style, e.g. `Mon Oct 19 12:34:56 UTC 2026`
+    pub fn to_long_string(self) -> String {
+        format!(
+            "{} {} {:2} {:02}:{:02}:{:02} UTC {}",
+            Self::WEEKDAYS[self.weekday()],
+            Self::MONTHS[self.month as usize - 1],
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.year
+        )
+    }
+
+    /// `ls -l` style, to the minute, e.g. `2026-10-19 12:34`
+    pub fn to_short_string(self) -> String {
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute
+        )
+    }
+}
+
+/// ISO 8601 style, e.g. `2026-10-19 12:34:56`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn days_in_month(year: u32, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * arch::counter_frequency() as u128 / 1_000_000_000;
+    ticks.min(u64::MAX as u128) as u64
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * 1_000_000_000 / arch::counter_frequency() as u128;
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
@@ -0,0 +1,149 @@
+//! One-shot and periodic kernel timers
+//!
+//! Timers expire in the timer interrupt, their callbacks then run on the
+//! workqueue so they are free to take sleeping locks and draw.
+
+use crate::arch;
+use crate::kernel::sched::{self, TaskId};
+use crate::kernel::time::{self, Instant};
+use crate::kernel::{interrupt, workqueue};
+use crate::sync::{IrqSafeSpinlock, SafeMutex, WaitQueue};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+type Periodic = Arc<SafeMutex<Box<dyn FnMut() + Send>>>;
+
+enum Callback {
+    Once(Box<dyn FnOnce() + Send>),
+    Periodic(Periodic, u64),
+    Wake(TaskId),
+}
+
+/// Pending timers ordered by deadline, then by id
+static TIMERS: IrqSafeSpinlock<BTreeMap<(u64, u64), Callback>> =
+    IrqSafeSpinlock::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Handle to a pending timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timer {
+    id: u64,
+}
+
+impl Timer {
+    /// Run `callback` once after `delay`
+    #[allow(dead_code, reason = "one-shot timers, no driver needs one yet")]
+    pub fn after(delay: Duration, callback: impl FnOnce() + Send + 'static) -> Timer {
+        add(Instant::now() + delay, Callback::Once(Box::new(callback)))
+    }
+
+    /// Run `callback` every `period` until the timer is cancelled
+    pub fn every(period: Duration, callback: impl FnMut() + Send + 'static) -> Timer {
+        let ticks = time::duration_to_ticks(period).max(1);
+        let callback: Periodic = Arc::new(SafeMutex::new(Box::new(callback)));
+        add(Instant::now() + period, Callback::Periodic(callback, ticks))
+    }
+
+    /// Stop the timer, a callback already handed to the workqueue still runs
+    pub fn cancel(self) {
+        let mut timers = TIMERS.lock();
+        let key = timers.keys().find(|(_, id)| *id == self.id).copied();
+        if let Some(key) = key {
+            timers.remove(&key);
+        }
+        program(&timers);
+    }
+}
+
+/// Block the current task for at least `duration`
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let daif = arch::local_irq_save();
+    let timer = add(deadline, Callback::Wake(sched::current()));
+    while Instant::now() < deadline {
+        sched::block_current();
+    }
+    arch::local_irq_restore(daif);
+    timer.cancel();
+}
+
+/// Park on `waiters` until `cond` holds or `timeout` has passed
+///
+/// Returns whether `cond` held in the end.
+pub fn wait_until_timeout(
+    waiters: &WaitQueue,
+    timeout: Duration,
+    mut cond: impl FnMut() -> bool,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    let timer = add(deadline, Callback::Wake(sched::current()));
+    let mut satisfied = false;
+    waiters.wait_until(|| {
+        satisfied = cond();
+        satisfied || Instant::now() >= deadline
+    });
+    timer.cancel();
+    satisfied
+}
+
+pub fn init() {
+    interrupt::register(arch::TIMER_IRQ, handle_irq);
+}
+
+fn add(deadline: Instant, callback: Callback) -> Timer {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut timers = TIMERS.lock();
+    timers.insert((deadline.ticks(), id), callback);
+    program(&timers);
+    Timer { id }
+}
+
+/// Arm the hardware timer for the earliest deadline
+fn program(timers: &BTreeMap<(u64, u64), Callback>) {
+    match timers.keys().next() {
+        Some((deadline, _)) => arch::timer_arm(*deadline),
+        None => arch::timer_disarm(),
+    }
+}
+
+fn handle_irq() {
+    let now = arch::counter();
+    let mut expired = Vec::new();
+
+    {
+        let mut timers = TIMERS.lock();
+        while let Some(entry) = timers.first_entry() {
+            let (deadline, id) = *entry.key();
+            if deadline > now {
+                break;
+            }
+            match entry.remove() {
+                Callback::Periodic(callback, period) => {
+                    // Skip missed periods instead of firing them in a burst
+                    let mut next = deadline + period;
+                    if next <= now {
+                        next = now + period;
+                    }
+                    timers.insert((next, id), Callback::Periodic(callback.clone(), period));
+                    expired.push(Callback::Periodic(callback, period));
+                }
+                callback => expired.push(callback),
+            }
+        }
+        program(&timers);
+    }
+
+    for callback in expired {
+        match callback {
+            Callback::Once(callback) => workqueue::schedule_boxed(callback),
+            Callback::Periodic(callback, _) => {
+                workqueue::schedule_work(move || (callback.lock())())
+            }
+            Callback::Wake(task) => sched::wake(task),
+        }
+    }
+}
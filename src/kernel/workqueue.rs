@@ -0,0 +1,45 @@
+//! Deferred work executed in thread context
+//!
+//! Interrupt handlers should only acknowledge their device and hand the
+//! rest of the job to [`schedule_work`], which runs it on the `kworker` task.
+
+use crate::kernel::sched;
+use crate::sync::{IrqSafeSpinlock, Semaphore};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+type Work = Box<dyn FnOnce() + Send>;
+
+static QUEUE: IrqSafeSpinlock<VecDeque<Work>> = IrqSafeSpinlock::new(VecDeque::new());
+/// One permit per queued item
+static PENDING: Semaphore = Semaphore::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Start the worker task
+pub fn init() {
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    sched::spawn("kworker", worker);
+}
+
+/// Run `work` later on the worker task, safe to call from interrupt handlers
+pub fn schedule_work(work: impl FnOnce() + Send + 'static) {
+    schedule_boxed(Box::new(work));
+}
+
+pub(crate) fn schedule_boxed(work: Work) {
+    QUEUE.lock().push_back(work);
+    PENDING.release();
+}
+
+fn worker() {
+    loop {
+        PENDING.acquire();
+        let work = QUEUE.lock().pop_front();
+        if let Some(work) = work {
+            work();
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Kernel logger
+//!
+//! Messages go to the PL011 UART, stamped with the wall-clock time of day.
+//! The UART lock is a plain spin lock so the lock debugging code can log
+//! without recursing into itself. The most recent [`HISTORY_SIZE`] bytes
+//! are also kept for [`read_history`], which is how the log reaches a
+//! virtio-console port.
+
+use crate::arch;
+use crate::drivers::uart::UART;
+use crate::kernel::time::{DateTime, SystemTime};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+
+pub const HISTORY_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+struct UartWriter<'a>(&'a crate::drivers::uart::Uart);
+
+impl Write for UartWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.0.putc(b'\r');
+            }
+            self.0.putc(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Time of day in front of every message
+struct Stamp(DateTime);
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = &self.0;
+        write!(f, "{:02}:{:02}:{:02}", time.hour, time.minute, time.second)
+    }
+}
+
+/// Ring buffer of everything logged, also a plain spin lock
+struct History {
+    data: [u8; HISTORY_SIZE],
+    /// Bytes ever written, the ring holds the last `HISTORY_SIZE` of them
+    written: u64,
+}
+
+impl Write for History {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.data[(self.written % HISTORY_SIZE as u64) as usize] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    data: [0; HISTORY_SIZE],
+    written: 0,
+});
+
+/// Copy logged bytes from `position` on, the oldest kept ones if it fell
+/// behind, and move `position` past them
+pub fn read_history(position: &mut u64, buffer: &mut [u8]) -> usize {
+    let daif = arch::local_irq_save();
+    let len = {
+        let history = HISTORY.lock();
+        let oldest = history.written.saturating_sub(HISTORY_SIZE as u64);
+        *position = (*position).clamp(oldest, history.written);
+        let len = ((history.written - *position) as usize).min(buffer.len());
+        for (i, byte) in buffer[..len].iter_mut().enumerate() {
+            *byte = history.data[((*position + i as u64) % HISTORY_SIZE as u64) as usize];
+        }
+        *position += len as u64;
+        len
+    };
+    arch::local_irq_restore(daif);
+    len
+}
+
+pub fn log(level: Level, args: fmt::Arguments) {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let stamp = Stamp(DateTime::from_system_time(SystemTime::now()));
+    let daif = arch::local_irq_save();
+    {
+        let mut uart = UART.lock();
+        uart.init();
+        let mut writer = UartWriter(&uart);
+        let _ = writeln!(writer, "{} [{}] {}", stamp, level.label(), args);
+    }
+    let _ = writeln!(HISTORY.lock(), "{} [{}] {}", stamp, level.label(), args);
+    arch::local_irq_restore(daif);
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => ($crate::logger::log($crate::logger::Level::Error, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => ($crate::logger::log($crate::logger::Level::Warn, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => ($crate::logger::log($crate::logger::Level::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => ($crate::logger::log($crate::logger::Level::Debug, format_args!($($arg)*)));
+}
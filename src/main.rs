@@ -5,78 +5,135 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
-use linked_list_allocator::LockedHeap;
+use kernel::heap::KernelHeap;
 
+mod arch;
+mod console;
 mod drivers;
+mod fs;
+mod kernel;
+mod logger;
+mod net;
+mod sync;
 mod ui;
 
-use drivers::{GPU, KEYBOARD, MOUSE};
+use drivers::display::DisplayEvent;
+use drivers::mouse::PointerEventKind;
+use drivers::{DISPLAY, KEYBOARD, MOUSE};
 use ui::Terminal;
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
-
-// Change parameter type from char to u32
-#[no_mangle]
-pub extern "C" fn keyboard_handler(keycode: u32) {
-    // Convert keycode to char if needed
-    if let Some(key) = char::from_u32(keycode) {
-        KEYBOARD.lock().push_key(key);
-    }
-}
-
-#[no_mangle]
-pub extern "C" fn mouse_handler(dx: i32, dy: i32, buttons: u8) {
-    MOUSE.lock().handle_interrupt(dx, dy, buttons);
-}
+static ALLOCATOR: KernelHeap = KernelHeap::empty();
 
 #[no_mangle]
 pub extern "C" fn _kernel_main() -> ! {
     // Initialize heap
     unsafe {
         let heap_start = 0x4100_0000 as *mut u8;
-        let heap_size = 1024 * 1024; // 1MB
-        ALLOCATOR.lock().init(heap_start, heap_size);
+        let heap_size = 64 * 1024 * 1024; // 64MB, framebuffers live here too
+        ALLOCATOR.init(heap_start, heap_size);
     }
 
+    // Interrupts, scheduler, timers and the workqueue
+    kernel::init();
+
     // Initialize hardware
-    GPU.lock().init();
+    DISPLAY.lock().init(ui::DESKTOP_COLOR);
+    drivers::virtio::input::init();
+    drivers::virtio::blk::init();
+    drivers::virtio::rng::init();
+    drivers::virtio::console::init();
+    fs::init();
+    ui::shell::init();
+    drivers::virtio::net::init();
+    net::init();
+    kernel::sched::spawn("hvcd", ui::hvc::serve);
     KEYBOARD.lock().init();
     MOUSE.lock().init();
 
-    // Create terminal
-    let mut terminal = Terminal::new(50, 50, 700, 500);
-
-    // Draw initial UI
-    let mut gpu = GPU.lock();
-    gpu.clear_screen(0x00336699);
+    // Create terminal, sized for the current display mode
+    let (width, height) = {
+        let display = DISPLAY.lock();
+        (display.width(), display.height())
+    };
+    MOUSE.lock().set_bounds(width, height);
+    let (x, y, w, h) = terminal_geometry(width, height);
+    let mut terminal = Terminal::new(x, y, w, h);
+
+    // Draw initial UI, the terminal takes the display lock itself
+    DISPLAY.lock().clear_screen(ui::DESKTOP_COLOR);
     terminal.draw();
-    drop(gpu); // Release the lock
+    DISPLAY.lock().flush_damage();
 
     // Main event loop
     loop {
-        // Handle keyboard input
-        if let Some(key) = KEYBOARD.lock().read_key() {
-            terminal.handle_key(key);
+        // Lay the UI out again after a mode change
+        let event = DISPLAY.lock().poll_event();
+        if let Some(DisplayEvent::Resized { width, height }) = event {
+            MOUSE.lock().set_bounds(width, height);
+            let (x, y, w, h) = terminal_geometry(width, height);
+            terminal.set_geometry(x, y, w, h);
+            DISPLAY.lock().clear_screen(ui::DESKTOP_COLOR);
+            terminal.draw();
         }
 
-        // Handle mouse input
-        if let Some((x, y, buttons)) = MOUSE.lock().poll() {
-            terminal.handle_mouse(x, y, buttons);
+        // Handle keyboard input without holding the lock while drawing
+        drivers::virtio::input::poll();
+        loop {
+            let event = KEYBOARD.lock().read_event();
+            let Some(event) = event else {
+                break;
+            };
+            terminal.handle_key_event(event);
+        }
 
-            // Draw cursor
-            let mut gpu = GPU.lock();
-            gpu.draw_rect(x as u32, y as u32, 5, 5, 0x00FFFFFF);
+        // Handle mouse input
+        loop {
+            let event = MOUSE.lock().poll();
+            let Some(event) = event else {
+                break;
+            };
+            match event.kind {
+                PointerEventKind::Scroll { vertical, .. } => terminal.handle_scroll(vertical),
+                PointerEventKind::Move => {
+                    DISPLAY.lock().move_cursor(event.x as u32, event.y as u32);
+                    terminal.handle_mouse(event.x, event.y, event.buttons.bits());
+                }
+                _ => terminal.handle_mouse(event.x, event.y, event.buttons.bits()),
+            }
         }
+
+        // Show whatever was drawn this round
+        DISPLAY.lock().flush_damage();
+
+        // Give deferred work and timer callbacks a chance to run
+        kernel::sched::yield_now();
     }
 }
 
+/// Terminal placement: a 50 pixel margin, but never smaller than 320x200
+fn terminal_geometry(width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let w = width.saturating_sub(100).max(320).min(width);
+    let h = height.saturating_sub(100).max(200).min(height);
+    ((width - w).min(50), (height - h).min(50), w, h)
+}
+
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    log_error!("kernel panic: {}", info);
+    loop {
+        unsafe { arch::wfi() };
+    }
 }
 
 #[alloc_error_handler]
-fn alloc_error_handler(_layout: core::alloc::Layout) -> ! {
-    loop {}
+fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+    log_error!(
+        "out of memory allocating {} bytes (align {})",
+        layout.size(),
+        layout.align()
+    );
+    loop {
+        unsafe { arch::wfi() };
+    }
 }
@@ -0,0 +1,171 @@
+//! Address Resolution Protocol
+//!
+//! Maps IPv4 addresses on the local network to Ethernet addresses. Answers
+//! are kept in a cache for [`ENTRY_LIFETIME`]; a sender that asks for our
+//! address is added right away since we are about to talk to it. The cache
+//! is shown in `/proc/arp`.
+
+use super::{ethernet, Error, Interface, Ipv4Addr, Result};
+use crate::drivers::net::MacAddress;
+use crate::kernel::time::Instant;
+use crate::kernel::timer;
+use crate::sync::{SafeMutex, WaitQueue};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::Write;
+use core::time::Duration;
+
+const ENTRY_LIFETIME: Duration = Duration::from_secs(300);
+/// Requests sent before giving up on a host
+const RETRIES: usize = 3;
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+const PACKET_LEN: usize = 28;
+const HARDWARE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+struct Entry {
+    mac: MacAddress,
+    updated: Instant,
+}
+
+static CACHE: SafeMutex<BTreeMap<Ipv4Addr, Entry>> = SafeMutex::new(BTreeMap::new());
+/// Tasks waiting for an answer
+static RESOLVE_WAITERS: WaitQueue = WaitQueue::new();
+
+struct Packet {
+    operation: u16,
+    sender_mac: MacAddress,
+    sender_ip: Ipv4Addr,
+    target_mac: MacAddress,
+    target_ip: Ipv4Addr,
+}
+
+impl Packet {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < PACKET_LEN {
+            return None;
+        }
+        let hardware = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let protocol = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if hardware != HARDWARE_ETHERNET
+            || protocol != ethernet::TYPE_IPV4
+            || bytes[4] != 6
+            || bytes[5] != 4
+        {
+            return None;
+        }
+        Some(Self {
+            operation: u16::from_be_bytes([bytes[6], bytes[7]]),
+            sender_mac: MacAddress(bytes[8..14].try_into().ok()?),
+            sender_ip: Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[14..18]).ok()?),
+            target_mac: MacAddress(bytes[18..24].try_into().ok()?),
+            target_ip: Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[24..28]).ok()?),
+        })
+    }
+
+    fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut bytes = [0; PACKET_LEN];
+        bytes[0..2].copy_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        bytes[2..4].copy_from_slice(&ethernet::TYPE_IPV4.to_be_bytes());
+        bytes[4] = 6;
+        bytes[5] = 4;
+        bytes[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        bytes[8..14].copy_from_slice(&self.sender_mac.0);
+        bytes[14..18].copy_from_slice(&self.sender_ip.octets());
+        bytes[18..24].copy_from_slice(&self.target_mac.0);
+        bytes[24..28].copy_from_slice(&self.target_ip.octets());
+        bytes
+    }
+}
+
+/// The cached hardware address of `address`
+pub fn lookup(address: Ipv4Addr) -> Option<MacAddress> {
+    let cache = CACHE.lock();
+    let entry = cache.get(&address)?;
+    (entry.updated.elapsed() < ENTRY_LIFETIME).then_some(entry.mac)
+}
+
+fn insert(address: Ipv4Addr, mac: MacAddress) {
+    CACHE.lock().insert(
+        address,
+        Entry {
+            mac,
+            updated: Instant::now(),
+        },
+    );
+    RESOLVE_WAITERS.wake_all();
+}
+
+/// The hardware address of `address` on the interface's network, asking
+/// for it if it is not cached
+pub fn resolve(interface: &Interface, address: Ipv4Addr) -> Result<MacAddress> {
+    let config = interface.config();
+    if address.is_broadcast() || address == config.broadcast() {
+        return Ok(MacAddress::BROADCAST);
+    }
+    if let Some(mac) = lookup(address) {
+        return Ok(mac);
+    }
+    let request = Packet {
+        operation: OP_REQUEST,
+        sender_mac: interface.mac_address(),
+        sender_ip: config.address,
+        target_mac: MacAddress::ZERO,
+        target_ip: address,
+    };
+    for _ in 0..RETRIES {
+        interface.send_frame(MacAddress::BROADCAST, ethernet::TYPE_ARP, &request.encode())?;
+        if timer::wait_until_timeout(&RESOLVE_WAITERS, RETRY_INTERVAL, || {
+            lookup(address).is_some()
+        }) {
+            return lookup(address).ok_or(Error::HostUnreachable);
+        }
+    }
+    Err(Error::HostUnreachable)
+}
+
+/// Learn from a received packet and answer requests for our address
+pub fn receive(interface: &Interface, payload: &[u8]) {
+    let Some(packet) = Packet::parse(payload) else {
+        interface.count_dropped();
+        return;
+    };
+    let config = interface.config();
+    let for_us = config.is_configured() && packet.target_ip == config.address;
+    let known = CACHE.lock().contains_key(&packet.sender_ip);
+    if (for_us || known) && !packet.sender_ip.is_unspecified() {
+        insert(packet.sender_ip, packet.sender_mac);
+    }
+
+    if for_us && packet.operation == OP_REQUEST {
+        let reply = Packet {
+            operation: OP_REPLY,
+            sender_mac: interface.mac_address(),
+            sender_ip: config.address,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        if let Err(err) =
+            interface.send_frame(packet.sender_mac, ethernet::TYPE_ARP, &reply.encode())
+        {
+            crate::log_warn!("arp: {}: reply failed: {}", interface.name(), err);
+        }
+    }
+}
+
+/// Contents of `/proc/arp`
+pub fn render_cache() -> String {
+    let mut text = String::from("address          hardware address   age\n");
+    for (address, entry) in CACHE.lock().iter() {
+        let _ = writeln!(
+            text,
+            "{:<16} {} {}s",
+            address,
+            entry.mac,
+            entry.updated.elapsed().as_secs()
+        );
+    }
+    text
+}
@@ -0,0 +1,312 @@
+//! DHCP client
+//!
+//! Each interface gets a task that broadcasts a DISCOVER, requests the
+//! first address offered and configures the interface from the ACK. Half
+//! way through the lease it asks the server to renew; once the lease runs
+//! out without a renewal the interface loses its address and the task
+//! starts over.
+
+use super::udp::UdpSocket;
+use super::{Config, Error, Interface, Ipv4Addr, Result, SocketAddrV4};
+use crate::kernel::random;
+use crate::kernel::time::Instant;
+use crate::kernel::timer;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+/// Attempts per message before giving up
+const RETRIES: usize = 4;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Wait before starting over when no server answers
+const RESTART_DELAY: Duration = Duration::from_secs(30);
+/// Lease assumed when the server does not say
+const DEFAULT_LEASE: Duration = Duration::from_secs(3600);
+const HOSTNAME: &[u8] = b"nyannix";
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HARDWARE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed part of a message, up to the magic cookie
+const FIXED_LEN: usize = 236;
+
+// Options
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_HOSTNAME: u8 = 12;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+// Message types
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+/// What the server handed out
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    config: Config,
+    server: Ipv4Addr,
+    duration: Duration,
+}
+
+/// The parts of a server reply we use
+#[derive(Debug, Default)]
+struct Reply {
+    message_type: u8,
+    your_address: Option<Ipv4Addr>,
+    server: Option<Ipv4Addr>,
+    netmask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns_server: Option<Ipv4Addr>,
+    lease: Option<Duration>,
+}
+
+impl Reply {
+    fn parse(bytes: &[u8], xid: u32) -> Option<Self> {
+        if bytes.len() < FIXED_LEN + MAGIC_COOKIE.len()
+            || bytes[0] != OP_REPLY
+            || bytes[4..8] != xid.to_be_bytes()
+            || bytes[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let address =
+            |bytes: &[u8]| Some(Ipv4Addr::from(<[u8; 4]>::try_from(bytes.get(..4)?).ok()?));
+        let mut reply = Reply {
+            your_address: address(&bytes[16..20]).filter(|a| !a.is_unspecified()),
+            ..Reply::default()
+        };
+
+        let mut options = &bytes[FIXED_LEN + 4..];
+        while let [code, rest @ ..] = options {
+            match *code {
+                OPTION_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let [len, rest @ ..] = rest else { break };
+            let Some(value) = rest.get(..*len as usize) else {
+                break;
+            };
+            match *code {
+                OPTION_MESSAGE_TYPE => reply.message_type = *value.first()?,
+                OPTION_SUBNET_MASK => reply.netmask = address(value),
+                OPTION_ROUTER => reply.router = address(value),
+                OPTION_DNS => reply.dns_server = address(value),
+                OPTION_SERVER_ID => reply.server = address(value),
+                OPTION_LEASE_TIME => {
+                    let seconds = u32::from_be_bytes(value.get(..4)?.try_into().ok()?);
+                    reply.lease = Some(Duration::from_secs(seconds as u64));
+                }
+                _ => {}
+            }
+            options = &rest[*len as usize..];
+        }
+        Some(reply)
+    }
+
+    fn lease(&self) -> Option<Lease> {
+        let netmask = u32::from(self.netmask.unwrap_or(Ipv4Addr::new(255, 255, 255, 0)));
+        Some(Lease {
+            config: Config {
+                address: self.your_address?,
+                prefix_len: netmask.leading_ones() as u8,
+                gateway: self.router,
+                dns_server: self.dns_server,
+            },
+            server: self.server?,
+            duration: self.lease.unwrap_or(DEFAULT_LEASE),
+        })
+    }
+}
+
+/// A client message; `client` is our current address when renewing
+fn build(
+    interface: &Interface,
+    xid: u32,
+    message_type: u8,
+    client: Ipv4Addr,
+    options: &[(u8, &[u8])],
+) -> Vec<u8> {
+    let mut message = vec![0; FIXED_LEN];
+    message[0] = OP_REQUEST;
+    message[1] = HARDWARE_ETHERNET;
+    message[2] = 6;
+    message[4..8].copy_from_slice(&xid.to_be_bytes());
+    // Without an address we cannot receive unicast replies
+    if client.is_unspecified() {
+        message[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    }
+    message[12..16].copy_from_slice(&client.octets());
+    message[28..34].copy_from_slice(&interface.mac_address().0);
+    message.extend_from_slice(&MAGIC_COOKIE);
+
+    let parameters = [
+        OPTION_SUBNET_MASK,
+        OPTION_ROUTER,
+        OPTION_DNS,
+        OPTION_LEASE_TIME,
+    ];
+    let common: [(u8, &[u8]); 3] = [
+        (OPTION_MESSAGE_TYPE, &[message_type]),
+        (OPTION_HOSTNAME, HOSTNAME),
+        (OPTION_PARAMETERS, &parameters),
+    ];
+    for (code, value) in common.iter().chain(options) {
+        message.push(*code);
+        message.push(value.len() as u8);
+        message.extend_from_slice(value);
+    }
+    message.push(OPTION_END);
+    message
+}
+
+/// Send `message` to `destination` until a reply of one of `expected`
+/// types arrives
+fn exchange(
+    socket: &UdpSocket,
+    message: &[u8],
+    destination: Ipv4Addr,
+    xid: u32,
+    expected: &[u8],
+) -> Result<Reply> {
+    let mut buffer = vec![0; 1500];
+    for _ in 0..RETRIES {
+        socket.send_to(message, SocketAddrV4::new(destination, SERVER_PORT))?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        while Instant::now() < deadline {
+            let Ok((len, _)) = socket.recv_from(&mut buffer) else {
+                break;
+            };
+            let reply = Reply::parse(&buffer[..len], xid);
+            if let Some(reply) = reply.filter(|r| expected.contains(&r.message_type)) {
+                return Ok(reply);
+            }
+        }
+    }
+    Err(Error::TimedOut)
+}
+
+/// DISCOVER, OFFER, REQUEST, ACK
+fn obtain(socket: &UdpSocket, interface: &Interface) -> Result<Lease> {
+    let xid = random::next_u32();
+    let discover = build(interface, xid, DISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+    let offer = exchange(socket, &discover, Ipv4Addr::BROADCAST, xid, &[OFFER])?;
+    let (Some(address), Some(server)) = (offer.your_address, offer.server) else {
+        return Err(Error::TimedOut);
+    };
+
+    let request = build(
+        interface,
+        xid,
+        REQUEST,
+        Ipv4Addr::UNSPECIFIED,
+        &[
+            (OPTION_REQUESTED_IP, &address.octets()),
+            (OPTION_SERVER_ID, &server.octets()),
+        ],
+    );
+    match exchange(socket, &request, Ipv4Addr::BROADCAST, xid, &[ACK, NAK])? {
+        reply if reply.message_type == ACK => reply.lease().ok_or(Error::TimedOut),
+        _ => Err(Error::HostUnreachable),
+    }
+}
+
+/// Ask the server that gave us `lease` to extend it
+fn renew(socket: &UdpSocket, interface: &Interface, lease: &Lease) -> Result<Lease> {
+    let xid = random::next_u32();
+    let request = build(interface, xid, REQUEST, lease.config.address, &[]);
+    match exchange(socket, &request, lease.server, xid, &[ACK, NAK])? {
+        reply if reply.message_type == ACK => reply.lease().ok_or(Error::TimedOut),
+        _ => Err(Error::HostUnreachable),
+    }
+}
+
+/// Keep `interface` configured, runs as the interface's DHCP task
+///
+/// If the first attempt fails the interface gets `fallback` and the task
+/// ends.
+pub fn run(interface: Arc<Interface>, fallback: Option<Config>) {
+    let mut socket = match UdpSocket::bind(CLIENT_PORT) {
+        Ok(socket) => socket,
+        Err(err) => {
+            crate::log_error!("dhcp: {}: {}", interface.name(), err);
+            return;
+        }
+    };
+    socket.bind_to_interface(interface.clone());
+    socket.set_read_timeout(Some(REPLY_TIMEOUT));
+    let mut first = true;
+
+    loop {
+        let mut lease = match obtain(&socket, &interface) {
+            Ok(lease) => lease,
+            Err(err) => {
+                crate::log_warn!("dhcp: {}: no lease: {}", interface.name(), err);
+                if first {
+                    if let Some(config) = fallback {
+                        interface.set_config(config);
+                        return;
+                    }
+                }
+                first = false;
+                timer::sleep(RESTART_DELAY);
+                continue;
+            }
+        };
+        first = false;
+        crate::log_info!(
+            "dhcp: {}: {} from {} for {}s",
+            interface.name(),
+            lease.config.address,
+            lease.server,
+            lease.duration.as_secs()
+        );
+        interface.set_config(lease.config);
+
+        // Renew half way through, then again half way through what is left
+        let mut expires = Instant::now() + lease.duration;
+        loop {
+            let remaining = expires.duration_since(Instant::now());
+            if remaining < REPLY_TIMEOUT * RETRIES as u32 {
+                break;
+            }
+            timer::sleep(remaining / 2);
+            match renew(&socket, &interface, &lease) {
+                Ok(renewed) => {
+                    if renewed.config != lease.config {
+                        interface.set_config(renewed.config);
+                    }
+                    lease = renewed;
+                    expires = Instant::now() + lease.duration;
+                }
+                Err(err) => crate::log_warn!("dhcp: {}: renew failed: {}", interface.name(), err),
+            }
+        }
+        timer::sleep(expires.duration_since(Instant::now()));
+        crate::log_warn!(
+            "dhcp: {}: lease of {} expired",
+            interface.name(),
+            lease.config.address
+        );
+        interface.set_config(Config::UNCONFIGURED);
+    }
+}
@@ -0,0 +1,189 @@
+//! Stub DNS resolver
+//!
+//! Names are looked up with a recursive query for A records to the DNS
+//! server of the first interface that has one, slirp's 10.0.2.3 by default.
+//! Answers are cached for their TTL, within [`MIN_TTL`] and [`MAX_TTL`];
+//! the cache is shown in `/proc/dns`.
+
+use super::udp::UdpSocket;
+use super::{interfaces, Error, Ipv4Addr, Result, SocketAddrV4, DEFAULT_CONFIG};
+use crate::kernel::random;
+use crate::kernel::time::Instant;
+use crate::sync::SafeMutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+
+const PORT: u16 = 53;
+const HEADER_LEN: usize = 12;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+const RETRIES: usize = 3;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const MIN_TTL: Duration = Duration::from_secs(10);
+const MAX_TTL: Duration = Duration::from_secs(3600);
+/// Longest name, RFC 1035
+const MAX_NAME_LEN: usize = 253;
+
+/// Resolved names and when they expire
+static CACHE: SafeMutex<BTreeMap<String, (Ipv4Addr, Instant)>> = SafeMutex::new(BTreeMap::new());
+
+/// The server queries go to
+fn server() -> Ipv4Addr {
+    interfaces()
+        .iter()
+        .find_map(|interface| interface.config().dns_server)
+        .or(DEFAULT_CONFIG.dns_server)
+        .unwrap_or(Ipv4Addr::UNSPECIFIED)
+}
+
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::NameNotFound);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Offset just past the name at `offset`, which may end in a pointer
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            // A compression pointer ends the name
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len,
+        }
+    }
+}
+
+/// The first A record of a response and its TTL, `None` if it is not ours
+fn parse_response(message: &[u8], id: u16) -> Option<Result<(Ipv4Addr, Duration)>> {
+    let word = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([
+            *message.get(offset)?,
+            *message.get(offset + 1)?,
+        ]))
+    };
+    if message.len() < HEADER_LEN || word(0)? != id {
+        return None;
+    }
+    let flags = word(2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    if flags & RCODE_MASK != 0 {
+        return Some(Err(Error::NameNotFound));
+    }
+    let questions = word(4)?;
+    let answers = word(6)?;
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = skip_name(message, offset)? + 4;
+    }
+    // CNAME records come first, the A record of their target follows
+    for _ in 0..answers {
+        offset = skip_name(message, offset)?;
+        let record_type = word(offset)?;
+        let class = word(offset + 2)?;
+        let ttl = u32::from_be_bytes(message.get(offset + 4..offset + 8)?.try_into().ok()?);
+        let len = word(offset + 8)? as usize;
+        let data = message.get(offset + 10..offset + 10 + len)?;
+        if record_type == TYPE_A && class == CLASS_IN && len == 4 {
+            let address = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            return Some(Ok((address, Duration::from_secs(ttl as u64))));
+        }
+        offset += 10 + len;
+    }
+    Some(Err(Error::NameNotFound))
+}
+
+/// Ask the server about `name`
+fn query(name: &str) -> Result<(Ipv4Addr, Duration)> {
+    // A random id makes forged answers harder to slip in
+    let id = random::next_u32() as u16;
+    let query = build_query(id, name)?;
+    let server = SocketAddrV4::new(server(), PORT);
+    let mut socket = UdpSocket::bind(0)?;
+    socket.set_read_timeout(Some(REPLY_TIMEOUT));
+
+    let mut buffer = vec![0; 512];
+    for _ in 0..RETRIES {
+        socket.send_to(&query, server)?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        while Instant::now() < deadline {
+            let Ok((len, source)) = socket.recv_from(&mut buffer) else {
+                break;
+            };
+            if source != server {
+                continue;
+            }
+            if let Some(result) = parse_response(&buffer[..len], id) {
+                return result;
+            }
+        }
+    }
+    Err(Error::TimedOut)
+}
+
+/// The address of `name`, which may also be a dotted address
+pub fn resolve(name: &str) -> Result<Ipv4Addr> {
+    if let Ok(address) = name.parse() {
+        return Ok(address);
+    }
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::NameNotFound);
+    }
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+    let now = Instant::now();
+    if let Some((address, expires)) = CACHE.lock().get(&name) {
+        if now < *expires {
+            return Ok(*address);
+        }
+    }
+    let (address, ttl) = query(&name)?;
+    let expires = Instant::now() + ttl.clamp(MIN_TTL, MAX_TTL);
+    CACHE.lock().insert(name, (address, expires));
+    Ok(address)
+}
+
+/// Contents of `/proc/dns`
+pub fn render_cache() -> String {
+    let now = Instant::now();
+    let mut text = String::from("name                             address          expires\n");
+    let mut cache = CACHE.lock();
+    cache.retain(|_, (_, expires)| now < *expires);
+    for (name, (address, expires)) in cache.iter() {
+        let _ = writeln!(
+            text,
+            "{:<32} {:<16} {}s",
+            name,
+            address,
+            expires.duration_since(now).as_secs()
+        );
+    }
+    text
+}
@@ -0,0 +1,69 @@
+//! Ethernet II framing
+//!
+//! A frame is the destination and source address, the type of the payload
+//! and the payload. Frames shorter than the Ethernet minimum are padded
+//! with zeros; the card adds the frame check sequence.
+
+use super::{arp, ipv4, Interface};
+use crate::drivers::net::MacAddress;
+use alloc::vec::Vec;
+
+pub const HEADER_LEN: usize = 14;
+/// Smallest frame without the check sequence
+const MIN_FRAME_LEN: usize = 60;
+
+pub const TYPE_IPV4: u16 = 0x0800;
+pub const TYPE_ARP: u16 = 0x0806;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ether_type: u16,
+}
+
+/// Split a frame into its header and payload
+pub fn parse(frame: &[u8]) -> Option<(Header, &[u8])> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let header = Header {
+        destination: MacAddress(frame[0..6].try_into().ok()?),
+        source: MacAddress(frame[6..12].try_into().ok()?),
+        ether_type: u16::from_be_bytes([frame[12], frame[13]]),
+    };
+    Some((header, &frame[HEADER_LEN..]))
+}
+
+pub fn build(
+    destination: MacAddress,
+    source: MacAddress,
+    ether_type: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity((HEADER_LEN + payload.len()).max(MIN_FRAME_LEN));
+    frame.extend_from_slice(&destination.0);
+    frame.extend_from_slice(&source.0);
+    frame.extend_from_slice(&ether_type.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.resize(frame.len().max(MIN_FRAME_LEN), 0);
+    frame
+}
+
+/// Hand a received frame to the protocol it carries
+pub fn receive(interface: &Interface, frame: &[u8]) {
+    let Some((header, payload)) = parse(frame) else {
+        interface.count_dropped();
+        return;
+    };
+    // Other hosts' traffic and multicast groups we never joined
+    if header.destination != interface.mac_address() && !header.destination.is_broadcast() {
+        return;
+    }
+    interface.count_received(frame.len());
+    match header.ether_type {
+        TYPE_ARP => arp::receive(interface, payload),
+        TYPE_IPV4 => ipv4::receive(interface, header.source, payload),
+        _ => interface.count_dropped(),
+    }
+}
@@ -0,0 +1,322 @@
+//! HTTP/1.1 client
+//!
+//! [`get`] sends one GET request per connection, with `Connection: close`,
+//! and follows redirects. Bodies come with a Content-Length, in chunks, or
+//! run until the server closes the connection.
+
+use super::dns;
+use super::tcp::TcpStream;
+use super::SocketAddrV4;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+const DEFAULT_PORT: u16 = 80;
+const USER_AGENT: &str = "NyanNix-wget/0.1";
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+/// Longest status or header line
+const MAX_LINE_LEN: usize = 8192;
+/// Largest body we keep in memory
+const MAX_BODY_LEN: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not an `http://` URL
+    InvalidUrl,
+    /// The server does not speak HTTP/1.x
+    BadResponse,
+    TooManyRedirects,
+    /// The body is larger than [`MAX_BODY_LEN`]
+    TooLarge,
+    Net(super::Error),
+}
+
+impl From<super::Error> for Error {
+    fn from(err: super::Error) -> Self {
+        Error::Net(err)
+    }
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::InvalidUrl => "Invalid URL",
+            Error::BadResponse => "Bad response",
+            Error::TooManyRedirects => "Too many redirects",
+            Error::TooLarge => "Response too large",
+            Error::Net(err) => err.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An `http://host[:port]/path` URL, the scheme may be left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(text: &str) -> Result<Url> {
+        let rest = match text.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some(_) => return Err(Error::InvalidUrl),
+            None => text,
+        };
+        let rest = rest.split('#').next().unwrap_or_default();
+        let split = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(split);
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| Error::InvalidUrl)?),
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl);
+        }
+        let path = match path {
+            "" => String::from("/"),
+            path if path.starts_with('?') => format!("/{}", path),
+            path => String::from(path),
+        };
+        Ok(Url {
+            host: String::from(host),
+            port,
+            path,
+        })
+    }
+
+    /// Where a `Location` header points, relative to this URL
+    fn join(&self, location: &str) -> Result<Url> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        let path = if location.starts_with('/') {
+            String::from(location)
+        } else {
+            let directory = self.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+            format!("{}/{}", directory, location)
+        };
+        Ok(Url {
+            path,
+            ..self.clone()
+        })
+    }
+
+    /// Last part of the path, what `wget` saves to
+    pub fn file_name(&self) -> Option<&str> {
+        let path = self.path.split('?').next().unwrap_or_default();
+        path.rsplit('/').next().filter(|name| !name.is_empty())
+    }
+
+    /// The Host header, with the port only if it is not the default
+    fn host_header(&self) -> String {
+        match self.port {
+            DEFAULT_PORT => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}{}", self.host_header(), self.path)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Value of the first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Buffered reads from a connection
+struct Reader<'a> {
+    stream: &'a TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(stream: &'a TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Read more into the buffer, false once the server has closed
+    fn fill(&mut self) -> Result<bool> {
+        let mut chunk = [0; 1500];
+        let len = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..len]);
+        Ok(len > 0)
+    }
+
+    /// The next line without its line ending
+    fn read_line(&mut self) -> Result<String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Ok(String::from(line.trim_end_matches(['\r', '\n'])));
+            }
+            if self.buffer.len() > MAX_LINE_LEN || !self.fill()? {
+                return Err(Error::BadResponse);
+            }
+        }
+    }
+
+    fn read_exact(&mut self, len: usize, body: &mut Vec<u8>) -> Result<()> {
+        if body.len().checked_add(len).is_none_or(|end| end > MAX_BODY_LEN) {
+            return Err(Error::TooLarge);
+        }
+        while self.buffer.len() < len {
+            if !self.fill()? {
+                return Err(Error::BadResponse);
+            }
+        }
+        body.extend(self.buffer.drain(..len));
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, body: &mut Vec<u8>) -> Result<()> {
+        loop {
+            body.append(&mut self.buffer);
+            if body.len() > MAX_BODY_LEN {
+                return Err(Error::TooLarge);
+            }
+            if !self.fill()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// A chunked body, RFC 9112 section 7.1
+    fn read_chunked(&mut self, body: &mut Vec<u8>) -> Result<()> {
+        loop {
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| Error::BadResponse)?;
+            if size == 0 {
+                break;
+            }
+            self.read_exact(size, body)?;
+            if !self.read_line()?.is_empty() {
+                return Err(Error::BadResponse);
+            }
+        }
+        // Trailer fields up to an empty line
+        while !self.read_line()?.is_empty() {}
+        Ok(())
+    }
+
+    /// Status line and header fields
+    fn read_head(&mut self) -> Result<Response> {
+        let line = self.read_line()?;
+        let mut parts = line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        if !version.starts_with("HTTP/1.") {
+            return Err(Error::BadResponse);
+        }
+        let status = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or(Error::BadResponse)?;
+        let reason = String::from(parts.next().unwrap_or_default());
+
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or(Error::BadResponse)?;
+            headers.push((String::from(name.trim()), String::from(value.trim())));
+        }
+        Ok(Response {
+            status,
+            reason,
+            headers,
+            body: Vec::new(),
+        })
+    }
+}
+
+/// One request and its response, without following redirects
+fn request(url: &Url) -> Result<Response> {
+    let address = dns::resolve(&url.host)?;
+    let mut stream = TcpStream::connect(SocketAddrV4::new(address, url.port))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT));
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+        Host: {}\r\n\
+        User-Agent: {}\r\n\
+        Accept: */*\r\n\
+        Connection: close\r\n\r\n",
+        url.path,
+        url.host_header(),
+        USER_AGENT
+    );
+    stream.write(request.as_bytes())?;
+
+    let mut reader = Reader::new(&stream);
+    let mut response = reader.read_head()?;
+    // Interim responses come before the real one
+    while (100..200).contains(&response.status) {
+        response = reader.read_head()?;
+    }
+
+    let mut body = Vec::new();
+    let chunked = response
+        .header("Transfer-Encoding")
+        .is_some_and(|coding| coding.to_ascii_lowercase().contains("chunked"));
+    if response.status == 204 || response.status == 304 {
+        // No body
+    } else if chunked {
+        reader.read_chunked(&mut body)?;
+    } else if let Some(len) = response.header("Content-Length") {
+        let len = len.parse().map_err(|_| Error::BadResponse)?;
+        reader.read_exact(len, &mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    response.body = body;
+    Ok(response)
+}
+
+/// Fetch `url`, following up to [`MAX_REDIRECTS`] redirects
+pub fn get(url: &Url) -> Result<Response> {
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let response = request(&url)?;
+        let location = response.header("Location");
+        match (response.status, location) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) => url = url.join(location)?,
+            _ => return Ok(response),
+        }
+    }
+    Err(Error::TooManyRedirects)
+}
@@ -0,0 +1,122 @@
+//! Internet Control Message Protocol, echo only
+//!
+//! Echo requests are answered with the same identifier, sequence number
+//! and data. Echo replies are kept until the [`ping`] that sent the
+//! matching request picks them up.
+
+use super::ipv4::{self, Packet};
+use super::{checksum, Error, Interface, Ipv4Addr, Result};
+use crate::kernel::time::Instant;
+use crate::kernel::timer;
+use crate::sync::{SafeMutex, WaitQueue};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::time::Duration;
+
+const HEADER_LEN: usize = 8;
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// Replies kept for pings that gave up waiting are dropped beyond this
+const MAX_PENDING_REPLIES: usize = 16;
+
+/// A received echo reply
+#[derive(Debug, Clone, Copy)]
+pub struct EchoReply {
+    pub source: Ipv4Addr,
+    pub identifier: u16,
+    pub sequence: u16,
+    pub ttl: u8,
+    /// Bytes of ICMP data, without the header
+    pub len: usize,
+    pub received: Instant,
+}
+
+static REPLIES: SafeMutex<Vec<EchoReply>> = SafeMutex::new(Vec::new());
+static REPLY_WAITERS: WaitQueue = WaitQueue::new();
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(1);
+
+fn build(type_: u8, identifier: u16, sequence: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&[type_, 0, 0, 0]);
+    message.extend_from_slice(&identifier.to_be_bytes());
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(data);
+    let sum = checksum(&[&message]);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+pub fn receive(interface: &Interface, packet: &Packet) {
+    let message = packet.payload;
+    if message.len() < HEADER_LEN || checksum(&[message]) != 0 {
+        interface.count_dropped();
+        return;
+    }
+    let identifier = u16::from_be_bytes([message[4], message[5]]);
+    let sequence = u16::from_be_bytes([message[6], message[7]]);
+    let data = &message[HEADER_LEN..];
+
+    match message[0] {
+        TYPE_ECHO_REQUEST => {
+            let reply = build(TYPE_ECHO_REPLY, identifier, sequence, data);
+            if let Err(err) = ipv4::reply(interface, packet, ipv4::PROTOCOL_ICMP, &reply) {
+                crate::log_warn!("icmp: reply to {} failed: {}", packet.source, err);
+            }
+        }
+        TYPE_ECHO_REPLY => {
+            let mut replies = REPLIES.lock();
+            if replies.len() >= MAX_PENDING_REPLIES {
+                replies.remove(0);
+            }
+            replies.push(EchoReply {
+                source: packet.source,
+                identifier,
+                sequence,
+                ttl: packet.ttl,
+                len: data.len(),
+                received: Instant::now(),
+            });
+            drop(replies);
+            REPLY_WAITERS.wake_all();
+        }
+        _ => {}
+    }
+}
+
+/// Identifier for the requests of one `ping` run
+pub fn next_identifier() -> u16 {
+    NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed)
+}
+
+fn take_reply(identifier: u16, sequence: u16) -> Option<EchoReply> {
+    let mut replies = REPLIES.lock();
+    let index = replies
+        .iter()
+        .position(|r| r.identifier == identifier && r.sequence == sequence)?;
+    Some(replies.remove(index))
+}
+
+/// Send an echo request with `len` bytes of data and wait for the reply
+///
+/// Returns the reply and the round trip time.
+pub fn ping(
+    destination: Ipv4Addr,
+    identifier: u16,
+    sequence: u16,
+    len: usize,
+    timeout: Duration,
+) -> Result<(EchoReply, Duration)> {
+    let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    let request = build(TYPE_ECHO_REQUEST, identifier, sequence, &data);
+    let sent = Instant::now();
+    ipv4::send(destination, ipv4::PROTOCOL_ICMP, &request)?;
+
+    let mut reply = None;
+    timer::wait_until_timeout(&REPLY_WAITERS, timeout, || {
+        reply = reply.or_else(|| take_reply(identifier, sequence));
+        reply.is_some()
+    });
+    let reply = reply.ok_or(Error::TimedOut)?;
+    Ok((reply, reply.received.duration_since(sent)))
+}
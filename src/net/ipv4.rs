@@ -0,0 +1,152 @@
+//! Internet Protocol version 4
+//!
+//! Packets are sent with a 20-byte header and never fragmented; received
+//! fragments are dropped. Packets for our address and broadcasts are passed
+//! to the protocol named in the header.
+
+use super::{arp, checksum, ethernet, icmp, route, tcp, udp, Error, Interface, Ipv4Addr, Result};
+use crate::drivers::net::MacAddress;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+pub const HEADER_LEN: usize = 20;
+pub const DEFAULT_TTL: u8 = 64;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+const VERSION: u8 = 4;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+/// Identification of the next packet sent
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// A received packet
+#[derive(Debug, Clone, Copy)]
+pub struct Packet<'a> {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    /// Hardware address of the previous hop, where replies go
+    pub source_mac: MacAddress,
+    pub payload: &'a [u8],
+}
+
+fn build(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let total_len = (HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.push(VERSION << 4 | (HEADER_LEN / 4) as u8);
+    packet.push(0);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&NEXT_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    packet.extend_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    let sum = checksum(&[&packet]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Send `payload` to `destination` on the interface that reaches it
+pub fn send(destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<()> {
+    let interface = route(destination)?;
+    send_on(&interface, destination, protocol, payload)
+}
+
+/// Send `payload` to `destination` on `interface`, resolving the next hop first
+pub fn send_on(
+    interface: &Interface,
+    destination: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let mac = arp::resolve(interface, interface.next_hop(destination)?)?;
+    transmit(interface, mac, destination, protocol, payload)
+}
+
+/// Answer `packet` through the hop it came from, without asking ARP
+///
+/// The receive task uses this, it cannot wait for ARP answers it has to
+/// process itself.
+pub fn reply(interface: &Interface, packet: &Packet, protocol: u8, payload: &[u8]) -> Result<()> {
+    transmit(
+        interface,
+        packet.source_mac,
+        packet.source,
+        protocol,
+        payload,
+    )
+}
+
+fn transmit(
+    interface: &Interface,
+    mac: MacAddress,
+    destination: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<()> {
+    if HEADER_LEN + payload.len() > interface.device().mtu() {
+        return Err(Error::TooLarge);
+    }
+    let source = interface.config().address;
+    let packet = build(source, destination, protocol, payload);
+    interface.send_frame(mac, ethernet::TYPE_IPV4, &packet)
+}
+
+/// Check a received packet and hand it to its protocol
+pub fn receive(interface: &Interface, source_mac: MacAddress, data: &[u8]) {
+    let Some(packet) = parse(data, source_mac) else {
+        interface.count_dropped();
+        return;
+    };
+    let config = interface.config();
+    let for_us = packet.destination == config.address
+        || packet.destination.is_broadcast()
+        || config.is_configured() && packet.destination == config.broadcast()
+        // Until an address is configured everything could be for us
+        || !config.is_configured();
+    if !for_us {
+        return;
+    }
+    match packet.protocol {
+        PROTOCOL_ICMP => icmp::receive(interface, &packet),
+        PROTOCOL_TCP => tcp::receive(interface, &packet),
+        PROTOCOL_UDP => udp::receive(interface, &packet),
+        _ => interface.count_dropped(),
+    }
+}
+
+fn parse(data: &[u8], source_mac: MacAddress) -> Option<Packet<'_>> {
+    if data.len() < HEADER_LEN || data[0] >> 4 != VERSION {
+        return None;
+    }
+    let header_len = (data[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    // Frames may carry padding after the packet
+    if header_len < HEADER_LEN || total_len < header_len || total_len > data.len() {
+        return None;
+    }
+    if checksum(&[&data[..header_len]]) != 0 {
+        return None;
+    }
+    let fragment = u16::from_be_bytes([data[6], data[7]]);
+    if fragment & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET_MASK) != 0 {
+        return None;
+    }
+    Some(Packet {
+        source: Ipv4Addr::from(<[u8; 4]>::try_from(&data[12..16]).ok()?),
+        destination: Ipv4Addr::from(<[u8; 4]>::try_from(&data[16..20]).ok()?),
+        protocol: data[9],
+        ttl: data[8],
+        source_mac,
+        payload: &data[header_len..total_len],
+    })
+}
@@ -0,0 +1,335 @@
+//! IPv4 network stack
+//!
+//! Every network card becomes an [`Interface`] with an address, a netmask
+//! and maybe a gateway. A kernel task takes received frames from the cards
+//! and hands them up through [`ethernet`] to [`arp`] and [`ipv4`], which
+//! passes packets on to [`icmp`], [`udp`] and [`tcp`]. Sending goes the
+//! other way: the protocol hands a payload to [`ipv4::send`], which picks
+//! the interface, resolves the next hop with ARP and frames the packet.
+//!
+//! Cards are configured by [`dhcp`] at boot. If nobody answers, the first
+//! card falls back to the address QEMU user networking hands out, so the
+//! slirp gateway 10.0.2.2 still answers. A [`telnet`] server offers the
+//! shell on port 23. Names are looked up by [`dns`], [`http`] fetches URLs.
+
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod ethernet;
+pub mod http;
+pub mod icmp;
+pub mod ipv4;
+pub mod tcp;
+pub mod telnet;
+pub mod udp;
+
+pub use core::net::{Ipv4Addr, SocketAddrV4};
+
+use crate::drivers::net::{self, MacAddress, NetDevice, RECEIVE_WAITERS};
+use crate::fs::proc;
+use crate::kernel::sched;
+use crate::sync::SafeMutex;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// What QEMU user networking hands out by DHCP
+pub const DEFAULT_CONFIG: Config = Config {
+    address: Ipv4Addr::new(10, 0, 2, 15),
+    prefix_len: 24,
+    gateway: Some(Ipv4Addr::new(10, 0, 2, 2)),
+    dns_server: Some(Ipv4Addr::new(10, 0, 2, 3)),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No interface reaches the destination
+    NoRoute,
+    /// The next hop did not answer ARP requests
+    HostUnreachable,
+    TimedOut,
+    /// The packet does not fit the interface MTU
+    TooLarge,
+    /// Another socket is bound to the port
+    AddressInUse,
+    /// Nobody listens on the remote port
+    ConnectionRefused,
+    /// The peer aborted the connection
+    ConnectionReset,
+    /// The connection is closed or being closed
+    NotConnected,
+    /// DNS has no address for the name
+    NameNotFound,
+    Device(net::Error),
+}
+
+impl From<net::Error> for Error {
+    fn from(err: net::Error) -> Self {
+        Error::Device(err)
+    }
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::NoRoute => "Network is unreachable",
+            Error::HostUnreachable => "No route to host",
+            Error::TimedOut => "Timed out",
+            Error::TooLarge => "Message too long",
+            Error::AddressInUse => "Address already in use",
+            Error::ConnectionRefused => "Connection refused",
+            Error::ConnectionReset => "Connection reset by peer",
+            Error::NotConnected => "Not connected",
+            Error::NameNotFound => "Name or service not known",
+            Error::Device(net::Error::LinkDown) => "Network is down",
+            Error::Device(_) => "I/O error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Address configuration of an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub address: Ipv4Addr,
+    /// Netmask as a number of leading one bits
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_server: Option<Ipv4Addr>,
+}
+
+impl Config {
+    /// No address yet
+    pub const UNCONFIGURED: Config = Config {
+        address: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+        gateway: None,
+        dns_server: None,
+    };
+
+    pub fn is_configured(&self) -> bool {
+        !self.address.is_unspecified()
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        let bits = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        Ipv4Addr::from(bits)
+    }
+
+    /// The broadcast address of the local network
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !u32::from(self.netmask()))
+    }
+
+    /// True if `address` is on the local network
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask());
+        self.is_configured() && u32::from(address) & mask == u32::from(self.address) & mask
+    }
+}
+
+/// Traffic counters since boot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// Frames that were malformed or for a protocol we do not speak
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+}
+
+/// A network card and its address configuration
+pub struct Interface {
+    device: Arc<dyn NetDevice>,
+    config: SafeMutex<Config>,
+    stats: SafeMutex<Stats>,
+}
+
+impl Interface {
+    fn new(device: Arc<dyn NetDevice>, config: Config) -> Self {
+        Self {
+            device,
+            config: SafeMutex::new(config),
+            stats: SafeMutex::new(Stats::default()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn device(&self) -> &Arc<dyn NetDevice> {
+        &self.device
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        self.device.mac_address()
+    }
+
+    pub fn config(&self) -> Config {
+        *self.config.lock()
+    }
+
+    pub fn set_config(&self, config: Config) {
+        *self.config.lock() = config;
+        crate::log_info!(
+            "net: {}: address {}/{}, gateway {}, DNS {}",
+            self.name(),
+            config.address,
+            config.prefix_len,
+            config.gateway.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            config.dns_server.unwrap_or(Ipv4Addr::UNSPECIFIED)
+        );
+    }
+
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock()
+    }
+
+    /// Where packets for `destination` go first: the destination itself if
+    /// it is on the local network, the gateway otherwise
+    pub fn next_hop(&self, destination: Ipv4Addr) -> Result<Ipv4Addr> {
+        let config = self.config();
+        if destination.is_broadcast() || config.contains(destination) {
+            return Ok(destination);
+        }
+        config.gateway.ok_or(Error::NoRoute)
+    }
+
+    /// Frame `payload` and send it to `destination`
+    pub fn send_frame(
+        &self,
+        destination: MacAddress,
+        ether_type: u16,
+        payload: &[u8],
+    ) -> Result<()> {
+        if !self.device.link_up() {
+            return Err(Error::Device(net::Error::LinkDown));
+        }
+        if payload.len() > self.device.mtu() {
+            return Err(Error::TooLarge);
+        }
+        let frame = ethernet::build(destination, self.mac_address(), ether_type, payload);
+        let result = self.device.send(&frame);
+        let mut stats = self.stats.lock();
+        match result {
+            Ok(()) => {
+                stats.tx_packets += 1;
+                stats.tx_bytes += frame.len() as u64;
+            }
+            Err(_) => stats.tx_errors += 1,
+        }
+        Ok(result?)
+    }
+
+    fn count_received(&self, len: usize) {
+        let mut stats = self.stats.lock();
+        stats.rx_packets += 1;
+        stats.rx_bytes += len as u64;
+    }
+
+    fn count_dropped(&self) {
+        self.stats.lock().rx_dropped += 1;
+    }
+}
+
+static INTERFACES: SafeMutex<Vec<Arc<Interface>>> = SafeMutex::new(Vec::new());
+
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES
+        .lock()
+        .iter()
+        .find(|interface| interface.name() == name)
+        .cloned()
+}
+
+/// The interface to send to `destination` on
+///
+/// Destinations on a local network go out there, anything else through the
+/// first interface that has a gateway.
+pub fn route(destination: Ipv4Addr) -> Result<Arc<Interface>> {
+    let interfaces = INTERFACES.lock();
+    interfaces
+        .iter()
+        .find(|i| {
+            let config = i.config();
+            config.contains(destination) || destination.is_broadcast() && config.is_configured()
+        })
+        .or_else(|| interfaces.iter().find(|i| i.config().gateway.is_some()))
+        .cloned()
+        .ok_or(Error::NoRoute)
+}
+
+/// Internet checksum over the concatenation of `parts`
+pub fn checksum(parts: &[&[u8]]) -> u16 {
+    let bytes = parts.iter().flat_map(|part| part.iter());
+    let mut sum = 0u32;
+    for (index, &byte) in bytes.enumerate() {
+        // Bytes at even offsets are the high half of a 16-bit word
+        sum += if index % 2 == 0 {
+            (byte as u32) << 8
+        } else {
+            byte as u32
+        };
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Take frames from the cards and pass them up the stack
+fn receive_task(interfaces: Vec<Arc<Interface>>) {
+    loop {
+        RECEIVE_WAITERS.wait_until(|| interfaces.iter().any(|i| i.device.can_receive()));
+        for interface in &interfaces {
+            loop {
+                let frame = interface.device.receive();
+                let Some(frame) = frame else {
+                    break;
+                };
+                ethernet::receive(interface, &frame);
+            }
+        }
+    }
+}
+
+/// Create an interface for every network card, start receiving and ask
+/// for addresses
+pub fn init() {
+    for device in net::devices() {
+        let interface = Interface::new(device, Config::UNCONFIGURED);
+        INTERFACES.lock().push(Arc::new(interface));
+    }
+    let interfaces = interfaces();
+    if interfaces.is_empty() {
+        return;
+    }
+    proc::register("arp", arp::render_cache);
+    proc::register("dns", dns::render_cache);
+    tcp::init();
+    sched::spawn("net", {
+        let interfaces = interfaces.clone();
+        move || receive_task(interfaces)
+    });
+    for (index, interface) in interfaces.into_iter().enumerate() {
+        let fallback = (index == 0).then_some(DEFAULT_CONFIG);
+        sched::spawn("dhcp", move || dhcp::run(interface, fallback));
+    }
+    sched::spawn("telnetd", telnet::serve);
+}
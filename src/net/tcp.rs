@@ -0,0 +1,1112 @@
+//! Transmission Control Protocol
+//!
+//! Every connection is a transmission control block ([`Tcb`]) in a global
+//! table, driven by three things: segments from the receive task, calls
+//! on its [`TcpStream`] and a [`TICK`] timer that retransmits and ends
+//! TIME-WAIT. Each of them updates the block under its lock and collects
+//! the segments to send, which go out once the lock is released.
+//!
+//! We send as much as the peer's window allows and go back to the oldest
+//! unacknowledged byte when the retransmission timeout expires; the
+//! timeout follows the measured round trip time (RFC 6298). Out of order
+//! segments are dropped and acknowledged with what we have, the peer
+//! retransmits them. Open connections and listeners are shown in
+//! `/proc/tcp`.
+
+use super::ipv4::{self, Packet};
+use super::{checksum, route, Error, Interface, Ipv4Addr, Result, SocketAddrV4};
+use crate::fs::proc;
+use crate::kernel::random;
+use crate::kernel::time::Instant;
+use crate::kernel::timer::{self, Timer};
+use crate::sync::{SafeMutex, WaitQueue};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+use core::time::Duration;
+
+const HEADER_LEN: usize = 20;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// Segment size assumed when the peer does not announce one
+const DEFAULT_MSS: usize = 536;
+/// Bytes buffered per direction, the largest window without scaling
+const BUFFER_SIZE: usize = 65535;
+
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(200);
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Retransmissions before the connection is given up
+const MAX_RETRIES: u32 = 8;
+const SYN_RETRIES: u32 = 5;
+/// Twice the maximum segment lifetime, kept short
+const TIME_WAIT: Duration = Duration::from_secs(10);
+/// How often timers are checked
+pub const TICK: Duration = Duration::from_millis(100);
+/// Connections waiting to be accepted, per listener
+const BACKLOG: usize = 16;
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::SynSent => "SYN-SENT",
+            State::SynReceived => "SYN-RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN-WAIT-1",
+            State::FinWait2 => "FIN-WAIT-2",
+            State::CloseWait => "CLOSE-WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST-ACK",
+            State::TimeWait => "TIME-WAIT",
+            State::Closed => "CLOSED",
+        }
+    }
+
+    fn is_opening(&self) -> bool {
+        matches!(self, State::SynSent | State::SynReceived)
+    }
+}
+
+/// Sequence number comparison modulo 2^32
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// A segment to send
+struct Segment {
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// Announced on SYN segments
+    mss: Option<u16>,
+    payload: Vec<u8>,
+}
+
+impl Segment {
+    fn encode(&self) -> Vec<u8> {
+        let header_len = HEADER_LEN + if self.mss.is_some() { 4 } else { 0 };
+        let mut bytes = Vec::with_capacity(header_len + self.payload.len());
+        bytes.extend_from_slice(&self.source.port().to_be_bytes());
+        bytes.extend_from_slice(&self.destination.port().to_be_bytes());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        bytes.extend_from_slice(&self.ack.to_be_bytes());
+        bytes.push(((header_len / 4) as u8) << 4);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        // Checksum and urgent pointer
+        bytes.extend_from_slice(&[0; 4]);
+        if let Some(mss) = self.mss {
+            bytes.extend_from_slice(&[OPTION_MSS, 4]);
+            bytes.extend_from_slice(&mss.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.payload);
+        let sum = segment_checksum(*self.source.ip(), *self.destination.ip(), &bytes);
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+/// Checksum over the IPv4 pseudo header and the segment
+fn segment_checksum(source: Ipv4Addr, destination: Ipv4Addr, segment: &[u8]) -> u16 {
+    let len = (segment.len() as u16).to_be_bytes();
+    let pseudo = [0, ipv4::PROTOCOL_TCP, len[0], len[1]];
+    checksum(&[&source.octets(), &destination.octets(), &pseudo, segment])
+}
+
+/// A received segment
+struct Incoming<'a> {
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &'a [u8],
+}
+
+impl<'a> Incoming<'a> {
+    fn parse(packet: &Packet<'a>) -> Option<Self> {
+        let bytes = packet.payload;
+        if bytes.len() < HEADER_LEN
+            || segment_checksum(packet.source, packet.destination, bytes) != 0
+        {
+            return None;
+        }
+        let header_len = (bytes[12] >> 4) as usize * 4;
+        if header_len < HEADER_LEN || header_len > bytes.len() {
+            return None;
+        }
+        let port = |offset: usize| u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        let word =
+            |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        let mut mss = None;
+        let mut options = &bytes[HEADER_LEN..header_len];
+        while let [kind, rest @ ..] = options {
+            match *kind {
+                OPTION_END => break,
+                OPTION_NOP => {
+                    options = rest;
+                    continue;
+                }
+                _ => {}
+            }
+            let len = *rest.first()? as usize;
+            if len < 2 || len > options.len() {
+                break;
+            }
+            if *kind == OPTION_MSS && len == 4 {
+                mss = Some(u16::from_be_bytes([options[2], options[3]]));
+            }
+            options = &options[len..];
+        }
+
+        Some(Self {
+            source: SocketAddrV4::new(packet.source, port(0)),
+            destination: SocketAddrV4::new(packet.destination, port(2)),
+            seq: word(4),
+            ack: word(8),
+            flags: bytes[13],
+            window: port(14),
+            mss,
+            payload: &bytes[header_len..],
+        })
+    }
+
+    fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Sequence space taken, SYN and FIN count as one each
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + self.has(FLAG_SYN) as u32 + self.has(FLAG_FIN) as u32
+    }
+
+    /// The reset that answers this segment when nothing should have got it
+    fn reset(&self) -> Option<Segment> {
+        if self.has(FLAG_RST) {
+            return None;
+        }
+        let (seq, ack, flags) = if self.has(FLAG_ACK) {
+            (self.ack, 0, FLAG_RST)
+        } else {
+            (0, self.seq.wrapping_add(self.len()), FLAG_RST | FLAG_ACK)
+        };
+        Some(Segment {
+            source: self.destination,
+            destination: self.source,
+            seq,
+            ack,
+            flags,
+            window: 0,
+            mss: None,
+            payload: Vec::new(),
+        })
+    }
+}
+
+/// Transmission control block, the state of one connection
+struct Tcb {
+    state: State,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    /// Port of the listener a passive open came in on
+    listener: Option<u16>,
+    /// Why the connection ended, if not by an orderly close
+    error: Option<Error>,
+
+    // Send sequence space
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// Window the peer announced
+    snd_wnd: u32,
+    /// Largest segment the peer takes
+    mss: usize,
+    /// Unacknowledged and unsent data, starting at `snd_una` once the SYN
+    /// is acknowledged
+    send_buffer: VecDeque<u8>,
+    /// The application is done writing, a FIN follows the data
+    fin_queued: bool,
+    fin_acked: bool,
+
+    // Receive sequence space
+    rcv_nxt: u32,
+    receive_buffer: VecDeque<u8>,
+    fin_received: bool,
+
+    // Retransmission
+    rto: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// Sequence number whose acknowledgement ends the timed round trip
+    rtt_sample: Option<(u32, Instant)>,
+    retransmit_at: Option<Instant>,
+    retries: u32,
+    time_wait_until: Option<Instant>,
+}
+
+impl Tcb {
+    fn new(local: SocketAddrV4, remote: SocketAddrV4, state: State) -> Self {
+        // Random so other hosts cannot guess it and inject segments
+        let iss = random::next_u32();
+        Self {
+            state,
+            local,
+            remote,
+            listener: None,
+            error: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: DEFAULT_MSS as u32,
+            mss: DEFAULT_MSS,
+            send_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_acked: false,
+            rcv_nxt: 0,
+            receive_buffer: VecDeque::new(),
+            fin_received: false,
+            rto: INITIAL_RTO,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rtt_sample: None,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: None,
+        }
+    }
+
+    /// Our largest segment, what fits the interface MTU
+    fn local_mss() -> usize {
+        1500 - ipv4::HEADER_LEN - HEADER_LEN
+    }
+
+    fn receive_window(&self) -> u32 {
+        (BUFFER_SIZE - self.receive_buffer.len()) as u32
+    }
+
+    /// Sequence number of the first byte in the send buffer
+    fn data_seq(&self) -> u32 {
+        if self.state.is_opening() {
+            self.iss.wrapping_add(1)
+        } else {
+            self.snd_una
+        }
+    }
+
+    fn fin_seq(&self) -> u32 {
+        self.data_seq().wrapping_add(self.send_buffer.len() as u32)
+    }
+
+    /// Bytes in the send buffer that were never sent
+    fn unsent(&self) -> usize {
+        let sent = self.snd_nxt.wrapping_sub(self.data_seq()) as usize;
+        self.send_buffer.len().saturating_sub(sent)
+    }
+
+    fn can_write(&self) -> bool {
+        matches!(self.state, State::Established | State::CloseWait) && !self.fin_queued
+    }
+
+    fn segment(&self, seq: u32, flags: u8, payload: Vec<u8>) -> Segment {
+        let ack = if self.state == State::SynSent {
+            0
+        } else {
+            self.rcv_nxt
+        };
+        Segment {
+            source: self.local,
+            destination: self.remote,
+            seq,
+            ack,
+            flags,
+            window: self.receive_window().min(u16::MAX as u32) as u16,
+            mss: None,
+            payload,
+        }
+    }
+
+    fn ack_segment(&self) -> Segment {
+        self.segment(self.snd_nxt, FLAG_ACK, Vec::new())
+    }
+
+    fn reset_segment(&self) -> Segment {
+        self.segment(self.snd_nxt, FLAG_RST, Vec::new())
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = Some(now + TIME_WAIT);
+    }
+
+    fn close_with(&mut self, error: Option<Error>) {
+        self.state = State::Closed;
+        self.error = error;
+        self.retransmit_at = None;
+    }
+
+    /// Keep the retransmission timer running while something waits for an
+    /// acknowledgement or for the peer's window to open
+    fn update_timer(&mut self, now: Instant) {
+        let waiting = self.snd_una != self.snd_nxt || self.snd_wnd == 0 && self.unsent() > 0;
+        if !waiting {
+            self.retransmit_at = None;
+        } else if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    /// Segments for everything the state and the peer's window allow,
+    /// `probe` sends a byte into a closed window
+    fn output(&mut self, now: Instant, probe: bool) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = match self.state {
+                        State::SynSent => FLAG_SYN,
+                        _ => FLAG_SYN | FLAG_ACK,
+                    };
+                    let mut syn = self.segment(self.iss, flags, Vec::new());
+                    syn.mss = Some(Self::local_mss() as u16);
+                    segments.push(syn);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    if self.retries == 0 {
+                        self.rtt_sample = Some((self.snd_nxt, now));
+                    }
+                }
+                self.update_timer(now);
+                return segments;
+            }
+            State::TimeWait | State::Closed => return segments,
+            _ => {}
+        }
+
+        let data_seq = self.data_seq();
+        let window = if probe {
+            self.snd_wnd.max(1)
+        } else {
+            self.snd_wnd
+        };
+        let window_end = self.snd_una.wrapping_add(window);
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(data_seq) as usize;
+            let usable = window_end.wrapping_sub(self.snd_nxt) as i32;
+            if offset >= self.send_buffer.len() || usable <= 0 {
+                break;
+            }
+            let len = (self.send_buffer.len() - offset)
+                .min(self.mss)
+                .min(usable as usize);
+            let payload: Vec<u8> = self
+                .send_buffer
+                .range(offset..offset + len)
+                .copied()
+                .collect();
+            let mut flags = FLAG_ACK;
+            if offset + len == self.send_buffer.len() {
+                flags |= FLAG_PSH;
+            }
+            segments.push(self.segment(self.snd_nxt, flags, payload));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            if self.rtt_sample.is_none() && self.retries == 0 {
+                self.rtt_sample = Some((self.snd_nxt, now));
+            }
+        }
+
+        if self.fin_queued && !self.fin_acked && self.snd_nxt == self.fin_seq() {
+            segments.push(self.segment(self.snd_nxt, FLAG_FIN | FLAG_ACK, Vec::new()));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+        }
+        self.update_timer(now);
+        segments
+    }
+
+    /// New round trip measurement, RFC 6298
+    fn update_rto(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let rto = self.srtt.unwrap_or_default() + (self.rttvar * 4).max(TICK);
+        self.rto = rto.clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Take an acknowledgement and the window that came with it
+    fn on_ack(&mut self, ack: u32, window: u16, now: Instant) {
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data);
+            acked -= data;
+            if acked > 0 && self.fin_queued {
+                self.fin_acked = true;
+            }
+            self.snd_una = ack;
+            if let Some((seq, sent)) = self.rtt_sample {
+                if seq_le(seq, ack) {
+                    self.update_rto(now.duration_since(sent));
+                    self.rtt_sample = None;
+                }
+            }
+            self.retries = 0;
+            self.retransmit_at = None;
+        }
+        if seq_le(self.snd_una, ack) {
+            self.snd_wnd = window as u32;
+        }
+        self.update_timer(now);
+    }
+
+    /// Handle a segment while our SYN waits for an answer
+    fn on_segment_syn_sent(&mut self, segment: &Incoming, now: Instant) -> Vec<Segment> {
+        let has_ack = segment.has(FLAG_ACK);
+        if has_ack && (seq_le(segment.ack, self.iss) || seq_lt(self.snd_nxt, segment.ack)) {
+            return segment.reset().into_iter().collect();
+        }
+        if segment.has(FLAG_RST) {
+            if has_ack {
+                self.close_with(Some(Error::ConnectionRefused));
+            }
+            return Vec::new();
+        }
+        if !segment.has(FLAG_SYN) {
+            return Vec::new();
+        }
+
+        self.rcv_nxt = segment.seq.wrapping_add(1);
+        self.snd_wnd = segment.window as u32;
+        self.mss = segment
+            .mss
+            .map_or(DEFAULT_MSS, |mss| mss as usize)
+            .min(Self::local_mss());
+        if !has_ack {
+            // Both sides opened at once, answer with SYN-ACK
+            self.state = State::SynReceived;
+            self.snd_nxt = self.iss;
+            return self.output(now, false);
+        }
+        self.state = State::Established;
+        self.on_ack(segment.ack, segment.window, now);
+        let mut segments = self.output(now, false);
+        if segments.is_empty() {
+            segments.push(self.ack_segment());
+        }
+        segments
+    }
+
+    /// Handle a segment for this connection, RFC 793 "SEGMENT ARRIVES"
+    fn on_segment(&mut self, segment: &Incoming, now: Instant) -> Vec<Segment> {
+        match self.state {
+            State::SynSent => return self.on_segment_syn_sent(segment, now),
+            State::Closed => return Vec::new(),
+            _ => {}
+        }
+
+        // Only segments that fall into the receive window count
+        let window = self.receive_window();
+        let in_window =
+            |seq: u32| seq_le(self.rcv_nxt, seq) && seq_lt(seq, self.rcv_nxt.wrapping_add(window));
+        let acceptable = match (segment.len(), window) {
+            (0, 0) => segment.seq == self.rcv_nxt,
+            (0, _) => in_window(segment.seq),
+            (_, 0) => false,
+            (len, _) => in_window(segment.seq) || in_window(segment.seq.wrapping_add(len - 1)),
+        };
+        if !acceptable {
+            if segment.has(FLAG_RST) {
+                return Vec::new();
+            }
+            return vec![self.ack_segment()];
+        }
+
+        if segment.has(FLAG_RST) {
+            let error = match self.state {
+                State::SynReceived => Some(Error::ConnectionRefused),
+                State::Closing | State::LastAck | State::TimeWait => None,
+                _ => Some(Error::ConnectionReset),
+            };
+            self.close_with(error);
+            return Vec::new();
+        }
+        if segment.has(FLAG_SYN) {
+            let reset = self.reset_segment();
+            self.close_with(Some(Error::ConnectionReset));
+            return vec![reset];
+        }
+        if !segment.has(FLAG_ACK) {
+            return Vec::new();
+        }
+
+        if self.state == State::SynReceived {
+            if !(seq_lt(self.snd_una, segment.ack) && seq_le(segment.ack, self.snd_nxt)) {
+                return segment.reset().into_iter().collect();
+            }
+            // The SYN is acknowledged, data starts after it
+            self.state = State::Established;
+            self.snd_una = self.iss.wrapping_add(1);
+            if let Some((seq, sent)) = self.rtt_sample.take() {
+                if seq_le(seq, segment.ack) {
+                    self.update_rto(now.duration_since(sent));
+                }
+            }
+            self.retries = 0;
+            self.retransmit_at = None;
+        }
+        if seq_lt(self.snd_nxt, segment.ack) {
+            return vec![self.ack_segment()];
+        }
+        self.on_ack(segment.ack, segment.window, now);
+        if self.fin_acked {
+            match self.state {
+                State::FinWait1 => self.state = State::FinWait2,
+                State::Closing => {
+                    self.enter_time_wait(now);
+                    return Vec::new();
+                }
+                State::LastAck => {
+                    self.close_with(None);
+                    return Vec::new();
+                }
+                _ => {}
+            }
+        }
+
+        let mut need_ack = false;
+        let mut in_order = true;
+        if !segment.payload.is_empty()
+            && matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            )
+        {
+            if seq_lt(self.rcv_nxt, segment.seq) {
+                // A gap before this segment, ask for what is missing
+                in_order = false;
+            } else {
+                let skip = self.rcv_nxt.wrapping_sub(segment.seq) as usize;
+                let data = segment.payload.get(skip..).unwrap_or_default();
+                let take = data.len().min(self.receive_window() as usize);
+                self.receive_buffer.extend(&data[..take]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(take as u32);
+            }
+            need_ack = true;
+        }
+
+        let fin_seq = segment.seq.wrapping_add(segment.payload.len() as u32);
+        if segment.has(FLAG_FIN) && in_order && fin_seq == self.rcv_nxt && !self.fin_received {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            need_ack = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        } else if self.state == State::TimeWait && segment.has(FLAG_FIN) {
+            // Our last ACK got lost, the peer sent its FIN again
+            self.enter_time_wait(now);
+            need_ack = true;
+        }
+
+        let mut segments = self.output(now, false);
+        if need_ack && segments.is_empty() {
+            segments.push(self.ack_segment());
+        }
+        segments
+    }
+
+    /// Retransmit what the peer has not acknowledged in time
+    fn on_timer(&mut self, now: Instant) -> Vec<Segment> {
+        if self.state == State::TimeWait {
+            if self.time_wait_until.is_some_and(|until| until <= now) {
+                self.close_with(None);
+            }
+            return Vec::new();
+        }
+        let Some(deadline) = self.retransmit_at else {
+            return Vec::new();
+        };
+        if now < deadline {
+            return Vec::new();
+        }
+
+        self.retries += 1;
+        let limit = if self.state.is_opening() {
+            SYN_RETRIES
+        } else {
+            MAX_RETRIES
+        };
+        if self.retries > limit {
+            let reset = self.reset_segment();
+            self.close_with(Some(Error::TimedOut));
+            return vec![reset];
+        }
+        // Karn: never time a retransmitted segment
+        self.rtt_sample = None;
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.retransmit_at = None;
+        self.snd_nxt = if self.state.is_opening() {
+            self.iss
+        } else {
+            self.snd_una
+        };
+        let probe = self.snd_wnd == 0;
+        self.output(now, probe)
+    }
+}
+
+type Connection = Arc<SafeMutex<Tcb>>;
+/// Established connections a listener has not handed out yet
+type AcceptQueue = Arc<SafeMutex<VecDeque<Connection>>>;
+
+static CONNECTIONS: SafeMutex<Vec<Connection>> = SafeMutex::new(Vec::new());
+static LISTENERS: SafeMutex<BTreeMap<u16, AcceptQueue>> = SafeMutex::new(BTreeMap::new());
+static NEXT_EPHEMERAL: SafeMutex<u16> = SafeMutex::new(*EPHEMERAL_PORTS.start());
+/// Tasks waiting for any connection to change
+static WAITERS: WaitQueue = WaitQueue::new();
+
+/// Send segments from task context, resolving the peer's address if needed
+fn transmit(segments: Vec<Segment>) -> Result<()> {
+    for segment in segments {
+        ipv4::send(
+            *segment.destination.ip(),
+            ipv4::PROTOCOL_TCP,
+            &segment.encode(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Drop connections that are closed for good
+fn reap() {
+    CONNECTIONS
+        .lock()
+        .retain(|connection| connection.lock().state != State::Closed);
+}
+
+fn find_connection(local: SocketAddrV4, remote: SocketAddrV4) -> Option<Connection> {
+    CONNECTIONS
+        .lock()
+        .iter()
+        .find(|connection| {
+            let tcb = connection.lock();
+            tcb.local.port() == local.port() && tcb.remote == remote
+        })
+        .cloned()
+}
+
+fn ephemeral_port() -> Result<u16> {
+    let connections = CONNECTIONS.lock();
+    let listeners = LISTENERS.lock();
+    let in_use = |port: u16| {
+        listeners.contains_key(&port) || connections.iter().any(|c| c.lock().local.port() == port)
+    };
+    let mut next = NEXT_EPHEMERAL.lock();
+    for _ in EPHEMERAL_PORTS {
+        let port = *next;
+        *next = if port == *EPHEMERAL_PORTS.end() {
+            *EPHEMERAL_PORTS.start()
+        } else {
+            port + 1
+        };
+        if !in_use(port) {
+            return Ok(port);
+        }
+    }
+    Err(Error::AddressInUse)
+}
+
+/// A SYN for a listening port starts a new connection
+fn listen_segment(segment: &Incoming, queue: &AcceptQueue, now: Instant) -> Vec<Segment> {
+    if segment.has(FLAG_RST) {
+        return Vec::new();
+    }
+    if segment.has(FLAG_ACK) {
+        return segment.reset().into_iter().collect();
+    }
+    if !segment.has(FLAG_SYN) {
+        return Vec::new();
+    }
+    let port = segment.destination.port();
+    let pending = CONNECTIONS
+        .lock()
+        .iter()
+        .filter(|c| {
+            let tcb = c.lock();
+            tcb.listener == Some(port) && tcb.state == State::SynReceived
+        })
+        .count();
+    if pending + queue.lock().len() >= BACKLOG {
+        // The peer tries again once the backlog has room
+        return Vec::new();
+    }
+
+    let mut tcb = Tcb::new(segment.destination, segment.source, State::SynReceived);
+    tcb.listener = Some(port);
+    tcb.rcv_nxt = segment.seq.wrapping_add(1);
+    tcb.snd_wnd = segment.window as u32;
+    tcb.mss = segment
+        .mss
+        .map_or(DEFAULT_MSS, |mss| mss as usize)
+        .min(Tcb::local_mss());
+    let segments = tcb.output(now, false);
+    CONNECTIONS.lock().push(Arc::new(SafeMutex::new(tcb)));
+    segments
+}
+
+/// Hand a received segment to its connection or listener
+pub fn receive(interface: &Interface, packet: &Packet) {
+    let Some(segment) = Incoming::parse(packet) else {
+        interface.count_dropped();
+        return;
+    };
+    let now = Instant::now();
+
+    let segments = if let Some(connection) = find_connection(segment.destination, segment.source) {
+        let (mut segments, accepted) = {
+            let mut tcb = connection.lock();
+            let opening = tcb.state == State::SynReceived;
+            let segments = tcb.on_segment(&segment, now);
+            let accepted = opening && !tcb.state.is_opening() && tcb.state != State::Closed;
+            (segments, accepted.then_some(tcb.listener).flatten())
+        };
+        if let Some(port) = accepted {
+            let queue = LISTENERS.lock().get(&port).cloned();
+            match queue {
+                Some(queue) => queue.lock().push_back(connection),
+                // The listener went away during the handshake
+                None => segments.extend(finish(&connection)),
+            }
+        }
+        reap();
+        segments
+    } else {
+        let queue = LISTENERS.lock().get(&segment.destination.port()).cloned();
+        match queue {
+            Some(queue) => listen_segment(&segment, &queue, now),
+            None => segment.reset().into_iter().collect(),
+        }
+    };
+    WAITERS.wake_all();
+
+    // Answer through the hop the segment came from, see `ipv4::reply`
+    for segment in segments {
+        if let Err(err) = ipv4::reply(interface, packet, ipv4::PROTOCOL_TCP, &segment.encode()) {
+            crate::log_debug!("tcp: send to {} failed: {}", segment.destination, err);
+        }
+    }
+}
+
+/// Queue a FIN after the data, returns what can be sent now
+fn finish(connection: &Connection) -> Vec<Segment> {
+    let mut tcb = connection.lock();
+    match tcb.state {
+        State::SynSent => {
+            tcb.close_with(None);
+            Vec::new()
+        }
+        State::Established | State::CloseWait | State::SynReceived => {
+            tcb.fin_queued = true;
+            tcb.output(Instant::now(), false)
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Close our side of `connection` from task context
+fn close(connection: &Connection) {
+    // A failure shows up as retransmissions running out
+    let _ = transmit(finish(connection));
+}
+
+/// Retransmit and expire TIME-WAIT, runs every [`TICK`]
+fn tick() {
+    let now = Instant::now();
+    let connections = CONNECTIONS.lock().clone();
+    let mut changed = false;
+    for connection in connections {
+        let segments = {
+            let mut tcb = connection.lock();
+            let state = tcb.state;
+            let segments = tcb.on_timer(now);
+            changed |= tcb.state != state;
+            segments
+        };
+        if let Err(err) = transmit(segments) {
+            crate::log_debug!("tcp: retransmission failed: {}", err);
+        }
+    }
+    if changed {
+        reap();
+        WAITERS.wake_all();
+    }
+}
+
+/// A connected stream, closed when dropped
+pub struct TcpStream {
+    connection: Connection,
+    read_timeout: Option<Duration>,
+}
+
+impl TcpStream {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            read_timeout: None,
+        }
+    }
+
+    /// Open a connection and wait for the handshake to finish
+    pub fn connect(remote: SocketAddrV4) -> Result<TcpStream> {
+        let interface = route(*remote.ip())?;
+        let local = SocketAddrV4::new(interface.config().address, ephemeral_port()?);
+        let mut tcb = Tcb::new(local, remote, State::SynSent);
+        let segments = tcb.output(Instant::now(), false);
+        let connection = Arc::new(SafeMutex::new(tcb));
+        CONNECTIONS.lock().push(connection.clone());
+
+        if let Err(err) = transmit(segments) {
+            connection.lock().close_with(Some(err));
+            reap();
+            return Err(err);
+        }
+        WAITERS.wait_until(|| !connection.lock().state.is_opening());
+        let tcb = connection.lock();
+        match tcb.state {
+            State::Closed => Err(tcb.error.unwrap_or(Error::ConnectionRefused)),
+            _ => {
+                drop(tcb);
+                Ok(TcpStream::new(connection))
+            }
+        }
+    }
+
+    #[allow(dead_code, reason = "mirrors peer_addr, no caller needs it yet")]
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.connection.lock().local
+    }
+
+    #[allow(dead_code, reason = "for diagnostics such as a netstat listing")]
+    pub fn state(&self) -> State {
+        self.connection.lock().state
+    }
+
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.connection.lock().remote
+    }
+
+    /// How long [`TcpStream::read`] waits, `None` waits forever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Wait for data, returns 0 once the peer has closed its side
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        let ready = || {
+            let tcb = self.connection.lock();
+            !tcb.receive_buffer.is_empty() || tcb.fin_received || tcb.state == State::Closed
+        };
+        match self.read_timeout {
+            Some(timeout) => {
+                if !timer::wait_until_timeout(&WAITERS, timeout, ready) {
+                    return Err(Error::TimedOut);
+                }
+            }
+            None => WAITERS.wait_until(ready),
+        }
+
+        let (len, segments) = {
+            let mut tcb = self.connection.lock();
+            if tcb.receive_buffer.is_empty() {
+                return match tcb.error {
+                    Some(err) => Err(err),
+                    None => Ok(0),
+                };
+            }
+            let window = tcb.receive_window();
+            let len = buffer.len().min(tcb.receive_buffer.len());
+            for (byte, value) in buffer.iter_mut().zip(tcb.receive_buffer.drain(..len)) {
+                *byte = value;
+            }
+            // Tell a peer that stopped on a full window that it may go on
+            let reopened = window < tcb.mss as u32 && tcb.receive_window() >= tcb.mss as u32;
+            let open = !matches!(tcb.state, State::Closed | State::TimeWait);
+            let segments = if reopened && open {
+                vec![tcb.ack_segment()]
+            } else {
+                Vec::new()
+            };
+            (len, segments)
+        };
+        let _ = transmit(segments);
+        Ok(len)
+    }
+
+    /// Queue all of `data`, waiting while the send buffer is full
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < data.len() {
+            WAITERS.wait_until(|| {
+                let tcb = self.connection.lock();
+                tcb.send_buffer.len() < BUFFER_SIZE || !tcb.can_write()
+            });
+            let segments = {
+                let mut tcb = self.connection.lock();
+                if !tcb.can_write() {
+                    // What is already queued still goes out
+                    if written > 0 {
+                        return Ok(written);
+                    }
+                    return Err(tcb.error.unwrap_or(Error::NotConnected));
+                }
+                let len = (BUFFER_SIZE - tcb.send_buffer.len()).min(data.len() - written);
+                tcb.send_buffer.extend(&data[written..written + len]);
+                written += len;
+                tcb.output(Instant::now(), false)
+            };
+            // The data is queued, the retransmit timer deals with failures
+            let _ = transmit(segments);
+        }
+        Ok(written)
+    }
+
+    /// Close our side after the queued data, reading still works
+    pub fn shutdown(&self) {
+        close(&self.connection);
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        close(&self.connection);
+    }
+}
+
+/// A port accepting connections, stops listening when dropped
+pub struct TcpListener {
+    port: u16,
+    queue: AcceptQueue,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<Self> {
+        let port = match port {
+            0 => ephemeral_port()?,
+            port => port,
+        };
+        let mut listeners = LISTENERS.lock();
+        if listeners.contains_key(&port) {
+            return Err(Error::AddressInUse);
+        }
+        let queue: AcceptQueue = Arc::new(SafeMutex::new(VecDeque::new()));
+        listeners.insert(port, queue.clone());
+        Ok(Self { port, queue })
+    }
+
+    #[allow(dead_code, reason = "the caller picked the port it listens on")]
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Wait for the next established connection
+    pub fn accept(&self) -> Result<TcpStream> {
+        loop {
+            WAITERS.wait_until(|| !self.queue.lock().is_empty());
+            let connection = self.queue.lock().pop_front();
+            if let Some(connection) = connection {
+                return Ok(TcpStream::new(connection));
+            }
+        }
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        LISTENERS.lock().remove(&self.port);
+        let queued: Vec<_> = self.queue.lock().drain(..).collect();
+        for connection in queued {
+            close(&connection);
+        }
+    }
+}
+
+/// Contents of `/proc/tcp`
+fn render_connections() -> String {
+    let mut text =
+        String::from("local                 remote                state        send-q recv-q\n");
+    for port in LISTENERS.lock().keys() {
+        let local = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, *port);
+        let _ = writeln!(text, "{:<21} {:<21} LISTEN", local, "*");
+    }
+    for connection in CONNECTIONS.lock().iter() {
+        let tcb = connection.lock();
+        let _ = writeln!(
+            text,
+            "{:<21} {:<21} {:<12} {:>6} {:>6}",
+            tcb.local,
+            tcb.remote,
+            tcb.state.as_str(),
+            tcb.send_buffer.len(),
+            tcb.receive_buffer.len()
+        );
+    }
+    text
+}
+
+/// Start the retransmission timer and publish `/proc/tcp`
+pub fn init() {
+    proc::register("tcp", render_connections);
+    Timer::every(TICK, tick);
+}
@@ -0,0 +1,49 @@
+//! Telnet server
+//!
+//! Every connection to [`PORT`] gets its own task running a telnet
+//! [`Session`], which negotiates options and edits the line.
+
+use super::tcp::{TcpListener, TcpStream};
+use crate::kernel::sched;
+use crate::ui::session::{Connection, Protocol, Session};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
+pub const PORT: u16 = 23;
+
+impl Connection for TcpStream {
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        TcpStream::read(self, buffer).unwrap_or(0)
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        let _ = TcpStream::write(self, bytes);
+    }
+
+    fn name(&self) -> String {
+        format!("telnet from {}", self.peer_addr())
+    }
+}
+
+/// Accept sessions on [`PORT`], runs as the `telnetd` task
+pub fn serve() {
+    let listener = match TcpListener::bind(PORT) {
+        Ok(listener) => listener,
+        Err(err) => {
+            crate::log_error!("telnet: port {}: {}", PORT, err);
+            return;
+        }
+    };
+    loop {
+        let Ok(stream) = listener.accept() else {
+            continue;
+        };
+        sched::spawn("telnet", move || {
+            let peer = stream.peer_addr();
+            crate::log_info!("telnet: session from {}", peer);
+            Session::new(Box::new(stream), Protocol::Telnet).run();
+            crate::log_info!("telnet: {} logged out", peer);
+        });
+    }
+}
@@ -0,0 +1,188 @@
+//! User Datagram Protocol
+//!
+//! A [`UdpSocket`] owns a local port until it is dropped. Datagrams for a
+//! bound port are queued on its socket, up to [`QUEUE_LIMIT`]; datagrams
+//! for other ports and beyond the limit are dropped.
+
+use super::ipv4::{self, Packet};
+use super::{checksum, route, Error, Interface, Ipv4Addr, Result, SocketAddrV4};
+use crate::kernel::timer;
+use crate::sync::{SafeMutex, WaitQueue};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const HEADER_LEN: usize = 8;
+/// Datagrams kept per socket until they are read
+const QUEUE_LIMIT: usize = 64;
+/// Ports handed out by `bind(0)`
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+type Datagram = (SocketAddrV4, Vec<u8>);
+
+/// Received datagrams of every bound port
+static PORTS: SafeMutex<BTreeMap<u16, Arc<SafeMutex<VecDeque<Datagram>>>>> =
+    SafeMutex::new(BTreeMap::new());
+static NEXT_EPHEMERAL: SafeMutex<u16> = SafeMutex::new(*EPHEMERAL_PORTS.start());
+/// Tasks waiting for a datagram on any socket
+static RECEIVE_WAITERS: WaitQueue = WaitQueue::new();
+
+pub struct UdpSocket {
+    port: u16,
+    queue: Arc<SafeMutex<VecDeque<Datagram>>>,
+    /// Send only through this interface, even without an address
+    interface: Option<Arc<Interface>>,
+    read_timeout: Option<Duration>,
+}
+
+impl UdpSocket {
+    /// Take a local port, 0 picks a free ephemeral one
+    pub fn bind(port: u16) -> Result<Self> {
+        let mut ports = PORTS.lock();
+        let port = match port {
+            0 => {
+                let mut next = NEXT_EPHEMERAL.lock();
+                let count = EPHEMERAL_PORTS.len();
+                let port = (0..count)
+                    .map(|_| {
+                        let port = *next;
+                        *next = if port == *EPHEMERAL_PORTS.end() {
+                            *EPHEMERAL_PORTS.start()
+                        } else {
+                            port + 1
+                        };
+                        port
+                    })
+                    .find(|port| !ports.contains_key(port));
+                port.ok_or(Error::AddressInUse)?
+            }
+            port if ports.contains_key(&port) => return Err(Error::AddressInUse),
+            port => port,
+        };
+        let queue = Arc::new(SafeMutex::new(VecDeque::new()));
+        ports.insert(port, queue.clone());
+        Ok(Self {
+            port,
+            queue,
+            interface: None,
+            read_timeout: None,
+        })
+    }
+
+    #[allow(dead_code, reason = "callers know the port they bound")]
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Send through `interface` instead of the routing table, e.g. before
+    /// the interface has an address
+    pub fn bind_to_interface(&mut self, interface: Arc<Interface>) {
+        self.interface = Some(interface);
+    }
+
+    /// How long [`UdpSocket::recv_from`] waits, `None` waits forever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn send_to(&self, data: &[u8], destination: SocketAddrV4) -> Result<usize> {
+        let interface = match &self.interface {
+            Some(interface) => interface.clone(),
+            None => route(*destination.ip())?,
+        };
+        let source = interface.config().address;
+        let datagram = build(SocketAddrV4::new(source, self.port), destination, data)?;
+        ipv4::send_on(&interface, *destination.ip(), ipv4::PROTOCOL_UDP, &datagram)?;
+        Ok(data.len())
+    }
+
+    /// The next datagram, if one has arrived
+    pub fn try_recv_from(&self, buffer: &mut [u8]) -> Option<(usize, SocketAddrV4)> {
+        let (source, data) = self.queue.lock().pop_front()?;
+        // Like any datagram socket, the rest of a long datagram is lost
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        Some((len, source))
+    }
+
+    /// Wait for the next datagram and copy it into `buffer`
+    pub fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+        let ready = || !self.queue.lock().is_empty();
+        match self.read_timeout {
+            Some(timeout) => {
+                if !timer::wait_until_timeout(&RECEIVE_WAITERS, timeout, ready) {
+                    return Err(Error::TimedOut);
+                }
+            }
+            None => RECEIVE_WAITERS.wait_until(ready),
+        }
+        self.try_recv_from(buffer).ok_or(Error::TimedOut)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        PORTS.lock().remove(&self.port);
+    }
+}
+
+/// Checksum over the IPv4 pseudo header and the datagram
+fn datagram_checksum(source: Ipv4Addr, destination: Ipv4Addr, datagram: &[u8]) -> u16 {
+    let len = (datagram.len() as u16).to_be_bytes();
+    let pseudo = [0, ipv4::PROTOCOL_UDP, len[0], len[1]];
+    checksum(&[&source.octets(), &destination.octets(), &pseudo, datagram])
+}
+
+fn build(source: SocketAddrV4, destination: SocketAddrV4, data: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(HEADER_LEN + data.len()).map_err(|_| Error::TooLarge)?;
+    let mut datagram = Vec::with_capacity(len as usize);
+    datagram.extend_from_slice(&source.port().to_be_bytes());
+    datagram.extend_from_slice(&destination.port().to_be_bytes());
+    datagram.extend_from_slice(&len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    // Zero means no checksum, so a computed zero is sent as all ones
+    let sum = match datagram_checksum(*source.ip(), *destination.ip(), &datagram) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    Ok(datagram)
+}
+
+/// Queue a received datagram on the socket bound to its port
+pub fn receive(interface: &Interface, packet: &Packet) {
+    let data = packet.payload;
+    if data.len() < HEADER_LEN {
+        interface.count_dropped();
+        return;
+    }
+    let source_port = u16::from_be_bytes([data[0], data[1]]);
+    let destination_port = u16::from_be_bytes([data[2], data[3]]);
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let sum = u16::from_be_bytes([data[6], data[7]]);
+    if len < HEADER_LEN || len > data.len() {
+        interface.count_dropped();
+        return;
+    }
+    let datagram = &data[..len];
+    if sum != 0 && datagram_checksum(packet.source, packet.destination, datagram) != 0 {
+        interface.count_dropped();
+        return;
+    }
+
+    let Some(queue) = PORTS.lock().get(&destination_port).cloned() else {
+        interface.count_dropped();
+        return;
+    };
+    let mut queue = queue.lock();
+    if queue.len() >= QUEUE_LIMIT {
+        interface.count_dropped();
+        return;
+    }
+    let source = SocketAddrV4::new(packet.source, source_port);
+    queue.push_back((source, datagram[HEADER_LEN..].to_vec()));
+    drop(queue);
+    RECEIVE_WAITERS.wake_all();
+}
@@ -0,0 +1,121 @@
+//! Spin lock diagnostics, enabled with the `lock-debug` feature
+//!
+//! Every spin lock remembers which CPU and task holds it and where it was
+//! taken. Taking a lock twice from the same context panics, and spinning
+//! for longer than [`SPIN_TIMEOUT_MS`] reports both call sites once.
+
+use crate::arch;
+use crate::kernel::sched;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// How long a lock may spin before it is reported
+pub const SPIN_TIMEOUT_MS: u64 = 1000;
+
+const FREE: usize = 0;
+
+pub struct LockDebug {
+    owner: AtomicUsize,
+    site: AtomicPtr<Location<'static>>,
+}
+
+/// Identifies the running context as CPU plus task
+fn current_owner() -> usize {
+    (arch::cpu_id() << 32 | sched::current().as_usize()) + 1
+}
+
+fn describe(owner: usize) -> (usize, usize) {
+    let owner = owner - 1;
+    (owner >> 32, owner & 0xFFFF_FFFF)
+}
+
+impl LockDebug {
+    pub const fn new() -> Self {
+        Self {
+            owner: AtomicUsize::new(FREE),
+            site: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn holder_site(&self) -> Option<&'static Location<'static>> {
+        let site = self.site.load(Ordering::Relaxed);
+        unsafe { site.as_ref() }
+    }
+
+    /// Acquire through `try_lock`, watching for self-deadlock and long spins
+    pub fn acquire<G>(
+        &self,
+        site: &'static Location<'static>,
+        mut try_lock: impl FnMut() -> Option<G>,
+    ) -> G {
+        let me = current_owner();
+
+        if self.owner.load(Ordering::Relaxed) == me {
+            let (cpu, task) = describe(me);
+            crate::log_error!(
+                "lock: recursive acquisition on cpu{} task {}\n  held since {}\n  requested at {}",
+                cpu,
+                task,
+                Self::format_site(self.holder_site()),
+                site
+            );
+            panic!("recursive spin lock acquisition at {}", site);
+        }
+
+        let timeout = arch::counter_frequency() * SPIN_TIMEOUT_MS / 1000;
+        let start = arch::counter();
+        let mut reported = false;
+
+        loop {
+            if let Some(guard) = try_lock() {
+                self.acquired(site);
+                if reported {
+                    crate::log_warn!("lock: acquired at {} after a long spin", site);
+                }
+                return guard;
+            }
+
+            if !reported && arch::counter().wrapping_sub(start) > timeout {
+                reported = true;
+                let (cpu, task) = describe(me);
+                let holder = self.owner.load(Ordering::Relaxed);
+                let (holder_cpu, holder_task) = if holder == FREE {
+                    (0, 0)
+                } else {
+                    describe(holder)
+                };
+                crate::log_error!(
+                    "lock: cpu{} task {} spinning for over {} ms, held by cpu{} task {}\n  held since {}\n  waiting at {}",
+                    cpu,
+                    task,
+                    SPIN_TIMEOUT_MS,
+                    holder_cpu,
+                    holder_task,
+                    Self::format_site(self.holder_site()),
+                    site
+                );
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Record the new holder of a lock that was just taken
+    pub fn acquired(&self, site: &'static Location<'static>) {
+        self.site.store(site as *const _ as *mut _, Ordering::Relaxed);
+        self.owner.store(current_owner(), Ordering::Relaxed);
+    }
+
+    /// Must be called before the underlying lock is released
+    pub fn release(&self) {
+        self.site.store(ptr::null_mut(), Ordering::Relaxed);
+        self.owner.store(FREE, Ordering::Relaxed);
+    }
+
+    fn format_site(site: Option<&'static Location<'static>>) -> &'static dyn core::fmt::Display {
+        match site {
+            Some(site) => site,
+            None => &"<unknown>",
+        }
+    }
+}
@@ -0,0 +1,523 @@
+//! Synchronization primitives
+//!
+//! Spin locks ([`SafeMutex`], [`IrqSafeSpinlock`]) are for short critical
+//! sections and may be used from interrupt handlers (the latter only).
+//! Sleeping locks ([`Mutex`], [`RwLock`], [`Semaphore`], [`Condvar`]) park
+//! the calling task in the scheduler and must only be used in task context.
+//!
+//! Building with the `lock-debug` feature makes the spin locks track their
+//! owner and report self-deadlocks and long spins through the logger.
+
+#[cfg(feature = "lock-debug")]
+mod debug;
+
+use crate::arch;
+use crate::kernel::sched::{self, TaskId};
+use alloc::collections::VecDeque;
+use core::cell::UnsafeCell;
+#[cfg(feature = "lock-debug")]
+use core::panic::Location;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::mutex::{SpinMutex, SpinMutexGuard};
+
+#[cfg(feature = "lock-debug")]
+pub use debug::LockDebug;
+
+/// A safe mutex implementation using spin locks
+pub struct SafeMutex<T> {
+    inner: SpinMutex<T>,
+    #[cfg(feature = "lock-debug")]
+    debug: LockDebug,
+}
+
+pub struct SafeMutexGuard<'a, T> {
+    guard: ManuallyDrop<SpinMutexGuard<'a, T>>,
+    #[cfg(feature = "lock-debug")]
+    debug: &'a LockDebug,
+}
+
+impl<T> SafeMutex<T> {
+    /// Create a new mutex
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinMutex::new(value),
+            #[cfg(feature = "lock-debug")]
+            debug: LockDebug::new(),
+        }
+    }
+
+    /// Lock the mutex and get mutable access to the value
+    #[track_caller]
+    pub fn lock(&self) -> SafeMutexGuard<'_, T> {
+        #[cfg(feature = "lock-debug")]
+        let guard = self
+            .debug
+            .acquire(Location::caller(), || self.inner.try_lock());
+        #[cfg(not(feature = "lock-debug"))]
+        let guard = self.inner.lock();
+
+        SafeMutexGuard {
+            guard: ManuallyDrop::new(guard),
+            #[cfg(feature = "lock-debug")]
+            debug: &self.debug,
+        }
+    }
+
+    #[track_caller]
+    #[allow(dead_code, reason = "for callers that must not spin on a held lock")]
+    pub fn try_lock(&self) -> Option<SafeMutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        #[cfg(feature = "lock-debug")]
+        self.debug.acquired(Location::caller());
+
+        Some(SafeMutexGuard {
+            guard: ManuallyDrop::new(guard),
+            #[cfg(feature = "lock-debug")]
+            debug: &self.debug,
+        })
+    }
+}
+
+impl<T> Deref for SafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for SafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for SafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lock-debug")]
+        self.debug.release();
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+    }
+}
+
+/// A spin lock that masks IRQs on the local core while it is held
+///
+/// Data shared with interrupt handlers must be protected by this lock,
+/// otherwise an IRQ arriving while the lock is held spins forever.
+pub struct IrqSafeSpinlock<T> {
+    inner: SpinMutex<T>,
+    #[cfg(feature = "lock-debug")]
+    debug: LockDebug,
+}
+
+pub struct IrqSafeSpinlockGuard<'a, T> {
+    guard: ManuallyDrop<SpinMutexGuard<'a, T>>,
+    daif: u64,
+    #[cfg(feature = "lock-debug")]
+    debug: &'a LockDebug,
+}
+
+impl<T> IrqSafeSpinlock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinMutex::new(value),
+            #[cfg(feature = "lock-debug")]
+            debug: LockDebug::new(),
+        }
+    }
+
+    /// Mask IRQs, then spin until the lock is acquired
+    #[track_caller]
+    pub fn lock(&self) -> IrqSafeSpinlockGuard<'_, T> {
+        let daif = arch::local_irq_save();
+
+        #[cfg(feature = "lock-debug")]
+        let guard = self
+            .debug
+            .acquire(Location::caller(), || self.inner.try_lock());
+        #[cfg(not(feature = "lock-debug"))]
+        let guard = self.inner.lock();
+
+        IrqSafeSpinlockGuard {
+            guard: ManuallyDrop::new(guard),
+            daif,
+            #[cfg(feature = "lock-debug")]
+            debug: &self.debug,
+        }
+    }
+
+    #[track_caller]
+    #[allow(dead_code, reason = "for callers that must not spin on a held lock")]
+    pub fn try_lock(&self) -> Option<IrqSafeSpinlockGuard<'_, T>> {
+        let daif = arch::local_irq_save();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                #[cfg(feature = "lock-debug")]
+                self.debug.acquired(Location::caller());
+
+                Some(IrqSafeSpinlockGuard {
+                    guard: ManuallyDrop::new(guard),
+                    daif,
+                    #[cfg(feature = "lock-debug")]
+                    debug: &self.debug,
+                })
+            }
+            None => {
+                arch::local_irq_restore(daif);
+                None
+            }
+        }
+    }
+}
+
+impl<T> Deref for IrqSafeSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSafeSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSafeSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lock-debug")]
+        self.debug.release();
+        // Release the lock before IRQs can fire again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        arch::local_irq_restore(self.daif);
+    }
+}
+
+/// A queue of tasks parked until some condition becomes true
+pub struct WaitQueue {
+    waiters: IrqSafeSpinlock<VecDeque<TaskId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: IrqSafeSpinlock::new(VecDeque::new()),
+        }
+    }
+
+    /// Park the current task until `cond` returns true
+    ///
+    /// `cond` is evaluated with IRQs masked, so a wakeup from an interrupt
+    /// handler cannot be lost between the check and parking.
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        let mut enqueued = false;
+        loop {
+            let daif = arch::local_irq_save();
+            if cond() {
+                // Another wakeup, e.g. a timeout, may have got us here first.
+                // Left in the queue the entry would swallow a `wake_one`.
+                if enqueued {
+                    self.dequeue_current();
+                }
+                arch::local_irq_restore(daif);
+                return;
+            }
+            self.enqueue_current();
+            enqueued = true;
+            sched::block_current();
+            arch::local_irq_restore(daif);
+        }
+    }
+
+    /// Wake the longest waiting task, returns false if nobody was waiting
+    pub fn wake_one(&self) -> bool {
+        let next = self.waiters.lock().pop_front();
+        match next {
+            Some(task) => {
+                sched::wake(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for task in waiters {
+            sched::wake(task);
+        }
+    }
+
+    fn enqueue_current(&self) {
+        let current = sched::current();
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&current) {
+            waiters.push_back(current);
+        }
+    }
+
+    fn dequeue_current(&self) {
+        let current = sched::current();
+        self.waiters.lock().retain(|&task| task != current);
+    }
+}
+
+/// A mutual exclusion lock that parks contending tasks
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire());
+        MutexGuard { mutex: self }
+    }
+
+    #[allow(dead_code, reason = "for callers that must not sleep on a held lock")]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.acquire() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.wake_one();
+    }
+}
+
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A reader-writer lock that parks contending tasks
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire_write());
+        RwLockWriteGuard { lock: self }
+    }
+
+    #[allow(dead_code, reason = "for callers that must not sleep on a held lock")]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.acquire_read().then(|| RwLockReadGuard { lock: self })
+    }
+
+    #[allow(dead_code, reason = "for callers that must not sleep on a held lock")]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.acquire_write().then(|| RwLockWriteGuard { lock: self })
+    }
+
+    fn acquire_read(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state & WRITER == 0
+            && self
+                .state
+                .compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    fn acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.waiters.wake_all();
+        }
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.waiters.wake_all();
+    }
+}
+
+/// A counting semaphore
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take one permit, parking until one is available
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.load(Ordering::Relaxed);
+        while count > 0 {
+            match self.count.compare_exchange_weak(
+                count,
+                count - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => count = actual,
+            }
+        }
+        false
+    }
+
+    /// Return one permit, safe to call from interrupt handlers
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+/// A condition variable paired with a sleeping [`Mutex`]
+///
+/// Like most condition variables, waits may wake up spuriously.
+#[allow(dead_code, reason = "waits on state guarded by a sleeping Mutex")]
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+#[allow(dead_code, reason = "waits on state guarded by a sleeping Mutex")]
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Release `guard`, park until notified and lock the mutex again
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let daif = arch::local_irq_save();
+        self.waiters.enqueue_current();
+        drop(guard);
+        sched::block_current();
+        arch::local_irq_restore(daif);
+        mutex.lock()
+    }
+
+    /// Wait until `cond` returns false
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
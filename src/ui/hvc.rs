@@ -0,0 +1,85 @@
+//! Sessions on virtio-console ports
+//!
+//! Every port the host adds gets a task. A port named [`LOG_PORT_NAME`]
+//! carries the kernel log, starting with what is still in the logger's
+//! history, so messages stay off the ports people type on. Any other port
+//! runs a shell whenever a host program is connected, and a new one after
+//! each logout, like getty.
+
+use super::session::{Connection, Protocol, Session};
+use crate::drivers::virtio::console::{self, Port};
+use crate::kernel::{sched, timer};
+use crate::logger;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::time::Duration;
+
+/// Ports with this name get the kernel log instead of a shell
+pub const LOG_PORT_NAME: &str = "org.nyannix.log";
+
+/// How often the log port checks for new messages
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct PortConnection(Arc<Port>);
+
+impl Connection for PortConnection {
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        self.0.read(buffer)
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        // A closed port ends the session at the next read
+        let _ = self.0.write(bytes);
+    }
+
+    fn name(&self) -> String {
+        match self.0.name() {
+            Some(name) => format!("{} ({})", self.0.tty(), name),
+            None => self.0.tty(),
+        }
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        self.0.size()
+    }
+}
+
+/// Copy the log to `port` while it is open
+fn pump_log(port: &Port) {
+    // Start from the oldest message still kept
+    let mut position = 0;
+    let mut buffer = [0; 1024];
+    while port.is_open() {
+        let len = logger::read_history(&mut position, &mut buffer);
+        if len == 0 {
+            timer::sleep(LOG_POLL_INTERVAL);
+        } else if port.write(&buffer[..len]).is_err() {
+            break;
+        }
+    }
+}
+
+/// Serve one port until the host removes it
+fn serve_port(port: Arc<Port>) {
+    while port.wait_until_open() {
+        let tty = port.tty();
+        if port.name().as_deref() == Some(LOG_PORT_NAME) {
+            crate::log_info!("hvc: kernel log on {}", tty);
+            pump_log(&port);
+        } else {
+            crate::log_info!("hvc: session on {}", tty);
+            Session::new(Box::new(PortConnection(port.clone())), Protocol::Raw).run();
+            crate::log_info!("hvc: {} logged out", tty);
+        }
+    }
+}
+
+/// Start a task for every port, runs as the `hvcd` task
+pub fn serve() {
+    loop {
+        let port = console::accept();
+        sched::spawn("hvc", move || serve_port(port));
+    }
+}
@@ -0,0 +1,390 @@
+//! Remote shell sessions
+//!
+//! A [`Session`] runs a [`Shell`] over any byte stream that implements
+//! [`Connection`]: telnet clients and virtio-console ports. The session
+//! edits the line itself, so the other end sends each key as it is typed.
+//!
+//! Over telnet the server offers to echo and to suppress go-ahead, and asks
+//! for the window size (NAWS, RFC 1073), which `tty` shows. Options change
+//! only when the other side asks for a different state, so negotiation
+//! cannot loop. A raw session just echoes.
+
+use super::shell::{Output, Shell, TEXT_COLOR};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Telnet commands
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// Telnet options
+const OPTION_ECHO: u8 = 1;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+const OPTION_NAWS: u8 = 31;
+
+/// Options we are willing to do, and to let the client do
+const LOCAL_OPTIONS: &[u8] = &[OPTION_ECHO, OPTION_SUPPRESS_GO_AHEAD];
+const REMOTE_OPTIONS: &[u8] = &[OPTION_NAWS, OPTION_SUPPRESS_GO_AHEAD];
+
+/// Window size until the client tells us
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+/// Byte stream a session runs over
+pub trait Connection: Send {
+    /// Wait for input, 0 once the other end has gone
+    fn read(&self, buffer: &mut [u8]) -> usize;
+
+    /// A broken connection ends the session at the next read
+    fn write(&self, bytes: &[u8]);
+
+    /// What `tty` calls the session
+    fn name(&self) -> String;
+
+    /// Columns and rows, if the connection itself knows them
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Telnet,
+    /// Plain bytes, e.g. a terminal in raw mode
+    Raw,
+}
+
+/// Where the input parser is
+enum Input {
+    Data,
+    /// After a carriage return, a line feed or NUL belongs to it
+    Return,
+    Iac,
+    /// After IAC and WILL, WONT, DO or DONT
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+    Escape,
+    /// Inside an ANSI control sequence, e.g. an arrow key
+    ControlSequence,
+}
+
+/// The remote end a shell writes to
+struct Client {
+    connection: Box<dyn Connection>,
+    /// Window size the client negotiated
+    size: (usize, usize),
+}
+
+impl Client {
+    fn send(&self, bytes: &[u8]) {
+        self.connection.write(bytes);
+    }
+}
+
+impl Output for Client {
+    fn write(&mut self, text: &str, color: u32) {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let mut bytes = Vec::new();
+        // Black suits the terminal window, not every client, keep its default
+        if color != TEXT_COLOR {
+            let [_, red, green, blue] = color.to_be_bytes();
+            bytes.extend(format!("\x1b[38;2;{};{};{}m", red, green, blue).bytes());
+        }
+        bytes.extend(text.replace('\n', "\r\n").bytes());
+        if color != TEXT_COLOR {
+            bytes.extend(b"\x1b[0m");
+        }
+        bytes.extend(b"\r\n");
+        self.send(&bytes);
+    }
+
+    fn clear(&mut self) {
+        self.send(b"\x1b[H\x1b[2J");
+    }
+
+    fn name(&self) -> String {
+        self.connection.name()
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.connection.size().unwrap_or(self.size)
+    }
+}
+
+/// One logged in client
+pub struct Session {
+    client: Client,
+    protocol: Protocol,
+    shell: Shell,
+    input: Input,
+    /// Bytes of an incomplete UTF-8 character
+    pending: Vec<u8>,
+    subnegotiation: Vec<u8>,
+    line: String,
+    history: Vec<String>,
+    /// Entry of `history` on the line, `history.len()` for a new line
+    history_index: usize,
+    /// Options in effect on our side and on the client's
+    local: [bool; 256],
+    remote: [bool; 256],
+    logged_out: bool,
+}
+
+impl Session {
+    pub fn new(connection: Box<dyn Connection>, protocol: Protocol) -> Self {
+        Self {
+            client: Client {
+                connection,
+                size: DEFAULT_SIZE,
+            },
+            protocol,
+            shell: Shell::new(),
+            input: Input::Data,
+            pending: Vec::new(),
+            subnegotiation: Vec::new(),
+            line: String::new(),
+            history: Vec::new(),
+            history_index: 0,
+            local: [false; 256],
+            remote: [false; 256],
+            logged_out: false,
+        }
+    }
+
+    /// Offer our options, greet and show the first prompt
+    fn start(&mut self) {
+        match self.protocol {
+            Protocol::Telnet => {
+                for option in LOCAL_OPTIONS {
+                    self.local[*option as usize] = true;
+                    self.client.send(&[IAC, WILL, *option]);
+                }
+                self.remote[OPTION_NAWS as usize] = true;
+                self.client.send(&[IAC, DO, OPTION_NAWS]);
+            }
+            Protocol::Raw => self.local[OPTION_ECHO as usize] = true,
+        }
+        self.client
+            .send(b"NyanNix, type help for the commands and exit to leave\r\n");
+        self.show_prompt();
+    }
+
+    fn show_prompt(&self) {
+        self.client.send(self.shell.prompt().as_bytes());
+    }
+
+    /// Echo `text` unless the client does that itself
+    fn echo(&self, text: &[u8]) {
+        if self.local[OPTION_ECHO as usize] {
+            self.client.send(text);
+        }
+    }
+
+    /// Answer WILL, WONT, DO or DONT, only when it changes something
+    fn negotiate(&mut self, command: u8, option: u8) {
+        let index = option as usize;
+        let reply = match command {
+            WILL if !self.remote[index] => {
+                self.remote[index] = REMOTE_OPTIONS.contains(&option);
+                if self.remote[index] {
+                    DO
+                } else {
+                    DONT
+                }
+            }
+            WONT if self.remote[index] => {
+                self.remote[index] = false;
+                DONT
+            }
+            DO if !self.local[index] => {
+                self.local[index] = LOCAL_OPTIONS.contains(&option);
+                if self.local[index] {
+                    WILL
+                } else {
+                    WONT
+                }
+            }
+            DONT if self.local[index] => {
+                self.local[index] = false;
+                WONT
+            }
+            _ => return,
+        };
+        self.client.send(&[IAC, reply, option]);
+    }
+
+    fn subnegotiate(&mut self) {
+        if let [OPTION_NAWS, width_high, width_low, height_high, height_low] =
+            self.subnegotiation[..]
+        {
+            let width = u16::from_be_bytes([width_high, width_low]) as usize;
+            let height = u16::from_be_bytes([height_high, height_low]) as usize;
+            // Zero means the client does not know
+            if width > 0 && height > 0 {
+                self.client.size = (width, height);
+            }
+        }
+        self.subnegotiation.clear();
+    }
+
+    /// Take one byte from the connection
+    fn receive(&mut self, byte: u8) {
+        self.input = match (&self.input, byte) {
+            (Input::Iac, IAC) => {
+                self.key(IAC);
+                Input::Data
+            }
+            (Input::Iac, WILL | WONT | DO | DONT) => Input::Negotiate(byte),
+            (Input::Iac, SB) => Input::Subnegotiation,
+            // Everything else after IAC has no meaning here
+            (Input::Iac, _) => Input::Data,
+            (Input::Negotiate(command), _) => {
+                let command = *command;
+                self.negotiate(command, byte);
+                Input::Data
+            }
+            (Input::Subnegotiation, IAC) => Input::SubnegotiationIac,
+            (Input::Subnegotiation, _) => {
+                self.subnegotiation.push(byte);
+                Input::Subnegotiation
+            }
+            (Input::SubnegotiationIac, SE) => {
+                self.subnegotiate();
+                Input::Data
+            }
+            (Input::SubnegotiationIac, _) => {
+                self.subnegotiation.push(byte);
+                Input::Subnegotiation
+            }
+            (Input::Escape, b'[') => Input::ControlSequence,
+            (Input::Escape, _) => Input::Data,
+            (Input::ControlSequence, b'A') => {
+                self.recall_history(true);
+                Input::Data
+            }
+            (Input::ControlSequence, b'B') => {
+                self.recall_history(false);
+                Input::Data
+            }
+            // Parameters until the final byte, other keys are ignored
+            (Input::ControlSequence, 0x20..=0x3f) => Input::ControlSequence,
+            (Input::ControlSequence, _) => Input::Data,
+            (_, IAC) if self.protocol == Protocol::Telnet => Input::Iac,
+            (Input::Return, b'\n' | 0) => Input::Data,
+            (_, b'\r') => {
+                self.submit();
+                Input::Return
+            }
+            (_, 0x1b) => Input::Escape,
+            _ => {
+                self.key(byte);
+                Input::Data
+            }
+        };
+    }
+
+    /// Edit the line with a byte the client typed
+    fn key(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.submit(),
+            // Backspace and delete
+            0x08 | 0x7f => {
+                if self.line.pop().is_some() {
+                    self.echo(b"\x08 \x08");
+                }
+            }
+            // Ctrl+C: abandon the line
+            0x03 => {
+                self.echo(b"^C");
+                self.client.send(b"\r\n");
+                self.line.clear();
+                self.history_index = self.history.len();
+                self.show_prompt();
+            }
+            // Ctrl+D on an empty line logs out
+            0x04 if self.line.is_empty() => self.logged_out = true,
+            // Ctrl+L: clear the screen
+            0x0c => {
+                self.client.clear();
+                self.redraw_line();
+            }
+            // Ctrl+U: delete the line
+            0x15 => {
+                self.line.clear();
+                self.redraw_line();
+            }
+            byte if byte < 0x20 => {}
+            byte => {
+                self.pending.push(byte);
+                if let Ok(text) = core::str::from_utf8(&self.pending) {
+                    self.line.push_str(text);
+                    let pending = core::mem::take(&mut self.pending);
+                    self.echo(&pending);
+                } else if self.pending.len() >= 4 {
+                    self.pending.clear();
+                }
+            }
+        }
+    }
+
+    fn redraw_line(&self) {
+        let text = format!("\r\x1b[K{}{}", self.shell.prompt(), self.line);
+        self.client.send(text.as_bytes());
+    }
+
+    /// Step through earlier commands, past the newest one the line is empty
+    fn recall_history(&mut self, older: bool) {
+        if older {
+            self.history_index = self.history_index.saturating_sub(1);
+        } else if self.history_index < self.history.len() {
+            self.history_index += 1;
+        }
+        self.line = self
+            .history
+            .get(self.history_index)
+            .cloned()
+            .unwrap_or_default();
+        self.redraw_line();
+    }
+
+    fn submit(&mut self) {
+        self.client.send(b"\r\n");
+        let line = core::mem::take(&mut self.line);
+        let command = line.trim();
+        if !command.is_empty() {
+            self.history.push(String::from(command));
+        }
+        self.history_index = self.history.len();
+        if matches!(command, "exit" | "logout") {
+            self.logged_out = true;
+            return;
+        }
+        self.shell.execute(command, &mut self.client);
+        self.show_prompt();
+    }
+
+    /// Serve the client until it logs out or goes away
+    pub fn run(&mut self) {
+        self.start();
+        let mut buffer = [0; 512];
+        while !self.logged_out {
+            let len = self.client.connection.read(&mut buffer);
+            if len == 0 {
+                break;
+            }
+            for &byte in &buffer[..len] {
+                self.receive(byte);
+                if self.logged_out {
+                    break;
+                }
+            }
+        }
+    }
+}
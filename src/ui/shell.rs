@@ -0,0 +1,1242 @@
+//! Command interpreter
+//!
+//! A [`Shell`] runs command lines against its own working directory and
+//! writes what they print to an [`Output`]. The terminal window is one
+//! output, remote [`super::session`]s over telnet or virtio-console ports
+//! are others, so every command works the same everywhere.
+
+use crate::drivers::block::{self, Size};
+use crate::drivers::keyboard::KEYBOARD;
+use crate::drivers::DISPLAY;
+use crate::drivers::{fw_cfg, keymap, pci, rtc};
+use crate::fs::{self, cache, dev, proc, FileType, Volume};
+use crate::kernel::time::{self, DateTime, Instant, SystemTime};
+use crate::kernel::{random, sched, timer};
+use crate::net::http::{self, Url};
+use crate::net::tcp::{TcpListener, TcpStream};
+use crate::net::udp::UdpSocket;
+use crate::net::{self, dns, icmp};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+pub const TEXT_COLOR: u32 = 0x00000000;
+pub const ERROR_COLOR: u32 = 0x00FF0000;
+pub const DIRECTORY_COLOR: u32 = 0x000000FF;
+
+/// Echo requests `ping` sends without `-c`
+const PING_COUNT: u16 = 4;
+const PING_DATA_LEN: usize = 56;
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+/// What `udp-echo` sends without text
+const UDP_ECHO_TEXT: &str = "nyan";
+const UDP_ECHO_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long `tcp-echo` waits for the echo to come back
+const TCP_ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Send every datagram on `socket` back where it came from
+fn serve_udp_echo(socket: UdpSocket) {
+    let mut buffer = [0; 1500];
+    loop {
+        let Ok((len, source)) = socket.recv_from(&mut buffer) else {
+            continue;
+        };
+        if let Err(err) = socket.send_to(&buffer[..len], source) {
+            crate::log_warn!("udp-echo: reply to {} failed: {}", source, err);
+        }
+    }
+}
+
+/// Accept connections on `listener`, each echoed by its own task
+fn serve_tcp_echo(listener: TcpListener) {
+    loop {
+        let Ok(stream) = listener.accept() else {
+            continue;
+        };
+        sched::spawn("tcp-echo", move || {
+            let mut buffer = [0; 1500];
+            loop {
+                let len = match stream.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(err) => {
+                        crate::log_warn!("tcp-echo: {}: {}", stream.peer_addr(), err);
+                        break;
+                    }
+                };
+                if let Err(err) = stream.write(&buffer[..len]) {
+                    crate::log_warn!("tcp-echo: {}: {}", stream.peer_addr(), err);
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Bytes as lines of 32 hex digits
+fn hex_lines(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for line in bytes.chunks(16) {
+        for byte in line {
+            text.push_str(&format!("{:02x}", byte));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Time since boot like `uptime` shows it, e.g. `2 days, 3:04` or `5 min`
+struct Uptime(Duration);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = self.0.as_secs() / 60;
+        let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+        match days {
+            0 => {}
+            1 => write!(f, "1 day, ")?,
+            days => write!(f, "{} days, ", days)?,
+        }
+        if days == 0 && hours == 0 {
+            write!(f, "{} min", minutes)
+        } else {
+            write!(f, "{}:{:02}", hours, minutes)
+        }
+    }
+}
+
+/// A duration in milliseconds with three decimals, e.g. `0.412 ms`
+struct Millis(Duration);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let micros = self.0.as_micros();
+        write!(f, "{}.{:03} ms", micros / 1000, micros % 1000)
+    }
+}
+
+/// Where a shell writes, the terminal window or a remote session
+pub trait Output {
+    /// Append text, a trailing newline does not add a blank line
+    fn write(&mut self, text: &str, color: u32);
+
+    /// Show what was written so far, for commands that take a while
+    fn flush(&mut self) {}
+
+    fn clear(&mut self);
+
+    /// What `tty` calls this output
+    fn name(&self) -> String;
+
+    /// Columns and rows of text that fit
+    fn size(&self) -> (usize, usize);
+}
+
+/// Working directory and text color of one session
+pub struct Shell {
+    file_system: FileSystem,
+    current_color: u32,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            file_system: FileSystem::new(),
+            current_color: TEXT_COLOR,
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        format!("{}$ ", self.file_system.current_path())
+    }
+
+    /// Color of `echo` and of the command line
+    pub fn color(&self) -> u32 {
+        self.current_color
+    }
+
+    /// Run one command line, writing what it prints to `out`
+    pub fn execute(&mut self, line: &str, out: &mut dyn Output) {
+        let cmd = line.trim();
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        if parts.is_empty() {
+            return;
+        }
+
+        match parts[0] {
+            "cd" => {
+                let target = parts.get(1).copied().unwrap_or("/");
+                if let Err(err) = self.file_system.change_directory(target) {
+                    out.write(&format!("cd: {}: {}\n", target, err), ERROR_COLOR);
+                }
+            }
+            "ls" if parts.get(1) == Some(&"-l") => {
+                for entry in self.file_system.list_long() {
+                    let color = if entry.starts_with('d') {
+                        DIRECTORY_COLOR
+                    } else {
+                        TEXT_COLOR
+                    };
+                    out.write(&entry, color);
+                }
+            }
+            "ls" => {
+                for entry in self.file_system.list_contents() {
+                    let color = if entry.ends_with('/') {
+                        DIRECTORY_COLOR
+                    } else {
+                        TEXT_COLOR
+                    };
+                    out.write(&entry, color);
+                }
+            }
+            "mkdir" => {
+                if parts.len() < 2 {
+                    out.write("Usage: mkdir <directory>\n", ERROR_COLOR);
+                } else if let Err(err) = self.file_system.create_directory(parts[1]) {
+                    out.write(&format!("mkdir: {}\n", err), ERROR_COLOR);
+                }
+            }
+            "touch" => {
+                if parts.len() < 2 {
+                    out.write("Usage: touch <filename>\n", ERROR_COLOR);
+                } else if !self.file_system.exists(parts[1]) {
+                    if let Err(err) = self.file_system.create_file(parts[1], "") {
+                        out.write(&format!("touch: {}\n", err), ERROR_COLOR);
+                    }
+                }
+            }
+            "rm" => {
+                if parts.len() < 2 {
+                    out.write("Usage: rm <file>\n", ERROR_COLOR);
+                } else if let Err(err) = self.file_system.delete(parts[1]) {
+                    out.write(&format!("rm: {}: {}\n", parts[1], err), ERROR_COLOR);
+                }
+            }
+            "mount" => self.mount(out, parts.get(1).copied(), parts.get(2).copied()),
+            "clear" => out.clear(),
+            "help" => out.write(
+                "Available commands:\n\
+                clear - Clear terminal\n\
+                help - Show this help\n\
+                echo [text] - Print text\n\
+                color [hex] - Change text color\n\
+                cd [dir] - Change directory\n\
+                ls [-l] - List files, -l with permissions and sizes\n\
+                mkdir <dir> - Create a directory\n\
+                touch <file> - Create an empty file\n\
+                rm <path> - Delete a file or an empty directory\n\
+                mount [disk dir] - List or mount file systems\n\
+                cat [file] - Show file contents\n\
+                lsblk - List block devices\n\
+                lspci - List PCI devices\n\
+                ifconfig [if addr/len [gw]] - Show or set network addresses\n\
+                ping [-c count] <address> - Send ICMP echo requests\n\
+                udp-echo <address> <port> [text] - Send a datagram, show the reply\n\
+                udp-echo -l <port> - Echo datagrams on a port in the background\n\
+                tcp-echo <address> <port> [text] - Send text over TCP, show the echo\n\
+                tcp-echo -l <port> - Echo TCP connections on a port in the background\n\
+                host <name> - Look up the address of a host name\n\
+                wget <url> [file] - Download a file over HTTP\n\
+                random [bytes] - Print random bytes in hex\n\
+                fwcfg [file] - List or show host-provided files\n\
+                keymap [name] - Show or change the keyboard layout\n\
+                resolution [WxH] - Show or change the display mode\n\
+                sync - Write cached disk blocks\n\
+                tty - Show the terminal name and size\n\
+                date [YYYY-MM-DD HH:MM[:SS]] - Show or set the date and time, UTC\n\
+                uptime - Show how long the system has been running\n\
+                exit - Log out of a telnet or hvc session\n\
+                version - Show version\n",
+                TEXT_COLOR,
+            ),
+            "echo" => {
+                let text = parts[1..].join(" ");
+                out.write(&text, self.current_color);
+            }
+            "color" => {
+                if parts.len() > 1 {
+                    if let Ok(color) = u32::from_str_radix(parts[1].trim_start_matches("0x"), 16) {
+                        self.current_color = color;
+                        out.write("Color changed\n", color);
+                    } else {
+                        out.write(
+                            "Invalid color format. Use hex (e.g., 0xFF0000)\n",
+                            ERROR_COLOR,
+                        );
+                    }
+                }
+            }
+            "cat" => {
+                if parts.len() > 1 {
+                    if let Some(content) = self.file_system.read_proc(parts[1]) {
+                        out.write(&content, TEXT_COLOR);
+                        return;
+                    }
+                    // Devices never end, show their first bytes in hex
+                    let mut buffer = [0; DEV_READ_LEN];
+                    if let Some(len) = self.file_system.read_dev(parts[1], &mut buffer) {
+                        out.write(&hex_lines(&buffer[..len]), TEXT_COLOR);
+                        return;
+                    }
+                    match self.file_system.read_file(parts[1]) {
+                        Some(content) => out.write(&content, TEXT_COLOR),
+                        None if parts[1] == "README.md" => out.write(
+                            "NyanNix Operating System\n\
+                            A cute and functional OS\n",
+                            TEXT_COLOR,
+                        ),
+                        None => out.write("File not found\n", ERROR_COLOR),
+                    }
+                } else {
+                    out.write("Usage: cat [file]\n", ERROR_COLOR);
+                }
+            }
+            "fwcfg" => self.fw_cfg(out, parts.get(1).copied()),
+            "keymap" => self.keymap(out, parts.get(1).copied()),
+            "lsblk" => self.lsblk(out),
+            "ifconfig" => self.ifconfig(out, &parts[1..]),
+            "ping" => self.ping(out, &parts[1..]),
+            "udp-echo" => self.udp_echo(out, &parts[1..]),
+            "tcp-echo" => self.tcp_echo(out, &parts[1..]),
+            "host" => match parts.get(1) {
+                Some(name) => match dns::resolve(name) {
+                    Ok(address) => {
+                        out.write(&format!("{} has address {}\n", name, address), TEXT_COLOR)
+                    }
+                    Err(err) => out.write(&format!("host: {}: {}\n", name, err), ERROR_COLOR),
+                },
+                None => out.write("Usage: host <name>\n", ERROR_COLOR),
+            },
+            "wget" => self.wget(out, &parts[1..]),
+            "random" => self.random(out, parts.get(1).copied()),
+            "lspci" => {
+                let devices = pci::devices();
+                if devices.is_empty() {
+                    out.write("No PCI devices found\n", TEXT_COLOR);
+                }
+                for device in devices {
+                    out.write(&format!("{}", device), TEXT_COLOR);
+                    for (index, bar) in device.bars() {
+                        out.write(&format!("    BAR{}: {}", index, bar), TEXT_COLOR);
+                    }
+                }
+            }
+            "sync" => match fs::sync_all().and_then(|()| Ok(cache::sync_all()?)) {
+                Ok(0) => {}
+                Ok(written) => out.write(&format!("{} blocks written\n", written), TEXT_COLOR),
+                Err(err) => out.write(&format!("sync: {:?}\n", err), ERROR_COLOR),
+            },
+            "resolution" => self.resolution(out, parts.get(1).copied()),
+            "tty" => {
+                let (columns, rows) = out.size();
+                let line = format!("{}, {} columns, {} rows\n", out.name(), columns, rows);
+                out.write(&line, TEXT_COLOR);
+            }
+            "date" => self.date(out, &parts[1..]),
+            "uptime" => {
+                let now = DateTime::from_system_time(SystemTime::now());
+                let line = format!(
+                    "{:02}:{:02}:{:02} up {}\n",
+                    now.hour,
+                    now.minute,
+                    now.second,
+                    Uptime(time::uptime())
+                );
+                out.write(&line, TEXT_COLOR);
+            }
+            "version" => out.write("NyanNix Terminal v0.1.0\n", TEXT_COLOR),
+            _ => out.write(&format!("Unknown command: {}\n", cmd), ERROR_COLOR),
+        }
+    }
+
+    fn fw_cfg(&mut self, out: &mut dyn Output, name: Option<&str>) {
+        let Some(name) = name else {
+            if !fw_cfg::is_present() {
+                out.write("fwcfg: no fw_cfg device\n", ERROR_COLOR);
+                return;
+            }
+            for file in fw_cfg::files() {
+                let line = format!("{:04x} {:>8} {}\n", file.select, file.size, file.name);
+                out.write(&line, TEXT_COLOR);
+            }
+            return;
+        };
+
+        match fw_cfg::read_file(name) {
+            Ok(data) => {
+                let text = String::from_utf8_lossy(&data).into_owned();
+                out.write(&text, TEXT_COLOR);
+                if !text.ends_with('\n') {
+                    out.write("\n", TEXT_COLOR);
+                }
+            }
+            Err(err) => out.write(&format!("fwcfg: {}: {:?}\n", name, err), ERROR_COLOR),
+        }
+    }
+
+    fn lsblk(&mut self, out: &mut dyn Output) {
+        let devices = block::devices();
+        if devices.is_empty() {
+            out.write("No block devices found\n", TEXT_COLOR);
+            return;
+        }
+        out.write("NAME    SIZE  BLOCK RO MODEL\n", TEXT_COLOR);
+        for device in devices {
+            let line = format!(
+                "{:<5} {:>6} {:>6} {:>2} {}\n",
+                device.name(),
+                format!("{}", Size(device.capacity())),
+                device.block_size(),
+                device.is_read_only() as u8,
+                device.model()
+            );
+            out.write(&line, TEXT_COLOR);
+        }
+    }
+
+    fn mount(&mut self, out: &mut dyn Output, device: Option<&str>, path: Option<&str>) {
+        let (Some(device), Some(path)) = (device, path) else {
+            for mount in fs::mounts() {
+                let line = format!(
+                    "{} on {} type {} ({})\n",
+                    mount.device,
+                    mount.path,
+                    mount.volume.fs_type(),
+                    if mount.volume.label().is_empty() {
+                        String::from("no label")
+                    } else {
+                        mount.volume.label()
+                    }
+                );
+                out.write(&line, TEXT_COLOR);
+            }
+            return;
+        };
+
+        let path = self.file_system.resolve(path);
+        match fs::mount(device, &path) {
+            Ok(_) => self.file_system.add_mount_point(&path),
+            Err(err) => out.write(&format!("mount: {}: {}\n", device, err), ERROR_COLOR),
+        }
+    }
+
+    fn keymap(&mut self, out: &mut dyn Output, name: Option<&str>) {
+        let Some(name) = name else {
+            let current = KEYBOARD.lock().keymap().name;
+            for keymap in keymap::KEYMAPS {
+                let marker = if keymap.name == current { " *" } else { "" };
+                let line = format!("  {:<4} {}{}\n", keymap.name, keymap.description, marker);
+                out.write(&line, TEXT_COLOR);
+            }
+            return;
+        };
+
+        match keymap::find(name) {
+            Some(keymap) => {
+                KEYBOARD.lock().set_keymap(keymap);
+                out.write(&format!("Keymap: {}\n", keymap.description), TEXT_COLOR);
+            }
+            None => out.write(&format!("keymap: unknown layout {}\n", name), ERROR_COLOR),
+        }
+    }
+
+    fn ifconfig(&mut self, out: &mut dyn Output, args: &[&str]) {
+        if let [name, address, rest @ ..] = args {
+            let Some(interface) = net::find(name) else {
+                out.write(
+                    &format!("ifconfig: {}: no such interface\n", name),
+                    ERROR_COLOR,
+                );
+                return;
+            };
+            let config = address.split_once('/').and_then(|(address, prefix_len)| {
+                let prefix_len = prefix_len.parse().ok().filter(|len| *len <= 32)?;
+                let gateway = match rest.first() {
+                    Some(gateway) => Some(gateway.parse().ok()?),
+                    None => None,
+                };
+                Some(net::Config {
+                    address: address.parse().ok()?,
+                    prefix_len,
+                    gateway,
+                    dns_server: interface.config().dns_server,
+                })
+            });
+            match config {
+                Some(config) => interface.set_config(config),
+                None => out.write("Usage: ifconfig <if> <addr>/<len> [gateway]\n", ERROR_COLOR),
+            }
+            return;
+        }
+
+        let interfaces = net::interfaces();
+        if interfaces.is_empty() {
+            out.write("No network interfaces found\n", TEXT_COLOR);
+        }
+        for interface in interfaces {
+            if args.first().is_some_and(|name| *name != interface.name()) {
+                continue;
+            }
+            let config = interface.config();
+            let stats = interface.stats();
+            let device = interface.device();
+            let mut text = format!(
+                "{}: link {}  mtu {}\n",
+                interface.name(),
+                if device.link_up() { "up" } else { "down" },
+                device.mtu()
+            );
+            if config.is_configured() {
+                text += &format!(
+                    "    inet {}  netmask {}  broadcast {}\n",
+                    config.address,
+                    config.netmask(),
+                    config.broadcast()
+                );
+            }
+            if let Some(gateway) = config.gateway {
+                text += &format!("    gateway {}\n", gateway);
+            }
+            if let Some(dns_server) = config.dns_server {
+                text += &format!("    dns {}\n", dns_server);
+            }
+            text += &format!(
+                "    ether {}\n\
+                \x20   RX packets {}  bytes {}  dropped {}\n\
+                \x20   TX packets {}  bytes {}  errors {}\n",
+                interface.mac_address(),
+                stats.rx_packets,
+                stats.rx_bytes,
+                stats.rx_dropped,
+                stats.tx_packets,
+                stats.tx_bytes,
+                stats.tx_errors
+            );
+            out.write(&text, TEXT_COLOR);
+        }
+    }
+
+    fn ping(&mut self, out: &mut dyn Output, args: &[&str]) {
+        let (count, target) = match args {
+            ["-c", count, target] => (count.parse().ok(), *target),
+            [target] => (Some(PING_COUNT), *target),
+            _ => (None, ""),
+        };
+        let (Some(count), Ok(destination)) = (count, target.parse::<net::Ipv4Addr>()) else {
+            out.write("Usage: ping [-c count] <address>\n", ERROR_COLOR);
+            return;
+        };
+
+        out.write(
+            &format!("PING {}: {} data bytes\n", destination, PING_DATA_LEN),
+            TEXT_COLOR,
+        );
+        out.flush();
+        let identifier = icmp::next_identifier();
+        let mut received = 0;
+        let mut times = Vec::new();
+        for sequence in 0..count {
+            if sequence > 0 {
+                timer::sleep(PING_INTERVAL);
+            }
+            match icmp::ping(
+                destination,
+                identifier,
+                sequence,
+                PING_DATA_LEN,
+                PING_TIMEOUT,
+            ) {
+                Ok((reply, time)) => {
+                    received += 1;
+                    times.push(time);
+                    let line = format!(
+                        "{} bytes from {}: icmp_seq={} ttl={} time={}\n",
+                        reply.len + 8,
+                        reply.source,
+                        reply.sequence,
+                        reply.ttl,
+                        Millis(time)
+                    );
+                    out.write(&line, TEXT_COLOR);
+                }
+                Err(err) => {
+                    out.write(&format!("icmp_seq={}: {}\n", sequence, err), ERROR_COLOR);
+                }
+            }
+            // Show each reply as it comes, not when the command is done
+            out.flush();
+        }
+
+        let loss = (count - received) as u32 * 100 / count.max(1) as u32;
+        out.write(
+            &format!(
+                "--- {} ping statistics ---\n\
+                {} packets transmitted, {} received, {}% packet loss\n",
+                destination, count, received, loss
+            ),
+            TEXT_COLOR,
+        );
+        if let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) {
+            let average = times.iter().sum::<Duration>() / times.len() as u32;
+            out.write(
+                &format!(
+                    "rtt min/avg/max = {}/{}/{}\n",
+                    Millis(*min),
+                    Millis(average),
+                    Millis(*max)
+                ),
+                TEXT_COLOR,
+            );
+        }
+    }
+
+    fn udp_echo(&mut self, out: &mut dyn Output, args: &[&str]) {
+        if let ["-l", port] = args {
+            let Ok(port) = port.parse() else {
+                out.write("Usage: udp-echo -l <port>\n", ERROR_COLOR);
+                return;
+            };
+            match UdpSocket::bind(port) {
+                Ok(socket) => {
+                    sched::spawn("udp-echo", move || serve_udp_echo(socket));
+                    out.write(&format!("Echoing UDP on port {}\n", port), TEXT_COLOR);
+                }
+                Err(err) => out.write(&format!("udp-echo: {}\n", err), ERROR_COLOR),
+            }
+            return;
+        }
+
+        let destination = match args {
+            [address, port, ..] => address.parse().ok().zip(port.parse().ok()),
+            _ => None,
+        };
+        let Some((address, port)) = destination else {
+            out.write("Usage: udp-echo <address> <port> [text]\n", ERROR_COLOR);
+            return;
+        };
+        let text = match args[2..].join(" ") {
+            text if text.is_empty() => String::from(UDP_ECHO_TEXT),
+            text => text,
+        };
+        let destination = net::SocketAddrV4::new(address, port);
+
+        let result = UdpSocket::bind(0).and_then(|mut socket| {
+            socket.set_read_timeout(Some(UDP_ECHO_TIMEOUT));
+            let sent = Instant::now();
+            socket.send_to(text.as_bytes(), destination)?;
+            let mut buffer = [0; 1500];
+            let (len, source) = socket.recv_from(&mut buffer)?;
+            Ok((
+                String::from_utf8_lossy(&buffer[..len]).into_owned(),
+                source,
+                sent.elapsed(),
+            ))
+        });
+        match result {
+            Ok((reply, source, time)) => out.write(
+                &format!(
+                    "{} bytes from {}: {} (time={})\n",
+                    reply.len(),
+                    source,
+                    reply,
+                    Millis(time)
+                ),
+                TEXT_COLOR,
+            ),
+            Err(err) => out.write(
+                &format!("udp-echo: {}: {}\n", destination, err),
+                ERROR_COLOR,
+            ),
+        }
+    }
+
+    fn tcp_echo(&mut self, out: &mut dyn Output, args: &[&str]) {
+        if let ["-l", port] = args {
+            let Ok(port) = port.parse() else {
+                out.write("Usage: tcp-echo -l <port>\n", ERROR_COLOR);
+                return;
+            };
+            match TcpListener::bind(port) {
+                Ok(listener) => {
+                    sched::spawn("tcp-echo", move || serve_tcp_echo(listener));
+                    out.write(&format!("Echoing TCP on port {}\n", port), TEXT_COLOR);
+                }
+                Err(err) => out.write(&format!("tcp-echo: {}\n", err), ERROR_COLOR),
+            }
+            return;
+        }
+
+        let destination = match args {
+            [address, port, ..] => address.parse().ok().zip(port.parse().ok()),
+            _ => None,
+        };
+        let Some((address, port)) = destination else {
+            out.write("Usage: tcp-echo <address> <port> [text]\n", ERROR_COLOR);
+            return;
+        };
+        let text = match args[2..].join(" ") {
+            text if text.is_empty() => String::from(UDP_ECHO_TEXT),
+            text => text,
+        };
+        let destination = net::SocketAddrV4::new(address, port);
+
+        // Send everything, close our side and read until the peer closes
+        let result = TcpStream::connect(destination).and_then(|mut stream| {
+            stream.set_read_timeout(Some(TCP_ECHO_TIMEOUT));
+            let sent = Instant::now();
+            stream.write(text.as_bytes())?;
+            stream.shutdown();
+            let mut reply = Vec::new();
+            let mut buffer = [0; 1500];
+            loop {
+                match stream.read(&mut buffer)? {
+                    0 => break,
+                    len => reply.extend_from_slice(&buffer[..len]),
+                }
+            }
+            Ok((String::from_utf8_lossy(&reply).into_owned(), sent.elapsed()))
+        });
+        match result {
+            Ok((reply, time)) => out.write(
+                &format!(
+                    "{} bytes from {}: {} (time={})\n",
+                    reply.len(),
+                    destination,
+                    reply,
+                    Millis(time)
+                ),
+                TEXT_COLOR,
+            ),
+            Err(err) => out.write(
+                &format!("tcp-echo: {}: {}\n", destination, err),
+                ERROR_COLOR,
+            ),
+        }
+    }
+
+    fn wget(&mut self, out: &mut dyn Output, args: &[&str]) {
+        let (url, file) = match args {
+            [url] => (*url, None),
+            [url, file] => (*url, Some(*file)),
+            _ => {
+                out.write("Usage: wget <url> [file]\n", ERROR_COLOR);
+                return;
+            }
+        };
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(err) => {
+                out.write(&format!("wget: {}: {}\n", url, err), ERROR_COLOR);
+                return;
+            }
+        };
+        let file = file.or(url.file_name()).unwrap_or("index.html");
+
+        out.write(&format!("Fetching {}\n", url), TEXT_COLOR);
+        out.flush();
+        let started = Instant::now();
+        let response = match http::get(&url) {
+            Ok(response) if response.status == 200 => response,
+            Ok(response) => {
+                let line = format!("wget: {}: {} {}\n", url, response.status, response.reason);
+                out.write(&line, ERROR_COLOR);
+                return;
+            }
+            Err(err) => {
+                out.write(&format!("wget: {}: {}\n", url, err), ERROR_COLOR);
+                return;
+            }
+        };
+        match self.file_system.write_file(file, &response.body) {
+            Ok(()) => out.write(
+                &format!(
+                    "Saved {} bytes to {} in {}\n",
+                    response.body.len(),
+                    file,
+                    Millis(started.elapsed())
+                ),
+                TEXT_COLOR,
+            ),
+            Err(err) => out.write(&format!("wget: {}: {}\n", file, err), ERROR_COLOR),
+        }
+    }
+
+    fn date(&mut self, out: &mut dyn Output, args: &[&str]) {
+        if args.is_empty() {
+            let now = DateTime::from_system_time(SystemTime::now());
+            out.write(&format!("{}\n", now.to_long_string()), TEXT_COLOR);
+            if !time::is_system_time_set() {
+                out.write(
+                    "date: no real-time clock, counting from boot\n",
+                    ERROR_COLOR,
+                );
+            }
+            return;
+        }
+        let text = args.join(" ");
+        let Some(date) = DateTime::parse(&text) else {
+            out.write("Usage: date [YYYY-MM-DD HH:MM[:SS]]\n", ERROR_COLOR);
+            return;
+        };
+        if let Some(time) = date.to_system_time() {
+            rtc::set_time(time);
+            out.write(&format!("{}\n", date.to_long_string()), TEXT_COLOR);
+        }
+    }
+
+    fn random(&mut self, out: &mut dyn Output, len: Option<&str>) {
+        let len = match len.map(str::parse::<usize>) {
+            None => 32,
+            Some(Ok(len)) if (1..=MAX_RANDOM_LEN).contains(&len) => len,
+            _ => {
+                let usage = format!("Usage: random [bytes], at most {}\n", MAX_RANDOM_LEN);
+                out.write(&usage, ERROR_COLOR);
+                return;
+            }
+        };
+        if !random::is_seeded() {
+            out.write("random: not seeded yet, waiting for entropy\n", TEXT_COLOR);
+            out.flush();
+            random::wait_until_seeded();
+        }
+        let mut bytes = vec![0; len];
+        random::fill_bytes(&mut bytes);
+        out.write(&hex_lines(&bytes), TEXT_COLOR);
+    }
+
+    fn resolution(&mut self, out: &mut dyn Output, mode: Option<&str>) {
+        let Some(mode) = mode else {
+            let display = DISPLAY.lock();
+            let current = (display.width(), display.height());
+            let modes = display.modes();
+            let backend = display.backend_name().unwrap_or("none");
+            drop(display);
+
+            out.write(
+                &format!("Current mode: {}x{} ({})\n", current.0, current.1, backend),
+                TEXT_COLOR,
+            );
+            out.write("Available modes:\n", TEXT_COLOR);
+            for (width, height) in modes {
+                let marker = if (width, height) == current { " *" } else { "" };
+                out.write(&format!("  {}x{}{}\n", width, height, marker), TEXT_COLOR);
+            }
+            return;
+        };
+
+        let size = mode
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)));
+        let Some((width, height)) = size else {
+            out.write("Usage: resolution [WIDTHxHEIGHT]\n", ERROR_COLOR);
+            return;
+        };
+        // The UI relayouts when the resize event comes around
+        if let Err(err) = DISPLAY.lock().set_mode(width, height) {
+            out.write(
+                &format!("resolution: cannot set {}x{}: {:?}\n", width, height, err),
+                ERROR_COLOR,
+            );
+        }
+    }
+}
+
+/// One session's view of the in-memory tree and the mounted volumes
+pub struct FileSystem {
+    current_path: String,
+}
+
+struct Directory {
+    files: BTreeMap<String, File>,
+    directories: BTreeMap<String, Directory>,
+}
+
+struct File {
+    #[allow(dead_code, reason = "entries are looked up by their map key")]
+    name: String,
+    content: String,
+    /// Set on every write
+    modified: SystemTime,
+}
+
+/// Directory the generated files of [`proc`] appear in
+const PROC_DIR: &str = "proc";
+
+/// Directory the devices of [`dev`] appear in
+const DEV_DIR: &str = "dev";
+
+/// Bytes `cat` reads from a device
+const DEV_READ_LEN: usize = 64;
+/// Most bytes `random` prints
+const MAX_RANDOM_LEN: usize = 4096;
+
+/// fw_cfg files below this prefix appear in the file system, under `/`
+const FW_CFG_PREFIX: &str = "opt/nyannix/";
+
+/// The in-memory tree, shared by all sessions
+static TREE: Mutex<Directory> = Mutex::new(Directory::new());
+
+/// Copy host-provided files into the file system, e.g. `opt/nyannix/etc/motd` to `/etc/motd`
+fn import_fw_cfg_files(fs: &FileSystem) {
+    for file in fw_cfg::files() {
+        let Some(path) = file.name.strip_prefix(FW_CFG_PREFIX) else {
+            continue;
+        };
+        let result = fw_cfg::read_file(&file.name)
+            .map_err(|_| "cannot read fw_cfg file")
+            .and_then(|data| fs.install_file(path, &String::from_utf8_lossy(&data)));
+        match result {
+            Ok(()) => crate::log_info!("fs: imported /{} ({} bytes)", path, file.size),
+            Err(err) => crate::log_warn!("fs: importing {}: {}", file.name, err),
+        }
+    }
+}
+
+/// Build the in-memory tree, after the volumes are mounted
+pub fn init() {
+    let file_system = FileSystem::new();
+    for directory in [PROC_DIR, DEV_DIR, "home"] {
+        let _ = file_system.create_directory(directory);
+    }
+    for mount in fs::mounts() {
+        file_system.add_mount_point(&mount.path);
+    }
+    import_fw_cfg_files(&file_system);
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        Self {
+            current_path: String::from("/"),
+        }
+    }
+
+    /// Create the directories leading to a mount point, so `ls` shows it
+    pub fn add_mount_point(&self, path: &str) {
+        let mut root = TREE.lock();
+        let mut directory = &mut *root;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            directory = directory
+                .directories
+                .entry(String::from(part))
+                .or_insert_with(Directory::new);
+        }
+    }
+
+    /// The volume `path` lies on, if it is below a mount point
+    fn mounted(&self, path: &str) -> Option<(Arc<dyn Volume>, String)> {
+        fs::lookup(&self.resolve(path))
+    }
+
+    pub fn current_path(&self) -> &str {
+        &self.current_path
+    }
+
+    /// Turn `path` into a normalized absolute path, resolving `.` and `..`
+    fn resolve(&self, path: &str) -> String {
+        let mut parts: Vec<&str> = Vec::new();
+        if !path.starts_with('/') {
+            parts.extend(self.current_path.split('/').filter(|p| !p.is_empty()));
+        }
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                name => parts.push(name),
+            }
+        }
+
+        let mut resolved = String::from("/");
+        resolved.push_str(&parts.join("/"));
+        resolved
+    }
+
+    /// Split `path` into its resolved parent directory and last component
+    fn split(&self, path: &str) -> Result<(String, String), &'static str> {
+        let resolved = self.resolve(path);
+        let (parent, name) = resolved.rsplit_once('/').unwrap_or(("", &resolved));
+        if name.is_empty() {
+            return Err("Not a file name");
+        }
+        Ok((String::from(parent), String::from(name)))
+    }
+
+    /// The directory `path` lies in below `root`, and its name there
+    fn parent<'a>(
+        &self,
+        root: &'a Directory,
+        path: &str,
+    ) -> Result<(&'a Directory, String), &'static str> {
+        let (parent, name) = self.split(path)?;
+        let directory = root.lookup(&parent).ok_or("No such directory")?;
+        Ok((directory, name))
+    }
+
+    fn parent_mut<'a>(
+        &self,
+        root: &'a mut Directory,
+        path: &str,
+    ) -> Result<(&'a mut Directory, String), &'static str> {
+        let (parent, name) = self.split(path)?;
+        let directory = root.lookup_mut(&parent).ok_or("No such directory")?;
+        Ok((directory, name))
+    }
+
+    fn get_current_directory<'a>(
+        &self,
+        root: &'a Directory,
+    ) -> Result<&'a Directory, &'static str> {
+        root.lookup(&self.current_path)
+            .ok_or("Current directory no longer exists")
+    }
+
+    pub fn change_directory(&mut self, path: &str) -> Result<(), &'static str> {
+        let resolved = self.resolve(path);
+        if let Some((volume, path)) = self.mounted(&resolved) {
+            let entry = volume.stat(&path).map_err(|err| err.as_str())?;
+            if entry.file_type != FileType::Directory {
+                return Err("Not a directory");
+            }
+        } else if TREE.lock().lookup(&resolved).is_none() {
+            return Err("No such directory");
+        }
+        self.current_path = resolved;
+        Ok(())
+    }
+
+    pub fn create_file(&self, path: &str, content: &str) -> Result<(), &'static str> {
+        if let Some((volume, path)) = self.mounted(path) {
+            volume.create_file(&path).map_err(|err| err.as_str())?;
+            if !content.is_empty() {
+                volume
+                    .write(&path, 0, content.as_bytes())
+                    .map_err(|err| err.as_str())?;
+            }
+            return Ok(());
+        }
+        let mut root = TREE.lock();
+        let (directory, name) = self.parent_mut(&mut root, path)?;
+        directory.files.insert(
+            name.clone(),
+            File {
+                name,
+                content: String::from(content),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Create or replace the file `name` with `data`
+    ///
+    /// Files in memory hold text, binary data needs a mounted volume.
+    pub fn write_file(&self, name: &str, data: &[u8]) -> Result<(), &'static str> {
+        if let Some((volume, path)) = self.mounted(name) {
+            if volume.stat(&path).is_err() {
+                volume.create_file(&path).map_err(|err| err.as_str())?;
+            }
+            volume.truncate(&path, 0).map_err(|err| err.as_str())?;
+            volume.write(&path, 0, data).map_err(|err| err.as_str())?;
+            return Ok(());
+        }
+        let text = core::str::from_utf8(data).map_err(|_| "Binary data needs a mounted volume")?;
+        self.create_file(name, text)
+    }
+
+    /// Create or replace the file at `path`, creating missing directories
+    pub fn install_file(&self, path: &str, content: &str) -> Result<(), &'static str> {
+        let (parent, name) = self.split(path)?;
+        let mut root = TREE.lock();
+        let mut directory = &mut *root;
+        for part in parent.split('/').filter(|p| !p.is_empty()) {
+            directory = directory
+                .directories
+                .entry(String::from(part))
+                .or_insert_with(Directory::new);
+        }
+        directory.files.insert(
+            name.clone(),
+            File {
+                name,
+                content: String::from(content),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn create_directory(&self, path: &str) -> Result<(), &'static str> {
+        if let Some((volume, path)) = self.mounted(path) {
+            return volume.create_dir(&path).map_err(|err| err.as_str());
+        }
+        let mut root = TREE.lock();
+        let (directory, name) = self.parent_mut(&mut root, path)?;
+        if directory.directories.contains_key(&name) {
+            return Err("Directory already exists");
+        }
+        directory.directories.insert(name, Directory::new());
+        Ok(())
+    }
+
+    pub fn delete(&self, path: &str) -> Result<(), &'static str> {
+        if let Some((volume, path)) = self.mounted(path) {
+            return volume.remove(&path).map_err(|err| err.as_str());
+        }
+        let mut root = TREE.lock();
+        let (directory, name) = self.parent_mut(&mut root, path)?;
+        if directory.files.remove(&name).is_some() || directory.directories.remove(&name).is_some()
+        {
+            Ok(())
+        } else {
+            Err("File or directory not found")
+        }
+    }
+
+    /// Contents of a generated file if `path` is below `/proc`
+    pub fn read_proc(&self, path: &str) -> Option<String> {
+        let resolved = self.resolve(path);
+        let name = resolved.strip_prefix("/proc/")?;
+        proc::read(name)
+    }
+
+    /// Read from a device if `path` is below `/dev`
+    pub fn read_dev(&self, path: &str, buffer: &mut [u8]) -> Option<usize> {
+        let resolved = self.resolve(path);
+        let name = resolved.strip_prefix("/dev/")?;
+        dev::read(name, buffer)
+    }
+
+    pub fn read_file(&self, path: &str) -> Option<String> {
+        if let Some((volume, path)) = self.mounted(path) {
+            let data = volume.read_to_end(&path).ok()?;
+            return Some(String::from_utf8_lossy(&data).into_owned());
+        }
+        let root = TREE.lock();
+        let (directory, name) = self.parent(&root, path).ok()?;
+        directory.files.get(&name).map(|file| file.content.clone())
+    }
+
+    /// True if `path` is a file or a directory
+    pub fn exists(&self, path: &str) -> bool {
+        if let Some((volume, path)) = self.mounted(path) {
+            return volume.stat(&path).is_ok();
+        }
+        let root = TREE.lock();
+        root.lookup(&self.resolve(path)).is_some()
+            || self
+                .parent(&root, path)
+                .is_ok_and(|(directory, name)| directory.files.contains_key(&name))
+    }
+
+    /// Entries of the current directory, directories end with `/`
+    pub fn list_contents(&self) -> Vec<String> {
+        let mut contents = Vec::new();
+        if let Some((volume, path)) = self.mounted(&self.current_path) {
+            for entry in volume.read_dir(&path).unwrap_or_default() {
+                match entry.file_type {
+                    FileType::Directory => contents.push(format!("{}/", entry.name)),
+                    FileType::File => contents.push(entry.name),
+                    FileType::Symlink => contents.push(format!("{}@", entry.name)),
+                }
+            }
+            return contents;
+        }
+        let root = TREE.lock();
+        let Ok(current_dir) = self.get_current_directory(&root) else {
+            return contents;
+        };
+
+        for dir in current_dir.directories.keys() {
+            contents.push(format!("{}/", dir));
+        }
+        for file in current_dir.files.keys() {
+            contents.push(file.clone());
+        }
+        if self.current_path.strip_prefix('/') == Some(PROC_DIR) {
+            contents.extend(proc::names().into_iter().map(String::from));
+        }
+        if self.current_path.strip_prefix('/') == Some(DEV_DIR) {
+            contents.extend(dev::names().into_iter().map(String::from));
+        }
+        contents
+    }
+
+    /// Entries of the current directory with type, permissions, size and
+    /// modification time
+    pub fn list_long(&self) -> Vec<String> {
+        let mut entries = Vec::new();
+        if let Some((volume, path)) = self.mounted(&self.current_path) {
+            for entry in volume.read_dir(&path).unwrap_or_default() {
+                let modified = entry.modified.map_or(String::from("-"), |modified| {
+                    DateTime::from_system_time(modified).to_short_string()
+                });
+                let mut line = format!(
+                    "{} {:>10} {:<16} {}",
+                    entry.mode_string(),
+                    entry.size,
+                    modified,
+                    entry.name
+                );
+                if entry.file_type == FileType::Symlink {
+                    let target = format!("{}/{}", path, entry.name);
+                    if let Ok(target) = volume.read_link(target.trim_start_matches('/')) {
+                        line = format!("{} -> {}", line, target);
+                    }
+                }
+                entries.push(line);
+            }
+            return entries;
+        }
+        let root = TREE.lock();
+        let Ok(current_dir) = self.get_current_directory(&root) else {
+            return entries;
+        };
+
+        // The in-memory tree has no owners or directory times, show the usual defaults
+        for dir in current_dir.directories.keys() {
+            entries.push(format!("drwxr-xr-x {:>10} {:<16} {}", 0, "-", dir));
+        }
+        for (name, file) in &current_dir.files {
+            let size = file.content.len();
+            let modified = DateTime::from_system_time(file.modified).to_short_string();
+            entries.push(format!("-rw-r--r-- {:>10} {:<16} {}", size, modified, name));
+        }
+        entries
+    }
+}
+
+impl Directory {
+    const fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            directories: BTreeMap::new(),
+        }
+    }
+
+    fn lookup(&self, path: &str) -> Option<&Directory> {
+        let mut current = self;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = current.directories.get(part)?;
+        }
+        Some(current)
+    }
+
+    fn lookup_mut(&mut self, path: &str) -> Option<&mut Directory> {
+        let mut current = self;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = current.directories.get_mut(part)?;
+        }
+        Some(current)
+    }
+}
@@ -1,25 +1,41 @@
-use super::{Menu, Window};
-use crate::drivers::virtio::GPU;
-use alloc::collections::BTreeMap;
+use super::shell::{Output, Shell, TEXT_COLOR};
+use super::{Menu, Scrollbar, Window, DESKTOP_COLOR};
+use crate::drivers::cursor::CursorShape;
+use crate::drivers::font::{FONT_HEIGHT, FONT_WIDTH};
+use crate::drivers::keyboard::{
+    KeyEvent, KEY_DELETE, KEY_DOWN, KEY_END, KEY_HOME, KEY_LEFT, KEY_PAGEDOWN, KEY_PAGEUP,
+    KEY_RIGHT, KEY_UP,
+};
+use crate::drivers::DISPLAY;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::fmt::Write;
+
+const LINE_HEIGHT: u32 = FONT_HEIGHT as u32 * 3 / 2;
 
 pub struct Terminal {
     pub window: Window,
     buffer: Vec<ColoredString>,
     scrollbar: Scrollbar,
+    /// Position of the text cursor in `current_command`, in characters
     cursor_x: usize,
     cursor_y: usize,
     scroll_offset: usize,
     command_history: Vec<String>,
     history_index: isize,
     current_command: String,
+    /// Prompt and color of the shell, kept for drawing while it runs
+    prompt: String,
+    current_color: u32,
     context_menu: Menu,
     selection_start: Option<(usize, usize)>,
     selection_end: Option<(usize, usize)>,
-    current_dir: String,
-    file_system: FileSystem,
+    clipboard: String,
+    last_buttons: u8,
+    /// Last mouse position, picks the cursor shape
+    pointer: (i32, i32),
+    /// Taken while a command runs, the shell writes back into the terminal
+    shell: Option<Shell>,
 }
 
 struct ColoredString {
@@ -27,43 +43,9 @@ struct ColoredString {
     color: u32,
 }
 
-pub struct FileSystem {
-    root: Directory,
-    current_path: String,
-}
-
-struct Directory {
-    name: String,
-    files: BTreeMap<String, File>,
-    directories: BTreeMap<String, Directory>,
-}
-
-struct File {
-    name: String,
-    content: String,
-}
-
 impl Terminal {
     pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
-        let mut fs = FileSystem {
-            root: Directory {
-                name: String::from("/"),
-                files: BTreeMap::new(),
-                directories: BTreeMap::new(),
-            },
-            current_path: String::from("/"),
-        };
-
-        // Create some initial directories and files
-        fs.root.directories.insert(
-            String::from("home"),
-            Directory {
-                name: String::from("home"),
-                files: BTreeMap::new(),
-                directories: BTreeMap::new(),
-            },
-        );
-
+        let shell = Shell::new();
         let mut context_menu = Menu::new(0, 0);
         context_menu.add_item("Copy");
         context_menu.add_item("Paste");
@@ -79,183 +61,315 @@ impl Terminal {
             command_history: Vec::new(),
             history_index: -1,
             current_command: String::new(),
+            prompt: shell.prompt(),
+            current_color: shell.color(),
             context_menu,
             selection_start: None,
             selection_end: None,
-            current_dir: String::from("/"),
-            file_system: fs,
+            clipboard: String::new(),
+            last_buttons: 0,
+            pointer: (0, 0),
+            shell: Some(shell),
         }
     }
 
-    pub fn print(&mut self, text: &str, color: u32) {
-        let lines: Vec<&str> = text.split('\n').collect();
-        for line in lines {
+    /// Move and resize the terminal, e.g. after a display mode change
+    pub fn set_geometry(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.window.x = x;
+        self.window.y = y;
+        self.window.width = width;
+        self.window.height = height;
+        self.scrollbar = Scrollbar::new(x + width - 15, y + 25, 10, height - 30);
+        self.scrollbar.max_value = self.buffer.len() as u32;
+        self.scroll_offset = self.buffer.len().saturating_sub(self.visible_lines());
+        self.scrollbar.value = self.scroll_offset as u32;
+    }
+
+    /// Number of output lines that fit above the prompt
+    fn visible_lines(&self) -> usize {
+        ((self.window.height.saturating_sub(30) / LINE_HEIGHT) as usize).saturating_sub(1)
+    }
+
+    /// Append output without redrawing, a trailing newline does not add a blank line
+    fn write(&mut self, text: &str, color: u32) {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        for line in text.split('\n') {
             self.buffer.push(ColoredString {
                 text: String::from(line),
                 color,
             });
             self.cursor_y += 1;
-            self.cursor_x = 0;
         }
         self.scrollbar.max_value = self.buffer.len() as u32;
-        self.draw();
+        self.scroll_offset = self.buffer.len().saturating_sub(self.visible_lines());
+        self.scrollbar.value = self.scroll_offset as u32;
     }
 
-    pub fn handle_mouse(&mut self, mouse_x: i32, mouse_y: i32, mouse_buttons: u8) {
-        self.window.handle_mouse(mouse_x, mouse_y, mouse_buttons);
-        self.scrollbar.handle_mouse(mouse_x, mouse_y, mouse_buttons);
-
-        // Right-click handling
-        if mouse_buttons & 2 != 0 {
-            // Right button pressed
-            self.context_menu.x = mouse_x as u32;
-            self.context_menu.y = mouse_y as u32;
-            self.context_menu.visible = true;
-        }
-
-        if let Some(item_index) = self
-            .context_menu
-            .handle_mouse(mouse_x, mouse_y, mouse_buttons)
-        {
-            match item_index {
-                0 => self.copy_selection(),
-                1 => self.paste_clipboard(),
-                2 => self.clear_buffer(),
-                _ => {}
-            }
-        }
+    #[allow(dead_code, reason = "nothing prints outside a command yet")]
+    pub fn print(&mut self, text: &str, color: u32) {
+        self.write(text, color);
+        self.draw();
     }
 
-    pub fn execute_command(&mut self) {
-        let cmd = self.current_command.trim();
-        let parts: Vec<&str> = cmd.split_whitespace().collect();
+    fn prompt(&self) -> &str {
+        &self.prompt
+    }
 
-        if parts.is_empty() {
-            self.print("\n", 0x00000000);
+    /// Line editing, history and scrolling keys, anything else types text
+    pub fn handle_key_event(&mut self, event: KeyEvent) {
+        if !event.pressed {
             return;
         }
-
-        match parts[0] {
-            "cd" => {
-                if parts.len() < 2 {
-                    self.print("Usage: cd <directory>\n", 0x00FF0000);
-                    return;
-                }
-                let new_dir = parts[1];
-                if new_dir == ".." {
-                    if let Some(last_slash) = self.current_dir.rfind('/') {
-                        self.current_dir.truncate(last_slash + 1);
-                    }
-                } else {
-                    self.current_dir.push_str(new_dir);
-                    self.current_dir.push('/');
+        let length = self.current_command.chars().count();
+        match event.code {
+            KEY_LEFT => self.cursor_x = self.cursor_x.saturating_sub(1),
+            KEY_RIGHT => self.cursor_x = (self.cursor_x + 1).min(length),
+            KEY_HOME => self.cursor_x = 0,
+            KEY_END => self.cursor_x = length,
+            KEY_DELETE => {
+                if self.cursor_x < length {
+                    let offset = self.command_offset(self.cursor_x);
+                    self.current_command.remove(offset);
                 }
             }
-            "ls" => {
-                let current = self.get_current_directory();
-                for dir in &current.directories {
-                    self.print(&format!("\x1b[34m{}/\x1b[0m\n", dir.name), 0x00000000);
-                }
-                for file in &current.files {
-                    self.print(&format!("{}\n", file.name), 0x00000000);
+            KEY_UP => self.recall_history(true),
+            KEY_DOWN => self.recall_history(false),
+            KEY_PAGEUP => self.scroll_by(-(self.visible_lines() as isize)),
+            KEY_PAGEDOWN => self.scroll_by(self.visible_lines() as isize),
+            _ => {
+                if let Some(c) = event.to_char() {
+                    self.handle_key(c);
                 }
+                return;
             }
-            "mkdir" => {
-                if parts.len() < 2 {
-                    self.print("Usage: mkdir <directory>\n", 0x00FF0000);
-                    return;
+        }
+        self.draw();
+    }
+
+    pub fn handle_key(&mut self, key: char) {
+        match key {
+            '\n' | '\r' => self.submit_command(),
+            '\x08' | '\x7f' => {
+                if self.cursor_x > 0 {
+                    self.cursor_x -= 1;
+                    let offset = self.command_offset(self.cursor_x);
+                    self.current_command.remove(offset);
                 }
-                let current = self.get_current_directory_mut();
-                current.directories.insert(
-                    String::from(parts[1]),
-                    Directory {
-                        name: String::from(parts[1]),
-                        files: BTreeMap::new(),
-                        directories: BTreeMap::new(),
-                    },
-                );
             }
-            "touch" => {
-                if parts.len() < 2 {
-                    self.print("Usage: touch <filename>\n", 0x00FF0000);
-                    return;
-                }
-                let current = self.get_current_directory_mut();
-                current.files.insert(
-                    String::from(parts[1]),
-                    File {
-                        name: String::from(parts[1]),
-                        content: String::new(),
-                    },
-                );
+            // Ctrl+A and Ctrl+E: start and end of the line
+            '\x01' => self.cursor_x = 0,
+            '\x05' => self.cursor_x = self.current_command.chars().count(),
+            // Ctrl+C: abandon the line
+            '\x03' => {
+                let line = format!("{}{}^C", self.prompt(), self.current_command);
+                self.write(&line, TEXT_COLOR);
+                self.current_command.clear();
+                self.cursor_x = 0;
+                self.history_index = -1;
             }
-            "clear" => self.clear_buffer(),
-            "help" => self.print(
-                "Available commands:\n\
-                clear - Clear terminal\n\
-                help - Show this help\n\
-                echo [text] - Print text\n\
-                color [hex] - Change text color\n\
-                ls - List files\n\
-                cat [file] - Show file contents\n\
-                version - Show version\n",
-                0x00000000,
-            ),
-            "echo" => {
-                let text = parts[1..].join(" ");
-                self.print(&format!("{}\n", text), self.current_color);
+            // Ctrl+L: clear the screen
+            '\x0c' => self.clear_buffer(),
+            // Ctrl+U: delete up to the cursor
+            '\x15' => {
+                let offset = self.command_offset(self.cursor_x);
+                self.current_command.drain(..offset);
+                self.cursor_x = 0;
             }
-            "color" => {
-                if parts.len() > 1 {
-                    if let Ok(color) = u32::from_str_radix(parts[1].trim_start_matches("0x"), 16) {
-                        self.current_color = color;
-                        self.print("Color changed\n", color);
-                    } else {
-                        self.print(
-                            "Invalid color format. Use hex (e.g., 0xFF0000)\n",
-                            0x00FF0000,
-                        );
+            c if !c.is_control() => self.insert_char(c),
+            _ => {}
+        }
+        self.draw();
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let offset = self.command_offset(self.cursor_x);
+        self.current_command.insert(offset, c);
+        self.cursor_x += 1;
+    }
+
+    /// Byte offset of the `index`th character of the command line
+    fn command_offset(&self, index: usize) -> usize {
+        self.current_command
+            .char_indices()
+            .nth(index)
+            .map_or(self.current_command.len(), |(offset, _)| offset)
+    }
+
+    /// Step through earlier commands, past the newest one the line is empty again
+    fn recall_history(&mut self, older: bool) {
+        let count = self.command_history.len() as isize;
+        let index = match (older, self.history_index) {
+            (true, -1) => count - 1,
+            (true, index) => (index - 1).max(0),
+            (false, -1) => return,
+            (false, index) if index + 1 < count => index + 1,
+            (false, _) => -1,
+        };
+        if index < 0 {
+            self.history_index = -1;
+            self.current_command.clear();
+        } else {
+            self.history_index = index;
+            self.current_command = self.command_history[index as usize].clone();
+        }
+        self.cursor_x = self.current_command.chars().count();
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        let max = self.buffer.len().saturating_sub(self.visible_lines());
+        let offset = self.scroll_offset as isize + lines;
+        self.scroll_offset = offset.clamp(0, max as isize) as usize;
+        self.scrollbar.value = self.scroll_offset as u32;
+    }
+
+    fn submit_command(&mut self) {
+        let line = format!("{}{}", self.prompt(), self.current_command);
+        self.write(&line, TEXT_COLOR);
+        if !self.current_command.trim().is_empty() {
+            self.command_history.push(self.current_command.clone());
+        }
+        self.history_index = -1;
+
+        DISPLAY.lock().set_cursor_shape(CursorShape::Busy);
+        self.execute_command();
+        self.update_cursor_shape();
+    }
+
+    fn execute_command(&mut self) {
+        let command = core::mem::take(&mut self.current_command);
+        self.cursor_x = 0;
+        let Some(mut shell) = self.shell.take() else {
+            return;
+        };
+        shell.execute(&command, self);
+        self.prompt = shell.prompt();
+        self.current_color = shell.color();
+        self.shell = Some(shell);
+    }
+
+    /// Wheel clicks scroll the buffer, positive is up
+    pub fn handle_scroll(&mut self, clicks: i32) {
+        if self.context_menu.visible || clicks == 0 {
+            return;
+        }
+        self.scroll_by(-(clicks as isize) * 3);
+        self.draw();
+    }
+
+    pub fn handle_mouse(&mut self, mouse_x: i32, mouse_y: i32, mouse_buttons: u8) {
+        let pressed = mouse_buttons & !self.last_buttons;
+        self.last_buttons = mouse_buttons;
+        self.pointer = (mouse_x, mouse_y);
+        self.update_cursor_shape();
+        let mut redraw = false;
+
+        // Clicks on an open menu belong to the menu
+        if self.context_menu.visible {
+            if let Some(item_index) = self.context_menu.handle_mouse(mouse_x, mouse_y, pressed) {
+                match item_index {
+                    0 => self.clipboard = self.copy_selection(),
+                    1 => {
+                        let text = self.clipboard.clone();
+                        self.paste_clipboard(&text);
                     }
+                    2 => self.clear_buffer(),
+                    _ => {}
                 }
             }
-            "cat" => {
-                if parts.len() > 1 {
-                    match parts[1] {
-                        "README.md" => self.print(
-                            "NyanNix Operating System\n\
-                            A cute and functional OS\n",
-                            0x00000000,
-                        ),
-                        _ => self.print("File not found\n", 0x00FF0000),
-                    }
-                } else {
-                    self.print("Usage: cat [file]\n", 0x00FF0000);
-                }
+            if !self.context_menu.visible {
+                self.redraw_desktop();
             }
-            "version" => self.print("NyanNix Terminal v0.1.0\n", 0x00000000),
-            _ => self.print(&format!("Unknown command: {}\n", cmd), 0x00FF0000),
+            return;
         }
 
-        self.current_command.clear();
-        self.cursor_x = 0;
+        // Right-click handling
+        if pressed & 2 != 0 {
+            self.context_menu.x = mouse_x as u32;
+            self.context_menu.y = mouse_y as u32;
+            self.context_menu.visible = true;
+            redraw = true;
+        }
+
+        let (old_x, old_y) = (self.window.x, self.window.y);
+        self.window.handle_mouse(mouse_x, mouse_y, mouse_buttons);
+        if (old_x, old_y) != (self.window.x, self.window.y) {
+            self.scrollbar.x = self.window.x + self.window.width - 15;
+            self.scrollbar.y = self.window.y + 25;
+            self.redraw_desktop();
+            return;
+        }
+
+        if self.scrollbar.handle_mouse(mouse_x, mouse_y, mouse_buttons) {
+            self.scroll_offset = self.scrollbar.value as usize;
+            redraw = true;
+        }
+
+        if self.in_text_area(mouse_x, mouse_y) {
+            if pressed & 1 != 0 {
+                self.start_selection(mouse_x, mouse_y);
+                redraw = true;
+            } else if mouse_buttons & 1 != 0 {
+                self.update_selection(mouse_x, mouse_y);
+                redraw = true;
+            }
+        }
+
+        if redraw {
+            self.draw();
+        }
     }
 
-    pub fn start_selection(&mut self, mouse_x: i32, mouse_y: i32) {
-        let x = ((mouse_x - self.window.x as i32 - 5) / FONT_WIDTH as i32) as usize;
-        let y = ((mouse_y - self.window.y as i32 - 25) / (FONT_HEIGHT as i32 * 3 / 2)) as usize
+    /// I-beam over text unless a menu is open, arrow elsewhere
+    fn update_cursor_shape(&self) {
+        let (x, y) = self.pointer;
+        let shape = if !self.context_menu.visible && self.in_text_area(x, y) {
+            CursorShape::IBeam
+        } else {
+            CursorShape::Arrow
+        };
+        DISPLAY.lock().set_cursor_shape(shape);
+    }
+
+    fn in_text_area(&self, x: i32, y: i32) -> bool {
+        self.window.contains(x, y)
+            && x < self.scrollbar.x as i32
+            && y >= (self.window.y + 25) as i32
+    }
+
+    fn clear_buffer(&mut self) {
+        self.buffer.clear();
+        self.cursor_y = 0;
+        self.scroll_offset = 0;
+        self.scrollbar.value = 0;
+        self.scrollbar.max_value = 0;
+        self.selection_start = None;
+        self.selection_end = None;
+        self.draw();
+    }
+
+    /// Map a screen position to a (column, line) in the buffer
+    fn text_position(&self, mouse_x: i32, mouse_y: i32) -> (usize, usize) {
+        let x = ((mouse_x - self.window.x as i32 - 5).max(0) / FONT_WIDTH as i32) as usize;
+        let y = ((mouse_y - self.window.y as i32 - 25).max(0) / LINE_HEIGHT as i32) as usize
             + self.scroll_offset;
+        (x, y)
+    }
+
+    pub fn start_selection(&mut self, mouse_x: i32, mouse_y: i32) {
+        let (x, y) = self.text_position(mouse_x, mouse_y);
 
         if y < self.buffer.len() {
             self.selection_start = Some((x, y));
             self.selection_end = Some((x, y));
+        } else {
+            self.selection_start = None;
+            self.selection_end = None;
         }
     }
 
     pub fn update_selection(&mut self, mouse_x: i32, mouse_y: i32) {
         if self.selection_start.is_some() {
-            let x = ((mouse_x - self.window.x as i32 - 5) / FONT_WIDTH as i32) as usize;
-            let y = ((mouse_y - self.window.y as i32 - 25) / (FONT_HEIGHT as i32 * 3 / 2)) as usize
-                + self.scroll_offset;
+            let (x, y) = self.text_position(mouse_x, mouse_y);
 
             if y < self.buffer.len() {
                 self.selection_end = Some((x, y));
@@ -263,173 +377,137 @@ impl Terminal {
         }
     }
 
-    pub fn copy_selection(&mut self) -> String {
-        if let (Some(start), Some(end)) = (self.selection_start, self.selection_end) {
-            let mut selected_text = String::new();
-            let (start_x, start_y, end_x, end_y) =
-                if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
-                    (start.0, start.1, end.0, end.1)
-                } else {
-                    (end.0, end.1, start.0, start.1)
-                };
+    /// Start and end of the selection in reading order
+    fn selection(&self) -> Option<(usize, usize, usize, usize)> {
+        let (start, end) = (self.selection_start?, self.selection_end?);
+        if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
+            Some((start.0, start.1, end.0, end.1))
+        } else {
+            Some((end.0, end.1, start.0, start.1))
+        }
+    }
 
-            for y in start_y..=end_y {
-                if y >= self.buffer.len() {
-                    break;
-                }
-                let line = &self.buffer[y].text;
-                let line_start = if y == start_y { start_x } else { 0 };
-                let line_end = if y == end_y { end_x } else { line.len() };
+    pub fn copy_selection(&mut self) -> String {
+        let Some((start_x, start_y, end_x, end_y)) = self.selection() else {
+            return String::new();
+        };
 
-                if line_start < line.len() {
-                    selected_text.push_str(&line[line_start..line_end.min(line.len())]);
-                }
-                if y != end_y {
-                    selected_text.push('\n');
-                }
+        let mut selected_text = String::new();
+        for y in start_y..=end_y {
+            if y >= self.buffer.len() {
+                break;
+            }
+            // Columns count characters, not bytes
+            let line = &self.buffer[y].text;
+            let line_start = if y == start_y { start_x } else { 0 };
+            let line_end = if y == end_y { end_x } else { usize::MAX };
+            selected_text.extend(
+                line.chars()
+                    .skip(line_start)
+                    .take(line_end.saturating_sub(line_start)),
+            );
+            if y != end_y {
+                selected_text.push('\n');
             }
-            selected_text
-        } else {
-            String::new()
         }
+        selected_text
     }
 
     pub fn paste_clipboard(&mut self, text: &str) {
         for c in text.chars() {
             if c == '\n' {
-                self.execute_command();
+                self.submit_command();
             } else {
-                self.current_command.push(c);
-                self.cursor_x += 1;
+                self.insert_char(c);
             }
         }
         self.draw();
     }
 
+    fn redraw_desktop(&self) {
+        DISPLAY.lock().clear_screen(DESKTOP_COLOR);
+        self.draw();
+    }
+
     pub fn draw(&self) {
-        // ... previous draw code ...
+        self.window.draw();
 
-        // Draw selection
-        if let (Some(start), Some(end)) = (self.selection_start, self.selection_end) {
-            let mut gpu = GPU.lock();
-            let (start_x, start_y, end_x, end_y) =
-                if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
-                    (start.0, start.1, end.0, end.1)
-                } else {
-                    (end.0, end.1, start.0, start.1)
-                };
+        let text_x = self.window.x + 5;
+        let text_y = self.window.y + 25;
+        let visible = self.visible_lines();
 
+        // Draw selection
+        if let Some((start_x, start_y, end_x, end_y)) = self.selection() {
+            let mut display = DISPLAY.lock();
             for y in start_y..=end_y {
-                if y >= self.buffer.len() || y < self.scroll_offset {
+                if y >= self.buffer.len()
+                    || y < self.scroll_offset
+                    || y >= self.scroll_offset + visible
+                {
                     continue;
                 }
-                let screen_y = self.window.y
-                    + 25
-                    + ((y - self.scroll_offset) as u32 * (FONT_HEIGHT as u32 * 3 / 2));
+                let screen_y = text_y + (y - self.scroll_offset) as u32 * LINE_HEIGHT;
                 let line_start = if y == start_y { start_x } else { 0 };
                 let line_end = if y == end_y {
                     end_x
                 } else {
-                    self.buffer[y].text.len()
+                    self.buffer[y].text.chars().count()
                 };
 
-                gpu.draw_rect(
-                    self.window.x + 5 + (line_start as u32 * FONT_WIDTH as u32),
+                display.draw_rect(
+                    text_x + (line_start as u32 * FONT_WIDTH as u32),
                     screen_y,
-                    ((line_end - line_start) as u32 * FONT_WIDTH as u32),
+                    line_end.saturating_sub(line_start) as u32 * FONT_WIDTH as u32,
                     FONT_HEIGHT as u32,
-                    0x400000FF,
+                    0x00AACCFF,
                 );
             }
         }
-    }
 
-    fn get_current_directory(&self) -> &Directory {
-        let mut current = &self.file_system.root;
-        for part in self.current_dir.split('/') {
-            if part.is_empty() {
-                continue;
-            }
-            if let Some(dir) = current.directories.iter().find(|d| d.name == part) {
-                current = dir;
+        {
+            let mut display = DISPLAY.lock();
+            let lines = self.buffer.iter().skip(self.scroll_offset).take(visible);
+            let mut row = 0;
+            for line in lines {
+                display.draw_text(text_x, text_y + row * LINE_HEIGHT, &line.text, line.color);
+                row += 1;
             }
-        }
-        current
-    }
 
-    fn get_current_directory_mut(&mut self) -> &mut Directory {
-        let mut current = &mut self.file_system.root;
-        for part in self.current_dir.split('/') {
-            if part.is_empty() {
-                continue;
-            }
-            if let Some(dir) = current.directories.iter_mut().find(|d| d.name == part) {
-                current = dir;
-            }
+            // Prompt with the command being typed and a block cursor
+            let prompt = format!("{}{}", self.prompt(), self.current_command);
+            let prompt_y = text_y + row * LINE_HEIGHT;
+            display.draw_text(text_x, prompt_y, &prompt, self.current_color);
+            let column = self.prompt().chars().count() + self.cursor_x;
+            let cursor_x = text_x + (column * FONT_WIDTH) as u32;
+            display.draw_rect(cursor_x, prompt_y, 2, FONT_HEIGHT as u32, TEXT_COLOR);
         }
-        current
+
+        self.scrollbar.draw();
+        self.context_menu.draw();
     }
 }
 
-impl FileSystem {
-    pub fn new() -> Self {
-        Self {
-            root: Directory::new("root"),
-            current_path: String::from("/"),
-        }
+impl Output for Terminal {
+    fn write(&mut self, text: &str, color: u32) {
+        Terminal::write(self, text, color);
     }
 
-    pub fn create_file(&mut self, name: &str, content: &str) -> Result<(), &'static str> {
-        let current_dir = self.get_current_directory_mut()?;
-        current_dir.files.insert(
-            String::from(name),
-            File {
-                name: String::from(name),
-                content: String::from(content),
-            },
-        );
-        Ok(())
+    fn flush(&mut self) {
+        self.draw();
+        // The main loop is blocked until the command returns
+        DISPLAY.lock().flush_damage();
     }
 
-    pub fn create_directory(&mut self, name: &str) -> Result<(), &'static str> {
-        let current_dir = self.get_current_directory_mut()?;
-        current_dir
-            .directories
-            .insert(String::from(name), Directory::new(name));
-        Ok(())
+    fn clear(&mut self) {
+        self.clear_buffer();
     }
 
-    pub fn delete(&mut self, name: &str) -> Result<(), &'static str> {
-        let current_dir = self.get_current_directory_mut()?;
-        if current_dir.files.remove(name).is_some()
-            || current_dir.directories.remove(name).is_some()
-        {
-            Ok(())
-        } else {
-            Err("File or directory not found")
-        }
+    fn name(&self) -> String {
+        String::from("console")
     }
 
-    pub fn list_contents(&self) -> Vec<String> {
-        let mut contents = Vec::new();
-        let current_dir = self.get_current_directory().unwrap();
-
-        for dir in current_dir.directories.keys() {
-            contents.push(format!("\x1b[34m{}/\x1b[0m", dir));
-        }
-        for file in current_dir.files.keys() {
-            contents.push(file.clone());
-        }
-        contents
-    }
-}
-
-impl Directory {
-    fn new(name: &str) -> Self {
-        Self {
-            name: String::from(name),
-            files: BTreeMap::new(),
-            directories: BTreeMap::new(),
-        }
+    fn size(&self) -> (usize, usize) {
+        let columns = self.window.width.saturating_sub(20) as usize / FONT_WIDTH;
+        (columns, self.visible_lines() + 1)
     }
 }
@@ -0,0 +1,127 @@
+use crate::drivers::font::{FONT_HEIGHT, FONT_WIDTH};
+use crate::drivers::DISPLAY;
+use alloc::vec::Vec;
+
+const MENU_ITEM_HEIGHT: u32 = FONT_HEIGHT as u32 + 8;
+const MENU_WIDTH: u32 = 100;
+
+/// Popup menu with a vertical list of items
+pub struct Menu {
+    pub x: u32,
+    pub y: u32,
+    pub visible: bool,
+    items: Vec<&'static str>,
+}
+
+impl Menu {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self {
+            x,
+            y,
+            visible: false,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn add_item(&mut self, label: &'static str) {
+        self.items.push(label);
+    }
+
+    pub fn draw(&self) {
+        if !self.visible {
+            return;
+        }
+        let height = self.items.len() as u32 * MENU_ITEM_HEIGHT;
+        let mut display = DISPLAY.lock();
+        display.draw_rect(self.x, self.y, MENU_WIDTH, height, 0x00EEEEEE);
+        display.draw_rect(self.x, self.y, MENU_WIDTH, 1, 0x00000000);
+        display.draw_rect(self.x, self.y + height - 1, MENU_WIDTH, 1, 0x00000000);
+        for (i, label) in self.items.iter().enumerate() {
+            let y = self.y + i as u32 * MENU_ITEM_HEIGHT;
+            display.draw_text(self.x + 6, y + 4, label, 0x00000000);
+        }
+    }
+
+    /// Returns the item picked by a left click, any click hides the menu
+    pub fn handle_mouse(&mut self, x: i32, y: i32, buttons: u8) -> Option<usize> {
+        if !self.visible || buttons & 1 == 0 {
+            return None;
+        }
+        self.visible = false;
+
+        let (dx, dy) = (x - self.x as i32, y - self.y as i32);
+        if dx < 0 || dy < 0 || dx >= MENU_WIDTH as i32 {
+            return None;
+        }
+        let index = dy as usize / MENU_ITEM_HEIGHT as usize;
+        (index < self.items.len()).then_some(index)
+    }
+}
+
+/// Vertical scrollbar, `value` ranges over `0..max_value`
+pub struct Scrollbar {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub value: u32,
+    pub max_value: u32,
+    dragging: bool,
+}
+
+impl Scrollbar {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            value: 0,
+            max_value: 0,
+            dragging: false,
+        }
+    }
+
+    pub fn draw(&self) {
+        let mut display = DISPLAY.lock();
+        display.draw_rect(self.x, self.y, self.width, self.height, 0x00DDDDDD);
+
+        let thumb_height = (FONT_WIDTH as u32 * 2).min(self.height);
+        let travel = self.height - thumb_height;
+        let offset = match self.max_value {
+            0 | 1 => 0,
+            max => travel * self.value.min(max - 1) / (max - 1),
+        };
+        display.draw_rect(
+            self.x,
+            self.y + offset,
+            self.width,
+            thumb_height,
+            0x00888888,
+        );
+    }
+
+    /// Drag the thumb with the left button, returns true if the value changed
+    pub fn handle_mouse(&mut self, x: i32, y: i32, buttons: u8) -> bool {
+        if buttons & 1 == 0 {
+            self.dragging = false;
+            return false;
+        }
+        let inside = x >= self.x as i32
+            && x < (self.x + self.width) as i32
+            && y >= self.y as i32
+            && y < (self.y + self.height) as i32;
+        if inside {
+            self.dragging = true;
+        }
+        if !self.dragging || self.max_value == 0 || self.height == 0 {
+            return false;
+        }
+
+        let offset = (y - self.y as i32).clamp(0, self.height as i32 - 1) as u32;
+        let value = offset * self.max_value / self.height;
+        let changed = value != self.value;
+        self.value = value;
+        changed
+    }
+}
@@ -1,6 +1,17 @@
+use crate::drivers::font::FONT_WIDTH;
+use crate::drivers::DISPLAY;
+use alloc::vec;
 use alloc::vec::Vec;
 
+pub const TITLE_BAR_HEIGHT: u32 = 20;
+
+const TITLE_BAR_COLOR: u32 = 0x00FF69B4;
+const TITLE_TEXT_COLOR: u32 = 0x00FFFFFF;
+const BACKGROUND_COLOR: u32 = 0x00FFFFFF;
+const BORDER_COLOR: u32 = 0x00000000;
+
 #[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code, reason = "nothing minimizes or maximizes a window yet")]
 pub enum WindowState {
     Normal,
     Minimizing(f32), // Animation progress 0.0 to 1.0
@@ -9,6 +20,94 @@ pub enum WindowState {
     Maximized,
 }
 
+pub struct Window {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub title: &'static str,
+    pub state: WindowState,
+    /// Grab point relative to the window while the title bar is dragged
+    drag_offset: Option<(i32, i32)>,
+}
+
+impl Window {
+    pub fn new(x: u32, y: u32, width: u32, height: u32, title: &'static str) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            title,
+            state: WindowState::Normal,
+            drag_offset: None,
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x as i32
+            && y >= self.y as i32
+            && x < (self.x + self.width) as i32
+            && y < (self.y + self.height) as i32
+    }
+
+    fn in_title_bar(&self, x: i32, y: i32) -> bool {
+        self.contains(x, y) && y < (self.y + TITLE_BAR_HEIGHT) as i32
+    }
+
+    pub fn draw(&self) {
+        let mut display = DISPLAY.lock();
+        let (x, y, w, h) = (self.x, self.y, self.width, self.height);
+
+        display.draw_rect(x, y, w, h, BACKGROUND_COLOR);
+        display.draw_rect(x, y, w, TITLE_BAR_HEIGHT, TITLE_BAR_COLOR);
+        display.draw_text(x + 6, y + 6, self.title, TITLE_TEXT_COLOR);
+
+        display.draw_rect(x, y, w, 1, BORDER_COLOR);
+        display.draw_rect(x, y, 1, h, BORDER_COLOR);
+        display.draw_rect(x + w - 1, y, 1, h, BORDER_COLOR);
+        display.draw_rect(x, y + h - 1, w, 1, BORDER_COLOR);
+    }
+
+    /// Draw the outline shrinking towards the bottom of the screen
+    pub fn draw_animated(&self, progress: f32) {
+        let progress = progress.clamp(0.0, 1.0);
+        let scale = 1.0 - progress;
+        let w = ((self.width as f32 * scale) as u32).max(1);
+        let h = ((self.height as f32 * scale) as u32).max(1);
+        let x = self.x + (self.width - w) / 2;
+        let y = self.y + ((self.height - h) as f32 * progress) as u32;
+
+        let mut display = DISPLAY.lock();
+        display.draw_rect(x, y, w, 1, BORDER_COLOR);
+        display.draw_rect(x, y, 1, h, BORDER_COLOR);
+        display.draw_rect(x + w - 1, y, 1, h, BORDER_COLOR);
+        display.draw_rect(x, y + h - 1, w, 1, BORDER_COLOR);
+        if (self.title.len() * FONT_WIDTH) as u32 + 12 < w {
+            display.draw_rect(x, y, w, TITLE_BAR_HEIGHT.min(h), TITLE_BAR_COLOR);
+        }
+    }
+
+    /// Move the window while the left button drags its title bar
+    pub fn handle_mouse(&mut self, x: i32, y: i32, buttons: u8) {
+        if buttons & 1 == 0 {
+            self.drag_offset = None;
+            return;
+        }
+        match self.drag_offset {
+            Some((dx, dy)) => {
+                self.x = (x - dx).max(0) as u32;
+                self.y = (y - dy).max(0) as u32;
+            }
+            None if self.in_title_bar(x, y) => {
+                self.drag_offset = Some((x - self.x as i32, y - self.y as i32));
+            }
+            None => {}
+        }
+    }
+}
+
+#[allow(dead_code, reason = "the terminal is the only window so far")]
 pub struct WindowManager {
     windows: Vec<Window>,
     current_desktop: usize,
@@ -16,6 +115,7 @@ pub struct WindowManager {
     focused_window: Option<usize>,
 }
 
+#[allow(dead_code, reason = "the terminal is the only window so far")]
 impl WindowManager {
     pub fn new() -> Self {
         Self {
@@ -59,9 +159,7 @@ impl WindowManager {
     }
 
     pub fn draw(&self) {
-        let mut gpu = GPU.lock();
-
-        // Draw only windows on current desktop
+        // Draw only windows on current desktop, each takes the display lock itself
         for &window_index in &self.desktops[self.current_desktop] {
             let window = &self.windows[window_index];
 